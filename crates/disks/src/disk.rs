@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use core::fmt;
-use std::fs;
+use std::{fs, io};
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
@@ -50,6 +50,8 @@ pub struct BasicDisk {
     pub(crate) name: String,
     /// Total number of sectors on the disk
     pub(crate) sectors: u64,
+    /// Logical block size in bytes, as used to address the partition table
+    pub(crate) logical_block_size: u64,
     /// Path to the device in /dev
     pub(crate) device: PathBuf,
     /// Optional disk model name
@@ -58,6 +60,8 @@ pub struct BasicDisk {
     pub(crate) vendor: Option<String>,
     /// Partitions
     pub(crate) partitions: Vec<Partition>,
+    /// Root the disk was discovered under, which holds its sysfs entries
+    pub(crate) sysroot: PathBuf,
 }
 
 impl fmt::Display for Disk {
@@ -117,6 +121,14 @@ impl BasicDisk {
         self.sectors() * 512
     }
 
+    /// Returns the logical block size in bytes, 512 if the kernel does not report one.
+    ///
+    /// Unlike [`BasicDisk::sectors`], which sysfs always counts in 512-byte units,
+    /// this is the unit LBAs in a partition table are measured in.
+    pub fn logical_block_size(&self) -> u64 {
+        self.logical_block_size
+    }
+
     /// Returns the model name of the disk.
     pub fn model(&self) -> Option<&str> {
         self.model.as_deref()
@@ -126,6 +138,31 @@ impl BasicDisk {
     pub fn vendor(&self) -> Option<&str> {
         self.vendor.as_deref()
     }
+
+    /// Returns the root the disk was discovered under, usually `/`.
+    pub fn sysroot(&self) -> &Path {
+        &self.sysroot
+    }
+
+    /// Re-reads the size and partitions of the disk from the kernel via sysfs under its sysroot.
+    pub(crate) fn refresh(&mut self) -> io::Result<()> {
+        self.partitions = read_partitions(&self.sysroot, &self.name)?;
+        self.sectors = sysfs::read(&self.sysroot.join(SYSFS_DIR).join(&self.name), "size").unwrap_or(0);
+        Ok(())
+    }
+}
+
+/// Reads the partitions of the named disk from sysfs, sorted by partition number.
+fn read_partitions(sysroot: &Path, name: &str) -> io::Result<Vec<Partition>> {
+    let mut partitions: Vec<_> = fs::read_dir(sysroot.join(SYSFS_DIR).join(name))?
+        .filter_map(Result::ok)
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            Partition::from_sysfs_path(sysroot, &name)
+        })
+        .collect();
+    partitions.sort_by_key(|p| p.number);
+    Ok(partitions)
 }
 
 /// Trait for initializing different types of disk devices from sysfs.
@@ -150,19 +187,14 @@ impl DiskInit for BasicDisk {
         log::debug!("Initializing disk at sysfs path: {node:?}");
 
         // Read the partitions of the disk if any
-        let mut partitions: Vec<_> = fs::read_dir(&node)
-            .ok()?
-            .filter_map(Result::ok)
-            .filter_map(|e| {
-                let name = e.file_name().to_string_lossy().to_string();
-                Partition::from_sysfs_path(sysroot, &name)
-            })
-            .collect();
-        partitions.sort_by_key(|p| p.number);
+        let partitions = read_partitions(sysroot, name).ok()?;
 
         let sectors = sysfs::read(&node, "size").unwrap_or(0);
         log::debug!("Read {sectors} sectors for disk {name}");
 
+        let logical_block_size = sysfs::read(&node, "queue/logical_block_size").unwrap_or(512);
+        log::debug!("Logical block size: {logical_block_size}");
+
         let device = PathBuf::from("/dev").join(name);
         log::debug!("Device path: {device:?}");
 
@@ -175,10 +207,12 @@ impl DiskInit for BasicDisk {
         Some(Self {
             name: name.to_owned(),
             sectors,
+            logical_block_size,
             device,
             model,
             vendor,
             partitions,
+            sysroot: sysroot.to_owned(),
         })
     }
 }
@@ -0,0 +1,53 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Device name filters for [`crate::enumerate_block_devices_with`]
+//!
+//! Each filter receives a kernel device name (e.g. `"sda"`, `"loop0"`) and
+//! returns `true` if the device should be included.
+
+/// Exclude loopback devices (`loop*`)
+pub fn exclude_loop() -> fn(&str) -> bool {
+    |name| !name.starts_with("loop")
+}
+
+/// Exclude RAM backed devices (`ram*`, `zram*`)
+pub fn exclude_ram() -> fn(&str) -> bool {
+    |name| !name.starts_with("ram") && !name.starts_with("zram")
+}
+
+/// Exclude optical drives (`sr*`)
+pub fn exclude_optical() -> fn(&str) -> bool {
+    |name| !name.starts_with("sr")
+}
+
+/// Only include physical disks, excluding loopback, RAM and optical devices
+pub fn only_physical() -> fn(&str) -> bool {
+    |name| exclude_loop()(name) && exclude_ram()(name) && exclude_optical()(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let names = ["sda", "nvme0n1", "mmcblk0", "vda", "loop0", "ram1", "zram0", "sr0"];
+        let included = |filter: fn(&str) -> bool| names.iter().copied().filter(|n| filter(n)).collect::<Vec<_>>();
+
+        assert_eq!(
+            included(exclude_loop()),
+            ["sda", "nvme0n1", "mmcblk0", "vda", "ram1", "zram0", "sr0"]
+        );
+        assert_eq!(
+            included(exclude_ram()),
+            ["sda", "nvme0n1", "mmcblk0", "vda", "loop0", "sr0"]
+        );
+        assert_eq!(
+            included(exclude_optical()),
+            ["sda", "nvme0n1", "mmcblk0", "vda", "loop0", "ram1", "zram0"]
+        );
+        assert_eq!(included(only_physical()), ["sda", "nvme0n1", "mmcblk0", "vda"]);
+    }
+}
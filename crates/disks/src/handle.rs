@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! A shared, buffered handle to an open block device
+
+use std::{
+    fmt, fs,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{SYSFS_DIR, mock::SparseImage, sysfs};
+
+/// Block size assumed when the kernel does not report one, e.g. for image files
+const DEFAULT_BLOCK_SIZE: u64 = 512;
+
+/// An open block device with its geometry read once up front
+///
+/// Superblock detection, partition table reads and the planner can all work
+/// from the same handle instead of each reopening the device and re-reading
+/// sysfs. Reads are buffered. The handle implements [`Read`], [`Seek`] and
+/// [`Write`], so it can be passed anywhere a file is expected.
+pub struct DeviceHandle {
+    path: PathBuf,
+    reader: Backing,
+    logical_block_size: u64,
+    physical_block_size: u64,
+    size: u64,
+}
+
+/// Where the contents of a [`DeviceHandle`] are read from
+enum Backing {
+    File(BufReader<fs::File>),
+    /// Disk contents held in memory, as used by mock devices
+    Memory(SparseImage),
+}
+
+impl fmt::Debug for DeviceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceHandle")
+            .field("path", &self.path)
+            .field("logical_block_size", &self.logical_block_size)
+            .field("physical_block_size", &self.physical_block_size)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl DeviceHandle {
+    /// Open a device, or an image file, read-only
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(path, fs::OpenOptions::new().read(true))
+    }
+
+    /// Open a device, or an image file, for reading and writing
+    pub fn open_writable(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(path, fs::OpenOptions::new().read(true).write(true))
+    }
+
+    fn open_with(path: impl AsRef<Path>, options: &fs::OpenOptions) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = options.open(path)?;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.rewind()?;
+
+        // The queue attributes live on the whole disk, which is the parent of a partition
+        let queue = fs::canonicalize(path)
+            .ok()
+            .and_then(|path| path.file_name().map(ToOwned::to_owned))
+            .map(|name| Path::new("/").join(SYSFS_DIR).join(name))
+            .and_then(|node| {
+                [node.join("queue"), node.join("../queue")]
+                    .into_iter()
+                    .find(|q| q.exists())
+            });
+        let block_size = |key| {
+            queue
+                .as_deref()
+                .and_then(|queue| sysfs::read(queue, key))
+                .unwrap_or(DEFAULT_BLOCK_SIZE)
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            logical_block_size: block_size("logical_block_size"),
+            physical_block_size: block_size("physical_block_size"),
+            reader: Backing::File(BufReader::new(file)),
+            size,
+        })
+    }
+
+    /// Wrap in-memory disk contents, reported as `path` with the given logical block size
+    pub(crate) fn from_memory(path: impl Into<PathBuf>, contents: SparseImage, logical_block_size: u64) -> Self {
+        Self {
+            path: path.into(),
+            size: contents.size(),
+            logical_block_size,
+            physical_block_size: logical_block_size,
+            reader: Backing::Memory(contents),
+        }
+    }
+
+    /// Returns the path the device was opened from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the logical block (sector) size in bytes, 512 if the kernel does not report one
+    pub fn logical_block_size(&self) -> u64 {
+        self.logical_block_size
+    }
+
+    /// Returns the physical block size in bytes, 512 if the kernel does not report one
+    pub fn physical_block_size(&self) -> u64 {
+        self.physical_block_size
+    }
+
+    /// Returns the total size of the device in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for DeviceHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.reader {
+            Backing::File(reader) => reader.read(buf),
+            Backing::Memory(image) => image.read(buf),
+        }
+    }
+}
+
+impl Seek for DeviceHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.reader {
+            Backing::File(reader) => reader.seek(pos),
+            Backing::Memory(image) => image.seek(pos),
+        }
+    }
+}
+
+impl Write for DeviceHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.reader {
+            Backing::File(reader) => {
+                // Seeking drops any read-ahead, so the file is positioned where the caller expects
+                let position = reader.stream_position()?;
+                reader.seek(SeekFrom::Start(position))?;
+                reader.get_mut().write(buf)
+            }
+            Backing::Memory(image) => image.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.reader {
+            Backing::File(reader) => reader.get_mut().flush(),
+            Backing::Memory(image) => image.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write() {
+        let image = std::env::temp_dir().join(format!("disks-rs-handle-{}.img", std::process::id()));
+        fs::write(&image, vec![0u8; 8192]).unwrap();
+
+        let mut handle = DeviceHandle::open_writable(&image).unwrap();
+        assert_eq!(handle.size(), 8192);
+        assert_eq!(handle.logical_block_size(), 512);
+        assert_eq!(handle.physical_block_size(), 512);
+
+        // A write after a buffered read lands at the logical position
+        let mut byte = [0u8; 1];
+        handle.read_exact(&mut byte).unwrap();
+        handle.write_all(b"disks").unwrap();
+        handle.rewind().unwrap();
+        let mut bytes = [0u8; 6];
+        handle.read_exact(&mut bytes).unwrap();
+        fs::remove_file(&image).unwrap();
+
+        assert_eq!(&bytes, b"\0disks");
+    }
+}
@@ -3,8 +3,16 @@
 // SPDX-License-Identifier: MPL-2.0
 
 mod disk;
+mod handle;
+pub use handle::*;
+mod mount;
+pub use mount::*;
+mod scheme;
+pub use scheme::*;
 mod sizing;
 pub use sizing::*;
+mod tree;
+pub use tree::*;
 
 use std::{
     fs, io,
@@ -13,6 +21,9 @@ use std::{
 
 pub use disk::*;
 use partition::Partition;
+pub use superblock;
+use superblock::Superblock;
+pub mod filters;
 pub mod loopback;
 pub mod mmc;
 pub mod mock;
@@ -25,6 +36,25 @@ pub mod virt;
 const SYSFS_DIR: &str = "sys/class/block";
 const DEVFS_DIR: &str = "dev";
 
+/// Enumerates all block devices present in the system.
+pub fn enumerate_block_devices() -> io::Result<Vec<BlockDevice>> {
+    BlockDevice::discover()
+}
+
+/// Enumerates the block devices whose kernel name (e.g. `"sda"`, `"loop0"`) passes `filter`.
+///
+/// See [`filters`] for common predicates.
+///
+/// # Examples
+///
+/// ```no_run
+/// let disks = disks::enumerate_block_devices_with(disks::filters::only_physical())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn enumerate_block_devices_with(filter: impl Fn(&str) -> bool) -> io::Result<Vec<BlockDevice>> {
+    BlockDevice::discover_in_sysroot_with("/", filter)
+}
+
 /// A block device on the system which can be either a physical disk or a partition.
 #[derive(Debug)]
 pub enum BlockDevice {
@@ -56,6 +86,17 @@ impl BlockDevice {
         self.sectors() * 512
     }
 
+    /// Returns the logical block size of the block device in bytes.
+    ///
+    /// This is the unit partition table LBAs are counted in, 4096 on 4Kn disks.
+    /// Mock devices and devices the kernel reports no size for use 512.
+    pub fn logical_block_size(&self) -> u64 {
+        match self {
+            BlockDevice::Disk(disk) => disk.logical_block_size(),
+            BlockDevice::Loopback(device) => device.disk().map_or(512, |d| d.logical_block_size()),
+        }
+    }
+
     /// Returns the partitions on the block device.
     pub fn partitions(&self) -> &[Partition] {
         match self {
@@ -64,6 +105,84 @@ impl BlockDevice {
         }
     }
 
+    /// Returns an iterator over the partitions paired with their detected superblocks.
+    ///
+    /// Each partition device is only opened and probed once the iterator reaches it,
+    /// so callers that stop early never touch the remaining partitions.
+    pub fn iter_partitions_with_superblocks(
+        &self,
+    ) -> impl Iterator<Item = (&Partition, Result<Superblock, superblock::Error>)> {
+        self.partitions().iter().map(move |partition| {
+            let superblock = self
+                .injected_superblock(partition.number)
+                .unwrap_or_else(|| Superblock::from_file(&partition.device));
+            (partition, superblock)
+        })
+    }
+
+    /// Re-reads the size and partitions of the device from the kernel.
+    ///
+    /// Call this after writing a partition table, once the kernel has been told
+    /// about the new partitions, rather than discovering the device again.
+    /// Mock devices keep their in-memory partitions.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        match self {
+            BlockDevice::Disk(disk) => match &mut **disk {
+                Disk::Scsi(disk) => disk.0.refresh(),
+                Disk::Mmc(disk) => disk.0.refresh(),
+                Disk::Nvme(disk) => disk.0.refresh(),
+                Disk::Virtual(disk) => disk.0.refresh(),
+                Disk::Mock(_) => Ok(()),
+            },
+            BlockDevice::Loopback(device) => device.refresh(),
+        }
+    }
+
+    /// Returns true if this is a mock device with no backing device node.
+    pub fn is_mock(&self) -> bool {
+        matches!(self, BlockDevice::Disk(disk) if matches!(**disk, Disk::Mock(_)))
+    }
+
+    /// Open the device for reading, e.g. to read its partition table.
+    ///
+    /// Mock devices created with [`BlockDevice::mock_device_with_gpt`] are read
+    /// from memory rather than from their device path.
+    pub fn open_handle(&self) -> io::Result<DeviceHandle> {
+        if let BlockDevice::Disk(disk) = self {
+            if let Disk::Mock(mock) = &**disk {
+                if let Some(image) = mock.image() {
+                    return Ok(DeviceHandle::from_memory(
+                        mock.device_path(),
+                        image.clone(),
+                        mock.logical_block_size(),
+                    ));
+                }
+            }
+        }
+        DeviceHandle::open(self.device())
+    }
+
+    /// Returns an injected superblock result for mock devices, if one was set.
+    fn injected_superblock(&self, partition_id: u32) -> Option<Result<Superblock, superblock::Error>> {
+        match self {
+            BlockDevice::Disk(disk) => match &**disk {
+                Disk::Mock(mock) => mock.superblock_result(partition_id),
+                _ => None,
+            },
+            BlockDevice::Loopback(_) => None,
+        }
+    }
+
+    /// Reads the partition scheme (GPT or MBR) from the device.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the device is not partitioned, or an IO error if it cannot be read.
+    pub fn read_partition_scheme(&self) -> io::Result<Option<PartitionScheme>> {
+        let mut file = fs::File::open(self.device())?;
+        PartitionScheme::detect(&mut file)
+    }
+
     /// Returns the path to the partition with the given index.
     /// No attempt is made to verify the existence of the partition.
     pub fn partition_path(&self, index: usize) -> PathBuf {
@@ -91,6 +210,16 @@ impl BlockDevice {
         BlockDevice::Disk(Box::new(Disk::Mock(disk)))
     }
 
+    /// Creates a mock block device holding a real GPT in memory.
+    ///
+    /// See [`mock::MockDisk::with_existing_gpt`].
+    pub fn mock_device_with_gpt(
+        size_bytes: u64,
+        partitions: &[(u64, u64, gpt::partition_types::Type)],
+    ) -> io::Result<Self> {
+        mock::MockDisk::with_existing_gpt(size_bytes, partitions).map(Self::mock_device)
+    }
+
     /// Creates a loopback block device from a file path.
     pub fn loopback_device(device: loopback::Device) -> Self {
         BlockDevice::Loopback(Box::new(device))
@@ -132,6 +261,21 @@ impl BlockDevice {
         }
     }
 
+    /// Returns a display name combining the vendor and model of the device.
+    ///
+    /// sysfs pads these fields with trailing spaces, which are trimmed. Falls
+    /// back to `"Unknown Device"` when neither is known.
+    pub fn device_model_name(&self) -> String {
+        let disk = match self {
+            BlockDevice::Disk(disk) => Some(&***disk),
+            BlockDevice::Loopback(device) => device.disk(),
+        };
+        combine_model_name(
+            disk.and_then(|d| d.vendor()).map(str::trim_end),
+            disk.and_then(|d| d.model()).map(str::trim_end),
+        )
+    }
+
     /// Returns the path to the block device in /dev.
     pub fn device(&self) -> &Path {
         match self {
@@ -140,6 +284,14 @@ impl BlockDevice {
         }
     }
 
+    /// Returns the root the device was discovered under, usually `/`.
+    pub fn sysroot(&self) -> &Path {
+        match self {
+            BlockDevice::Disk(disk) => disk.sysroot(),
+            BlockDevice::Loopback(device) => device.sysroot(),
+        }
+    }
+
     /// Discovers block devices in a specified sysroot directory.
     ///
     /// # Arguments
@@ -150,6 +302,14 @@ impl BlockDevice {
     ///
     /// A vector of discovered block devices or an IO error if the discovery fails.
     pub fn discover_in_sysroot(sysroot: impl AsRef<str>) -> io::Result<Vec<BlockDevice>> {
+        Self::discover_in_sysroot_with(sysroot, |_| true)
+    }
+
+    /// Discovers block devices in a sysroot, only probing those whose name passes `filter`.
+    fn discover_in_sysroot_with(
+        sysroot: impl AsRef<str>,
+        filter: impl Fn(&str) -> bool,
+    ) -> io::Result<Vec<BlockDevice>> {
         let sysroot = sysroot.as_ref();
         let sysfs_dir = PathBuf::from(sysroot).join(SYSFS_DIR);
         let mut devices = Vec::new();
@@ -158,6 +318,7 @@ impl BlockDevice {
         let mut entries = fs::read_dir(&sysfs_dir)?
             .filter_map(Result::ok)
             .filter_map(|e| Some(e.file_name().to_str()?.to_owned()))
+            .filter(|name| filter(name))
             .collect::<Vec<_>>();
         entries.sort();
 
@@ -173,6 +334,18 @@ impl BlockDevice {
     }
 }
 
+/// Combines an optional vendor and model into a single display name, ignoring empty fields.
+fn combine_model_name(vendor: Option<&str>, model: Option<&str>) -> String {
+    let vendor = vendor.filter(|v| !v.is_empty());
+    let model = model.filter(|m| !m.is_empty());
+    match (vendor, model) {
+        (Some(vendor), Some(model)) => format!("{vendor} {model}"),
+        (None, Some(model)) => model.to_owned(),
+        (Some(vendor), None) => format!("{vendor} (unknown model)"),
+        (None, None) => "Unknown Device".to_owned(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,6 +380,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_partition_superblocks() {
+        let mut disk = mock::MockDisk::new(1024 * 1024 * 1024);
+        disk.add_partition(1024 * 1024, 100 * 1024 * 1024);
+        disk.add_partition(100 * 1024 * 1024, 200 * 1024 * 1024);
+        disk.add_partition(200 * 1024 * 1024, 300 * 1024 * 1024);
+
+        // An otherwise empty image carrying a FAT boot signature and BIOS parameter block
+        let mut bytes = vec![0u8; 128 * 1024];
+        bytes[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bytes[13] = 1;
+        bytes[16] = 2;
+        bytes[510..512].copy_from_slice(&[0x55, 0xAA]);
+        disk.inject_superblock_result(1, move || Superblock::from_bytes(&bytes));
+        disk.inject_superblock_result(2, || Err(superblock::Error::UnknownSuperblock));
+
+        // Every pass sees the injected results
+        let device = BlockDevice::mock_device(disk);
+        for _ in 0..2 {
+            let results = device.iter_partitions_with_superblocks().collect::<Vec<_>>();
+            assert_eq!(results.len(), 3);
+
+            assert_eq!(results[0].0.number, 1);
+            assert_eq!(results[0].1.as_ref().unwrap().kind(), superblock::Kind::Fat);
+            assert!(matches!(results[1].1, Err(superblock::Error::UnknownSuperblock)));
+            // Without an injected result the (nonexistent) device node is opened
+            assert!(matches!(results[2].1, Err(superblock::Error::Io { .. })));
+        }
+    }
+
     #[test]
     fn test_partition_paths() {
         // Create a mock SCSI disk
@@ -221,4 +424,43 @@ mod tests {
         assert_eq!(device.partition_path(1).to_str().unwrap(), "/dev/nvme0n1p1");
         assert_eq!(device.partition_path(2).to_str().unwrap(), "/dev/nvme0n1p2");
     }
+
+    #[test]
+    fn test_device_model_name() {
+        assert_eq!(
+            combine_model_name(Some("Samsung"), Some("SSD 980 PRO 1TB")),
+            "Samsung SSD 980 PRO 1TB"
+        );
+        assert_eq!(combine_model_name(None, Some("SSD 980 PRO 1TB")), "SSD 980 PRO 1TB");
+        assert_eq!(combine_model_name(Some("ATA"), None), "ATA (unknown model)");
+        assert_eq!(combine_model_name(None, None), "Unknown Device");
+        assert_eq!(combine_model_name(Some(""), Some("")), "Unknown Device");
+
+        let device = BlockDevice::mock_device(mock::MockDisk::new(1000));
+        assert_eq!(device.device_model_name(), "Mock Vendor Mock Device");
+    }
+
+    #[test]
+    fn test_refresh_in_sysroot() {
+        let sysroot = std::env::temp_dir().join(format!("disks-rs-sysroot-{}", std::process::id()));
+        let disk = sysroot.join(SYSFS_DIR).join("sdz");
+        fs::create_dir_all(disk.join("sdz1")).unwrap();
+        fs::write(disk.join("size"), "2048\n").unwrap();
+        let mut device = BlockDevice::from_sysfs_path(&sysroot, "sdz").unwrap();
+        assert!(device.partitions().is_empty());
+
+        // The disk grows and gains a partition, which must be read from the same sysroot
+        fs::write(disk.join("size"), "4096\n").unwrap();
+        fs::create_dir_all(sysroot.join(SYSFS_DIR).join("sdz1")).unwrap();
+        for (key, value) in [("partition", "1"), ("start", "2048"), ("size", "1024")] {
+            fs::write(sysroot.join(SYSFS_DIR).join("sdz1").join(key), value).unwrap();
+        }
+        let result = device.refresh();
+        fs::remove_dir_all(&sysroot).unwrap();
+
+        result.unwrap();
+        assert_eq!(device.size(), 4096 * 512);
+        assert_eq!(device.partitions().len(), 1);
+        assert_eq!(device.partitions()[0].start, 2048);
+    }
 }
@@ -8,7 +8,10 @@
 //! This module handles enumeration and management of these devices,
 //! which appear as `/dev/loop*` block devices.
 
-use std::path::{Path, PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use crate::{BasicDisk, DEVFS_DIR, DiskInit, SYSFS_DIR, sysfs};
 
@@ -26,6 +29,9 @@ pub struct Device {
 
     /// Optional disk device if the loop device is backed by a disk
     disk: Option<BasicDisk>,
+
+    /// Root the device was discovered under, which holds its sysfs entries
+    sysroot: PathBuf,
 }
 
 impl Device {
@@ -51,6 +57,7 @@ impl Device {
                 device: PathBuf::from("/").join(DEVFS_DIR).join(name),
                 file,
                 disk,
+                sysroot: sysroot.to_owned(),
             })
         } else {
             None
@@ -78,6 +85,24 @@ impl Device {
         self.file.as_deref()
     }
 
+    /// Returns the root the device was discovered under, usually `/`.
+    pub fn sysroot(&self) -> &Path {
+        &self.sysroot
+    }
+
+    /// Re-reads the backing file and partitions of the loop device from the kernel.
+    pub(crate) fn refresh(&mut self) -> io::Result<()> {
+        self.file = sysfs::read(&self.sysroot.join(SYSFS_DIR).join(&self.name), "loop/backing_file");
+        self.disk = match &self.file {
+            Some(_) => Some(
+                BasicDisk::from_sysfs_path(&self.sysroot, &self.name)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Device not found"))?,
+            ),
+            None => None,
+        };
+        Ok(())
+    }
+
     /// Returns the disk device if the loop device is backed by a disk.
     pub fn disk(&self) -> Option<&BasicDisk> {
         self.disk.as_ref()
@@ -7,7 +7,17 @@
 //! This module provides a mock disk implementation that can be used for testing
 //! disk-related functionality without requiring actual hardware devices.
 
-use std::{ops::Deref, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Deref,
+    path::PathBuf,
+};
+
+use gpt::{GptConfig, disk::LogicalBlockSize, mbr::ProtectiveMBR, partition_types};
+
+use superblock::Superblock;
 
 use crate::{BasicDisk, partition::Partition};
 
@@ -18,6 +28,127 @@ use crate::{BasicDisk, partition::Partition};
 pub struct MockDisk {
     basic_disk: BasicDisk,
     pub parts_prefix: bool,
+    /// Superblock results to hand out instead of probing partition devices
+    superblocks: InjectedSuperblocks,
+    /// In-memory disk contents holding a real partition table
+    image: Option<SparseImage>,
+}
+
+/// Produces the superblock result of a mock partition each time it is probed
+type SuperblockFactory = Box<dyn Fn() -> Result<Superblock, superblock::Error> + Send + Sync>;
+
+/// Superblock results injected into a [`MockDisk`], keyed by partition number
+#[derive(Default)]
+struct InjectedSuperblocks(HashMap<u32, SuperblockFactory>);
+
+impl fmt::Debug for InjectedSuperblocks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Size of the chunks a [`SparseImage`] stores its contents in
+const CHUNK_SIZE: u64 = 4096;
+
+/// Disk contents held in memory, keeping only the chunks that were written to
+///
+/// Anything never written reads back as zeroes, so a mock disk of any size only
+/// costs the memory of the partition table at either end.
+#[derive(Clone)]
+pub struct SparseImage {
+    size: u64,
+    chunks: BTreeMap<u64, Vec<u8>>,
+    position: u64,
+}
+
+impl fmt::Debug for SparseImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseImage")
+            .field("size", &self.size)
+            .field("chunks", &self.chunks.len())
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl SparseImage {
+    /// Creates an image of `size` bytes reading as all zeroes
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            chunks: BTreeMap::new(),
+            position: 0,
+        }
+    }
+
+    /// Returns the size of the image in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Write the contents out to `file`, leaving the chunks never written as holes
+    pub fn write_to(&self, file: &mut fs::File) -> io::Result<()> {
+        file.set_len(self.size)?;
+        for (index, chunk) in &self.chunks {
+            file.seek(SeekFrom::Start(index * CHUNK_SIZE))?;
+            file.write_all(chunk)?;
+        }
+        file.flush()
+    }
+
+    /// The chunk holding the current position, the offset into it and the bytes left in it
+    fn current_chunk(&self) -> (u64, usize, usize) {
+        let offset = self.position % CHUNK_SIZE;
+        let len = (CHUNK_SIZE - offset).min(self.size.saturating_sub(self.position));
+        (self.position / CHUNK_SIZE, offset as usize, len as usize)
+    }
+}
+
+impl Read for SparseImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (index, offset, len) = self.current_chunk();
+        let len = len.min(buf.len());
+        match self.chunks.get(&index) {
+            Some(chunk) => buf[..len].copy_from_slice(&chunk[offset..offset + len]),
+            None => buf[..len].fill(0),
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for SparseImage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (index, offset, len) = self.current_chunk();
+        let len = len.min(buf.len());
+        if len > 0 {
+            let chunk = self.chunks.entry(index).or_insert_with(|| vec![0; CHUNK_SIZE as usize]);
+            chunk[offset..offset + len].copy_from_slice(&buf[..len]);
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SparseImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
 }
 
 impl Deref for MockDisk {
@@ -39,18 +170,91 @@ impl MockDisk {
         let disk = BasicDisk {
             name: name.to_string(),
             sectors,
+            logical_block_size: 512,
             device: PathBuf::from(format!("/dev/{name}")),
             model: Some("Mock Device".to_string()),
             vendor: Some("Mock Vendor".to_string()),
             partitions: Vec::new(),
+            sysroot: PathBuf::from("/"),
         };
 
         Self {
             basic_disk: disk,
             parts_prefix,
+            superblocks: InjectedSuperblocks::default(),
+            image: None,
         }
     }
 
+    /// Creates a mock disk holding a real GPT with the given partitions in memory
+    ///
+    /// Only the partition table is kept, in a [`SparseImage`], so the disk may be
+    /// of any size.
+    ///
+    /// Each partition is given as `(start_bytes, end_bytes, type)` and is numbered
+    /// in order from 1. Anything reading the partition table itself through
+    /// [`crate::BlockDevice::open_handle`], such as `Planner::from_existing`, sees
+    /// the same layout as the in-memory partition list.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if a partition ends before it
+    /// starts or extends past the end of the disk.
+    pub fn with_existing_gpt(size_bytes: u64, partitions: &[(u64, u64, partition_types::Type)]) -> io::Result<Self> {
+        if let Some((start, end, _)) = partitions
+            .iter()
+            .find(|(start, end, _)| end <= start || *end > size_bytes)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid mock partition {start}..{end} on a disk of {size_bytes} bytes"),
+            ));
+        }
+
+        let mut disk = Self::new(size_bytes);
+        let mut image = SparseImage::new(size_bytes);
+
+        let mbr = ProtectiveMBR::with_lb_size(u32::try_from((size_bytes / 512).saturating_sub(1)).unwrap_or(u32::MAX));
+        mbr.overwrite_lba0(&mut image).map_err(io::Error::other)?;
+
+        let mut table = GptConfig::default()
+            .writable(true)
+            .logical_block_size(LogicalBlockSize::Lb512)
+            .create_from_device(image, None)
+            .map_err(io::Error::other)?;
+        for (index, (start, end, part_type)) in partitions.iter().enumerate() {
+            table
+                .add_partition_at(
+                    "",
+                    index as u32 + 1,
+                    start / 512,
+                    (end - start) / 512,
+                    part_type.clone(),
+                    0,
+                )
+                .map_err(io::Error::other)?;
+            disk.add_partition(*start, *end);
+        }
+        disk.image = Some(table.write().map_err(io::Error::other)?);
+
+        Ok(disk)
+    }
+
+    /// Returns the in-memory disk contents created by [`MockDisk::with_existing_gpt`]
+    pub fn image(&self) -> Option<&SparseImage> {
+        self.image.as_ref()
+    }
+
+    /// Point the mock at a different device path, e.g. an image file for code that opens it directly
+    pub fn with_device_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.basic_disk.device = path.into();
+        self
+    }
+
+    /// Report a logical block size other than 512 bytes, e.g. to mock a 4Kn disk
+    pub fn with_logical_block_size(mut self, logical_block_size: u64) -> Self {
+        self.basic_disk.logical_block_size = logical_block_size;
+        self
+    }
+
     /// Add a partition to the mock disk at the specified byte offsets
     pub fn add_partition(&mut self, start_bytes: u64, end_bytes: u64) {
         let partition_number = self.basic_disk.partitions().len() + 1;
@@ -69,4 +273,65 @@ impl MockDisk {
 
         self.basic_disk.partitions_mut().push(partition);
     }
+
+    /// Set the superblock result reported for the given partition number
+    ///
+    /// `result` is called every time the partition is probed via
+    /// [`crate::BlockDevice::iter_partitions_with_superblocks`], so each pass
+    /// sees the same result.
+    pub fn inject_superblock_result(
+        &mut self,
+        partition_id: u32,
+        result: impl Fn() -> Result<Superblock, superblock::Error> + Send + Sync + 'static,
+    ) {
+        self.superblocks.0.insert(partition_id, Box::new(result));
+    }
+
+    /// The injected superblock result for the given partition number, if any
+    pub(crate) fn superblock_result(&self, partition_id: u32) -> Option<Result<Superblock, superblock::Error>> {
+        self.superblocks.0.get(&partition_id).map(|result| result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_image() {
+        let mut image = SparseImage::new(3 * CHUNK_SIZE);
+
+        // Writes spanning chunks only allocate the chunks they touch
+        image.seek(SeekFrom::Start(CHUNK_SIZE - 2)).unwrap();
+        image.write_all(b"abcd").unwrap();
+        assert_eq!(image.chunks.len(), 2);
+
+        let mut contents = vec![];
+        image.rewind().unwrap();
+        image.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len() as u64, 3 * CHUNK_SIZE);
+        assert_eq!(&contents[CHUNK_SIZE as usize - 3..CHUNK_SIZE as usize + 3], b"\0abcd\0");
+
+        // Nothing can be written past the end
+        image.seek(SeekFrom::End(-1)).unwrap();
+        assert!(image.write_all(b"xy").is_err());
+        assert!(image.seek(SeekFrom::Current(-(4 * CHUNK_SIZE as i64))).is_err());
+    }
+
+    #[test]
+    fn test_existing_gpt_is_sparse() {
+        const TB: u64 = 1024 * 1024 * 1024 * 1024;
+
+        let disk = MockDisk::with_existing_gpt(16 * TB, &[(1 << 20, 8 * TB, partition_types::LINUX_FS)]).unwrap();
+        let image = disk.image().unwrap();
+        assert_eq!(image.size(), 16 * TB);
+        // The MBR, both headers and entry arrays, not the 16TiB between them
+        assert!(image.chunks.len() < 16);
+
+        let table = GptConfig::default()
+            .writable(false)
+            .open_from_device(image.clone())
+            .unwrap();
+        assert_eq!(table.partitions()[&1].last_lba + 1, 8 * TB / 512);
+    }
 }
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    process::Command,
+};
+
+use superblock::{Kind, Superblock};
+
+/// Offset of the swap signature, at the end of the first 4KiB page
+const SWAP_MAGIC_OFFSET: u64 = 4096 - 10;
+
+/// Signature of a swap area created by `mkswap`
+const SWAP_MAGIC: &[u8; 10] = b"SWAPSPACE2";
+
+/// Returns the filesystem type passed to `mount -t` for a detected superblock
+fn mount_type(kind: &Kind) -> Option<&'static str> {
+    match kind {
+        Kind::Btrfs => Some("btrfs"),
+        Kind::Ext4 => Some("ext4"),
+        Kind::F2FS => Some("f2fs"),
+        Kind::Xfs => Some("xfs"),
+        Kind::Fat => Some("vfat"),
+        Kind::Nilfs2 => Some("nilfs2"),
+        Kind::Luks2 => None,
+    }
+}
+
+/// Returns true if the device holds a swap area
+fn is_swap(device: &Path) -> io::Result<bool> {
+    let mut file = fs::File::open(device)?;
+    let mut magic = [0u8; SWAP_MAGIC.len()];
+    file.seek(SeekFrom::Start(SWAP_MAGIC_OFFSET))?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == SWAP_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs a command, turning a failed exit status into an error carrying its stderr
+pub fn run_command(command: &mut Command) -> io::Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Mounts `device` on `target`, detecting the filesystem type from its superblock.
+///
+/// Swap areas are enabled with `swapon` instead, and `target` is ignored.
+/// LUKS2 containers are refused with [`io::ErrorKind::InvalidInput`], as they
+/// must be unlocked and the mapped device mounted instead.
+pub fn mount_detected(device: &Path, target: &Path) -> io::Result<()> {
+    mount_detected_with_options(device, target, &[])
+}
+
+/// Mounts `device` on `target` as [`mount_detected`] does, passing `options` to `mount -o`.
+///
+/// The options are ignored for swap areas.
+pub fn mount_detected_with_options(device: &Path, target: &Path, options: &[String]) -> io::Result<()> {
+    if is_swap(device)? {
+        return run_command(Command::new("swapon").arg(device));
+    }
+
+    let kind = Superblock::from_file(device)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .kind();
+    let Some(fstype) = mount_type(&kind) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is a {kind} container, unlock it and mount the mapped device instead",
+                device.display()
+            ),
+        ));
+    };
+
+    let mut command = Command::new("mount");
+    command.arg("-t").arg(fstype);
+    if !options.is_empty() {
+        command.arg("-o").arg(options.join(","));
+    }
+    run_command(command.arg(device).arg(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+
+    #[test]
+    fn test_mount_detected() {
+        // Mounting needs root, and the image is attached to a loop device by `mount`
+        if fs::metadata("/proc/self").map_or(true, |meta| meta.uid() != 0) {
+            eprintln!("Skipping: mounting requires root");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("disks-rs-mount-{}", std::process::id()));
+        let image = dir.join("ext4.img");
+        let target = dir.join("target");
+        fs::create_dir_all(&target).unwrap();
+        fs::File::create(&image).unwrap().set_len(32 * 1024 * 1024).unwrap();
+
+        let Ok(output) = Command::new("mkfs.ext4").arg("-q").arg(&image).output() else {
+            eprintln!("Skipping: mkfs.ext4 not available");
+            fs::remove_dir_all(&dir).ok();
+            return;
+        };
+        assert!(output.status.success());
+
+        let result = mount_detected(&image, &target);
+        let mounted = result.is_ok() && target.join("lost+found").exists();
+        if result.is_ok() {
+            run_command(Command::new("umount").arg(&target)).unwrap();
+        }
+
+        // Options are passed through to the mount
+        let with_options = mount_detected_with_options(&image, &target, &["ro".into(), "noatime".into()]);
+        let options = fs::read_to_string("/proc/self/mounts")
+            .unwrap()
+            .lines()
+            .find(|line| line.split(' ').nth(1) == target.to_str())
+            .and_then(|line| line.split(' ').nth(3).map(ToOwned::to_owned));
+        if with_options.is_ok() {
+            run_command(Command::new("umount").arg(&target)).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert!(mounted);
+        with_options.unwrap();
+        let options = options.unwrap();
+        assert!(options.split(',').any(|o| o == "ro"));
+        assert!(options.split(',').any(|o| o == "noatime"));
+    }
+}
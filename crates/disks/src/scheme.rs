@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Partition table scheme detection
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+/// GPT header signature
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Boot signature at the end of the MBR
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Logical block sizes probed for the GPT header at LBA1
+const GPT_BLOCK_SIZES: [u64; 2] = [512, 4096];
+
+/// The partitioning scheme used on a disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionScheme {
+    /// GUID Partition Table
+    Gpt,
+    /// Legacy MBR (DOS) partition table
+    Mbr,
+}
+
+impl fmt::Display for PartitionScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gpt => f.write_str("gpt"),
+            Self::Mbr => f.write_str("mbr"),
+        }
+    }
+}
+
+impl PartitionScheme {
+    /// Detects the partition scheme from the start of a disk image.
+    ///
+    /// A GPT header at LBA1 takes precedence over the (protective) MBR.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the disk carries neither a GPT header nor an MBR signature.
+    pub fn detect<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Self>> {
+        for block_size in GPT_BLOCK_SIZES {
+            let mut signature = [0u8; 8];
+            reader.seek(SeekFrom::Start(block_size))?;
+            if read_fully(reader, &mut signature)? && &signature == GPT_SIGNATURE {
+                return Ok(Some(Self::Gpt));
+            }
+        }
+
+        let mut boot_signature = [0u8; 2];
+        reader.seek(SeekFrom::Start(510))?;
+        if read_fully(reader, &mut boot_signature)? && boot_signature == MBR_SIGNATURE {
+            return Ok(Some(Self::Mbr));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Fill `buf` from the reader, returning `false` if the input ends first
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn image_with(writes: &[(usize, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut image = vec![0u8; 8192];
+        for (offset, bytes) in writes {
+            image[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        Cursor::new(image)
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut gpt = image_with(&[(510, &MBR_SIGNATURE), (512, GPT_SIGNATURE)]);
+        assert_eq!(PartitionScheme::detect(&mut gpt).unwrap(), Some(PartitionScheme::Gpt));
+
+        let mut gpt_4k = image_with(&[(510, &MBR_SIGNATURE), (4096, GPT_SIGNATURE)]);
+        assert_eq!(
+            PartitionScheme::detect(&mut gpt_4k).unwrap(),
+            Some(PartitionScheme::Gpt)
+        );
+
+        let mut mbr = image_with(&[(510, &MBR_SIGNATURE)]);
+        assert_eq!(PartitionScheme::detect(&mut mbr).unwrap(), Some(PartitionScheme::Mbr));
+
+        let mut empty = image_with(&[]);
+        assert_eq!(PartitionScheme::detect(&mut empty).unwrap(), None);
+    }
+
+    #[test]
+    fn test_detect_short_input() {
+        let mut short = Cursor::new(vec![0u8; 100]);
+        assert_eq!(PartitionScheme::detect(&mut short).unwrap(), None);
+    }
+}
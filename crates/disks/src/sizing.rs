@@ -3,6 +3,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use thiserror::Error;
+
 /// Format a size in bytes into a human readable string
 /// Format a byte size into a human-readable string with appropriate units
 ///
@@ -33,6 +35,76 @@ pub fn format_size(size: u64) -> String {
     }
 }
 
+/// Errors that can occur when parsing a human-readable size
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseSizeError {
+    /// The string is not a number followed by an optional unit
+    #[error("invalid size format: {0}")]
+    InvalidFormat(String),
+
+    /// The unit suffix is not recognised
+    #[error("invalid size unit: {0}")]
+    InvalidUnit(String),
+
+    /// The size does not fit in 64 bits
+    #[error("size overflows 64 bits: {0}")]
+    Overflow(String),
+}
+
+/// Parse a human-readable size string into a number of bytes
+///
+/// This is the inverse of [`format_size`], accepting binary units (`KiB`, `MiB`, ...),
+/// decimal units (`KB`, `MB`, ...) and plain bytes (`B` or no suffix). Units are
+/// case-insensitive and fractional values are rounded to the nearest byte.
+///
+/// # Examples
+///
+/// ```
+/// use disks::{format_size, parse_size};
+/// assert_eq!(parse_size("512MiB"), Ok(512 * 1024 * 1024));
+/// assert_eq!(parse_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+/// assert_eq!(parse_size(&format_size(4096)), Ok(4096));
+/// ```
+pub fn parse_size(s: &str) -> Result<u64, ParseSizeError> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    if number.is_empty() || number.starts_with('.') || number.ends_with('.') {
+        return Err(ParseSizeError::InvalidFormat(s.to_owned()));
+    }
+
+    let multiplier: u64 = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "kib" => 1024,
+        "mib" => 1024u64.pow(2),
+        "gib" => 1024u64.pow(3),
+        "tib" => 1024u64.pow(4),
+        _ => return Err(ParseSizeError::InvalidUnit(unit.trim_start().to_owned())),
+    };
+
+    // Whole numbers are handled exactly, fractions go through floating point
+    if let Ok(whole) = number.parse::<u64>() {
+        return whole
+            .checked_mul(multiplier)
+            .ok_or_else(|| ParseSizeError::Overflow(s.to_owned()));
+    }
+
+    let value = number
+        .parse::<f64>()
+        .map_err(|_| ParseSizeError::InvalidFormat(s.to_owned()))?;
+    let bytes = (value * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(ParseSizeError::Overflow(s.to_owned()));
+    }
+
+    Ok(bytes as u64)
+}
+
 /// Format a disk position as a percentage and absolute size
 /// Format a disk position as both a percentage and absolute size
 ///
@@ -49,6 +121,28 @@ pub fn format_position(pos: u64, total: u64) -> String {
     format!("{}% ({})", (pos as f64 / total as f64 * 100.0) as u64, format_size(pos))
 }
 
+/// Format a disk position like [`format_position`], with a fractional percentage
+///
+/// `format_position` truncates to a whole percentage, which hides the
+/// difference between small partitions on large disks. An empty `total` is
+/// shown as `0%` rather than dividing by zero.
+///
+/// # Examples
+///
+/// ```
+/// use disks::format_position_f64;
+/// assert_eq!(format_position_f64(333, 1000, 1), "33.3% (333B)");
+/// assert_eq!(format_position_f64(2, 3, 2), "66.67% (2B)");
+/// ```
+pub fn format_position_f64(pos: u64, total: u64, decimal_places: usize) -> String {
+    let pct = if total == 0 {
+        0.0
+    } else {
+        pos as f64 / total as f64 * 100.0
+    };
+    format!("{:.prec$}% ({})", pct, format_size(pos), prec = decimal_places)
+}
+
 /// Check if a value is already aligned to the given boundary
 pub fn is_aligned(value: u64, alignment: u64) -> bool {
     value % alignment == 0
@@ -71,3 +165,70 @@ pub fn align_down(value: u64, alignment: u64) -> u64 {
         remainder => value + (alignment - remainder),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512MiB"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+        assert_eq!(parse_size("4.0KiB"), Ok(4096));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("2 gb"), Ok(2_000_000_000));
+        assert_eq!(parse_size(" 1TiB "), Ok(1024u64.pow(4)));
+    }
+
+    #[test]
+    fn test_parse_size_errors() {
+        assert!(matches!(parse_size(""), Err(ParseSizeError::InvalidFormat(_))));
+        assert!(matches!(parse_size("MiB"), Err(ParseSizeError::InvalidFormat(_))));
+        assert!(matches!(parse_size("1..5MiB"), Err(ParseSizeError::InvalidFormat(_))));
+        assert!(matches!(parse_size(".5MiB"), Err(ParseSizeError::InvalidFormat(_))));
+        assert!(matches!(parse_size("12PiB"), Err(ParseSizeError::InvalidUnit(_))));
+        assert!(matches!(parse_size("12 bytes"), Err(ParseSizeError::InvalidUnit(_))));
+        assert!(matches!(
+            parse_size("18446744073709551616"),
+            Err(ParseSizeError::Overflow(_))
+        ));
+        assert!(matches!(parse_size("20000000TiB"), Err(ParseSizeError::Overflow(_))));
+        assert!(matches!(parse_size("20000000.5TiB"), Err(ParseSizeError::Overflow(_))));
+    }
+
+    #[test]
+    fn test_format_position_f64() {
+        assert_eq!(format_position(333, 1000), "33% (333B)");
+        assert_eq!(format_position_f64(333, 1000, 0), "33% (333B)");
+        assert_eq!(format_position_f64(333, 1000, 1), "33.3% (333B)");
+        assert_eq!(format_position_f64(1000, 1000, 2), "100.00% (1000B)");
+        assert_eq!(format_position_f64(1024, 3072, 3), "33.333% (1.0KiB)");
+        assert_eq!(format_position_f64(0, 0, 0), "0% (0B)");
+        assert_eq!(format_position_f64(512, 0, 1), "0.0% (512B)");
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        // Sizes exactly representable with a single decimal survive the round trip
+        for size in [
+            0,
+            1,
+            1023,
+            1024,
+            1536,
+            512 * 1024 * 1024,
+            3 * 1024 * 1024 * 1024 / 2,
+            1024u64.pow(4),
+        ] {
+            let parsed = parse_size(&format_size(size)).unwrap();
+            assert!(parsed.abs_diff(size) <= 1, "{size} became {parsed}");
+        }
+
+        // Anything format_size produces parses back to the same display
+        for size in [1500, 1_500_000, 123_456_789, 987_654_321_000] {
+            let formatted = format_size(size);
+            assert_eq!(format_size(parse_size(&formatted).unwrap()), formatted);
+        }
+    }
+}
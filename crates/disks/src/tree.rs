@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Machine-readable dump of block devices, in the spirit of `lsblk --json`
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::BlockDevice;
+
+/// A disk and its partitions, as emitted by [`tree_json`]
+#[derive(Debug, Serialize)]
+struct DiskNode<'a> {
+    name: &'a str,
+    /// Size in bytes
+    size: u64,
+    model: Option<&'a str>,
+    partitions: Vec<PartitionNode<'a>>,
+}
+
+/// A single partition, as emitted by [`tree_json`]
+#[derive(Debug, Serialize)]
+struct PartitionNode<'a> {
+    number: u32,
+    /// Size in bytes
+    size: u64,
+    device: &'a Path,
+    filesystem: Option<FilesystemNode>,
+}
+
+/// The filesystem detected on a partition
+#[derive(Debug, Serialize)]
+struct FilesystemNode {
+    kind: String,
+    uuid: Option<String>,
+    label: Option<String>,
+}
+
+/// Returns the model name of a device, if known
+fn model(device: &BlockDevice) -> Option<&str> {
+    match device {
+        BlockDevice::Disk(disk) => disk.model(),
+        BlockDevice::Loopback(device) => device.disk().and_then(|d| d.model()),
+    }
+}
+
+/// Renders the devices and their partitions as a JSON tree, similar to `lsblk --json`.
+///
+/// Each disk carries its name, size, model and a nested array of partitions,
+/// each with its number, size, device path and detected filesystem (kind,
+/// UUID and label), or `null` if no filesystem could be detected. All sizes
+/// are raw byte counts.
+pub fn tree_json(devices: &[BlockDevice]) -> String {
+    let disks = devices
+        .iter()
+        .map(|device| DiskNode {
+            name: device.name(),
+            size: device.size(),
+            model: model(device),
+            partitions: device
+                .iter_partitions_with_superblocks()
+                .map(|(partition, superblock)| PartitionNode {
+                    number: partition.number,
+                    size: partition.size * 512,
+                    device: &partition.device,
+                    filesystem: superblock.ok().map(|sb| FilesystemNode {
+                        kind: sb.kind().to_string(),
+                        uuid: sb.uuid().ok(),
+                        label: sb.label().ok(),
+                    }),
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_string_pretty(&serde_json::json!({ "blockdevices": disks }))
+        .expect("device tree is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use superblock::{Kind, Superblock};
+
+    use super::*;
+    use crate::mock::MockDisk;
+
+    #[test]
+    fn test_tree_json() {
+        const MB: u64 = 1024 * 1024;
+        let mut disk = MockDisk::new(64 * MB);
+        disk.add_partition(MB, 9 * MB);
+        disk.add_partition(9 * MB, 41 * MB);
+        disk.inject_superblock_result(1, || Ok(Superblock::synthetic(Kind::Fat, "1234-ABCD", "EFI")));
+        disk.inject_superblock_result(2, || {
+            Err(superblock::Error::Io {
+                source: std::io::Error::from(std::io::ErrorKind::NotFound),
+            })
+        });
+        let devices = [BlockDevice::mock_device(disk)];
+
+        let tree: Value = serde_json::from_str(&tree_json(&devices)).unwrap();
+        let disks = tree["blockdevices"].as_array().unwrap();
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0]["name"], "mock0");
+        assert_eq!(disks[0]["size"], 64 * MB);
+        assert_eq!(disks[0]["model"], "Mock Device");
+
+        let partitions = disks[0]["partitions"].as_array().unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0]["number"], 1);
+        assert_eq!(partitions[0]["size"], 8 * MB);
+        assert_eq!(partitions[0]["device"], "/dev/mock0p1");
+        assert_eq!(partitions[0]["filesystem"]["kind"], "fat");
+        assert_eq!(partitions[0]["filesystem"]["uuid"], "1234-ABCD");
+        assert_eq!(partitions[0]["filesystem"]["label"], "EFI");
+        assert_eq!(partitions[1]["size"], 32 * MB);
+        assert!(partitions[1]["filesystem"].is_null());
+    }
+}
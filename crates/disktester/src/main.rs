@@ -5,7 +5,10 @@
 use std::path::{Path, PathBuf};
 
 use disks::BlockDevice;
-use partitioning::{Formatter, blkpg, loopback, sparsefile, writer::DiskWriter};
+use partitioning::{
+    Formatter, blkpg, loopback, sparsefile,
+    writer::{DiskWriter, WriteConfirmation},
+};
 use provisioning::{Parser, Provisioner, StrategyDefinition};
 
 /// Loads provisioning strategies from a configuration file
@@ -25,10 +28,11 @@ fn load_provisioning(path: impl Into<PathBuf>) -> Result<Vec<StrategyDefinition>
 ///
 /// # Arguments
 /// * `whence` - Path to the block device to partition
+/// * `dry_run` - Print the planned changes as JSON to stdout instead of applying them
 ///
 /// # Returns
 /// * `Result<()>` - Success or error status
-fn apply_partitioning(whence: &Path) -> Result<(), Box<dyn std::error::Error>> {
+fn apply_partitioning(whence: &Path, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize provisioner and load strategies
     let mut prov = Provisioner::new();
     let strategies = load_provisioning("crates/provisioning/tests/use_whole_disk.kdl")?;
@@ -38,11 +42,16 @@ fn apply_partitioning(whence: &Path) -> Result<(), Box<dyn std::error::Error>> {
 
     // Set up block device
     let device = disks::loopback::Device::from_device_path(whence).ok_or("Not a loop device")?;
-    let blk = BlockDevice::loopback_device(device);
+    let mut blk = BlockDevice::loopback_device(device);
     prov.push_device(&blk);
 
+    if dry_run {
+        println!("{:#}", prov.dry_run()?);
+        return Ok(());
+    }
+
     // Generate and validate partitioning plans
-    let plans = prov.plan();
+    let plans = prov.plan()?;
     for plan in &plans {
         eprintln!("Plan: {}", plan.strategy.name);
     }
@@ -53,7 +62,8 @@ fn apply_partitioning(whence: &Path) -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("strategy for {} is now: {}", disk, device_plan.strategy.describe());
         eprintln!("After: {}", device_plan.planner.describe_changes());
 
-        let disk_writer = DiskWriter::new(device_plan.device, &device_plan.planner);
+        let disk_writer = DiskWriter::new(device_plan.device, &device_plan.planner)
+            .with_confirmation(WriteConfirmation::for_device(device_plan.device));
         disk_writer.simulate()?;
         eprintln!("Simulation passed");
         disk_writer.write()?;
@@ -61,6 +71,11 @@ fn apply_partitioning(whence: &Path) -> Result<(), Box<dyn std::error::Error>> {
 
     // Sync partition table changes
     blkpg::sync_gpt_partitions(whence)?;
+    let expected_partitions = plan
+        .device_assignments
+        .values()
+        .map(|device_plan| device_plan.planner.current_layout().len())
+        .sum::<usize>();
 
     let mut formatters = plan
         .filesystems
@@ -95,11 +110,28 @@ fn apply_partitioning(whence: &Path) -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("To mount: {:?} as {:?} (`{}`)", device, role, role.as_path());
     }
 
+    // The kernel should now report the planned partitions
+    drop(plans);
+    drop(prov);
+    blk.refresh()?;
+    eprintln!("Partitions after write: {}", blk.partitions().len());
+    if blk.partitions().len() != expected_partitions {
+        return Err(format!(
+            "expected {expected_partitions} partitions after write, found {}",
+            blk.partitions().len()
+        )
+        .into());
+    }
+
     Ok(())
 }
 
 /// Main entry point - creates and partitions a loopback device
+///
+/// With `--dry-run` the planned changes are printed as JSON and nothing is written.
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dry_run = std::env::args().skip(1).any(|arg| arg == "--dry-run");
+
     // Create sparse file and attach loopback device
     sparsefile::create("lesparse.img", 32 * 1024 * 1024 * 1024)?;
     let l = loopback::LoopDevice::create()?;
@@ -109,7 +141,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Apply partitioning and handle errors
     let whence = PathBuf::from(&l.path);
-    if let Err(e) = apply_partitioning(&whence) {
+    if let Err(e) = apply_partitioning(&whence, dry_run) {
         eprintln!("Error applying partitioning: {e}");
     }
 
@@ -2,8 +2,10 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::fmt;
+
 use gpt::partition_types;
-use types::{Filesystem, PartitionRole};
+use types::{BtrfsSubvol, EncryptionSpec, Filesystem, GptFlags, PartitionRole};
 use uuid::Uuid;
 
 /// Represents the table attributes of a GPT partition
@@ -15,6 +17,8 @@ pub struct GptAttributes {
     pub name: Option<String>,
     /// Optional UUID for the partition
     pub uuid: Option<Uuid>,
+    /// Attribute flags for the partition entry
+    pub flags: GptFlags,
 }
 
 impl Default for GptAttributes {
@@ -23,10 +27,101 @@ impl Default for GptAttributes {
             type_guid: partition_types::BASIC,
             name: None,
             uuid: None,
+            flags: GptFlags::empty(),
         }
     }
 }
 
+impl GptAttributes {
+    /// Returns the well-known type of this partition, if its type GUID is recognised
+    pub fn known_type(&self) -> Option<KnownPartitionType> {
+        KnownPartitionType::from_type_guid(&self.type_guid)
+    }
+}
+
+/// Well-known GPT partition types, recognised by their type GUID
+///
+/// This covers the partitions found on a typical Linux or Windows install, so
+/// that existing partitions can be named and the Windows ones left alone when
+/// dual booting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownPartitionType {
+    /// EFI System Partition
+    EfiSystem,
+    /// Extended boot loader partition (XBOOTLDR)
+    ExtendedBoot,
+    /// Microsoft Reserved Partition (MSR)
+    MicrosoftReserved,
+    /// Microsoft Basic Data, used for Windows volumes and FAT or exFAT data partitions
+    MicrosoftBasicData,
+    /// Windows Recovery Environment
+    WindowsRecovery,
+    /// Linux filesystem data
+    LinuxFilesystem,
+    /// Linux swap
+    LinuxSwap,
+}
+
+impl KnownPartitionType {
+    /// Every recognised type
+    pub const ALL: [Self; 7] = [
+        Self::EfiSystem,
+        Self::ExtendedBoot,
+        Self::MicrosoftReserved,
+        Self::MicrosoftBasicData,
+        Self::WindowsRecovery,
+        Self::LinuxFilesystem,
+        Self::LinuxSwap,
+    ];
+
+    /// Look up the well-known type for a GPT type GUID
+    pub fn from_type_guid(type_guid: &partition_types::Type) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.type_guid().guid == type_guid.guid)
+    }
+
+    /// Returns the GPT type GUID for this type
+    pub fn type_guid(&self) -> partition_types::Type {
+        match self {
+            Self::EfiSystem => partition_types::EFI,
+            Self::ExtendedBoot => partition_types::FREEDESK_BOOT,
+            Self::MicrosoftReserved => partition_types::MICROSOFT_RESERVED,
+            Self::MicrosoftBasicData => partition_types::BASIC,
+            Self::WindowsRecovery => partition_types::WINDOWS_RECOVERY,
+            Self::LinuxFilesystem => partition_types::LINUX_FS,
+            Self::LinuxSwap => partition_types::LINUX_SWAP,
+        }
+    }
+
+    /// Returns a human readable name for this type
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::EfiSystem => "EFI System",
+            Self::ExtendedBoot => "Extended Boot Loader",
+            Self::MicrosoftReserved => "Microsoft Reserved",
+            Self::MicrosoftBasicData => "Microsoft Basic Data",
+            Self::WindowsRecovery => "Windows Recovery",
+            Self::LinuxFilesystem => "Linux Filesystem",
+            Self::LinuxSwap => "Linux Swap",
+        }
+    }
+
+    /// Returns true for partitions that belong to a Windows install and must not be modified
+    ///
+    /// Basic Data partitions are included, as they hold the Windows volumes.
+    pub fn is_windows(&self) -> bool {
+        matches!(
+            self,
+            Self::MicrosoftReserved | Self::MicrosoftBasicData | Self::WindowsRecovery
+        )
+    }
+}
+
+impl fmt::Display for KnownPartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 /// Represents attributes specific to different partition table types
 #[derive(Debug, Clone)]
 pub enum TableAttributes {
@@ -54,4 +149,13 @@ pub struct PartitionAttributes {
     pub table: TableAttributes,
     pub role: Option<PartitionRole>,
     pub filesystem: Option<Filesystem>,
+    /// Encryption to set up before formatting the filesystem, if any
+    pub encryption: Option<EncryptionSpec>,
+}
+
+impl PartitionAttributes {
+    /// Returns the Btrfs subvolumes to create on the partition's filesystem, if any
+    pub fn subvolumes(&self) -> &[BtrfsSubvol] {
+        self.filesystem.as_ref().map_or(&[], Filesystem::subvolumes)
+    }
 }
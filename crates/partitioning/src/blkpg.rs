@@ -2,13 +2,16 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use disks::{BasicDisk, DiskInit};
+use disks::{BasicDisk, DeviceHandle, DiskInit};
 use log::{debug, error, info};
 use std::{
-    fs::File,
+    fs::{self, File},
     io,
     os::fd::{AsFd, AsRawFd},
     path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -25,8 +28,14 @@ pub enum Error {
     /// GPT-specific error
     #[error("GPT error: {0}")]
     Gpt(#[from] gpt::GptError),
+    /// The kernel did not complete the partition ioctls in time
+    #[error("Partition ioctls timed out after {0:?}")]
+    IoctlTimeout(Duration),
 }
 
+/// Default time to wait for the kernel to synchronise partitions
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Represents a block device partition for IOCTL operations
 #[repr(C)]
 struct BlkpgPartition {
@@ -48,6 +57,7 @@ struct BlkpgIoctl {
 
 const BLKPG_ADD_PARTITION: i32 = 1;
 const BLKPG_DEL_PARTITION: i32 = 2;
+const BLKPG_RESIZE_PARTITION: i32 = 3;
 
 /// Adds a new partition to the specified block device
 ///
@@ -127,6 +137,105 @@ where
     Ok(())
 }
 
+/// Resizes a partition on the specified block device
+///
+/// The kernel requires `start` to match the partition's current start.
+///
+/// # Arguments
+/// * `fd` - File descriptor for the block device
+/// * `partition_number` - Number of the partition to resize
+/// * `start` - Current starting offset in bytes
+/// * `length` - New length of partition in bytes
+///
+/// # Returns
+/// `io::Result<()>` indicating success or failure
+pub(crate) fn resize_partition<F>(fd: F, partition_number: i32, start: i64, length: i64) -> io::Result<()>
+where
+    F: AsRawFd,
+{
+    debug!("Initiating partition resize - Number: {partition_number}, Start: {start}, Length: {length}");
+    let mut part = BlkpgPartition {
+        start,
+        length,
+        pno: partition_number,
+        devname: [0; 64],
+        volname: [0; 64],
+    };
+
+    let mut ioctl = BlkpgIoctl {
+        op: BLKPG_RESIZE_PARTITION,
+        flags: 0,
+        datalen: std::mem::size_of::<BlkpgPartition>() as i32,
+        data: &mut part,
+    };
+
+    let res = unsafe { libc::ioctl(fd.as_raw_fd(), BLKPG as _, &mut ioctl) };
+    if res < 0 {
+        let err = io::Error::last_os_error();
+        error!("Failed to resize partition {partition_number}: {err}");
+        return Err(err);
+    }
+    info!("Successfully resized partition {partition_number}");
+    Ok(())
+}
+
+/// Tells the kernel about a single new partition, leaving the others untouched
+///
+/// # Arguments
+/// * `device` - Path to the block device
+/// * `id` - Number of the new partition
+/// * `start_bytes` - Starting offset in bytes
+/// * `size_bytes` - Length of the partition in bytes
+///
+/// # Returns
+/// `Result<(), Error>` indicating success or partition operation failure
+pub fn add_kernel_partition(device: &Path, id: u32, start_bytes: u64, size_bytes: u64) -> Result<(), Error> {
+    info!("Adding kernel partition {id} to {device:?}");
+    let file = File::open(device)?;
+    add_partition(file.as_fd(), id as i32, start_bytes as i64, size_bytes as i64)?;
+    Ok(())
+}
+
+/// Tells the kernel about the new size of a single partition, e.g. after growing it online
+///
+/// The partition keeps its current start, which is read from sysfs for the
+/// canonicalised `device` path.
+///
+/// # Arguments
+/// * `device` - Path to the block device
+/// * `id` - Number of the partition to resize
+/// * `new_size_bytes` - New length of the partition in bytes
+///
+/// # Returns
+/// `Result<(), Error>` indicating success or partition operation failure
+pub fn update_kernel_partition(device: &Path, id: u32, new_size_bytes: u64) -> Result<(), Error> {
+    info!("Resizing kernel partition {id} on {device:?} to {new_size_bytes} bytes");
+    let file = File::open(device)?;
+
+    // Resolve symlinks such as /dev/disk/by-id to the kernel name used in sysfs
+    let name = fs::canonicalize(device)?
+        .file_name()
+        .ok_or(Error::Io(io::Error::from(io::ErrorKind::InvalidInput)))?
+        .to_string_lossy()
+        .to_string();
+    let disk = BasicDisk::from_sysfs_path(&PathBuf::from("/"), &name)
+        .ok_or(Error::Io(io::Error::from(io::ErrorKind::InvalidInput)))?;
+    let partition = disk
+        .partitions()
+        .iter()
+        .find(|partition| partition.number == id)
+        .ok_or(Error::Io(io::Error::from(io::ErrorKind::NotFound)))?;
+
+    // sysfs reports the start in 512-byte sectors
+    resize_partition(
+        file.as_fd(),
+        id as i32,
+        partition.start as i64 * 512,
+        new_size_bytes as i64,
+    )?;
+    Ok(())
+}
+
 /// Removes all kernel partitions for the specified block device
 ///
 /// # Arguments
@@ -167,11 +276,16 @@ pub fn create_kernel_partitions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     info!("Creating kernel partitions from GPT for {:?}", path.as_ref());
     let file = File::open(&path)?;
 
-    // Read GPT table
+    // Read GPT table, whose LBAs are in the device's logical block size
     debug!("Reading GPT partition table");
-    let gpt = gpt::GptConfig::new().writable(false).open(&path)?;
+    let mut device = DeviceHandle::open(&path)?;
+    let block_size = device.logical_block_size();
+    let gpt = gpt::GptConfig::new()
+        .writable(false)
+        .logical_block_size(gpt::disk::LogicalBlockSize::try_from(block_size)?)
+        .open_from_device(&mut device)?;
     let partitions = gpt.partitions();
-    let block_size = 512;
+    let block_size = block_size as i64;
     info!("Located {} partitions (block size: {})", partitions.len(), block_size);
 
     // Add partitions from GPT
@@ -205,3 +319,111 @@ pub fn sync_gpt_partitions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     info!("GPT partition synchronization completed successfully");
     Ok(())
 }
+
+/// Updates kernel partition representations to match the GPT table, giving up
+/// after `timeout`
+///
+/// The ioctls run on a separate thread as they may block indefinitely on
+/// degraded storage. If the timeout fires the thread is abandoned and left to
+/// finish (or not) in the background.
+///
+/// # Arguments
+/// * `device` - Path to the block device
+/// * `timeout` - Maximum time to wait for synchronization
+///
+/// # Returns
+/// `Result<(), Error>` indicating success, partition operation failure or
+/// [`Error::IoctlTimeout`]
+pub fn sync_gpt_partitions_with_timeout(device: &Path, timeout: Duration) -> Result<(), Error> {
+    let device = device.to_owned();
+    run_with_timeout(timeout, move || sync_gpt_partitions(device))
+}
+
+/// Replaces the kernel partitions of `device` with `partitions`, giving up after `timeout`
+///
+/// Each partition is given as its number, start and length in bytes. This is
+/// for tables the kernel partitions cannot be read back from with
+/// [`create_kernel_partitions`], such as an MBR.
+pub fn sync_partitions_with_timeout(
+    device: &Path,
+    partitions: Vec<(u32, u64, u64)>,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let device = device.to_owned();
+    run_with_timeout(timeout, move || {
+        remove_kernel_partitions(&device)?;
+        for (id, start, size) in partitions {
+            add_kernel_partition(&device, id, start, size)?;
+        }
+        Ok(())
+    })
+}
+
+/// Run `f` on a separate thread, waiting at most `timeout` for it to complete
+fn run_with_timeout<F>(timeout: Duration, f: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<(), Error> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone if we already timed out
+        let _ = sender.send(f());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            error!("Partition ioctls did not complete within {timeout:?}");
+            Err(Error::IoctlTimeout(timeout))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(Error::Io(io::Error::other("partition sync thread panicked"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_with_timeout() {
+        assert!(run_with_timeout(Duration::from_secs(5), || Ok(())).is_ok());
+
+        let result = run_with_timeout(Duration::from_secs(5), || Err(io::Error::other("failed").into()));
+        assert!(matches!(result, Err(Error::Io(_))));
+
+        let result = run_with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(())
+        });
+        assert!(matches!(result, Err(Error::IoctlTimeout(t)) if t == Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_single_kernel_partition() {
+        const MB: u64 = 1024 * 1024;
+
+        let Some(result) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let path = blk.device();
+            // Parallel tests may leave stale partitions on a reused loop device
+            remove_kernel_partitions(path).ok();
+            let partition_size = |id| {
+                BasicDisk::from_sysfs_path(&PathBuf::from("/"), blk.name())
+                    .and_then(|disk| disk.partitions().iter().find(|p| p.number == id).map(|p| p.size * 512))
+            };
+
+            let result = add_kernel_partition(path, 1, MB, 8 * MB)
+                .and_then(|_| add_kernel_partition(path, 2, 16 * MB, 8 * MB))
+                .map(|_| (partition_size(1), partition_size(2)))
+                .and_then(|before| update_kernel_partition(path, 1, 12 * MB).map(|_| before))
+                .map(|before| (before, (partition_size(1), partition_size(2))));
+            remove_kernel_partitions(path).ok();
+            result
+        }) else {
+            return;
+        };
+
+        let (before, after) = result.unwrap();
+        assert_eq!(before, (Some(8 * MB), Some(8 * MB)));
+        assert_eq!(after, (Some(12 * MB), Some(8 * MB)));
+    }
+}
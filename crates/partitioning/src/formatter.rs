@@ -2,9 +2,78 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{path::Path, process::Command};
+use std::{
+    io,
+    path::Path,
+    process::{Command, ExitStatus},
+};
 
+use log::{debug, warn};
+use superblock::{Kind, Superblock};
+use thiserror::Error;
 use types::Filesystem;
+use uuid::Uuid;
+
+/// Errors that can occur while formatting a device
+#[derive(Debug, Error)]
+pub enum FormatError {
+    /// The mkfs command could not be run
+    #[error("failed to run formatter: {0}")]
+    Io(#[from] io::Error),
+
+    /// The mkfs command reported an error
+    #[error("formatter failed: {stderr}")]
+    Failed { stderr: String },
+
+    /// No filesystem could be read back from the device after formatting
+    #[error("failed to read back format: {0}")]
+    ReadBack(#[from] superblock::Error),
+
+    /// Verification found a different filesystem than requested after formatting
+    #[error("verification failed: expected {expected} filesystem after format, found {found}")]
+    VerificationFailed { expected: Kind, found: Kind },
+}
+
+/// Output captured from a mkfs command
+#[derive(Debug, Clone)]
+pub struct FormatOutput {
+    /// Exit status of the mkfs command
+    pub exit_status: ExitStatus,
+    /// Standard output, split into lines
+    pub stdout_lines: Vec<String>,
+    /// Standard error, split into lines
+    pub stderr_lines: Vec<String>,
+    /// Filesystem geometry, if reported on standard output
+    pub geometry: Option<FormatGeometry>,
+}
+
+/// Filesystem geometry reported by a mkfs command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatGeometry {
+    /// Size of a filesystem block in bytes
+    pub block_size: u64,
+    /// Total number of blocks in the filesystem
+    pub total_blocks: u64,
+}
+
+impl FormatGeometry {
+    /// Parse the geometry from mkfs output lines
+    ///
+    /// Currently understands the summary printed by `mke2fs`, e.g.
+    /// `Creating filesystem with 65536 4k blocks and 16384 inodes`.
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Option<Self> {
+        lines.iter().find_map(|line| {
+            let rest = line.as_ref().trim().strip_prefix("Creating filesystem with ")?;
+            let mut words = rest.split_whitespace();
+            let total_blocks = words.next()?.parse().ok()?;
+            let block_size = words.next()?.strip_suffix('k')?.parse::<u64>().ok()? * 1024;
+            (words.next()? == "blocks").then_some(Self {
+                block_size,
+                total_blocks,
+            })
+        })
+    }
+}
 
 /// Trait for generating filesystem-specific formatting commands and arguments
 pub trait FilesystemExt {
@@ -19,6 +88,19 @@ pub trait FilesystemExt {
 
     /// Returns the force format argument if applicable
     fn force_arg(&self) -> Vec<String>;
+
+    /// Returns the superblock kind expected on disk after formatting, if it can be detected
+    fn superblock_kind(&self) -> Option<Kind>;
+
+    /// Returns the arguments fixing otherwise random or time-based metadata, if applicable
+    fn deterministic_arg(&self, epoch: u64) -> Vec<String>;
+
+    /// Returns true if a detected superblock already satisfies this filesystem
+    ///
+    /// The filesystem type must match, as must the label and UUID (or FAT volume
+    /// ID) when they are specified. Filesystems whose superblock cannot be
+    /// detected, such as swap, never match.
+    fn matches_detected(&self, superblock: &Superblock) -> bool;
 }
 
 impl FilesystemExt for Filesystem {
@@ -26,6 +108,7 @@ impl FilesystemExt for Filesystem {
         match self {
             Filesystem::Fat32 { .. } => "mkfs.fat",
             Filesystem::Standard { filesystem_type, .. } => match filesystem_type {
+                types::StandardFilesystemType::Btrfs => "mkfs.btrfs",
                 types::StandardFilesystemType::F2fs => "mkfs.f2fs",
                 types::StandardFilesystemType::Ext4 => "mkfs.ext4",
                 types::StandardFilesystemType::Xfs => "mkfs.xfs",
@@ -48,6 +131,7 @@ impl FilesystemExt for Filesystem {
             } => {
                 if let Some(uuid) = uuid {
                     match filesystem_type {
+                        types::StandardFilesystemType::Btrfs => vec!["-U".to_string(), uuid.to_string()],
                         types::StandardFilesystemType::Ext4 => vec!["-U".to_string(), uuid.to_string()],
                         types::StandardFilesystemType::F2fs => vec!["-U".to_string(), uuid.to_string()],
                         types::StandardFilesystemType::Xfs => vec!["-m".to_string(), format!("uuid={}", uuid)],
@@ -74,6 +158,7 @@ impl FilesystemExt for Filesystem {
             } => {
                 if let Some(label) = label {
                     match filesystem_type {
+                        types::StandardFilesystemType::Btrfs => vec!["-L".to_string(), label.to_string()],
                         types::StandardFilesystemType::Ext4 => vec!["-L".to_string(), label.to_string()],
                         types::StandardFilesystemType::F2fs => vec!["-l".to_string(), label.to_string()],
                         types::StandardFilesystemType::Xfs => vec!["-L".to_string(), label.to_string()],
@@ -90,6 +175,7 @@ impl FilesystemExt for Filesystem {
         match self {
             Filesystem::Fat32 { .. } => vec![],
             Filesystem::Standard { filesystem_type, .. } => match filesystem_type {
+                types::StandardFilesystemType::Btrfs => vec!["-f".to_string()],
                 types::StandardFilesystemType::F2fs => vec!["-f".to_string()],
                 types::StandardFilesystemType::Ext4 => vec!["-F".to_string()],
                 types::StandardFilesystemType::Xfs => vec!["-f".to_string()],
@@ -97,12 +183,71 @@ impl FilesystemExt for Filesystem {
             },
         }
     }
+
+    fn superblock_kind(&self) -> Option<Kind> {
+        match self {
+            Filesystem::Fat32 { .. } => Some(Kind::Fat),
+            Filesystem::Standard { filesystem_type, .. } => match filesystem_type {
+                types::StandardFilesystemType::Btrfs => Some(Kind::Btrfs),
+                types::StandardFilesystemType::F2fs => Some(Kind::F2FS),
+                types::StandardFilesystemType::Ext4 => Some(Kind::Ext4),
+                types::StandardFilesystemType::Xfs => Some(Kind::Xfs),
+                types::StandardFilesystemType::Swap => None,
+            },
+        }
+    }
+
+    fn matches_detected(&self, superblock: &Superblock) -> bool {
+        if self.superblock_kind() != Some(superblock.kind()) {
+            return false;
+        }
+
+        let (label, uuid) = match self {
+            Filesystem::Fat32 { label, volume_id } => (
+                label,
+                volume_id.map(|id| format!("{:04X}-{:04X}", id >> 16, id & 0xFFFF)),
+            ),
+            Filesystem::Standard { label, uuid, .. } => (label, uuid.clone()),
+        };
+        let label_matches = label
+            .as_ref()
+            .is_none_or(|label| superblock.label().is_ok_and(|found| found.trim_end() == label));
+        let uuid_matches = uuid
+            .as_ref()
+            .is_none_or(|uuid| superblock.uuid().is_ok_and(|found| found.eq_ignore_ascii_case(uuid)));
+
+        label_matches && uuid_matches
+    }
+
+    fn deterministic_arg(&self, epoch: u64) -> Vec<String> {
+        match self {
+            Filesystem::Fat32 { .. } => vec![],
+            Filesystem::Standard {
+                filesystem_type, uuid, ..
+            } => match filesystem_type {
+                types::StandardFilesystemType::Ext4 => {
+                    // The directory hash seed is otherwise random, so derive it from the
+                    // filesystem UUID when known, else from the epoch
+                    let seed = uuid
+                        .clone()
+                        .unwrap_or_else(|| Uuid::from_u64_pair(epoch, 0).to_string());
+                    vec!["-E".to_string(), format!("hash_seed={seed}")]
+                }
+                types::StandardFilesystemType::F2fs => vec!["-T".to_string(), epoch.to_string()],
+                types::StandardFilesystemType::Btrfs
+                | types::StandardFilesystemType::Xfs
+                | types::StandardFilesystemType::Swap => vec![],
+            },
+        }
+    }
 }
 
 /// Struct for formatting filesystems on devices
 pub struct Formatter {
     pub filesystem: Filesystem,
     pub force: bool,
+    pub verify_after_format: bool,
+    pub deterministic_epoch: Option<u64>,
 }
 
 impl Formatter {
@@ -111,6 +256,8 @@ impl Formatter {
         Self {
             filesystem,
             force: false,
+            verify_after_format: false,
+            deterministic_epoch: None,
         }
     }
 
@@ -119,6 +266,27 @@ impl Formatter {
         Self { force: true, ..self }
     }
 
+    /// Produces reproducible metadata, using `epoch` (seconds since the Unix epoch)
+    /// for all timestamps
+    ///
+    /// `SOURCE_DATE_EPOCH` is set for the mkfs command, along with any flags fixing
+    /// seeds the tool would otherwise randomise. Combine with a fixed UUID for
+    /// byte-identical images.
+    pub fn deterministic(self, epoch: u64) -> Self {
+        Self {
+            deterministic_epoch: Some(epoch),
+            ..self
+        }
+    }
+
+    /// Read the superblock back after [`Formatter::execute`] to confirm the filesystem was created
+    pub fn with_verify(self, verify: bool) -> Self {
+        Self {
+            verify_after_format: verify,
+            ..self
+        }
+    }
+
     /// Returns a Command configured to format the given device with the filesystem
     pub fn format(&self, device: &Path) -> Command {
         let mut cmd = Command::new(self.filesystem.mkfs_command());
@@ -128,15 +296,78 @@ impl Formatter {
         if self.force {
             cmd.args(self.filesystem.force_arg());
         }
+        if let Some(epoch) = self.deterministic_epoch {
+            cmd.env("SOURCE_DATE_EPOCH", epoch.to_string());
+            cmd.args(self.filesystem.deterministic_arg(epoch));
+        }
 
         cmd.arg(device);
         cmd
     }
+
+    /// Formats the given device, waiting for the mkfs command to complete
+    ///
+    /// If verification is enabled the device's superblock is read back afterwards
+    /// and must match the requested filesystem.
+    pub fn execute(&self, device: &Path) -> Result<(), FormatError> {
+        let output = self.format_and_capture(device)?;
+        if !output.exit_status.success() {
+            return Err(FormatError::Failed {
+                stderr: output.stderr_lines.join("\n"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Formats the given device, capturing the mkfs output for display or parsing
+    ///
+    /// Unlike [`Formatter::execute`], a mkfs command that fails is not an error:
+    /// check [`FormatOutput::exit_status`]. Verification, if enabled, only runs
+    /// after a successful format.
+    pub fn format_and_capture(&self, device: &Path) -> Result<FormatOutput, FormatError> {
+        let output = self.format(device).output()?;
+        let lines = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .lines()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+        let stdout_lines = lines(&output.stdout);
+        let geometry = FormatGeometry::parse(&stdout_lines);
+
+        if output.status.success() && self.verify_after_format {
+            self.verify(device)?;
+        }
+
+        Ok(FormatOutput {
+            exit_status: output.status,
+            stdout_lines,
+            stderr_lines: lines(&output.stderr),
+            geometry,
+        })
+    }
+
+    /// Confirm the device holds the filesystem this formatter creates
+    fn verify(&self, device: &Path) -> Result<(), FormatError> {
+        let Some(expected) = self.filesystem.superblock_kind() else {
+            warn!("Cannot verify {} format of {}", self.filesystem, device.display());
+            return Ok(());
+        };
+
+        let found = Superblock::from_file(device)?.kind();
+        if found != expected {
+            return Err(FormatError::VerificationFailed { expected, found });
+        }
+
+        debug!("Verified {} is formatted as {found}", device.display());
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use types::StandardFilesystemType;
     use uuid::Uuid;
 
     #[test]
@@ -158,6 +389,7 @@ mod tests {
             filesystem_type: types::StandardFilesystemType::Ext4,
             label: Some("root".to_string()),
             uuid: Some(uuid.to_string()),
+            subvolumes: None,
         };
 
         assert_eq!(fs.mkfs_command(), "mkfs.ext4");
@@ -172,10 +404,185 @@ mod tests {
             filesystem_type: types::StandardFilesystemType::Xfs,
             label: Some("data".to_string()),
             uuid: Some(uuid.to_string()),
+            subvolumes: None,
         };
 
         assert_eq!(fs.mkfs_command(), "mkfs.xfs");
         assert_eq!(fs.uuid_arg(), vec!["-m".to_string(), format!("uuid={uuid}")]);
         assert_eq!(fs.label_arg(), vec!["-L", "data"]);
     }
+
+    #[test]
+    fn test_deterministic() {
+        let uuid = Uuid::new_v4();
+        let fs = Filesystem::Standard {
+            filesystem_type: types::StandardFilesystemType::Ext4,
+            label: None,
+            uuid: Some(uuid.to_string()),
+            subvolumes: None,
+        };
+
+        let cmd = Formatter::new(fs)
+            .deterministic(1700000000)
+            .format(Path::new("/dev/null"));
+        let envs = cmd.get_envs().collect::<Vec<_>>();
+        assert_eq!(
+            envs,
+            vec![(
+                std::ffi::OsStr::new("SOURCE_DATE_EPOCH"),
+                Some(std::ffi::OsStr::new("1700000000"))
+            )]
+        );
+
+        let args = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect::<Vec<_>>();
+        let hash_seed = format!("hash_seed={uuid}");
+        assert_eq!(args, vec!["-U", &uuid.to_string(), "-E", &hash_seed, "/dev/null"]);
+
+        // Without a UUID the seed still only depends on the epoch
+        let fs = Filesystem::Standard {
+            filesystem_type: types::StandardFilesystemType::Ext4,
+            label: None,
+            uuid: None,
+            subvolumes: None,
+        };
+        assert_eq!(fs.deterministic_arg(1700000000), fs.deterministic_arg(1700000000));
+        assert_ne!(fs.deterministic_arg(1700000000), fs.deterministic_arg(1700000001));
+
+        // Nothing is fixed unless asked for
+        let cmd = Formatter::new(fs).format(Path::new("/dev/null"));
+        assert_eq!(cmd.get_envs().count(), 0);
+    }
+
+    #[test]
+    fn test_parse_geometry() {
+        let output = [
+            "mke2fs 1.47.0 (5-Feb-2023)",
+            "Creating filesystem with 65536 4k blocks and 16384 inodes",
+            "Filesystem UUID: 0286cfc3-106c-4f3b-b5cf-87d7d56f7383",
+        ];
+        assert_eq!(
+            FormatGeometry::parse(&output),
+            Some(FormatGeometry {
+                block_size: 4096,
+                total_blocks: 65536
+            })
+        );
+        assert_eq!(FormatGeometry::parse(&output[..1]), None);
+        assert_eq!(
+            FormatGeometry::parse(&["Creating filesystem with many 4k blocks"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_and_capture() {
+        let image = std::env::temp_dir().join(format!("disks-rs-capture-{}.img", std::process::id()));
+        crate::sparsefile::create(&image, 64 * 1024 * 1024).unwrap();
+
+        let ext4 = Filesystem::Standard {
+            filesystem_type: types::StandardFilesystemType::Ext4,
+            label: None,
+            uuid: None,
+            subvolumes: None,
+        };
+        let result = Formatter::new(ext4).force().format_and_capture(&image);
+        std::fs::remove_file(&image).unwrap();
+
+        // mkfs.ext4 may not be installed
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("Skipping: unable to format image: {e}");
+                return;
+            }
+        };
+        assert!(output.exit_status.success());
+        assert!(!output.stdout_lines.is_empty());
+        let geometry = output.geometry.unwrap();
+        assert_eq!(geometry.block_size * geometry.total_blocks, 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_verify_after_format() {
+        let image = std::env::temp_dir().join(format!("disks-rs-format-{}.img", std::process::id()));
+        crate::sparsefile::create(&image, 64 * 1024 * 1024).unwrap();
+
+        let ext4 = Filesystem::Standard {
+            filesystem_type: types::StandardFilesystemType::Ext4,
+            label: None,
+            uuid: None,
+            subvolumes: None,
+        };
+        let formatter = Formatter::new(ext4).force().with_verify(true);
+        assert!(formatter.verify_after_format);
+
+        // Nothing to read back before formatting
+        assert!(matches!(formatter.verify(&image), Err(FormatError::ReadBack(_))));
+
+        // mkfs.ext4 may not be installed
+        if let Err(e) = formatter.execute(&image) {
+            eprintln!("Skipping: unable to format image: {e}");
+            std::fs::remove_file(&image).ok();
+            return;
+        }
+
+        let xfs = Filesystem::Standard {
+            filesystem_type: types::StandardFilesystemType::Xfs,
+            label: None,
+            uuid: None,
+            subvolumes: None,
+        };
+        let result = Formatter::new(xfs).verify(&image);
+        std::fs::remove_file(&image).unwrap();
+        assert!(matches!(
+            result,
+            Err(FormatError::VerificationFailed {
+                expected: Kind::Xfs,
+                found: Kind::Ext4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_matches_detected() {
+        const UUID: &str = "9b2a5c1e-4d3f-4a8b-9c7d-1e2f3a4b5c6d";
+        let standard = |filesystem_type, label: Option<&str>, uuid: Option<&str>| Filesystem::Standard {
+            filesystem_type,
+            label: label.map(str::to_owned),
+            uuid: uuid.map(str::to_owned),
+            subvolumes: None,
+        };
+        let ext4 = Superblock::synthetic(Kind::Ext4, UUID, "root");
+
+        // The type alone, or with a matching label and UUID
+        assert!(standard(StandardFilesystemType::Ext4, None, None).matches_detected(&ext4));
+        assert!(standard(StandardFilesystemType::Ext4, Some("root"), None).matches_detected(&ext4));
+        assert!(
+            standard(StandardFilesystemType::Ext4, Some("root"), Some(&UUID.to_uppercase())).matches_detected(&ext4)
+        );
+
+        // A different type, label or UUID
+        assert!(!standard(StandardFilesystemType::Xfs, None, None).matches_detected(&ext4));
+        assert!(!standard(StandardFilesystemType::Ext4, Some("home"), None).matches_detected(&ext4));
+        assert!(
+            !standard(
+                StandardFilesystemType::Ext4,
+                None,
+                Some("00000000-0000-0000-0000-000000000000")
+            )
+            .matches_detected(&ext4)
+        );
+
+        // Swap is never detected
+        assert!(!standard(StandardFilesystemType::Swap, None, None).matches_detected(&ext4));
+
+        let fat = Superblock::synthetic(Kind::Fat, "1234-ABCD", "EFI");
+        let fat32 = |volume_id| Filesystem::Fat32 {
+            label: Some("EFI".to_owned()),
+            volume_id,
+        };
+        assert!(fat32(Some(0x1234_ABCD)).matches_detected(&fat));
+        assert!(!fat32(Some(0x1234_ABCE)).matches_detected(&fat));
+        assert!(fat32(None).matches_detected(&fat));
+    }
 }
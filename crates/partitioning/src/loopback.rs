@@ -5,9 +5,14 @@
 use std::{
     fs, io,
     os::fd::{AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    process::Command,
 };
 
-use linux_raw_sys::loop_device::{LOOP_CLR_FD, LOOP_CTL_GET_FREE, LOOP_SET_FD, LOOP_SET_STATUS64};
+use linux_raw_sys::loop_device::{
+    LO_FLAGS_DIRECT_IO, LOOP_CLR_FD, LOOP_CTL_GET_FREE, LOOP_GET_STATUS64, LOOP_SET_BLOCK_SIZE, LOOP_SET_DIRECT_IO,
+    LOOP_SET_FD, LOOP_SET_STATUS64, loop_info64,
+};
 use log::{debug, error, info};
 use nix::libc;
 
@@ -46,6 +51,67 @@ impl LoopDevice {
         Ok(LoopDevice { fd, path })
     }
 
+    /// Opens an existing loop device such as `/dev/loop0`
+    fn open(path: &Path) -> io::Result<Self> {
+        let fd = fs::OpenOptions::new().read(true).write(true).open(path)?.into();
+        Ok(LoopDevice {
+            fd,
+            path: path.to_string_lossy().into_owned(),
+        })
+    }
+
+    /// Lists the loop devices currently attached to the given backing file
+    ///
+    /// # Arguments
+    /// * `path` - Path to the backing file
+    ///
+    /// # Returns
+    /// `io::Result<Vec<PathBuf>>` containing the loop device paths (e.g. `/dev/loop0`)
+    pub fn list_attached(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        debug!("Listing loop devices attached to {}", path.display());
+
+        let output = Command::new("losetup")
+            .args(["--list", "--json", "--associated"])
+            .arg(path)
+            .output()?;
+        if !output.status.success() {
+            error!("losetup failed: {}", String::from_utf8_lossy(&output.stderr));
+            return Err(io::Error::other(format!("losetup exited with {}", output.status)));
+        }
+
+        // No associated devices produces no output at all
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(vec![]);
+        }
+
+        let listing: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(io::Error::other)?;
+        let devices = listing["loopdevices"]
+            .as_array()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing loopdevices in losetup output"))?
+            .iter()
+            .filter_map(|device| device["name"].as_str())
+            .map(PathBuf::from)
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Detaches every loop device attached to the given backing file
+    ///
+    /// # Arguments
+    /// * `path` - Path to the backing file
+    ///
+    /// # Returns
+    /// `io::Result<usize>` with the number of detached loop devices
+    pub fn detach_all_for_file(path: impl AsRef<Path>) -> io::Result<usize> {
+        let devices = Self::list_attached(path)?;
+        for device in &devices {
+            Self::open(device)?.detach()?;
+        }
+        Ok(devices.len())
+    }
+
     /// Attaches a backing file to this loop device, allowing the file to be
     /// accessed as a block device.
     ///
@@ -68,7 +134,7 @@ impl LoopDevice {
         }
 
         // Force loop device to immediately update by setting empty status
-        let info: linux_raw_sys::loop_device::loop_info64 = unsafe { std::mem::zeroed() };
+        let info: loop_info64 = unsafe { std::mem::zeroed() };
         let res = unsafe { libc::ioctl(our_fd, LOOP_SET_STATUS64 as _, &info) };
         if res < 0 {
             error!("Failed to update loop device status - device may be in inconsistent state");
@@ -79,6 +145,64 @@ impl LoopDevice {
         Ok(())
     }
 
+    /// Attaches a backing file to this loop device with direct I/O enabled,
+    /// bypassing the page cache (as `losetup --direct-io=on` does).
+    ///
+    /// # Arguments
+    /// * `backing_file` - Path to the file to attach
+    ///
+    /// # Returns
+    /// `io::Result<()>` indicating success or failure. The file is detached
+    /// again if direct I/O could not be enabled, e.g. on filesystems without
+    /// `O_DIRECT` support.
+    pub fn attach_with_direct_io(&self, backing_file: impl AsRef<Path>) -> io::Result<()> {
+        let backing_file = backing_file
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "backing file path is not valid UTF-8"))?;
+        self.attach(backing_file)?;
+
+        debug!("Enabling direct I/O on {}", self.path);
+        let res = unsafe { libc::ioctl(self.fd.as_raw_fd(), LOOP_SET_DIRECT_IO as _, 1 as libc::c_ulong) };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            error!("Failed to enable direct I/O on {}: {err}", self.path);
+            self.detach()?;
+            return Err(err);
+        }
+
+        info!("Enabled direct I/O on loop device {}", self.path);
+        Ok(())
+    }
+
+    /// Sets the logical block size of the attached loop device, as `losetup --sector-size` does
+    ///
+    /// This allows a 4Kn disk to be emulated with an ordinary image file.
+    ///
+    /// # Arguments
+    /// * `size` - Block size in bytes, a power of two from 512 to the page size
+    ///
+    /// # Returns
+    /// `io::Result<()>` indicating success or failure
+    pub fn set_block_size(&self, size: u32) -> io::Result<()> {
+        debug!("Setting block size of {} to {size}", self.path);
+        let res = unsafe { libc::ioctl(self.fd.as_raw_fd(), LOOP_SET_BLOCK_SIZE as _, size as libc::c_ulong) };
+        if res < 0 {
+            error!("Failed to set block size of {} to {size}", self.path);
+            return Err(io::Error::last_os_error());
+        }
+
+        info!("Set block size of loop device {} to {size}", self.path);
+        Ok(())
+    }
+
+    /// Returns true if this loop device is attached and using direct I/O
+    pub fn is_direct_io(&self) -> bool {
+        let mut info: loop_info64 = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::ioctl(self.fd.as_raw_fd(), LOOP_GET_STATUS64 as _, &mut info) };
+        res == 0 && info.lo_flags & LO_FLAGS_DIRECT_IO as u32 != 0
+    }
+
     /// Detaches the current backing file from this loop device.
     ///
     /// # Returns
@@ -95,3 +219,82 @@ impl LoopDevice {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sparsefile;
+    use disks::{BlockDevice, PartitionScheme};
+
+    #[test]
+    fn test_gpt_partition_scheme() {
+        const SIZE: u64 = 64 * 1024 * 1024;
+
+        let Some(scheme) = sparsefile::with_loop_device(SIZE, |blk| {
+            sparsefile::write_partition_table(blk.device(), SIZE, &[])?;
+            blk.read_partition_scheme()
+        }) else {
+            return;
+        };
+
+        assert_eq!(scheme.unwrap(), Some(PartitionScheme::Gpt));
+    }
+
+    #[test]
+    fn test_direct_io() {
+        let Some(result) = sparsefile::with_loop_device(16 * 1024 * 1024, |blk| -> io::Result<_> {
+            let buffered = LoopDevice::open(blk.device())?.is_direct_io();
+
+            // The temporary directory may not support O_DIRECT
+            let device = LoopDevice::create()?;
+            let direct = device.attach_with_direct_io(backing_file(&blk));
+            Ok((buffered, direct.map(|_| device.is_direct_io())))
+        }) else {
+            return;
+        };
+        let (buffered, direct) = result.unwrap();
+
+        assert!(!buffered);
+        match direct {
+            Ok(is_direct_io) => assert!(is_direct_io),
+            Err(e) => eprintln!("Skipping: direct I/O unsupported: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_list_and_detach_attached() {
+        let Some(result) = sparsefile::with_loop_device(16 * 1024 * 1024, |blk| -> io::Result<_> {
+            let image = backing_file(&blk);
+            // Close the device again so detaching it takes effect straight away
+            let other = {
+                let device = LoopDevice::create()?;
+                device.attach(image.to_str().unwrap())?;
+                PathBuf::from(&device.path)
+            };
+
+            let mut attached = LoopDevice::list_attached(&image)?;
+            attached.sort();
+            let mut expected = vec![blk.device().to_owned(), other];
+            expected.sort();
+
+            let detached = LoopDevice::detach_all_for_file(&image)?;
+            let remaining = LoopDevice::list_attached(&image)?;
+            Ok((attached, expected, detached, remaining))
+        }) else {
+            return;
+        };
+        let (attached, expected, detached, remaining) = result.unwrap();
+
+        assert_eq!(attached, expected);
+        assert_eq!(detached, 2);
+        assert!(remaining.is_empty());
+    }
+
+    /// The sparse file backing a loop device from [`sparsefile::with_loop_device`]
+    fn backing_file(blk: &BlockDevice) -> PathBuf {
+        match blk {
+            BlockDevice::Loopback(device) => device.file_path().unwrap().to_owned(),
+            BlockDevice::Disk(_) => unreachable!("fixture devices are loop devices"),
+        }
+    }
+}
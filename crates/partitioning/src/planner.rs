@@ -12,12 +12,21 @@
 //! - Track and undo changes
 //! - Validate that changes won't conflict with existing partitions
 
-use disks::{BlockDevice, align_down, align_up, format_position, format_size, is_aligned};
+use disks::{
+    BlockDevice, DeviceHandle, align_down, align_up, format_position, format_position_f64, format_size, is_aligned,
+};
 use log::{debug, warn};
-use std::collections::VecDeque;
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, io,
+};
 use thiserror::Error;
+use types::GptFlags;
 
-use crate::PartitionAttributes;
+use crate::{
+    GptAttributes, KnownPartitionType, PartitionAttributes, TableAttributes,
+    strategy::{SizeRequirement, Strategy},
+};
 
 /// Errors that can occur while planning partition changes
 ///
@@ -31,6 +40,16 @@ pub enum PlanError {
     RegionOutOfBounds { start: u64, end: u64 },
     #[error("No free regions available")]
     NoFreeRegions,
+    #[error("Region {start}..{end} overlaps another partition, cannot normalize layout")]
+    CannotNormalizeWithOverlap { start: u64, end: u64 },
+    #[error("Partition table only has room for {max} partitions")]
+    TooManyPartitions { max: usize },
+    #[error("Target disk of {available} bytes is smaller than the required {required} bytes")]
+    TargetTooSmall { required: u64, available: u64 },
+    #[error("No free gap in the region fits {required}, the largest is {largest}")]
+    GapTooSmall { required: u64, largest: u64 },
+    #[error("No partition with ID {0}")]
+    PartitionNotFound(u32),
 }
 
 /// A planned modification to the disk's partition layout
@@ -44,11 +63,20 @@ pub enum Change {
     AddPartition {
         start: u64,
         end: u64,
+        /// Unit of `start` and `end`
+        unit: Unit,
         partition_id: u32,
-        attributes: Option<PartitionAttributes>,
+        attributes: Option<Box<PartitionAttributes>>,
     },
     /// Delete an existing partition
     DeletePartition { original_index: usize, partition_id: u32 },
+    /// Change the GPT type of a partition in place, keeping its unique GUID and data
+    SetPartitionType {
+        partition_id: u32,
+        type_guid: gpt::partition_types::Type,
+    },
+    /// Change the GPT name of a partition in place, keeping its unique GUID and data
+    SetPartitionName { partition_id: u32, name: String },
 }
 
 /// A disk partitioning planner.
@@ -62,18 +90,78 @@ pub struct Planner {
     changes: VecDeque<Change>,
     /// Original partition layout for reference
     original_regions: Vec<Region>,
+    /// Effective layout after all pending changes, rebuilt whenever they change
+    layout: Vec<Region>,
     /// Track original partition IDs
     original_partition_ids: Vec<u32>,
-    /// Next available partition ID for new partitions
-    next_partition_id: u32,
 
     wipe_disk: bool,
+    /// Number of entries available in the partition table
+    max_partitions: usize,
+    /// Unit of every offset and size handled by this planner
+    unit: Unit,
+    /// Names of the strategies applied via [`Planner::apply_strategy`], in order
+    applied_strategies: Vec<String>,
+    /// Partition alignment in bytes, or 0 for none
+    alignment_bytes: u64,
+    /// Logical block size of the disk in bytes, which the GPT is addressed in
+    logical_block_size: u64,
+    /// Size of the disk in bytes
+    disk_size: u64,
+}
+
+/// The unit in which a [`Planner`] measures offsets and sizes
+///
+/// Every position accepted or returned by the planner is in the planner's
+/// unit, and the [`Change`]s and [`Region`]s it hands out record that unit.
+/// Sizes given to strategies are always in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unit {
+    /// Bytes from the start of the disk
+    #[default]
+    Bytes,
+    /// Logical sectors (LBAs) of the device's logical block size from the start of the disk
+    Sectors,
+}
+
+impl Unit {
+    /// Size of one unit in bytes on a disk with the given logical block size
+    pub const fn size(&self, logical_block_size: u64) -> u64 {
+        match self {
+            Unit::Bytes => 1,
+            Unit::Sectors => logical_block_size,
+        }
+    }
+
+    /// Convert a value in this unit to bytes
+    pub const fn to_bytes(&self, value: u64, logical_block_size: u64) -> u64 {
+        value * self.size(logical_block_size)
+    }
+
+    /// Convert a byte value to this unit, rounding down
+    pub const fn from_bytes(&self, bytes: u64, logical_block_size: u64) -> u64 {
+        bytes / self.size(logical_block_size)
+    }
+}
+
+/// A captured copy of a planner's pending state
+///
+/// Obtained from [`Planner::snapshot()`] and handed back to [`Planner::restore()`]
+/// to roll back every change made in between in a single step.
+#[derive(Debug, Clone)]
+pub struct PlannerState {
+    changes: VecDeque<Change>,
+    original_regions: Vec<Region>,
+    original_partition_ids: Vec<u32>,
+    wipe_disk: bool,
+    applied_strategies: Vec<String>,
 }
 
 /// A contiguous region of disk space defined by absolute start and end positions
 ///
 /// Used to represent both existing partitions and planned partition changes.
-/// All positions are measured in bytes from the start of the disk.
+/// All positions are measured from the start of the disk in the region's
+/// [`Unit`], which is bytes unless it came from a planner working in sectors.
 ///
 /// # Examples
 ///
@@ -84,12 +172,15 @@ pub struct Planner {
 /// ```
 #[derive(Debug, Clone)]
 pub struct Region {
-    /// The absolute start position of this region in bytes
+    /// The absolute start position of this region in `unit`
     pub start: u64,
 
-    /// The absolute end position of this region in bytes
+    /// The absolute end position of this region in `unit`
     pub end: u64,
 
+    /// Unit of `start` and `end`
+    pub unit: Unit,
+
     /// The partition ID of this region if it represents a partition
     pub partition_id: Option<u32>,
 
@@ -100,33 +191,96 @@ pub struct Region {
 /// performance and compatibility.
 pub const PARTITION_ALIGNMENT: u64 = 1024 * 1024;
 
+/// Number of partition entries in a standard GPT partition table
+pub const DEFAULT_MAX_PARTITIONS: usize = 128;
+
+/// Free space left by a planned layout, see [`Planner::free_space_summary`]
+///
+/// Sizes are always in bytes, whatever the planner's [`Unit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpaceSummary {
+    /// Number of separate free regions
+    pub region_count: usize,
+    /// Total free space in bytes
+    pub total_free: u64,
+    /// Size of the largest free region in bytes
+    pub largest_region: u64,
+}
+
+impl fmt::Display for FreeSpaceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = if self.region_count == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} free region{plural}, largest {}, total free {}",
+            self.region_count,
+            format_size(self.largest_region),
+            format_size(self.total_free)
+        )
+    }
+}
+
 /// Represents a contiguous region on disk between two absolute positions.
-/// Both start and end are absolute positions in bytes from the beginning of the disk.
+/// Both start and end are absolute positions in the region's unit from the beginning of the disk.
 /// For example, a 1MB partition starting at the beginning of the disk would have
 /// start=0 and end=1048576.
 impl Region {
-    /// Create a new region with the given bounds
+    /// Create a new region with the given bounds in bytes
     pub fn new(start: u64, end: u64) -> Self {
         Self {
             start,
             end,
+            unit: Unit::Bytes,
             partition_id: None,
             attributes: None,
         }
     }
 
-    /// Get the size of this region in bytes
+    /// Get the size of this region in its unit
     pub fn size(&self) -> u64 {
         self.end - self.start
     }
 
+    /// Convert this region to `unit` on a disk with the given logical block size
+    ///
+    /// Positions are rounded down when converting to a larger unit.
+    pub fn to_unit(&self, unit: Unit, logical_block_size: u64) -> Region {
+        let convert = |value| unit.from_bytes(self.unit.to_bytes(value, logical_block_size), logical_block_size);
+        Region {
+            start: convert(self.start),
+            end: convert(self.end),
+            unit,
+            ..self.clone()
+        }
+    }
+
     /// Check if this region overlaps with another
     pub fn overlaps_with(&self, other: &Region) -> bool {
         self.start < other.end && other.start < self.end
     }
 
-    /// Get a human readable description of this region
-    pub fn describe(&self, disk_size: u64) -> String {
+    /// Check if `offset` lies within this region, i.e. in `[start, end)`
+    pub fn contains_offset(&self, offset: u64) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    /// Returns the well-known GPT type of the partition in this region, if any
+    ///
+    /// Only regions carrying GPT attributes, such as those read by
+    /// [`Planner::from_existing`], can be classified.
+    pub fn known_type(&self) -> Option<KnownPartitionType> {
+        self.attributes.as_ref()?.table.as_gpt()?.known_type()
+    }
+
+    /// Get a human readable description of this region on a disk of `disk_size` bytes
+    ///
+    /// Regions in [`Unit::Sectors`] are converted to bytes using `logical_block_size`.
+    pub fn describe(&self, disk_size: u64, logical_block_size: u64) -> String {
+        self.to_unit(Unit::Bytes, logical_block_size).describe_bytes(disk_size)
+    }
+
+    /// Describe this region as [`Region::describe`] does, given its bounds in bytes
+    pub(crate) fn describe_bytes(&self, disk_size: u64) -> String {
         format!(
             "{} at {}..{}",
             format_size(self.size()),
@@ -134,6 +288,73 @@ impl Region {
             format_position(self.end, disk_size)
         )
     }
+
+    /// Get the logical block addresses covered by this region
+    ///
+    /// The start is rounded down and the end up to whole sectors of `sector_size`
+    /// bytes, and the end LBA is exclusive, e.g. `LBA 2048..4096 (2048 sectors)`.
+    /// Regions in [`Unit::Sectors`] are taken to be in sectors of `sector_size`.
+    pub fn describe_sectors(&self, sector_size: u64) -> String {
+        let region = self.to_unit(Unit::Bytes, sector_size);
+        let start = region.start / sector_size;
+        let end = region.end.div_ceil(sector_size);
+        format!("LBA {start}..{end} ({} sectors)", end - start)
+    }
+}
+
+/// Format regions as a plain text table, one row per region
+///
+/// Positions are shown as percentages of `total` bytes to one decimal place, so
+/// small partitions on large disks remain distinguishable. Regions in
+/// [`Unit::Sectors`] are converted to bytes using `logical_block_size`.
+/// Regions without a partition ID, such as free space, are listed with `-`.
+///
+/// ```
+/// use partitioning::planner::{Region, format_layout_table};
+/// let table = format_layout_table(&[Region::new(0, 1000)], 3000, 512);
+/// assert_eq!(table.lines().nth(2), Some("-   0.0% (0B)  33.3% (1000B)  1000B"));
+/// ```
+pub fn format_layout_table(regions: &[Region], total: u64, logical_block_size: u64) -> String {
+    let header = ["ID", "Start", "End", "Size"].map(str::to_owned);
+    let rows = regions
+        .iter()
+        .map(|region| {
+            let region = region.to_unit(Unit::Bytes, logical_block_size);
+            [
+                region.partition_id.map_or_else(|| "-".to_owned(), |id| id.to_string()),
+                format_position_f64(region.start, total, 1),
+                format_position_f64(region.end, total, 1),
+                format_size(region.size()),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let widths: [usize; 4] = std::array::from_fn(|column| {
+        rows.iter()
+            .chain([&header])
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or(0)
+    });
+    let format_row = |row: &[String; 4]| {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut table = format_row(&header);
+    table.push_str(&format!(
+        "{}\n",
+        "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1))
+    ));
+    for row in &rows {
+        table.push_str(&format_row(row));
+    }
+    table
 }
 
 impl Change {
@@ -150,7 +371,7 @@ impl Change {
                     "Add new partition #{}: {} ({} at {})",
                     partition_id,
                     format_size(end - start),
-                    Region::new(*start, *end).describe(disk_size),
+                    Region::new(*start, *end).describe_bytes(disk_size),
                     format_position(*start, disk_size)
                 )
             }
@@ -160,6 +381,15 @@ impl Change {
             } => {
                 format!("Delete partition #{} (index {})", partition_id, original_index + 1)
             }
+            Change::SetPartitionType {
+                partition_id,
+                type_guid,
+            } => {
+                format!("Set type of partition #{partition_id} to {}", type_guid.guid)
+            }
+            Change::SetPartitionName { partition_id, name } => {
+                format!("Rename partition #{partition_id} to \"{name}\"")
+            }
         }
     }
 }
@@ -171,34 +401,215 @@ impl Planner {
 
         // Extract original regions and partition IDs from device
         let mut original_regions = Vec::new();
-        let mut original_partition_ids = Vec::new();
-        let mut max_id = 0u32;
 
+        // The kernel reports partition bounds in 512-byte sectors, whatever the block size
         for part in device.partitions() {
-            let mut region = Region::new(part.start, part.end);
+            let mut region = Region::new(part.start * 512, part.end * 512);
             region.partition_id = Some(part.number);
             original_regions.push(region);
-            original_partition_ids.push(part.number);
-            max_id = max_id.max(part.number);
         }
 
+        Self {
+            logical_block_size: device.logical_block_size(),
+            ..Self::from_regions(device.size(), original_regions)
+        }
+        .within_gpt_bounds()
+    }
+
+    /// Creates a new partitioning planner for the given disk, working in `unit`
+    ///
+    /// In [`Unit::Sectors`] partition starts are still aligned to 1MiB, but ends
+    /// are kept exactly as requested, as a sector is already the smallest unit
+    /// the partition table can address.
+    pub fn new_with_units(device: &BlockDevice, unit: Unit) -> Self {
+        match unit {
+            Unit::Bytes => Self::new(device),
+            Unit::Sectors => {
+                // The kernel reports partition bounds in 512-byte sectors, whatever the block size
+                let block_size = device.logical_block_size();
+                let original_regions = device
+                    .partitions()
+                    .iter()
+                    .map(|part| Region {
+                        unit,
+                        partition_id: Some(part.number),
+                        ..Region::new(part.start * 512 / block_size, part.end * 512 / block_size)
+                    })
+                    .collect();
+
+                Self {
+                    unit,
+                    logical_block_size: block_size,
+                    disk_size: device.size(),
+                    ..Self::from_regions(unit.from_bytes(device.size(), block_size), original_regions)
+                }
+                .within_gpt_bounds()
+            }
+        }
+    }
+
+    /// Creates a new partitioning planner from the GPT currently on the device.
+    ///
+    /// Unlike [`Planner::new`], which relies on the kernel's view of the disk,
+    /// this reads the partition table itself so that each existing partition
+    /// carries its type GUID, name and UUID.
+    pub fn from_existing(device: &BlockDevice) -> io::Result<Self> {
+        Self::from_handle(&mut device.open_handle()?)
+    }
+
+    /// Creates a new partitioning planner from the GPT on an already open device
+    ///
+    /// The table is read with the handle's logical block size, and the handle
+    /// can be reused afterwards, e.g. to probe for superblocks.
+    pub fn from_handle(handle: &mut DeviceHandle) -> io::Result<Self> {
+        debug!("Reading existing GPT from {:?}", handle.path());
+
+        let table = gpt::GptConfig::default()
+            .writable(false)
+            .logical_block_size(gpt::disk::LogicalBlockSize::try_from(handle.logical_block_size())?)
+            .open_from_device(&mut *handle)
+            .map_err(|e| match e {
+                gpt::GptError::Io(e) => e,
+                e => io::Error::other(e),
+            })?;
+        let block_size = u64::from(*table.logical_block_size());
+
+        let original_regions = table
+            .partitions()
+            .iter()
+            .map(|(id, part)| Region {
+                start: part.first_lba * block_size,
+                end: (part.last_lba + 1) * block_size,
+                unit: Unit::Bytes,
+                partition_id: Some(*id),
+                attributes: Some(PartitionAttributes {
+                    table: TableAttributes::Gpt(GptAttributes {
+                        type_guid: part.part_type_guid.clone(),
+                        name: Some(part.name.clone()).filter(|n| !n.is_empty()),
+                        uuid: Some(part.part_guid),
+                        flags: GptFlags::from_bits_retain(part.flags),
+                    }),
+                    role: None,
+                    filesystem: None,
+                    encryption: None,
+                }),
+            })
+            .collect();
+
+        Ok(Self {
+            logical_block_size: block_size,
+            ..Self::from_regions(handle.size(), original_regions)
+        }
+        .within_gpt_bounds())
+    }
+
+    /// Creates a planner for a disk of `size` bytes holding the given partitions
+    fn from_regions(size: u64, original_regions: Vec<Region>) -> Self {
+        let original_partition_ids = original_regions
+            .iter()
+            .filter_map(|r| r.partition_id)
+            .collect::<Vec<_>>();
+
         Self {
             usable_start: 0,
-            usable_end: device.size(),
+            usable_end: size,
             changes: VecDeque::new(),
+            layout: original_regions.clone(),
             original_regions,
             original_partition_ids,
-            next_partition_id: max_id + 1,
             wipe_disk: false,
+            max_partitions: DEFAULT_MAX_PARTITIONS,
+            unit: Unit::Bytes,
+            applied_strategies: Vec::new(),
+            alignment_bytes: PARTITION_ALIGNMENT,
+            logical_block_size: 512,
+            disk_size: size,
         }
     }
 
+    /// Shrink the usable region so it never includes the GPT headers and entry arrays
+    fn within_gpt_bounds(self) -> Self {
+        let (start, end) = self.gpt_usable_bounds();
+        Self {
+            usable_start: self.usable_start.max(start),
+            usable_end: self.usable_end.min(end),
+            ..self
+        }
+    }
+
+    /// The unit of every offset and size handled by this planner
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    /// Logical block size of the disk in bytes
+    pub fn logical_block_size(&self) -> u64 {
+        self.logical_block_size
+    }
+
+    /// Convert a value in the planner's unit to bytes
+    pub fn to_bytes(&self, value: u64) -> u64 {
+        self.unit.to_bytes(value, self.logical_block_size)
+    }
+
+    /// Convert a byte value to the planner's unit, rounding down
+    pub fn from_bytes(&self, bytes: u64) -> u64 {
+        self.unit.from_bytes(bytes, self.logical_block_size)
+    }
+
+    /// A region between two positions in the planner's unit
+    pub(crate) fn region(&self, start: u64, end: u64) -> Region {
+        Region {
+            unit: self.unit,
+            ..Region::new(start, end)
+        }
+    }
+
+    /// Partition start alignment in the planner's unit
+    pub fn alignment(&self) -> u64 {
+        self.from_bytes(self.alignment_bytes).max(1)
+    }
+
+    /// Partition alignment in bytes, or 0 if partitions are not aligned
+    pub fn alignment_bytes(&self) -> u64 {
+        self.alignment_bytes
+    }
+
+    /// Set the partition alignment in bytes, returning the previous alignment
+    ///
+    /// Defaults to [`PARTITION_ALIGNMENT`]. Zero disables alignment.
+    pub fn set_alignment_bytes(&mut self, alignment_bytes: u64) -> u64 {
+        std::mem::replace(&mut self.alignment_bytes, alignment_bytes)
+    }
+
+    /// Set the number of entries available in the partition table
+    pub fn with_max_partitions(self, max_partitions: usize) -> Self {
+        Self { max_partitions, ..self }.within_gpt_bounds()
+    }
+
+    /// The region partitions may occupy without touching the GPT, in the planner's unit
+    ///
+    /// This runs from the first to past the last usable LBA of a GPT with room
+    /// for [`Planner::max_partitions`] entries, which is LBA 34 to 33 LBAs
+    /// before the end with the default 128 entries and 512-byte blocks, or LBA
+    /// 6 to 5 LBAs before the end with 4096-byte blocks. The planner's usable
+    /// region, see [`Planner::offsets`], always lies within these bounds.
+    pub fn gpt_usable_bounds(&self) -> (u64, u64) {
+        let block_size = self.logical_block_size;
+        let entries = (self.max_partitions as u64 * 128).div_ceil(block_size) * block_size;
+        let start = 2 * block_size + entries;
+        let end = self.disk_size.saturating_sub(block_size + entries);
+        let unit_size = self.unit.size(block_size);
+        (start.div_ceil(unit_size), end / unit_size)
+    }
+
     /// Set the usable disk region offsets
     pub fn with_start_offset(self, offset: u64) -> Self {
         Self {
             usable_start: offset,
             ..self
         }
+        .within_gpt_bounds()
     }
 
     /// Set the usable disk region offsets
@@ -207,10 +618,23 @@ impl Planner {
             usable_end: offset,
             ..self
         }
+        .within_gpt_bounds()
     }
 
     /// Get a human readable description of pending changes
     pub fn describe_changes(&self) -> String {
+        self.describe_changes_impl(None)
+    }
+
+    /// Get a human readable description of pending changes, including the LBAs of new partitions
+    ///
+    /// Each added partition is followed by [`Region::describe_sectors`] for
+    /// sectors of `sector_size` bytes, which helps when debugging alignment.
+    pub fn describe_changes_with_sectors(&self, sector_size: u64) -> String {
+        self.describe_changes_impl(Some(sector_size))
+    }
+
+    fn describe_changes_impl(&self, sector_size: Option<u64>) -> String {
         if self.changes.is_empty() {
             return "No pending changes".to_string();
         }
@@ -218,14 +642,136 @@ impl Planner {
         let mut description = "Pending changes:\n".to_string();
 
         for (i, change) in self.changes.iter().enumerate() {
-            description.push_str(&format!("  {}: {}\n", i + 1, change.describe(self.usable_size())));
+            // Descriptions are always given in bytes
+            let change = match change {
+                Change::AddPartition {
+                    start,
+                    end,
+                    unit,
+                    partition_id,
+                    attributes,
+                } => Change::AddPartition {
+                    start: unit.to_bytes(*start, self.logical_block_size),
+                    end: unit.to_bytes(*end, self.logical_block_size),
+                    unit: Unit::Bytes,
+                    partition_id: *partition_id,
+                    attributes: attributes.clone(),
+                },
+                change => change.clone(),
+            };
+            let disk_size = self.to_bytes(self.usable_size());
+            description.push_str(&format!("  {}: {}", i + 1, change.describe(disk_size)));
+            if let (Some(sector_size), Change::AddPartition { start, end, .. }) = (sector_size, &change) {
+                description.push_str(&format!(
+                    " [{}]",
+                    Region::new(*start, *end).describe_sectors(sector_size)
+                ));
+            }
+            description.push('\n');
         }
 
         description
     }
 
+    /// Get a machine-readable description of pending changes
+    ///
+    /// Returns a JSON array with one object per change, in the order they were
+    /// planned. Each object has an `operation` of `"add"`, `"delete"`,
+    /// `"set-type"` or `"set-name"`, the `partition_id`, `start_bytes`,
+    /// `end_bytes` and `size_bytes` of the partition, and its `type_guid`. New
+    /// partitions without attributes are written as [`gpt::partition_types::BASIC`],
+    /// while deleted partitions have a `null` type unless the planner was created
+    /// with [`Planner::from_existing`]. Renames also carry the new `name`.
+    pub fn describe_changes_json(&self) -> serde_json::Value {
+        let type_guid = |attributes: Option<&PartitionAttributes>| {
+            attributes
+                .and_then(|a| a.table.as_gpt())
+                .map(|gpt| gpt.type_guid.guid.to_string())
+        };
+        let layout = self.current_layout();
+        let bounds = |id: u32| {
+            layout
+                .iter()
+                .find(|region| region.partition_id == Some(id))
+                .map_or((0, 0), |region| (region.start, region.end))
+        };
+
+        self.changes
+            .iter()
+            .map(|change| {
+                let (operation, partition_id, start, end, type_guid) = match change {
+                    Change::AddPartition {
+                        start,
+                        end,
+                        partition_id,
+                        attributes,
+                        ..
+                    } => (
+                        "add",
+                        *partition_id,
+                        *start,
+                        *end,
+                        Some(
+                            type_guid(attributes.as_deref())
+                                .unwrap_or_else(|| gpt::partition_types::BASIC.guid.to_string()),
+                        ),
+                    ),
+                    Change::DeletePartition {
+                        original_index,
+                        partition_id,
+                    } => {
+                        let region = &self.original_regions[*original_index];
+                        (
+                            "delete",
+                            *partition_id,
+                            region.start,
+                            region.end,
+                            type_guid(region.attributes.as_ref()),
+                        )
+                    }
+                    Change::SetPartitionType {
+                        partition_id,
+                        type_guid,
+                    } => {
+                        let (start, end) = bounds(*partition_id);
+                        ("set-type", *partition_id, start, end, Some(type_guid.guid.to_string()))
+                    }
+                    Change::SetPartitionName { partition_id, .. } => {
+                        let (start, end) = bounds(*partition_id);
+                        let region = layout.iter().find(|region| region.partition_id == Some(*partition_id));
+                        let type_guid = type_guid(region.and_then(|r| r.attributes.as_ref()));
+                        ("set-name", *partition_id, start, end, type_guid)
+                    }
+                };
+                let (start, end) = (self.to_bytes(start), self.to_bytes(end));
+                let mut description = serde_json::json!({
+                    "operation": operation,
+                    "partition_id": partition_id,
+                    "start_bytes": start,
+                    "end_bytes": end,
+                    "size_bytes": end - start,
+                    "type_guid": type_guid,
+                });
+                if let Change::SetPartitionName { name, .. } = change {
+                    description["name"] = name.as_str().into();
+                }
+                description
+            })
+            .collect()
+    }
+
     /// Returns the current effective layout after all pending changes
     pub fn current_layout(&self) -> Vec<Region> {
+        self.layout.clone()
+    }
+
+    /// Rebuild the cached layout after the pending changes were modified
+    fn refresh_layout(&mut self) {
+        self.layout = self.build_layout();
+    }
+
+    /// Compute the effective layout from the original regions and pending changes
+    fn build_layout(&self) -> Vec<Region> {
         let mut layout = self.original_regions.clone();
         let mut deleted_indices = Vec::new();
 
@@ -252,6 +798,7 @@ impl Planner {
             if let Change::AddPartition {
                 start,
                 end,
+                unit,
                 partition_id,
                 attributes,
             } = change
@@ -260,16 +807,91 @@ impl Planner {
                 layout.push(Region {
                     start: *start,
                     end: *end,
+                    unit: *unit,
                     partition_id: Some(*partition_id),
-                    attributes: attributes.clone(),
+                    attributes: attributes.as_deref().cloned(),
                 });
             }
         }
 
+        // Third pass: update GPT metadata in place
+        for change in &self.changes {
+            let (partition_id, type_guid, name) = match change {
+                Change::SetPartitionType {
+                    partition_id,
+                    type_guid,
+                } => (partition_id, Some(type_guid), None),
+                Change::SetPartitionName { partition_id, name } => (partition_id, None, Some(name)),
+                _ => continue,
+            };
+            let Some(region) = layout.iter_mut().find(|r| r.partition_id == Some(*partition_id)) else {
+                continue;
+            };
+            let attributes = region.attributes.get_or_insert_with(|| PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes::default()),
+                role: None,
+                filesystem: None,
+                encryption: None,
+            });
+            let TableAttributes::Gpt(gpt) = &mut attributes.table;
+            if let Some(type_guid) = type_guid {
+                gpt.type_guid = type_guid.clone();
+            }
+            if let Some(name) = name {
+                gpt.name = Some(name.clone());
+            }
+        }
+
         debug!("Current layout has {} partitions", layout.len());
         layout
     }
 
+    /// Find partitions in the current layout that overlap each other
+    ///
+    /// The planner refuses to add overlapping partitions, but the existing table
+    /// read from disk may already contain them.
+    ///
+    /// # Returns
+    /// Pairs of overlapping partition IDs, lowest ID first
+    pub fn detect_overlaps(&self) -> Vec<(u32, u32)> {
+        let layout = self.current_layout();
+        let mut overlaps = vec![];
+
+        for (i, a) in layout.iter().enumerate() {
+            for b in &layout[i + 1..] {
+                if let (Some(id_a), Some(id_b)) = (a.partition_id, b.partition_id) {
+                    if a.overlaps_with(b) {
+                        overlaps.push((id_a.min(id_b), id_a.max(id_b)));
+                    }
+                }
+            }
+        }
+
+        overlaps.sort_unstable();
+        overlaps
+    }
+
+    /// Find the first partition in the current layout with the given GPT type GUID
+    pub fn find_partition_by_type_guid(&self, guid: &gpt::partition_types::Type) -> Option<&Region> {
+        self.find_partition_by_gpt(|attributes| attributes.type_guid == *guid)
+    }
+
+    /// Find the first partition in the current layout with the given GPT partition name
+    pub fn find_partition_by_label(&self, label: &str) -> Option<&Region> {
+        self.find_partition_by_gpt(|attributes| attributes.name.as_deref() == Some(label))
+    }
+
+    /// Find the first partition whose GPT attributes match `predicate`
+    fn find_partition_by_gpt(&self, predicate: impl Fn(&GptAttributes) -> bool) -> Option<&Region> {
+        self.layout.iter().find(|region| {
+            region
+                .attributes
+                .as_ref()
+                .and_then(|a| a.table.as_gpt())
+                .is_some_and(&predicate)
+        })
+    }
+
     pub fn plan_add_partition(&mut self, start: u64, end: u64) -> Result<(), PlanError> {
         self.plan_add_partition_with_attributes(start, end, None)
     }
@@ -277,8 +899,8 @@ impl Planner {
     /// Plan to add a new partition between two absolute positions on disk.
     ///
     /// # Arguments
-    /// * `start` - The absolute starting position in bytes from the beginning of the disk
-    /// * `end` - The absolute ending position in bytes from the beginning of the disk
+    /// * `start` - The absolute starting position in the planner's unit from the beginning of the disk
+    /// * `end` - The absolute ending position in the planner's unit from the beginning of the disk
     ///
     /// Both positions will be aligned to the nearest appropriate boundary (usually 1MB).
     /// The partition will occupy the range [start, end).
@@ -292,29 +914,35 @@ impl Planner {
         debug!("Planning to add partition {start}..{end}");
         debug!("Original size requested: {}", end - start);
 
-        // Align start and end positions, capping to usable bounds
-        let aligned_start = std::cmp::max(align_up(start, PARTITION_ALIGNMENT), self.usable_start);
-        let aligned_end = std::cmp::min(align_down(end, PARTITION_ALIGNMENT), self.usable_end);
+        // Align start and end positions, capping to the aligned usable bounds
+        let alignment = self.alignment();
+        let (usable_start, usable_end) = self.aligned_offsets();
+        let aligned_start = std::cmp::max(align_up(start, alignment), usable_start);
+        let aligned_end = match self.unit {
+            Unit::Bytes => align_down(end, alignment),
+            Unit::Sectors => end,
+        };
+        let aligned_end = std::cmp::min(aligned_end, usable_end);
 
         debug!("Aligned positions: {aligned_start}..{aligned_end}");
         debug!("Size after alignment: {}", aligned_end - aligned_start);
 
         // Validate input alignments
-        if is_aligned(start, PARTITION_ALIGNMENT) && aligned_start != start {
+        if is_aligned(start, alignment) && aligned_start != start {
             warn!("Start position was already aligned but was re-aligned differently");
             return Err(PlanError::RegionOutOfBounds {
                 start: aligned_start,
                 end: aligned_end,
             });
         }
-        if is_aligned(end, PARTITION_ALIGNMENT) && aligned_end != end {
+        if is_aligned(end, alignment) && aligned_end != end {
             warn!("End position was already aligned but was re-aligned differently");
             return Err(PlanError::RegionOutOfBounds {
                 start: aligned_start,
                 end: aligned_end,
             });
         }
-        // Validate bounds against usable disk region
+        // Validate bounds against usable disk region, which excludes the GPT
         if aligned_start < self.usable_start || aligned_end > self.usable_end {
             warn!("Partition would be outside usable disk region");
             return Err(PlanError::RegionOutOfBounds {
@@ -333,7 +961,7 @@ impl Planner {
         }
 
         // Check for overlaps with current layout
-        let new_region = Region::new(aligned_start, aligned_end);
+        let new_region = self.region(aligned_start, aligned_end);
         let current = self.current_layout();
         for region in &current {
             if new_region.overlaps_with(region) {
@@ -348,17 +976,101 @@ impl Planner {
             }
         }
 
+        if current.len() >= self.max_partitions {
+            warn!("Partition table is full ({} entries)", self.max_partitions);
+            return Err(PlanError::TooManyPartitions {
+                max: self.max_partitions,
+            });
+        }
+
         let partition_id = self.allocate_partition_id();
         debug!("Adding new partition with ID {partition_id} to change queue");
         self.changes.push_back(Change::AddPartition {
             start: aligned_start,
             end: aligned_end,
+            unit: self.unit,
             partition_id,
-            attributes,
+            attributes: attributes.map(Box::new),
         });
+        self.refresh_layout();
         Ok(())
     }
 
+    /// Plan to add a partition in the first free space within `region` that satisfies `size`
+    ///
+    /// Free space is searched from the start of `region`, skipping anything
+    /// already occupied in the current layout. The partition starts on the next
+    /// alignment boundary of the first gap large enough, and takes as much of the
+    /// gap as `size` allows. `region` may be in any unit, while the returned
+    /// bounds are in the planner's unit.
+    ///
+    /// Returns the chosen `(start, end)` of the new partition, or
+    /// [`PlanError::GapTooSmall`] if no gap within `region` is large enough.
+    pub fn plan_add_constrained(
+        &mut self,
+        region: &Region,
+        size: &SizeRequirement,
+        attributes: Option<PartitionAttributes>,
+    ) -> Result<(u64, u64), PlanError> {
+        let region = region.to_unit(self.unit, self.logical_block_size);
+        let size = &size.to_unit(self.unit, self.logical_block_size);
+        let alignment = self.alignment();
+        let bound_start = region.start.max(self.usable_start);
+        let bound_end = region.end.min(self.usable_end);
+
+        // Collect the gaps between partitions within the bounds
+        let mut layout = self.current_layout();
+        layout.sort_by_key(|r| r.start);
+        let mut gaps = vec![];
+        let mut cursor = bound_start;
+        for existing in layout.iter().filter(|r| r.end > bound_start && r.start < bound_end) {
+            if existing.start > cursor {
+                gaps.push((cursor, existing.start));
+            }
+            cursor = cursor.max(existing.end);
+        }
+        if cursor < bound_end {
+            gaps.push((cursor, bound_end));
+        }
+
+        let mut largest = 0;
+        for (gap_start, gap_end) in gaps {
+            let start = gap_start.div_ceil(alignment) * alignment;
+            let end = match self.unit {
+                Unit::Bytes => gap_end / alignment * alignment,
+                Unit::Sectors => gap_end,
+            };
+            let available = end.saturating_sub(start);
+            largest = largest.max(available);
+
+            let wanted = match size {
+                SizeRequirement::Exact(size) => *size,
+                SizeRequirement::AtLeast(_) | SizeRequirement::Remaining => available,
+                SizeRequirement::Range { max, .. } | SizeRequirement::AtMost(max) => available.min(*max),
+            };
+            let wanted = match self.unit {
+                Unit::Bytes => wanted / alignment * alignment,
+                Unit::Sectors => wanted,
+            };
+            if wanted == 0 || wanted < size.minimum() || wanted > available {
+                continue;
+            }
+
+            debug!("Placing constrained partition at {start}..{}", start + wanted);
+            self.plan_add_partition_with_attributes(start, start + wanted, attributes)?;
+            return Ok((start, start + wanted));
+        }
+
+        warn!(
+            "No free space within {}..{} satisfies {size:?}",
+            region.start, region.end
+        );
+        Err(PlanError::GapTooSmall {
+            required: size.minimum(),
+            largest,
+        })
+    }
+
     /// Plan to delete an existing partition
     pub fn plan_delete_partition(&mut self, index: usize) -> Result<(), PlanError> {
         debug!("Planning to delete partition at index {index}");
@@ -383,13 +1095,61 @@ impl Planner {
             original_index: index,
             partition_id,
         });
+        self.refresh_layout();
+        Ok(())
+    }
+
+    /// Plan to change the GPT type of a partition without recreating it
+    ///
+    /// Unlike deleting and adding the partition again, this keeps its unique
+    /// GUID and leaves its contents untouched. `partition_id` may refer to an
+    /// existing partition or one added earlier in the plan.
+    pub fn plan_set_partition_type(
+        &mut self,
+        partition_id: u32,
+        type_guid: gpt::partition_types::Type,
+    ) -> Result<(), PlanError> {
+        self.check_partition_exists(partition_id)?;
+        debug!("Planning to set type of partition {partition_id} to {}", type_guid.guid);
+        self.changes.push_back(Change::SetPartitionType {
+            partition_id,
+            type_guid,
+        });
+        self.refresh_layout();
         Ok(())
     }
 
+    /// Plan to change the GPT name of a partition without recreating it
+    ///
+    /// See [`Planner::plan_set_partition_type`].
+    pub fn plan_set_partition_name(&mut self, partition_id: u32, name: impl Into<String>) -> Result<(), PlanError> {
+        self.check_partition_exists(partition_id)?;
+        let name = name.into();
+        debug!("Planning to rename partition {partition_id} to {name:?}");
+        self.changes.push_back(Change::SetPartitionName { partition_id, name });
+        self.refresh_layout();
+        Ok(())
+    }
+
+    /// Fail unless the current layout holds a partition with the given ID
+    fn check_partition_exists(&self, partition_id: u32) -> Result<(), PlanError> {
+        if self
+            .current_layout()
+            .iter()
+            .any(|region| region.partition_id == Some(partition_id))
+        {
+            Ok(())
+        } else {
+            warn!("No partition with ID {partition_id}");
+            Err(PlanError::PartitionNotFound(partition_id))
+        }
+    }
+
     /// Undo the most recent change
     pub fn undo(&mut self) -> bool {
         if let Some(change) = self.changes.pop_back() {
             debug!("Undoing last change: {change:?}");
+            self.refresh_layout();
             true
         } else {
             debug!("No changes to undo");
@@ -397,10 +1157,133 @@ impl Planner {
         }
     }
 
+    /// Capture the current plan so it can be restored later
+    pub fn snapshot(&self) -> PlannerState {
+        PlannerState {
+            changes: self.changes.clone(),
+            original_regions: self.original_regions.clone(),
+            original_partition_ids: self.original_partition_ids.clone(),
+            wipe_disk: self.wipe_disk,
+            applied_strategies: self.applied_strategies.clone(),
+        }
+    }
+
+    /// Restore a plan previously captured with [`Planner::snapshot()`]
+    ///
+    /// This discards every change made since the snapshot was taken, including
+    /// a planned disk initialization.
+    pub fn restore(&mut self, state: PlannerState) {
+        debug!("Restoring planner snapshot with {} changes", state.changes.len());
+        self.changes = state.changes;
+        self.original_regions = state.original_regions;
+        self.original_partition_ids = state.original_partition_ids;
+        self.wipe_disk = state.wipe_disk;
+        self.applied_strategies = state.applied_strategies;
+        self.refresh_layout();
+    }
+
+    /// Sort the planned partition additions by their start offset
+    ///
+    /// Existing partitions keep their on-disk order so that indices passed to
+    /// [`Planner::plan_delete_partition()`] remain valid. Deletions stay in place
+    /// within the change queue.
+    pub fn sort_partitions_by_start(&mut self) {
+        let slots = self
+            .changes
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(c, Change::AddPartition { .. }))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let mut additions = slots.iter().map(|i| self.changes[*i].clone()).collect::<Vec<_>>();
+        additions.sort_by_key(|c| match c {
+            Change::AddPartition { start, .. } => *start,
+            _ => 0,
+        });
+
+        for (slot, change) in slots.into_iter().zip(additions) {
+            self.changes[slot] = change;
+        }
+        self.refresh_layout();
+    }
+
+    /// Sort planned partitions and shift them towards the start of the disk to close gaps
+    ///
+    /// Existing partitions never move, so planned partitions only close up against
+    /// them. Fails if the layout already contains overlapping partitions.
+    pub fn normalize(&mut self) -> Result<(), PlanError> {
+        self.sort_partitions_by_start();
+
+        let deleted = self
+            .changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::DeletePartition { original_index, .. } => Some(*original_index),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Bounds of every partition, with the change index for those we may move
+        let mut entries = self
+            .original_regions
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !deleted.contains(i))
+            .map(|(_, r)| (r.start, r.end, None))
+            .chain(self.changes.iter().enumerate().filter_map(|(i, c)| match c {
+                Change::AddPartition { start, end, .. } => Some((*start, *end, Some(i))),
+                _ => None,
+            }))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(start, _, _)| *start);
+
+        let mut cursor = self.usable_start;
+        for (start, end, change_index) in entries {
+            if start < cursor {
+                warn!("Partition {start}..{end} overlaps, cannot normalize");
+                return Err(PlanError::CannotNormalizeWithOverlap { start, end });
+            }
+
+            let Some(index) = change_index else {
+                cursor = end;
+                continue;
+            };
+
+            let new_start = std::cmp::min(cursor.div_ceil(self.alignment()) * self.alignment(), start);
+            let new_end = new_start + (end - start);
+            if let Change::AddPartition { start, end, .. } = &mut self.changes[index] {
+                debug!("Shifting partition {start}..{end} to {new_start}..{new_end}");
+                *start = new_start;
+                *end = new_end;
+            }
+            cursor = new_end;
+        }
+
+        self.refresh_layout();
+        Ok(())
+    }
+
     /// Clear all planned changes
     pub fn reset(&mut self) {
         eprintln!("Resetting all planned changes");
         self.changes.clear();
+        self.applied_strategies.clear();
+        self.refresh_layout();
+    }
+
+    /// Apply a strategy to this planner, recording its name on success
+    ///
+    /// This is equivalent to [`Strategy::apply`], and likewise leaves the
+    /// planner untouched if the strategy cannot be applied.
+    pub fn apply_strategy(&mut self, strategy: &Strategy) -> Result<(), PlanError> {
+        strategy.apply(self)?;
+        self.applied_strategies.push(strategy.name().to_owned());
+        Ok(())
+    }
+
+    /// Names of the strategies applied via [`Planner::apply_strategy`], in order
+    pub fn applied_strategies(&self) -> &[String] {
+        &self.applied_strategies
     }
 
     /// Check if there are any pending changes
@@ -412,34 +1295,114 @@ impl Planner {
         &self.changes
     }
 
-    /// Get the size of the usable disk region in bytes
+    /// Get the size of the usable disk region in the planner's unit
     pub fn usable_size(&self) -> u64 {
         self.usable_end - self.usable_start
     }
 
+    /// Get the size of the whole disk in the planner's unit, including the GPT
+    pub fn disk_size(&self) -> u64 {
+        self.from_bytes(self.disk_size)
+    }
+
     /// Get the usable disk region offsets
     pub fn offsets(&self) -> (u64, u64) {
         (self.usable_start, self.usable_end)
     }
 
+    /// The usable disk region rounded inwards to whole alignment units
+    ///
+    /// Ends are only rounded when working in bytes, as in [`Unit::Sectors`]
+    /// partition ends are kept exactly as requested.
+    pub fn aligned_offsets(&self) -> (u64, u64) {
+        let alignment = self.alignment();
+        let start = self.usable_start.div_ceil(alignment) * alignment;
+        let end = match self.unit {
+            Unit::Bytes => self.usable_end / alignment * alignment,
+            Unit::Sectors => self.usable_end,
+        };
+        // Disks smaller than the alignment have no aligned space at all
+        (start, end.max(start))
+    }
+
+    /// Find the unpartitioned gaps within the usable disk region, in order
+    ///
+    /// The region is first rounded inwards to the partition alignment, so the
+    /// slack between the GPT and the first aligned offset is never offered.
+    pub fn free_regions(&self) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let (mut current, disk_size) = self.aligned_offsets();
+
+        // Sort existing partitions by start position
+        let mut layout = self.current_layout();
+        layout.sort_by_key(|r| r.start);
+
+        // Find gaps between partitions, ignoring any nested within an earlier one
+        for region in layout {
+            if region.start > current {
+                regions.push(self.region(current, region.start));
+            }
+            if region.start >= current || region.contains_offset(current) {
+                current = region.end;
+            }
+        }
+
+        // Add final region if there's space after last partition
+        if current < disk_size {
+            regions.push(self.region(current, disk_size));
+        }
+
+        regions
+    }
+
+    /// Summarise the free space left by the current layout
+    pub fn free_space_summary(&self) -> FreeSpaceSummary {
+        let sizes = self
+            .free_regions()
+            .iter()
+            .map(|r| self.to_bytes(r.size()))
+            .collect::<Vec<_>>();
+
+        FreeSpaceSummary {
+            region_count: sizes.len(),
+            total_free: sizes.iter().sum(),
+            largest_region: sizes.iter().copied().max().unwrap_or(0),
+        }
+    }
+
     /// Plan to initialize a clean partition layout
     pub fn plan_initialize_disk(&mut self) -> Result<(), PlanError> {
         debug!("Planning to create new GPT partition table");
         self.changes.clear(); // Clear any existing changes
         self.original_regions.clear(); // Clear original partitions
         self.original_partition_ids.clear();
-        self.next_partition_id = 1;
         self.wipe_disk = true;
+        self.refresh_layout();
         Ok(())
     }
 
+    /// Number of entries available in the partition table
+    pub fn max_partitions(&self) -> usize {
+        self.max_partitions
+    }
+
     pub fn wipe_disk(&self) -> bool {
         self.wipe_disk
     }
-    /// Get the next available partition ID and increment the counter
+    /// Get the lowest partition ID not used in the current layout
+    ///
+    /// IDs freed by planned deletions are handed out again, so new partitions
+    /// stay within the [`Planner::max_partitions`] entries of the table.
     pub fn allocate_partition_id(&mut self) -> u32 {
-        let id = self.next_partition_id;
-        self.next_partition_id += 1;
+        let used = self
+            .layout
+            .iter()
+            .filter_map(|region| region.partition_id)
+            .collect::<HashSet<_>>();
+        let mut id = 1;
+        while used.contains(&id) {
+            id += 1;
+        }
         id
     }
 
@@ -452,7 +1415,9 @@ impl Planner {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::TableAttributes;
     use disks::mock::MockDisk;
+    use gpt::partition_types;
     use test_log::test;
 
     const MB: u64 = 1024 * 1024;
@@ -484,13 +1449,13 @@ mod tests {
         let disk = create_mock_disk();
         let mut planner = Planner::new(&BlockDevice::mock_device(disk));
 
-        // Create typical Linux partition layout with absolute positions
-        // - 0 -> 512MB: EFI System Partition
-        // - 512MB -> 4.5GB: Swap
-        // - 4.5GB -> 500GB: Root
-        assert!(planner.plan_add_partition(0, 512 * MB).is_ok());
-        assert!(planner.plan_add_partition(512 * MB, 4 * GB + 512 * MB).is_ok());
-        assert!(planner.plan_add_partition(4 * GB + 512 * MB, 500 * GB).is_ok());
+        // Create typical Linux partition layout with absolute positions, clear of the GPT
+        // - 1MB -> 513MB: EFI System Partition
+        // - 513MB -> 4.5GB: Swap
+        // - 4.5GB -> 500GB: Root, ending 1MB before the backup GPT
+        assert!(planner.plan_add_partition(MB, 513 * MB).is_ok());
+        assert!(planner.plan_add_partition(513 * MB, 4 * GB + 513 * MB).is_ok());
+        assert!(planner.plan_add_partition(4 * GB + 513 * MB, 500 * GB - MB).is_ok());
 
         eprintln!("\nPlanned fresh installation:");
         eprintln!("{}", planner.describe_changes());
@@ -513,13 +1478,22 @@ mod tests {
         // - 4GB swap
         // - Rest for root
         assert!(planner.plan_add_partition(start, start + 4 * GB).is_ok());
-        assert!(planner.plan_add_partition(start + 4 * GB, 500 * GB).is_ok());
+        assert!(planner.plan_add_partition(start + 4 * GB, 500 * GB - MB).is_ok());
 
         eprintln!("\nPlanned dual-boot changes:");
         eprintln!("{}", planner.describe_changes());
 
         let layout = planner.current_layout();
         assert_eq!(layout.len(), 6); // 4 Windows + 2 Linux partitions
+
+        // None of the Windows partitions overlap the added Linux partitions
+        let (windows, linux) = layout.split_at(4);
+        assert!(windows.iter().all(|w| linux.iter().all(|l| !w.overlaps_with(l))));
+        assert!(planner.detect_overlaps().is_empty());
+        assert!(linux[0].contains_offset(start));
+        assert!(!linux[0].contains_offset(start + 4 * GB));
+        assert!(linux[1].contains_offset(start + 4 * GB));
+        assert!(!windows.iter().any(|w| w.contains_offset(start)));
     }
 
     #[test]
@@ -541,7 +1515,7 @@ mod tests {
         // - 8GB swap (larger than before)
         // - Rest for root
         assert!(planner.plan_add_partition(512 * MB, 8 * GB + 512 * MB).is_ok());
-        assert!(planner.plan_add_partition(8 * GB + 512 * MB, 500 * GB).is_ok());
+        assert!(planner.plan_add_partition(8 * GB + 512 * MB, 500 * GB - MB).is_ok());
 
         eprintln!("\nPlanned Linux replacement changes:");
         eprintln!("{}", planner.describe_changes());
@@ -562,8 +1536,14 @@ mod tests {
             Err(PlanError::RegionOutOfBounds { .. })
         ));
 
+        // The whole disk includes the GPT headers
+        assert!(matches!(
+            planner.plan_add_partition(0, 500 * GB),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+
         // Add a partition and test overlap
-        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.plan_add_partition(MB, 100 * GB).is_ok());
         assert!(matches!(
             planner.plan_add_partition(50 * GB, 150 * GB),
             Err(PlanError::RegionOverlap { .. })
@@ -576,7 +1556,7 @@ mod tests {
         let mut planner = Planner::new(&BlockDevice::mock_device(disk));
 
         // Add some partitions
-        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.plan_add_partition(MB, 100 * GB).is_ok());
         assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
         assert_eq!(planner.current_layout().len(), 2);
 
@@ -597,8 +1577,8 @@ mod tests {
         let disk = create_mock_disk();
         let mut planner = Planner::new(&BlockDevice::mock_device(disk));
 
-        // Add first partition from 0 to 100GB
-        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        // Add first partition from the first aligned usable offset to 100GB
+        assert!(planner.plan_add_partition(MB, 100 * GB).is_ok());
 
         // Next partition should be able to start exactly where previous one ended
         assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
@@ -663,6 +1643,701 @@ mod tests {
         assert_eq!(align_down(4 * mb + (600 * kb), mb), 5 * mb);
     }
 
+    /// Flatten a layout for comparison
+    fn layout_bounds(planner: &Planner) -> Vec<(u64, u64, Option<u32>)> {
+        planner
+            .current_layout()
+            .iter()
+            .map(|r| (r.start, r.end, r.partition_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let start = 200 * GB + 616 * MB;
+        assert!(planner.plan_add_partition(start, start + 4 * GB).is_ok());
+
+        let before = layout_bounds(&planner);
+        let state = planner.snapshot();
+
+        // Speculatively wipe the disk and lay out something else entirely
+        assert!(planner.plan_initialize_disk().is_ok());
+        assert!(planner.plan_add_partition(MB, 100 * GB).is_ok());
+        assert!(planner.wipe_disk());
+
+        planner.restore(state);
+        assert!(!planner.wipe_disk());
+        assert_eq!(planner.changes().len(), 1);
+        assert_eq!(layout_bounds(&planner), before);
+
+        // Partition IDs continue from where the snapshot left off
+        assert!(planner.plan_add_partition(start + 4 * GB, 500 * GB - MB).is_ok());
+        assert_eq!(planner.current_layout().last().unwrap().partition_id, Some(6));
+    }
+
+    #[test]
+    fn test_snapshot_restore_failed_strategy() {
+        use crate::strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy};
+
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let start = 200 * GB + 616 * MB;
+        assert!(planner.plan_add_partition(start, start + 4 * GB).is_ok());
+
+        let before = layout_bounds(&planner);
+        let state = planner.snapshot();
+
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Exact(100 * GB),
+            name: None,
+            attributes: None,
+        });
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::AtLeast(600 * GB),
+            name: None,
+            attributes: None,
+        });
+        assert!(strategy.apply(&mut planner).is_err());
+
+        planner.restore(state);
+        assert!(!planner.wipe_disk());
+        assert_eq!(layout_bounds(&planner), before);
+    }
+
+    #[test]
+    fn test_free_space_summary() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(MockDisk::new(100 * GB)));
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        planner.plan_add_partition(20 * GB, 50 * GB).unwrap();
+        planner.plan_add_partition(90 * GB, 100 * GB - MB).unwrap();
+
+        let summary = planner.free_space_summary();
+        assert_eq!(
+            summary,
+            FreeSpaceSummary {
+                region_count: 2,
+                total_free: 50 * GB,
+                largest_region: 40 * GB,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "2 free regions, largest 40.0GiB, total free 50.0GiB"
+        );
+
+        // Sizes are reported in bytes when planning in sectors
+        let mut planner = Planner::new_with_units(&BlockDevice::mock_device(MockDisk::new(GB)), Unit::Sectors);
+        let (start, end) = planner.aligned_offsets();
+        planner.plan_add_partition(start, start + GB / 1024).unwrap();
+        let summary = planner.free_space_summary();
+        assert_eq!(
+            (summary.region_count, summary.total_free),
+            (1, (end - start - GB / 1024) * 512)
+        );
+    }
+
+    #[test]
+    fn test_plan_add_constrained() {
+        use crate::strategy::SizeRequirement;
+
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        planner.plan_add_partition(20 * GB, 30 * GB).unwrap();
+
+        // The first gap large enough is the one between the two partitions
+        let whole = Region::new(0, 500 * GB);
+        let placed = planner
+            .plan_add_constrained(
+                &whole,
+                &SizeRequirement::Range {
+                    min: 4 * GB,
+                    max: 6 * GB,
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(placed, (10 * GB, 16 * GB));
+
+        // Flexible requests fill the rest of the gap
+        let placed = planner
+            .plan_add_constrained(&whole, &SizeRequirement::AtLeast(GB), None)
+            .unwrap();
+        assert_eq!(placed, (16 * GB, 20 * GB));
+        assert!(planner.detect_overlaps().is_empty());
+
+        // Placement is confined to the given region
+        let region = Region::new(30 * GB, 40 * GB);
+        let placed = planner
+            .plan_add_constrained(&region, &SizeRequirement::Remaining, None)
+            .unwrap();
+        assert_eq!(placed, (30 * GB, 40 * GB));
+    }
+
+    #[test]
+    fn test_plan_add_constrained_no_fit() {
+        use crate::strategy::SizeRequirement;
+
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        planner.plan_add_partition(12 * GB, 500 * GB - MB).unwrap();
+
+        let result = planner.plan_add_constrained(&Region::new(0, 500 * GB), &SizeRequirement::Exact(4 * GB), None);
+        assert!(matches!(
+            result,
+            Err(PlanError::GapTooSmall {
+                required,
+                largest
+            }) if required == 4 * GB && largest == 2 * GB
+        ));
+        assert_eq!(planner.changes().len(), 2);
+    }
+
+    #[test]
+    fn test_apply_strategy() {
+        use crate::strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy};
+
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        assert!(planner.applied_strategies().is_empty());
+
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Exact(100 * GB),
+            name: None,
+            attributes: None,
+        });
+        planner.apply_strategy(&strategy).unwrap();
+        assert_eq!(planner.current_layout().len(), 1);
+
+        // A failed strategy is not recorded
+        let mut too_large = Strategy::new(AllocationStrategy::LargestFree).with_name("too-large");
+        too_large.add_request(PartitionRequest {
+            size: SizeRequirement::AtLeast(600 * GB),
+            name: None,
+            attributes: None,
+        });
+        assert!(planner.apply_strategy(&too_large).is_err());
+
+        let named = Strategy::new(AllocationStrategy::LargestFree).with_name("fill");
+        planner.apply_strategy(&named).unwrap();
+        assert_eq!(planner.applied_strategies(), ["initialize-whole-disk", "fill"]);
+
+        planner.reset();
+        assert!(planner.applied_strategies().is_empty());
+    }
+
+    #[test]
+    fn test_sort_partitions_by_start() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
+        assert!(planner.plan_add_partition(300 * GB, 400 * GB).is_ok());
+        assert!(planner.undo());
+        assert!(planner.plan_add_partition(MB, 50 * GB).is_ok());
+        assert!(planner.plan_add_partition(250 * GB, 300 * GB).is_ok());
+
+        planner.sort_partitions_by_start();
+        let starts = planner.current_layout().iter().map(|r| r.start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![MB, 100 * GB, 250 * GB]);
+
+        // Partition IDs travel with their partitions, reusing the ID freed by the undo
+        assert_eq!(layout_bounds(&planner)[0], (MB, 50 * GB, Some(2)));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(planner.plan_add_partition(300 * GB, 400 * GB).is_ok());
+        assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
+        assert!(planner.plan_add_partition(200 * MB, 10 * GB).is_ok());
+
+        assert!(planner.normalize().is_ok());
+        eprintln!("{}", planner.describe_changes());
+
+        assert_eq!(
+            layout_bounds(&planner),
+            vec![
+                (MB, 10 * GB - 199 * MB, Some(3)),
+                (10 * GB - 199 * MB, 110 * GB - 199 * MB, Some(2)),
+                (110 * GB - 199 * MB, 210 * GB - 199 * MB, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_with_overlap() {
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(50 * MB, 150 * MB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(matches!(
+            planner.normalize(),
+            Err(PlanError::CannotNormalizeWithOverlap { .. })
+        ));
+    }
+
+    #[test]
+    fn test_detect_overlaps() {
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(50 * MB, 150 * MB);
+        disk.add_partition(200 * MB, 300 * MB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        assert_eq!(planner.detect_overlaps(), vec![(1, 2)]);
+
+        // Deleting one side of the overlap resolves it
+        assert!(planner.plan_delete_partition(1).is_ok());
+        assert!(planner.detect_overlaps().is_empty());
+    }
+
+    #[test]
+    fn test_find_partition() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        let gpt = |type_guid, name: &str| {
+            Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes {
+                    type_guid,
+                    name: Some(name.into()),
+                    uuid: None,
+                    ..Default::default()
+                }),
+                role: None,
+                filesystem: None,
+                encryption: None,
+            })
+        };
+
+        planner
+            .plan_add_partition_with_attributes(MB, 101 * MB, gpt(partition_types::EFI, "ESP"))
+            .unwrap();
+        planner
+            .plan_add_partition_with_attributes(101 * MB, 201 * MB, gpt(partition_types::LINUX_FS, "root"))
+            .unwrap();
+
+        let esp = planner.find_partition_by_type_guid(&partition_types::EFI).unwrap();
+        assert_eq!((esp.start, esp.partition_id), (MB, Some(1)));
+        assert!(
+            planner
+                .find_partition_by_type_guid(&partition_types::LINUX_SWAP)
+                .is_none()
+        );
+
+        let root = planner.find_partition_by_label("root").unwrap();
+        assert_eq!((root.start, root.partition_id), (101 * MB, Some(2)));
+        assert!(planner.find_partition_by_label("home").is_none());
+    }
+
+    #[test]
+    fn test_from_existing() {
+        let partitions = [(MB, 33 * MB), (33 * MB, 63 * MB)];
+
+        let Some(planner) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            crate::sparsefile::write_partition_table(blk.device(), 64 * MB, &partitions)?;
+            Planner::from_existing(&blk)
+        }) else {
+            return;
+        };
+
+        let planner = planner.unwrap();
+        let layout = planner.current_layout();
+        let found = layout
+            .iter()
+            .map(|r| (r.partition_id, r.start, r.end))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(Some(1), MB, 33 * MB), (Some(2), 33 * MB, 63 * MB)]);
+        assert!(
+            layout
+                .iter()
+                .all(|r| r.attributes.as_ref().unwrap().table.as_gpt().unwrap().type_guid == partition_types::LINUX_FS)
+        );
+
+        // New partitions get fresh IDs and cannot overlap the existing ones or the backup GPT
+        let mut planner = planner;
+        assert!(planner.plan_add_partition(10 * MB, 20 * MB).is_err());
+        assert!(planner.plan_add_partition(63 * MB, 64 * MB).is_err());
+        planner.set_alignment_bytes(0);
+        planner.plan_add_partition(63 * MB, 64 * MB - 33 * 512).unwrap();
+        assert_eq!(planner.current_layout()[2].partition_id, Some(3));
+    }
+
+    #[test]
+    fn test_from_existing_mock_gpt() {
+        let device = BlockDevice::mock_device_with_gpt(
+            64 * MB,
+            &[
+                (MB, 9 * MB, partition_types::EFI),
+                (9 * MB, 63 * MB, partition_types::LINUX_FS),
+            ],
+        )
+        .unwrap();
+
+        let planner = Planner::from_existing(&device).unwrap();
+        let found = planner
+            .current_layout()
+            .iter()
+            .map(|r| (r.partition_id, r.start, r.end))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(Some(1), MB, 9 * MB), (Some(2), 9 * MB, 63 * MB)]);
+        let esp = planner.find_partition_by_type_guid(&partition_types::EFI).unwrap();
+        assert_eq!(esp.partition_id, Some(1));
+        assert_eq!(device.partitions().len(), 2);
+
+        // Partitions that end before they start, or beyond the disk, are rejected
+        for partitions in [
+            [(9 * MB, MB, partition_types::EFI)],
+            [(MB, 65 * MB, partition_types::EFI)],
+        ] {
+            let error = BlockDevice::mock_device_with_gpt(64 * MB, &partitions).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_set_partition_metadata() {
+        let device = BlockDevice::mock_device_with_gpt(
+            64 * MB,
+            &[
+                (MB, 9 * MB, partition_types::BASIC),
+                (9 * MB, 63 * MB, partition_types::LINUX_FS),
+            ],
+        )
+        .unwrap();
+        let mut planner = Planner::from_existing(&device).unwrap();
+        let original = planner.current_layout()[0].attributes.clone().unwrap();
+
+        planner.plan_set_partition_type(1, partition_types::EFI).unwrap();
+        planner.plan_set_partition_name(1, "ESP").unwrap();
+        assert!(matches!(
+            planner.plan_set_partition_type(3, partition_types::EFI),
+            Err(PlanError::PartitionNotFound(3))
+        ));
+
+        // Only the metadata changes, the partition keeps its place and unique GUID
+        let layout = planner.current_layout();
+        assert_eq!(
+            (layout[0].partition_id, layout[0].start, layout[0].end),
+            (Some(1), MB, 9 * MB)
+        );
+        let gpt = layout[0].attributes.as_ref().unwrap().table.as_gpt().unwrap();
+        assert_eq!(gpt.type_guid, partition_types::EFI);
+        assert_eq!(gpt.name.as_deref(), Some("ESP"));
+        assert_eq!(gpt.uuid, original.table.as_gpt().unwrap().uuid);
+        assert_eq!(
+            planner
+                .find_partition_by_type_guid(&partition_types::EFI)
+                .unwrap()
+                .partition_id,
+            Some(1)
+        );
+
+        assert_eq!(planner.describe_changes_json()[1]["operation"], "set-name");
+        assert_eq!(planner.describe_changes_json()[1]["name"], "ESP");
+        assert!(planner.undo());
+        assert!(planner.undo());
+        assert_eq!(
+            planner.current_layout()[0]
+                .attributes
+                .as_ref()
+                .unwrap()
+                .table
+                .as_gpt()
+                .unwrap()
+                .type_guid,
+            partition_types::BASIC
+        );
+    }
+
+    #[test]
+    fn test_gpt_header_area() {
+        const SECTOR: u64 = 512;
+        let device = BlockDevice::mock_device_with_gpt(64 * MB, &[]).unwrap();
+        let mut planner = Planner::from_existing(&device).unwrap();
+        assert_eq!(planner.gpt_usable_bounds(), (34 * SECTOR, 64 * MB - 33 * SECTOR));
+
+        // Without alignment moving it, a start at LBA 1 would overwrite the primary header
+        planner.set_alignment_bytes(0);
+        assert!(matches!(
+            planner.plan_add_partition(SECTOR, MB),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            planner.plan_add_partition(MB, 64 * MB),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+        planner.plan_add_partition(34 * SECTOR, MB).unwrap();
+        planner.plan_add_partition(MB, 64 * MB - 33 * SECTOR).unwrap();
+
+        // A larger table pushes the first usable LBA further in
+        let planner = planner.with_max_partitions(256);
+        assert_eq!(planner.gpt_usable_bounds().0, 66 * SECTOR);
+    }
+
+    #[test]
+    fn test_sector_units() {
+        const SECTOR: u64 = 512;
+        let mut disk = create_mock_disk();
+        disk.add_partition(MB, 2 * MB);
+        let device = BlockDevice::mock_device(disk);
+
+        let mut planner = Planner::new_with_units(&device, Unit::Sectors);
+        assert_eq!(planner.unit(), Unit::Sectors);
+        assert_eq!(planner.offsets(), (34, 500 * GB / SECTOR - 33));
+        assert_eq!(planner.current_layout()[0].end, 4096);
+
+        // The kernel's 512-byte sectors are converted when planning in bytes
+        let existing = Planner::new(&device).current_layout()[0].clone();
+        assert_eq!((existing.start, existing.end), (MB, 2 * MB));
+
+        // 1.5MiB plus one sector is not a whole number of MiB, and is kept exactly
+        let size = 3 * MB / 2 / SECTOR + 1;
+        planner.plan_add_partition(4096, 4096 + size).unwrap();
+        let added = &planner.current_layout()[1];
+        assert_eq!((added.start, added.end), (4096, 4096 + size));
+        assert_eq!(added.unit, Unit::Sectors);
+        assert!(planner.free_regions().iter().all(|r| r.unit == Unit::Sectors));
+
+        // Starts are still aligned to 1MiB
+        planner.plan_add_partition(4096 + size, 4096 + 2 * size).unwrap();
+        assert_eq!(planner.current_layout()[2].start, 4096 + 2048 * 2);
+
+        // In bytes the same request is rounded to whole MiB
+        let mut bytes = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        bytes.plan_add_partition(2 * MB, 2 * MB + size * SECTOR).unwrap();
+        assert_eq!(bytes.current_layout()[0].size(), 2 * MB);
+        assert_eq!(bytes.current_layout()[0].unit, Unit::Bytes);
+    }
+
+    #[test]
+    fn test_sector_units_4k() {
+        const SECTOR: u64 = 4096;
+        let mut disk = create_mock_disk().with_logical_block_size(SECTOR);
+        disk.add_partition(MB, 2 * MB);
+        let device = BlockDevice::mock_device(disk);
+
+        // Sectors are the device's logical blocks, while the kernel still reports 512-byte sectors
+        let mut planner = Planner::new_with_units(&device, Unit::Sectors);
+        assert_eq!(planner.offsets(), (6, 500 * GB / SECTOR - 5));
+        assert_eq!(planner.to_bytes(1), SECTOR);
+        let existing = &planner.current_layout()[0];
+        assert_eq!((existing.start, existing.end), (256, 512));
+
+        // Regions are converted between units using the same block size
+        let region = existing.to_unit(Unit::Bytes, SECTOR);
+        assert_eq!((region.start, region.end, region.unit), (MB, 2 * MB, Unit::Bytes));
+
+        planner.plan_add_partition(512, 512 + 385).unwrap();
+        let free = planner.offsets().1 - (512 + 385);
+        assert_eq!(planner.free_space_summary().total_free, free * SECTOR);
+    }
+
+    #[test]
+    fn test_describe_sectors() {
+        let region = Region::new(MB, 2 * MB);
+        assert_eq!(region.describe_sectors(512), "LBA 2048..4096 (2048 sectors)");
+        assert_eq!(region.describe_sectors(4096), "LBA 256..512 (256 sectors)");
+
+        // Regions in sectors are described by the same LBAs and sizes as in bytes
+        let sectors = region.to_unit(Unit::Sectors, 512);
+        assert_eq!(sectors.start, 2048);
+        assert_eq!(sectors.describe_sectors(512), "LBA 2048..4096 (2048 sectors)");
+        assert_eq!(sectors.describe(4 * MB, 512), region.describe(4 * MB, 512));
+        assert_eq!(
+            region.to_unit(Unit::Sectors, 4096).describe(4 * MB, 4096),
+            region.describe(4 * MB, 4096)
+        );
+        assert!(sectors.describe(4 * MB, 512).starts_with("1.0MiB at"));
+
+        // Planners in sectors describe the same LBAs as planners in bytes
+        for unit in [Unit::Bytes, Unit::Sectors] {
+            let mut planner = Planner::new_with_units(&BlockDevice::mock_device(create_mock_disk()), unit);
+            let (start, end) = (planner.from_bytes(MB), planner.from_bytes(2 * MB));
+            planner.plan_add_partition(start, end).unwrap();
+            let description = planner.describe_changes_with_sectors(512);
+            assert!(description.contains("[LBA 2048..4096 (2048 sectors)]"), "{description}");
+            assert!(!planner.describe_changes().contains("LBA"));
+        }
+    }
+
+    #[test]
+    fn test_format_layout_table() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(MockDisk::new(1000 * MB)));
+        planner.plan_add_partition(MB, 333 * MB).unwrap();
+        planner.plan_add_partition(333 * MB, 999 * MB).unwrap();
+
+        let table = format_layout_table(&planner.current_layout(), 1000 * MB, 512);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("ID  Start"));
+        assert!(lines[1].chars().all(|c| c == '-'));
+        assert!(lines[2].starts_with("1   0.1% (1.0MiB)"));
+        assert!(lines[2].contains("33.3% (333.0MiB)"));
+        assert!(lines[3].ends_with("99.9% (999.0MiB)  666.0MiB"));
+        assert_eq!(lines[2].find("0.1%"), lines[3].find("33.3%"));
+
+        // Sector-unit layouts are shown in bytes all the same
+        let mut planner = Planner::new_with_units(&BlockDevice::mock_device(MockDisk::new(1000 * MB)), Unit::Sectors);
+        planner.plan_add_partition(2048, 333 * MB / 512).unwrap();
+        planner.plan_add_partition(333 * MB / 512, 999 * MB / 512).unwrap();
+        assert_eq!(format_layout_table(&planner.current_layout(), 1000 * MB, 512), table);
+    }
+
+    #[test]
+    fn test_describe_changes_json() {
+        let device = BlockDevice::mock_device_with_gpt(
+            64 * MB,
+            &[
+                (MB, 9 * MB, partition_types::EFI),
+                (9 * MB, 63 * MB, partition_types::LINUX_FS),
+            ],
+        )
+        .unwrap();
+        let mut planner = Planner::from_existing(&device).unwrap();
+        assert_eq!(planner.describe_changes_json(), serde_json::json!([]));
+
+        planner.plan_delete_partition(1).unwrap();
+        planner.plan_add_partition(9 * MB, 20 * MB).unwrap();
+        assert_eq!(
+            planner.describe_changes_json(),
+            serde_json::json!([
+                {
+                    "operation": "delete",
+                    "partition_id": 2,
+                    "start_bytes": 9 * MB,
+                    "end_bytes": 63 * MB,
+                    "size_bytes": 54 * MB,
+                    "type_guid": "0fc63daf-8483-4772-8e79-3d69d8477de4",
+                },
+                {
+                    "operation": "add",
+                    "partition_id": 2,
+                    "start_bytes": 9 * MB,
+                    "end_bytes": 20 * MB,
+                    "size_bytes": 11 * MB,
+                    "type_guid": partition_types::BASIC.guid.to_string(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_known_partition_types() {
+        let device = BlockDevice::mock_device_with_gpt(
+            64 * MB,
+            &[
+                (MB, 9 * MB, partition_types::EFI),
+                (9 * MB, 25 * MB, partition_types::MICROSOFT_RESERVED),
+                (25 * MB, 50 * MB, partition_types::BASIC),
+                (50 * MB, 63 * MB, partition_types::WINDOWS_RECOVERY),
+            ],
+        )
+        .unwrap();
+        let planner = Planner::from_existing(&device).unwrap();
+        let types = planner
+            .current_layout()
+            .iter()
+            .map(|region| region.known_type())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                Some(KnownPartitionType::EfiSystem),
+                Some(KnownPartitionType::MicrosoftReserved),
+                Some(KnownPartitionType::MicrosoftBasicData),
+                Some(KnownPartitionType::WindowsRecovery),
+            ]
+        );
+
+        let msr = planner
+            .find_partition_by_type_guid(&partition_types::MICROSOFT_RESERVED)
+            .unwrap();
+        let msr_type = msr.known_type().unwrap();
+        assert_eq!(msr_type.to_string(), "Microsoft Reserved");
+        assert!(msr_type.is_windows());
+        assert!(!KnownPartitionType::EfiSystem.is_windows());
+        assert_eq!(
+            KnownPartitionType::from_type_guid(&partition_types::WINDOWS_METADATA),
+            None
+        );
+
+        // Without a partition table to read, partitions can't be classified
+        let planner = Planner::new(&device);
+        assert!(
+            planner
+                .current_layout()
+                .iter()
+                .all(|region| region.known_type().is_none())
+        );
+    }
+
+    #[test]
+    fn test_from_handle() {
+        let device = BlockDevice::mock_device_with_gpt(
+            64 * MB,
+            &[
+                (MB, 9 * MB, partition_types::EFI),
+                (9 * MB, 63 * MB, partition_types::LINUX_FS),
+            ],
+        )
+        .unwrap();
+        let mut handle = device.open_handle().unwrap();
+
+        // Probing and reading the table both work from the one handle
+        assert!(matches!(
+            superblock::Superblock::from_reader(&mut handle),
+            Err(superblock::Error::UnknownSuperblock)
+        ));
+        let planner = Planner::from_handle(&mut handle).unwrap();
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[1].start, 9 * MB);
+        assert_eq!(layout[1].known_type(), Some(KnownPartitionType::LinuxFilesystem));
+        assert_eq!(handle.size(), 64 * MB);
+    }
+
+    #[test]
+    fn test_too_many_partitions() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        for i in 1..=DEFAULT_MAX_PARTITIONS as u64 {
+            assert!(planner.plan_add_partition(i * MB, (i + 1) * MB).is_ok());
+        }
+        let extra = DEFAULT_MAX_PARTITIONS as u64 + 1;
+        assert!(matches!(
+            planner.plan_add_partition(extra * MB, (extra + 1) * MB),
+            Err(PlanError::TooManyPartitions {
+                max: DEFAULT_MAX_PARTITIONS
+            })
+        ));
+    }
+
+    #[test]
+    fn test_max_partitions_with_deletes() {
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 512 * MB);
+        disk.add_partition(512 * MB, GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk)).with_max_partitions(2);
+
+        assert!(matches!(
+            planner.plan_add_partition(2 * GB, 3 * GB),
+            Err(PlanError::TooManyPartitions { max: 2 })
+        ));
+
+        // Deleting partition 1 frees up its table entry, and its ID
+        assert!(planner.plan_delete_partition(0).is_ok());
+        assert!(planner.plan_add_partition(2 * GB, 3 * GB).is_ok());
+        assert!(matches!(
+            planner.changes().back(),
+            Some(Change::AddPartition { partition_id: 1, .. })
+        ));
+    }
+
     #[test]
     fn test_initialize_disk_partition_numbers() {
         let mut disk = create_mock_disk();
@@ -677,7 +2352,7 @@ mod tests {
         assert!(planner.plan_initialize_disk().is_ok());
 
         // Add new partitions - should start from 1
-        assert!(planner.plan_add_partition(0, 100 * MB).is_ok());
+        assert!(planner.plan_add_partition(MB, 100 * MB).is_ok());
         assert!(planner.plan_add_partition(100 * MB, 200 * MB).is_ok());
 
         let layout = planner.current_layout();
@@ -2,8 +2,22 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use disks::BlockDevice;
+use gpt::{GptConfig, disk::LogicalBlockSize, mbr::ProtectiveMBR, partition_types};
 use log::{debug, info};
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::loopback::LoopDevice;
+
+/// Sector size used for partition tables written to sparse files
+const SECTOR_SIZE: u64 = 512;
+
+/// Keeps the sparse files of concurrent [`with_loop_device`] calls apart
+static NEXT_FIXTURE: AtomicUsize = AtomicUsize::new(0);
 
 /// Creates a sparse file at the specified path with the given size.
 ///
@@ -35,3 +49,181 @@ where
     );
     Ok(())
 }
+
+/// Creates a sparse file containing a GPT with the given partitions.
+///
+/// The file is temporarily attached to a loop device while the partition
+/// table is written, and detached again before returning.
+///
+/// # Arguments
+/// * `path` - Path where the sparse file should be created
+/// * `size` - Size in bytes for the sparse file
+/// * `partitions` - Partitions to create as `(start_bytes, end_bytes)` tuples, numbered from 1
+///
+/// # Returns
+/// `io::Result<()>` indicating success or failure
+pub fn create_with_partition_table<P>(path: P, size: u64, partitions: &[(u64, u64)]) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    create(&path, size)?;
+    attached(path.as_ref(), None, |device| {
+        write_partition_table(device, size, partitions)
+    })?
+}
+
+/// Runs `f` with a loop device attached to a fresh sparse file of `size` bytes.
+///
+/// Once `f` returns, every loop device still attached to the sparse file is
+/// detached and the file is removed, so `f` may attach further devices to it.
+///
+/// # Arguments
+/// * `size` - Size in bytes for the sparse file
+/// * `f` - Called with the attached loop device
+///
+/// # Returns
+/// `Some` result of `f`, or `None` if the loop device could not be set up,
+/// e.g. without the privileges loop devices need. The reason is printed, so
+/// tests can simply return early.
+pub fn with_loop_device<T>(size: u64, f: impl FnOnce(BlockDevice) -> T) -> Option<T> {
+    skip_without_loop(with_loop_device_impl(size, None, f))
+}
+
+/// Like [`with_loop_device`], with the loop device using the given logical block size.
+///
+/// This allows tests to emulate a 4Kn disk.
+pub fn with_loop_device_block_size<T>(size: u64, block_size: u32, f: impl FnOnce(BlockDevice) -> T) -> Option<T> {
+    skip_without_loop(with_loop_device_impl(size, Some(block_size), f))
+}
+
+/// Turn a failure to set up loop devices into `None`, printing why the caller skips them
+pub(crate) fn skip_without_loop<T>(result: io::Result<T>) -> Option<T> {
+    result
+        .inspect_err(|e| eprintln!("Skipping: unable to create loop device: {e}"))
+        .ok()
+}
+
+fn with_loop_device_impl<T>(size: u64, block_size: Option<u32>, f: impl FnOnce(BlockDevice) -> T) -> io::Result<T> {
+    let id = NEXT_FIXTURE.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("disks-rs-loop-{}-{id}.img", std::process::id()));
+    create(&path, size)?;
+
+    let result = attached(&path, block_size, |device| {
+        disks::loopback::Device::from_device_path(device)
+            .map(BlockDevice::loopback_device)
+            .map(f)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "loop device not found"))
+    });
+    let removed = fs::remove_file(&path);
+    let result = result??;
+    removed?;
+    Ok(result)
+}
+
+/// Attach a loop device to `path` and run `f` with the device path,
+/// detaching every loop device attached to `path` afterwards
+fn attached<T>(path: &Path, block_size: Option<u32>, f: impl FnOnce(&Path) -> T) -> io::Result<T> {
+    let backing_file = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sparse file path is not valid UTF-8"))?;
+    let device = LoopDevice::create()?;
+    device.attach(backing_file)?;
+
+    // Always detach, even if the block size could not be set
+    let configured = block_size.map_or(Ok(()), |block_size| device.set_block_size(block_size));
+    let device_path = device.path.clone();
+    // The kernel defers detaching a loop device until it is closed
+    drop(device);
+    let result = configured.map(|_| f(Path::new(&device_path)));
+    LoopDevice::detach_all_for_file(path)?;
+    result
+}
+
+/// Write a fresh protective MBR and GPT containing `partitions` to `device`
+pub(crate) fn write_partition_table(device: &Path, size: u64, partitions: &[(u64, u64)]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(device)?;
+
+    let total_lba = size / SECTOR_SIZE;
+    ProtectiveMBR::with_lb_size(u32::try_from(total_lba.saturating_sub(1)).unwrap_or(0xFF_FF_FF_FF))
+        .overwrite_lba0(&mut file)
+        .map_err(io::Error::other)?;
+
+    let mut table = GptConfig::default()
+        .writable(true)
+        .logical_block_size(LogicalBlockSize::Lb512)
+        .create_from_device(file, None)
+        .map_err(io::Error::other)?;
+
+    for (index, (start, end)) in partitions.iter().enumerate() {
+        debug!("Adding partition {} at {start}..{end}", index + 1);
+        table
+            .add_partition_at(
+                "",
+                index as u32 + 1,
+                start / SECTOR_SIZE,
+                (end - start) / SECTOR_SIZE,
+                partition_types::LINUX_FS,
+                0,
+            )
+            .map_err(io::Error::other)?;
+    }
+
+    table.write().map_err(io::Error::other)?.sync_all()?;
+
+    info!(
+        "Wrote partition table with {} partitions to {}",
+        partitions.len(),
+        device.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_with_partition_table() {
+        const MB: u64 = 1024 * 1024;
+
+        let image = std::env::temp_dir().join(format!("disks-rs-fixture-{}.img", std::process::id()));
+        let partitions = [(MB, 33 * MB), (33 * MB, 63 * MB)];
+
+        if skip_without_loop(create_with_partition_table(&image, 64 * MB, &partitions)).is_none() {
+            fs::remove_file(&image).ok();
+            return;
+        }
+
+        let table = GptConfig::default().writable(false).open(&image).unwrap();
+        let attached = LoopDevice::list_attached(&image).unwrap();
+        fs::remove_file(&image).unwrap();
+
+        let found = table
+            .partitions()
+            .iter()
+            .map(|(id, p)| (*id, p.first_lba * SECTOR_SIZE, (p.last_lba + 1) * SECTOR_SIZE))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(1, MB, 33 * MB), (2, 33 * MB, 63 * MB)]);
+        assert!(attached.is_empty());
+    }
+
+    #[test]
+    fn test_with_loop_device() {
+        const MB: u64 = 1024 * 1024;
+
+        let Some((size, block_size, file)) = with_loop_device_block_size(16 * MB, 4096, |blk| {
+            let file = match &blk {
+                BlockDevice::Loopback(device) => device.file_path().map(ToOwned::to_owned),
+                BlockDevice::Disk(_) => None,
+            };
+            (blk.size(), blk.logical_block_size(), file)
+        }) else {
+            return;
+        };
+
+        let file = file.unwrap();
+        assert_eq!((size, block_size), (16 * MB, 4096));
+        assert!(LoopDevice::list_attached(&file).unwrap().is_empty());
+        assert!(!file.exists());
+    }
+}
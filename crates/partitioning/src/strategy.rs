@@ -20,18 +20,22 @@
 //! // Request needed partitions
 //! strategy.add_request(PartitionRequest {
 //!     size: SizeRequirement::Exact(512 * 1024 * 1024), // 512MB EFI partition
+//!     name: Some("EFI System Partition".into()),
 //!     attributes: None,
 //! });
 //! strategy.add_request(PartitionRequest {
 //!     size: SizeRequirement::Remaining, // Rest for root
+//!     name: None,
 //!     attributes: None,
 //! });
 //! ```
 
-use crate::planner::{PlanError, Planner};
+use log::warn;
+
+use crate::planner::{Change, PARTITION_ALIGNMENT, PlanError, Planner, Unit};
 
-use crate::PartitionAttributes;
 use crate::planner::Region;
+use crate::{GptAttributes, PartitionAttributes, TableAttributes};
 
 /// Strategy for allocating partitions
 #[derive(Debug, Clone)]
@@ -44,7 +48,105 @@ pub enum AllocationStrategy {
     /// Use first free region that fits on existing table
     FirstFit,
     /// Use specific region on existing table
-    SpecificRegion(Region),
+    SpecificRegion(Box<Region>),
+    /// Spread requests across all free regions on existing table.
+    /// Each partition still lives within a single region.
+    BestFitAcrossRegions,
+    /// Use the free space after the last partition on existing table,
+    /// leaving any gaps between existing partitions untouched
+    AppendAfterLast,
+    /// Initialize the whole disk and recreate the partitions of a reference disk.
+    /// Any partition requests on the strategy are ignored.
+    Clone {
+        /// Partitions of the reference disk
+        from: Vec<ExistingPartition>,
+        /// Size of the reference disk in bytes
+        source_size: u64,
+        /// How to use any extra space on a larger target disk
+        growth: CloneGrowth,
+    },
+}
+
+impl AllocationStrategy {
+    /// A short, stable name for this allocation method
+    pub fn name(&self) -> &'static str {
+        match self {
+            AllocationStrategy::InitializeWholeDisk => "initialize-whole-disk",
+            AllocationStrategy::LargestFree => "largest-free",
+            AllocationStrategy::FirstFit => "first-fit",
+            AllocationStrategy::SpecificRegion(_) => "specific-region",
+            AllocationStrategy::BestFitAcrossRegions => "best-fit-across-regions",
+            AllocationStrategy::AppendAfterLast => "append-after-last",
+            AllocationStrategy::Clone { .. } => "clone",
+        }
+    }
+}
+
+/// A partition on a reference disk, to be reproduced by [`AllocationStrategy::Clone`]
+#[derive(Debug, Clone)]
+pub struct ExistingPartition {
+    /// The absolute start position of the partition in bytes
+    pub start: u64,
+    /// The absolute end position of the partition in bytes
+    pub end: u64,
+    /// Type GUID, name, role and filesystem to carry over
+    pub attributes: Option<PartitionAttributes>,
+}
+
+impl ExistingPartition {
+    /// The partition in `region`, on a disk with the given logical block size
+    pub fn from_region(region: Region, logical_block_size: u64) -> Self {
+        let region = region.to_unit(Unit::Bytes, logical_block_size);
+        Self {
+            start: region.start,
+            end: region.end,
+            attributes: region.attributes,
+        }
+    }
+}
+
+/// Determines how a cloned layout uses a target disk larger than the reference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CloneGrowth {
+    /// Scale every partition proportionally to the target disk size
+    #[default]
+    Proportional,
+    /// Keep partition offsets and grow the last partition to the end of the disk
+    GrowLast,
+    /// Keep partition offsets and leave the extra space unallocated
+    LeaveFree,
+}
+
+/// Base size of an EFI System Partition before accounting for kernels
+const EFI_BASE_SIZE: u64 = 256 * 1024 * 1024;
+/// Space reserved on the EFI System Partition for each installed kernel
+const EFI_SIZE_PER_KERNEL: u64 = 64 * 1024 * 1024;
+/// Smallest recommended EFI System Partition
+const EFI_MIN_SIZE: u64 = 100 * 1024 * 1024;
+/// Largest recommended EFI System Partition
+const EFI_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Number of kernels assumed when no explicit count is known
+pub const DEFAULT_KERNEL_COUNT: usize = 4;
+
+/// Recommended size in bytes of an EFI System Partition holding `num_kernels` kernels
+///
+/// Scales at 64MiB per kernel on top of a 256MiB base, clamped to between 100MiB and 2GiB.
+/// With [`DEFAULT_KERNEL_COUNT`] kernels this is the customary 512MiB.
+pub fn recommended_efi_size(num_kernels: usize) -> u64 {
+    EFI_BASE_SIZE
+        .saturating_add(EFI_SIZE_PER_KERNEL.saturating_mul(num_kernels as u64))
+        .clamp(EFI_MIN_SIZE, EFI_MAX_SIZE)
+}
+
+/// Outcome of checking whether a strategy fits on a planner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FitReport {
+    /// Whether every request can be satisfied
+    pub fits: bool,
+    /// Free space in bytes left after applying the strategy, or the current
+    /// free space if it does not fit
+    pub remaining: u64,
 }
 
 /// Defines how to size a partition within its allocated region
@@ -56,22 +158,75 @@ pub enum SizeRequirement {
     AtLeast(u64),
     /// Between min and max bytes
     Range { min: u64, max: u64 },
+    /// Up to max bytes, using less if that is all that is available
+    AtMost(u64),
     /// Use all remaining space
     Remaining,
 }
 
+impl SizeRequirement {
+    /// The smallest size in bytes that satisfies this requirement
+    pub fn minimum(&self) -> u64 {
+        match self {
+            SizeRequirement::Exact(size) => *size,
+            SizeRequirement::AtLeast(min) | SizeRequirement::Range { min, .. } => *min,
+            SizeRequirement::AtMost(_) | SizeRequirement::Remaining => 0,
+        }
+    }
+
+    /// Convert this requirement to `unit` on a disk with the given logical block size
+    ///
+    /// Sizes are rounded up to whole units, so a partition is never smaller than requested.
+    pub fn to_unit(&self, unit: Unit, logical_block_size: u64) -> SizeRequirement {
+        let convert = |bytes: u64| bytes.div_ceil(unit.size(logical_block_size));
+        match self {
+            SizeRequirement::Exact(size) => SizeRequirement::Exact(convert(*size)),
+            SizeRequirement::AtLeast(min) => SizeRequirement::AtLeast(convert(*min)),
+            SizeRequirement::Range { min, max } => SizeRequirement::Range {
+                min: convert(*min),
+                max: convert(*max),
+            },
+            SizeRequirement::AtMost(max) => SizeRequirement::AtMost(convert(*max)),
+            SizeRequirement::Remaining => SizeRequirement::Remaining,
+        }
+    }
+}
+
 /// A partition request for the strategy to plan
 #[derive(Debug, Clone)]
 pub struct PartitionRequest {
     pub size: SizeRequirement,
+    /// Partition name, used when no explicit attributes are given
+    pub name: Option<String>,
     pub attributes: Option<PartitionAttributes>,
 }
 
+impl PartitionRequest {
+    /// Attributes for the planned partition, falling back to a GPT name-only set
+    fn resolved_attributes(&self) -> Option<PartitionAttributes> {
+        match (&self.attributes, &self.name) {
+            (Some(attributes), _) => Some(attributes.clone()),
+            (None, Some(name)) => Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes {
+                    name: Some(name.clone()),
+                    ..Default::default()
+                }),
+                role: None,
+                filesystem: None,
+                encryption: None,
+            }),
+            (None, None) => None,
+        }
+    }
+}
+
 /// Handles planning partition layouts according to specific strategies
 #[derive(Debug, Clone)]
 pub struct Strategy {
     allocation: AllocationStrategy,
     requests: Vec<PartitionRequest>,
+    name: Option<String>,
+    alignment: Option<u64>,
 }
 
 impl Strategy {
@@ -80,49 +235,85 @@ impl Strategy {
         Self {
             allocation,
             requests: Vec::new(),
+            name: None,
+            alignment: None,
+        }
+    }
+
+    /// Align partitions to `alignment_bytes` rather than the planner's alignment
+    ///
+    /// The planner's alignment, 1MiB unless changed, is restored once the
+    /// strategy has been applied. Zero places partitions without alignment.
+    pub fn with_alignment(self, alignment_bytes: u64) -> Self {
+        Self {
+            alignment: Some(alignment_bytes),
+            ..self
+        }
+    }
+
+    /// Name this strategy, e.g. after the provisioning strategy it implements
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Compose this strategy with `next`, which is applied once this one has been
+    ///
+    /// For example [`AllocationStrategy::InitializeWholeDisk`] for the system
+    /// partitions followed by [`AllocationStrategy::LargestFree`] for the rest.
+    pub fn then(self, next: Strategy) -> ComposedStrategy {
+        ComposedStrategy {
+            strategies: vec![self, next],
         }
     }
 
+    /// The name of this strategy, defaulting to the name of its allocation method
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.allocation.name())
+    }
+
+    /// The allocation method used by this strategy
+    pub fn allocation(&self) -> &AllocationStrategy {
+        &self.allocation
+    }
+
     /// Add a partition request to this strategy
     pub fn add_request(&mut self, request: PartitionRequest) {
         self.requests.push(request);
     }
 
-    /// Find available free regions on the disk
-    fn find_free_regions(&self, planner: &Planner) -> Vec<Region> {
-        let mut regions = Vec::new();
-        let (mut current, disk_size) = planner.offsets();
+    /// Find the free region following the last partition on the disk
+    fn find_trailing_region(&self, planner: &Planner) -> Result<Region, PlanError> {
+        let (usable_start, disk_size) = planner.aligned_offsets();
+        let last_end = planner
+            .current_layout()
+            .iter()
+            .map(|r| r.end)
+            .max()
+            .unwrap_or(usable_start)
+            .max(usable_start);
 
-        // Sort existing partitions by start position
-        let mut layout = planner.current_layout();
-        layout.sort_by_key(|r| r.start);
-
-        // Find gaps between partitions
-        for region in layout {
-            if region.start > current {
-                regions.push(Region::new(current, region.start));
-            }
-            current = region.end;
+        if last_end >= disk_size {
+            return Err(PlanError::NoFreeRegions);
         }
-
-        // Add final region if there's space after last partition
-        if current < disk_size {
-            regions.push(Region::new(current, disk_size));
-        }
-
-        regions
+        Ok(planner.region(last_end, disk_size))
     }
 
     /// Get a human readable description of this strategy
     pub fn describe(&self) -> String {
         use disks::format_size;
 
-        let mut desc = match &self.allocation {
-            AllocationStrategy::InitializeWholeDisk => "Initialize new partition layout on entire disk".to_string(),
-            AllocationStrategy::LargestFree => "Use largest free region".to_string(),
-            AllocationStrategy::FirstFit => "Use first available region".to_string(),
-            AllocationStrategy::SpecificRegion(r) => format!("Use specific region: {}", r.describe(r.end - r.start)),
-        };
+        let mut desc = self.describe_allocation();
+
+        match self.alignment {
+            Some(0) => desc.push_str("\nPartitions are not aligned"),
+            Some(alignment) if alignment != PARTITION_ALIGNMENT => {
+                desc.push_str(&format!("\nAligned to {}", format_size(alignment)))
+            }
+            _ => {}
+        }
 
         if !self.requests.is_empty() {
             desc.push_str("\nRequested partitions:\n");
@@ -133,6 +324,7 @@ impl Strategy {
                     SizeRequirement::Range { min, max } => {
                         format!("between {} and {}", format_size(*min), format_size(*max))
                     }
+                    SizeRequirement::AtMost(max) => format!("at most {}", format_size(*max)),
                     SizeRequirement::Remaining => "remaining space".to_string(),
                 };
                 desc.push_str(&format!("  {}: {}\n", i + 1, size_desc));
@@ -141,34 +333,393 @@ impl Strategy {
         desc
     }
 
+    /// Get a human readable description of where this strategy places each partition
+    ///
+    /// Unlike [`Strategy::describe`], which lists the requested sizes, this
+    /// applies the strategy to a scratch copy of `planner` and lists the
+    /// resolved bounds of each new partition. Fails if the strategy cannot be
+    /// applied to the planner.
+    pub fn describe_resolved(&self, planner: &Planner) -> Result<String, PlanError> {
+        use disks::{format_position, format_size};
+
+        let mut scratch = planner.clone();
+        self.apply(&mut scratch)?;
+
+        let existing = Self::added_bounds(planner);
+        let mut added = Self::added_bounds(&scratch)
+            .into_iter()
+            .filter(|bounds| !existing.contains(bounds))
+            .collect::<Vec<_>>();
+        added.sort();
+
+        let total = scratch.to_bytes(scratch.disk_size());
+        let mut desc = self.describe_allocation();
+        desc.push_str("\nResolved partitions:\n");
+        for (i, (start, end)) in added.into_iter().enumerate() {
+            let (start, end) = (scratch.to_bytes(start), scratch.to_bytes(end));
+            desc.push_str(&format!(
+                "  {}: {} to {}, {} (bytes {start}..{end})\n",
+                i + 1,
+                format_position(start, total),
+                format_position(end, total),
+                format_size(end - start),
+            ));
+        }
+        Ok(desc)
+    }
+
+    /// Bounds of every partition planned for addition on a planner
+    fn added_bounds(planner: &Planner) -> Vec<(u64, u64)> {
+        planner
+            .changes()
+            .iter()
+            .filter_map(|change| match change {
+                Change::AddPartition { start, end, .. } => Some((*start, *end)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Describe the allocation method of this strategy
+    fn describe_allocation(&self) -> String {
+        use disks::format_size;
+
+        match &self.allocation {
+            AllocationStrategy::InitializeWholeDisk => "Initialize new partition layout on entire disk".to_string(),
+            AllocationStrategy::LargestFree => "Use largest free region".to_string(),
+            AllocationStrategy::FirstFit => "Use first available region".to_string(),
+            AllocationStrategy::BestFitAcrossRegions => "Spread partitions across free regions".to_string(),
+            AllocationStrategy::AppendAfterLast => "Append after the last partition".to_string(),
+            AllocationStrategy::SpecificRegion(r) => match r.unit {
+                Unit::Bytes => format!("Use specific region: {}", r.describe_bytes(r.size())),
+                // The sector size is only known once applied to a planner
+                Unit::Sectors => format!("Use specific region: sectors {}..{}", r.start, r.end),
+            },
+            AllocationStrategy::Clone { from, source_size, .. } => format!(
+                "Clone {} partitions from a {} reference disk",
+                from.len(),
+                format_size(*source_size)
+            ),
+        }
+    }
+
+    /// Check whether this strategy can be applied to a planner
+    ///
+    /// Unlike [`Strategy::can_fit`], nothing is planned. This only checks that the
+    /// partition table has room for every request, that any specific region is
+    /// free and within the usable disk, and that the minimum size of every request
+    /// fits the free space the allocation method would use. Requests without a
+    /// minimum still need room for one aligned unit.
+    pub fn validate(&self, planner: &Planner) -> Result<(), PlanError> {
+        if let AllocationStrategy::Clone { from, source_size, .. } = &self.allocation {
+            let available = planner.to_bytes(planner.disk_size());
+            if available < *source_size {
+                return Err(PlanError::TargetTooSmall {
+                    required: *source_size,
+                    available,
+                });
+            }
+            if from.len() > planner.max_partitions() {
+                return Err(PlanError::TooManyPartitions {
+                    max: planner.max_partitions(),
+                });
+            }
+            return Ok(());
+        }
+
+        let requests = self.requests_in_unit(planner);
+        if let AllocationStrategy::InitializeWholeDisk = self.allocation {
+            // The existing partitions are cleared first
+            if requests.len() > planner.max_partitions() {
+                return Err(PlanError::TooManyPartitions {
+                    max: planner.max_partitions(),
+                });
+            }
+        } else {
+            Self::check_partition_count(planner, requests.len())?;
+        }
+
+        let regions = self.target_regions(planner)?;
+        if let AllocationStrategy::SpecificRegion(_) = self.allocation {
+            let target = &regions[0];
+            let (usable_start, usable_end) = planner.offsets();
+            if target.start < usable_start || target.end > usable_end || target.end <= target.start {
+                return Err(PlanError::RegionOutOfBounds {
+                    start: target.start,
+                    end: target.end,
+                });
+            }
+            if planner.current_layout().iter().any(|r| r.overlaps_with(target)) {
+                return Err(PlanError::RegionOverlap {
+                    start: target.start,
+                    end: target.end,
+                });
+            }
+        }
+
+        let assigned = match self.allocation {
+            AllocationStrategy::BestFitAcrossRegions => Self::assign_regions(&regions, &requests)?,
+            _ => vec![(0..requests.len()).collect()],
+        };
+        for (region, indices) in regions.iter().zip(assigned) {
+            // Every partition takes up at least one alignment unit, whatever its minimum
+            let required = indices
+                .iter()
+                .map(|i| requests[*i].size.minimum().max(planner.alignment()))
+                .sum::<u64>();
+            if required > region.size() {
+                return Err(PlanError::RegionOutOfBounds {
+                    start: region.start,
+                    end: region.start + required,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether this strategy fits on a planner, and how much space it would leave
+    ///
+    /// Runs the same space calculations as [`Strategy::apply`] on a scratch copy of the
+    /// planner, so the caller's planner is never modified. Running out of space is
+    /// reported through [`FitReport::fits`], while any other planning error is returned.
+    pub fn can_fit(&self, planner: &Planner) -> Result<FitReport, PlanError> {
+        let mut scratch = planner.clone();
+        match self.apply(&mut scratch) {
+            Ok(()) => Ok(FitReport {
+                fits: true,
+                remaining: self.free_space(&scratch),
+            }),
+            Err(PlanError::RegionOutOfBounds { .. } | PlanError::NoFreeRegions | PlanError::TargetTooSmall { .. }) => {
+                Ok(FitReport {
+                    fits: false,
+                    remaining: self.free_space(planner),
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Total size in bytes of all free regions on the planner
+    fn free_space(&self, planner: &Planner) -> u64 {
+        planner.free_space_summary().total_free
+    }
+
     /// Apply this strategy to a planner
     /// This will plan the necessary partition changes to fulfill the requirements
     /// Returns an error if the strategy cannot be applied due to insufficient space
-    /// or other constraints
+    /// or other constraints, in which case the planner is left as it was before the call
     pub fn apply(&self, planner: &mut Planner) -> Result<(), PlanError> {
-        // Determine the target region for our partitions
-        let target = match &self.allocation {
+        // Roll back everything planned so far if any request can't be satisfied
+        let state = planner.snapshot();
+        let previous = self.alignment.map(|alignment| planner.set_alignment_bytes(alignment));
+        let result = self.plan_requests(planner);
+        if let Some(previous) = previous {
+            planner.set_alignment_bytes(previous);
+        }
+        if result.is_err() {
+            planner.restore(state);
+        }
+        result
+    }
+
+    /// Ensure the partition table has room for `count` more partitions
+    fn check_partition_count(planner: &Planner, count: usize) -> Result<(), PlanError> {
+        let max = planner.max_partitions();
+        if planner.current_layout().len() + count > max {
+            return Err(PlanError::TooManyPartitions { max });
+        }
+        Ok(())
+    }
+
+    /// Recreate a reference layout on a freshly initialized disk
+    fn plan_clone(
+        planner: &mut Planner,
+        from: &[ExistingPartition],
+        source_size: u64,
+        growth: CloneGrowth,
+    ) -> Result<(), PlanError> {
+        planner.plan_initialize_disk()?;
+        // The reference layout is in bytes, whatever unit the planner works in
+        let target_size = planner.to_bytes(planner.disk_size());
+        let usable_end = planner.to_bytes(planner.aligned_offsets().1);
+        if target_size < source_size {
+            return Err(PlanError::TargetTooSmall {
+                required: source_size,
+                available: target_size,
+            });
+        }
+
+        Self::check_partition_count(planner, from.len())?;
+
+        // Scale an absolute offset from the reference disk onto the target disk
+        let scale = |offset: u64| match source_size {
+            0 => offset,
+            _ => (u128::from(offset) * u128::from(target_size) / u128::from(source_size)) as u64,
+        };
+
+        let mut partitions = from.to_vec();
+        partitions.sort_by_key(|p| p.start);
+        let last = partitions.len().saturating_sub(1);
+
+        for (i, partition) in partitions.into_iter().enumerate() {
+            let (start, end) = match growth {
+                CloneGrowth::Proportional => (scale(partition.start), scale(partition.end).min(usable_end)),
+                CloneGrowth::GrowLast if i == last => (partition.start, usable_end),
+                CloneGrowth::GrowLast | CloneGrowth::LeaveFree => (partition.start, partition.end),
+            };
+            planner.plan_add_partition_with_attributes(
+                planner.from_bytes(start),
+                planner.from_bytes(end),
+                partition.attributes,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Plan the partition changes for every request, without cleaning up on failure
+    fn plan_requests(&self, planner: &mut Planner) -> Result<(), PlanError> {
+        let requests = self.requests_in_unit(planner);
+
+        match &self.allocation {
             AllocationStrategy::InitializeWholeDisk => {
                 // Clear existing partitions and start fresh
                 planner.plan_initialize_disk()?;
-                let (start, end) = planner.offsets();
-                Region::new(start, end)
             }
-            AllocationStrategy::LargestFree => {
-                let free_regions = self.find_free_regions(planner);
-                free_regions
-                    .iter()
-                    .max_by_key(|r| r.size())
-                    .cloned()
-                    .ok_or(PlanError::NoFreeRegions)?
+            AllocationStrategy::BestFitAcrossRegions => return Self::plan_across_regions(planner, &requests),
+            AllocationStrategy::Clone {
+                from,
+                source_size,
+                growth,
+            } => return Self::plan_clone(planner, from, *source_size, *growth),
+            _ => {}
+        }
+
+        let target = self.target_regions(planner)?.remove(0);
+        Self::check_partition_count(planner, requests.len())?;
+        Self::plan_in_region(planner, &target, &requests.iter().collect::<Vec<_>>())
+    }
+
+    /// The regions the allocation method places partitions in, in the planner's unit
+    ///
+    /// This is a single region for every method but [`AllocationStrategy::BestFitAcrossRegions`].
+    /// Methods that initialize the disk use the whole usable disk, as if the
+    /// partition table had already been cleared.
+    fn target_regions(&self, planner: &Planner) -> Result<Vec<Region>, PlanError> {
+        let region = match &self.allocation {
+            AllocationStrategy::InitializeWholeDisk | AllocationStrategy::Clone { .. } => {
+                let (start, end) = planner.aligned_offsets();
+                planner.region(start, end)
             }
-            AllocationStrategy::FirstFit => {
-                let free_regions = self.find_free_regions(planner);
-                free_regions.first().cloned().ok_or(PlanError::NoFreeRegions)?
+            AllocationStrategy::LargestFree => planner
+                .free_regions()
+                .into_iter()
+                .max_by_key(|r| r.size())
+                .ok_or(PlanError::NoFreeRegions)?,
+            AllocationStrategy::FirstFit => planner
+                .free_regions()
+                .into_iter()
+                .next()
+                .ok_or(PlanError::NoFreeRegions)?,
+            AllocationStrategy::SpecificRegion(region) => region.to_unit(planner.unit(), planner.logical_block_size()),
+            AllocationStrategy::AppendAfterLast => self.find_trailing_region(planner)?,
+            AllocationStrategy::BestFitAcrossRegions => {
+                let regions = planner.free_regions();
+                if regions.is_empty() {
+                    return Err(PlanError::NoFreeRegions);
+                }
+                return Ok(regions);
             }
-            AllocationStrategy::SpecificRegion(region) => region.clone(),
         };
+        Ok(vec![region])
+    }
+
+    /// The partition requests with their sizes converted from bytes to the planner's unit
+    fn requests_in_unit(&self, planner: &Planner) -> Vec<PartitionRequest> {
+        self.requests
+            .iter()
+            .map(|request| PartitionRequest {
+                size: request.size.to_unit(planner.unit(), planner.logical_block_size()),
+                ..request.clone()
+            })
+            .collect()
+    }
+
+    /// Assign each request to the smallest free region that can hold its minimum size,
+    /// largest requests first, then plan every region independently
+    fn plan_across_regions(planner: &mut Planner, requests: &[PartitionRequest]) -> Result<(), PlanError> {
+        Self::check_partition_count(planner, requests.len())?;
+
+        let regions = planner.free_regions();
+        if regions.is_empty() {
+            return Err(PlanError::NoFreeRegions);
+        }
+        let assigned = Self::assign_regions(&regions, requests)?;
+
+        for (region, indices) in regions.iter().zip(assigned) {
+            if indices.is_empty() {
+                continue;
+            }
+            let requests = indices.iter().map(|i| &requests[*i]).collect::<Vec<_>>();
+            Self::plan_in_region(planner, region, &requests)?;
+        }
 
+        Ok(())
+    }
+
+    /// Assign each request to a region by its minimum size, returning the request
+    /// indices for every region in the requested order
+    fn assign_regions(regions: &[Region], requests: &[PartitionRequest]) -> Result<Vec<Vec<usize>>, PlanError> {
+        let mut capacity = regions.iter().map(|r| r.size()).collect::<Vec<_>>();
+        let mut assigned = vec![Vec::new(); regions.len()];
+
+        let mut order = (0..requests.len()).collect::<Vec<_>>();
+        order.sort_by_key(|idx| std::cmp::Reverse(requests[*idx].size.minimum()));
+
+        for idx in order {
+            let min = requests[idx].size.minimum();
+            let region = if min == 0 {
+                // Requests without a minimum take whatever is left in the roomiest region
+                (0..regions.len()).max_by_key(|i| capacity[*i])
+            } else {
+                (0..regions.len())
+                    .filter(|i| capacity[*i] >= min)
+                    .min_by_key(|i| capacity[*i])
+            };
+            let Some(region) = region else {
+                warn!("No free region can hold a partition of size {min}");
+                return Err(PlanError::NoFreeRegions);
+            };
+            capacity[region] -= min;
+            assigned[region].push(idx);
+        }
+
+        // Keep the requested order within each region
+        for indices in &mut assigned {
+            indices.sort_unstable();
+        }
+        Ok(assigned)
+    }
+
+    /// Plan a partition, returning where it ends once the planner has aligned it
+    ///
+    /// Continuing from the aligned end, rather than the requested one, keeps the
+    /// next partition from being rounded back over this one.
+    fn plan_partition(
+        planner: &mut Planner,
+        start: u64,
+        end: u64,
+        attributes: Option<PartitionAttributes>,
+    ) -> Result<u64, PlanError> {
+        planner.plan_add_partition_with_attributes(start, end, attributes)?;
+        match planner.changes().back() {
+            Some(Change::AddPartition { end, .. }) => Ok(*end),
+            _ => Ok(end),
+        }
+    }
+
+    /// Plan the given requests, sized in the planner's unit, within a single region
+    fn plan_in_region(planner: &mut Planner, target: &Region, requests: &[&PartitionRequest]) -> Result<(), PlanError> {
         let mut current = target.start;
         let mut remaining = target.end - target.start;
 
@@ -177,7 +728,7 @@ impl Strategy {
         let mut min_flexible = 0u64;
 
         // First pass: Calculate space requirements
-        for (current_idx, request) in self.requests.iter().enumerate() {
+        for (current_idx, request) in requests.iter().enumerate() {
             match &request.size {
                 SizeRequirement::Exact(size) => total_fixed += size,
                 SizeRequirement::AtLeast(min) => {
@@ -188,6 +739,9 @@ impl Strategy {
                     min_flexible += min;
                     flexible_requests.push((current_idx, *min, Some(*max)));
                 }
+                SizeRequirement::AtMost(max) => {
+                    flexible_requests.push((current_idx, 0, Some(*max)));
+                }
                 SizeRequirement::Remaining => {
                     flexible_requests.push((current_idx, 0, None));
                 }
@@ -203,25 +757,22 @@ impl Strategy {
         }
 
         // First pass: allocate exact size partitions
-        for request in &self.requests {
+        for request in requests {
             if let SizeRequirement::Exact(size) = request.size {
-                planner.plan_add_partition_with_attributes(current, current + size, request.attributes.clone())?;
-                current += size;
-                remaining -= size;
+                current = Self::plan_partition(planner, current, current + size, request.resolved_attributes())?;
+                remaining = target.end.saturating_sub(current);
             }
         }
 
         // Second pass: allocate flexible partitions
         let mut remaining_flexible = flexible_requests.len();
+        let mut pending_min = min_flexible;
         for (idx, min, max_opt) in &flexible_requests {
             remaining_flexible -= 1;
+            pending_min -= min;
 
             // First verify we have enough space for minimum requirement
             if *min > remaining {
-                // Clean up any changes we made since we can't complete all requests
-                while planner.has_changes() {
-                    planner.undo();
-                }
                 return Err(PlanError::RegionOutOfBounds {
                     start: current,
                     end: current + min,
@@ -237,41 +788,88 @@ impl Strategy {
                     size.max(*min)
                 }
             } else {
-                // Other flexible partitions get fair share plus minimum
-                let share = remaining / (remaining_flexible + 1) as u64;
+                // Other flexible partitions get fair share plus minimum, without
+                // eating into the space reserved for the minimums of later requests
+                let spare = remaining.saturating_sub(min + pending_min);
+                let share = spare / (remaining_flexible + 1) as u64;
                 let size = min + share;
-                if let Some(max) = max_opt { size.min(*max) } else { size }
+                let size = if let Some(max) = max_opt { size.min(*max) } else { size };
+
+                // End on an alignment boundary, so the planner never rounds it up
+                // into the space reserved for later requests
+                let alignment = planner.alignment();
+                let end = (current + size) / alignment * alignment;
+                if end > current && end - current >= *min {
+                    end - current
+                } else {
+                    size
+                }
             };
 
-            match planner.plan_add_partition_with_attributes(
+            current = Self::plan_partition(
+                planner,
                 current,
                 current + size,
-                self.requests.get(*idx).and_then(|r| r.attributes.clone()),
-            ) {
-                Ok(_) => {
-                    current += size;
-                    remaining -= size;
-                }
-                Err(e) => {
-                    // Clean up any changes we made since we can't complete all requests
-                    while planner.has_changes() {
-                        planner.undo();
-                    }
-                    return Err(e);
-                }
-            }
+                requests.get(*idx).and_then(|r| r.resolved_attributes()),
+            )?;
+            remaining = target.end.saturating_sub(current);
         }
 
         Ok(())
     }
 }
 
+/// Strategies applied to a planner one after another, created with [`Strategy::then`]
+#[derive(Debug, Clone)]
+pub struct ComposedStrategy {
+    strategies: Vec<Strategy>,
+}
+
+impl ComposedStrategy {
+    /// Append `next`, to be applied after every strategy already composed
+    pub fn then(mut self, next: Strategy) -> Self {
+        self.strategies.push(next);
+        self
+    }
+
+    /// The composed strategies, in the order they are applied
+    pub fn strategies(&self) -> &[Strategy] {
+        &self.strategies
+    }
+
+    /// Get a human readable description of each strategy, in order
+    pub fn describe(&self) -> String {
+        self.strategies
+            .iter()
+            .enumerate()
+            .map(|(i, strategy)| format!("Step {}: {}", i + 1, strategy.describe()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Apply each strategy to the planner in turn
+    ///
+    /// If any strategy cannot be applied, the changes planned by the earlier
+    /// ones are rolled back and the planner is left as it was before the call.
+    pub fn apply(&self, planner: &mut Planner) -> Result<(), PlanError> {
+        let state = planner.snapshot();
+        let result = self.strategies.iter().try_for_each(|strategy| strategy.apply(planner));
+        if result.is_err() {
+            planner.restore(state);
+        }
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::planner::Planner;
+    use crate::planner::{DEFAULT_MAX_PARTITIONS, Planner};
+    use crate::{GptAttributes, TableAttributes};
     use disks::{BlockDevice, mock::MockDisk};
+    use gpt::partition_types;
     use test_log::test;
+    use types::PartitionRole;
 
     const MB: u64 = 1024 * 1024;
     const GB: u64 = 1024 * MB;
@@ -284,58 +882,49 @@ mod tests {
     const ROOT_MIN: u64 = 20 * GB; // Minimum root partition size
     const ROOT_MAX: u64 = 100 * GB; // Maximum root partition size
 
-    /// Creates a root partition request that uses remaining space with a minimum size
-    fn root_partition() -> PartitionRequest {
+    /// Creates an unnamed partition request without attributes
+    fn request(size: SizeRequirement) -> PartitionRequest {
         PartitionRequest {
-            size: SizeRequirement::AtLeast(ROOT_MIN),
+            size,
+            name: None,
             attributes: None,
         }
     }
 
+    /// Creates a root partition request that uses remaining space with a minimum size
+    fn root_partition() -> PartitionRequest {
+        request(SizeRequirement::AtLeast(ROOT_MIN))
+    }
+
     /// Creates a root partition request capped at 100GB, suitable for layouts with home partition
     fn capped_root_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Range {
-                min: ROOT_MIN,
-                max: ROOT_MAX,
-            },
-            attributes: None,
-        }
+        request(SizeRequirement::Range {
+            min: ROOT_MIN,
+            max: ROOT_MAX,
+        })
     }
 
     /// Creates a standard EFI system partition request
     fn efi_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Exact(EFI_SIZE),
-            attributes: None,
-        }
+        request(SizeRequirement::Exact(EFI_SIZE))
     }
 
     /// Creates a /boot partition request
     fn boot_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Exact(BOOT_SIZE),
-            attributes: None,
-        }
+        request(SizeRequirement::Exact(BOOT_SIZE))
     }
 
     /// Creates a swap partition request that scales with system RAM
     fn swap_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Range {
-                min: SWAP_MIN,
-                max: SWAP_MAX,
-            },
-            attributes: None,
-        }
+        request(SizeRequirement::Range {
+            min: SWAP_MIN,
+            max: SWAP_MAX,
+        })
     }
 
     /// Creates a home partition request that uses all remaining space
     fn home_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Remaining,
-            attributes: None,
-        }
+        request(SizeRequirement::Remaining)
     }
     fn create_test_disk() -> MockDisk {
         MockDisk::new(500 * GB)
@@ -403,17 +992,519 @@ mod tests {
 
         // Simple layout - just boot and root
         strategy.add_request(boot_partition());
+        strategy.add_request(request(SizeRequirement::Remaining));
+
+        eprintln!("\nMinimal Server Strategy:\n{}", strategy.describe());
+        assert!(strategy.apply(&mut planner).is_ok());
+        eprintln!("{}", planner.describe_changes());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+    }
+
+    #[test]
+    fn test_append_after_last() {
+        let mut disk = create_test_disk();
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(200 * MB, 300 * MB);
+
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let original = planner.current_layout();
+        let last_end = original.iter().map(|r| r.end).max().unwrap();
+
+        let mut strategy = Strategy::new(AllocationStrategy::AppendAfterLast);
+        strategy.add_request(swap_partition());
+        strategy.add_request(root_partition());
+        strategy.apply(&mut planner).unwrap();
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 4);
+
+        // Existing partitions are untouched
+        let bounds = |regions: &[Region]| {
+            regions
+                .iter()
+                .map(|r| (r.start, r.end, r.partition_id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bounds(&layout[..2]), bounds(&original));
+
+        // New partitions follow the last one, even though there is a gap before it
+        assert!(layout[2].start >= last_end);
+        assert!(layout[3].start >= layout[2].end);
+    }
+
+    #[test]
+    fn test_append_after_last_no_space() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let (start, end) = planner.aligned_offsets();
+        planner.plan_add_partition(start, end).unwrap();
+
+        let mut strategy = Strategy::new(AllocationStrategy::AppendAfterLast);
+        strategy.add_request(root_partition());
+        assert!(matches!(strategy.apply(&mut planner), Err(PlanError::NoFreeRegions)));
+    }
+
+    #[test]
+    fn test_at_most() {
+        let capped = || request(SizeRequirement::AtMost(8 * GB));
+
+        // Plenty of room: capped at the maximum
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(capped());
+        assert!(strategy.describe().contains("at most 8.0GiB"));
+        strategy.apply(&mut planner).unwrap();
+        assert_eq!(planner.current_layout()[0].size(), 8 * GB);
+
+        // Short on space: takes what is left rather than failing
+        let disk = MockDisk::new(4 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(capped());
+        strategy.apply(&mut planner).unwrap();
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 1);
+        let (start, end) = planner.aligned_offsets();
+        assert_eq!(layout[0].size(), end - start);
+    }
+
+    #[test]
+    fn test_can_fit() {
+        let planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let (start, end) = planner.aligned_offsets();
+
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(request(SizeRequirement::Exact(100 * GB)));
+        let report = strategy.can_fit(&planner).unwrap();
+        assert!(report.fits);
+        assert_eq!(report.remaining, end - start - 100 * GB);
+
+        strategy.add_request(request(SizeRequirement::AtLeast(450 * GB)));
+        let report = strategy.can_fit(&planner).unwrap();
+        assert!(!report.fits);
+        assert_eq!(report.remaining, end - start);
+
+        // The planner is only ever inspected
+        assert!(!planner.has_changes());
+        assert!(planner.current_layout().is_empty());
+
+        // Remaining space is reported in bytes when planning in sectors
+        let planner = Planner::new_with_units(&BlockDevice::mock_device(create_test_disk()), Unit::Sectors);
+        let (start, end) = planner.aligned_offsets();
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(request(SizeRequirement::Exact(100 * GB)));
+        let report = strategy.can_fit(&planner).unwrap();
+        assert!(report.fits);
+        assert_eq!(report.remaining, (end - start) * 512 - 100 * GB);
+    }
+
+    #[test]
+    fn test_validate() {
+        let request = |size| PartitionRequest {
+            size,
+            name: None,
+            attributes: None,
+        };
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(request(SizeRequirement::Exact(100 * GB)));
+        strategy.add_request(request(SizeRequirement::Remaining));
+        assert!(strategy.validate(&planner).is_ok());
+        strategy.add_request(request(SizeRequirement::AtLeast(450 * GB)));
+        assert!(matches!(
+            strategy.validate(&planner),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+
+        // Specific regions must be free and within the usable disk
+        let specific = |start, end| {
+            let mut strategy = Strategy::new(AllocationStrategy::SpecificRegion(Box::new(Region::new(start, end))));
+            strategy.add_request(request(SizeRequirement::Remaining));
+            strategy.validate(&planner)
+        };
+        assert!(specific(20 * GB, 30 * GB).is_ok());
+        assert!(matches!(
+            specific(5 * GB, 30 * GB),
+            Err(PlanError::RegionOverlap { .. })
+        ));
+        assert!(matches!(specific(0, GB), Err(PlanError::RegionOutOfBounds { .. })));
+
+        // Nothing is ever planned
+        assert_eq!(planner.changes().len(), 1);
+    }
+
+    #[test]
+    fn test_sector_units() {
+        let exact = |size| request(SizeRequirement::Exact(size));
+
+        // Requested sizes are bytes, and are rounded up to whole sectors of the device
+        for block_size in [512, 4096] {
+            let disk = create_test_disk().with_logical_block_size(block_size);
+            let mut planner = Planner::new_with_units(&BlockDevice::mock_device(disk), Unit::Sectors);
+            let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+            strategy.add_request(exact(EFI_SIZE));
+            strategy.add_request(exact(3 * MB / 2 + 1));
+            strategy.apply(&mut planner).unwrap();
+
+            let layout = planner.current_layout();
+            assert_eq!(layout[0].start, MB / block_size);
+            assert_eq!(layout[0].size(), EFI_SIZE / block_size);
+            assert_eq!(layout[1].size(), (3 * MB / 2).div_ceil(block_size) + 1);
+        }
+
+        // A specific region given in bytes is placed at the same bytes
+        let mut planner = Planner::new_with_units(&BlockDevice::mock_device(create_test_disk()), Unit::Sectors);
+        let mut strategy = Strategy::new(AllocationStrategy::SpecificRegion(Box::new(Region::new(GB, 2 * GB))));
+        strategy.add_request(exact(EFI_SIZE));
+        strategy.apply(&mut planner).unwrap();
+        let region = planner.current_layout()[0].to_unit(Unit::Bytes, 512);
+        assert_eq!((region.start, region.end), (GB, GB + EFI_SIZE));
+    }
+
+    #[test]
+    fn test_with_alignment() {
+        let layout = |strategy: Strategy| {
+            let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+            strategy.apply(&mut planner).unwrap();
+            assert_eq!(planner.alignment_bytes(), PARTITION_ALIGNMENT);
+            planner
+                .current_layout()
+                .iter()
+                .map(|r| (r.start, r.end))
+                .collect::<Vec<_>>()
+        };
+        let strategy = |sizes: &[u64]| {
+            let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+            for size in sizes {
+                strategy.add_request(request(SizeRequirement::Exact(*size)));
+            }
+            strategy
+        };
+
+        assert_eq!(
+            layout(strategy(&[8 * MB, 3 * MB])),
+            vec![(MB, 9 * MB), (9 * MB, 12 * MB)]
+        );
+        assert_eq!(
+            layout(strategy(&[8 * MB, 3 * MB]).with_alignment(4 * MB)),
+            vec![(4 * MB, 12 * MB), (12 * MB, 16 * MB)]
+        );
+        // Unaligned partitions start right after the GPT
+        let gpt_end = 34 * 512;
+        assert_eq!(
+            layout(strategy(&[MB + 4096]).with_alignment(0)),
+            vec![(gpt_end, gpt_end + MB + 4096)]
+        );
+
+        assert!(
+            !strategy(&[])
+                .with_alignment(PARTITION_ALIGNMENT)
+                .describe()
+                .contains("lign")
+        );
+        assert!(
+            strategy(&[])
+                .with_alignment(4 * MB)
+                .describe()
+                .contains("Aligned to 4.0MiB")
+        );
+        assert!(strategy(&[]).with_alignment(0).describe().contains("not aligned"));
+    }
+
+    #[test]
+    fn test_describe_resolved() {
+        let planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(efi_partition());
+        strategy.add_request(swap_partition());
+        strategy.add_request(root_partition());
+
+        let desc = strategy.describe_resolved(&planner).unwrap();
+        eprintln!("{desc}");
+        assert!(desc.starts_with("Initialize new partition layout on entire disk\nResolved partitions:\n"));
+
+        let bounds = desc
+            .lines()
+            .filter_map(|line| {
+                line.split_once("(bytes ")
+                    .map(|(_, bounds)| bounds.trim_end_matches(')'))
+            })
+            .map(|bounds| {
+                let (start, end) = bounds.split_once("..").unwrap();
+                (start.parse::<u64>().unwrap(), end.parse::<u64>().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(bounds.len(), 3);
+        assert_eq!(bounds[0].1 - bounds[0].0, EFI_SIZE);
+        assert!(bounds.iter().all(|(start, end)| start < end));
+        assert!(bounds.windows(2).all(|pair| pair[0].1 <= pair[1].0));
+
+        // The planner itself is left untouched
+        assert!(!planner.has_changes());
+
+        strategy.add_request(request(SizeRequirement::AtLeast(600 * GB)));
+        assert!(strategy.describe_resolved(&planner).is_err());
+    }
+
+    #[test]
+    fn test_recommended_efi_size() {
+        assert_eq!(recommended_efi_size(0), 256 * MB);
+        assert_eq!(recommended_efi_size(1), 320 * MB);
+        assert_eq!(recommended_efi_size(DEFAULT_KERNEL_COUNT), 512 * MB);
+        assert_eq!(recommended_efi_size(5), 576 * MB);
+        assert_eq!(recommended_efi_size(20), 1536 * MB);
+        assert_eq!(recommended_efi_size(100), 2 * GB);
+        assert_eq!(recommended_efi_size(usize::MAX), 2 * GB);
+    }
+
+    /// Creates a reference layout of EFI, swap and root on a 100GB disk
+    fn reference_layout() -> Vec<ExistingPartition> {
+        let attributes = |type_guid, role| {
+            Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes {
+                    type_guid,
+                    name: None,
+                    uuid: None,
+                    ..Default::default()
+                }),
+                role: Some(role),
+                filesystem: None,
+                encryption: None,
+            })
+        };
+
+        vec![
+            ExistingPartition {
+                start: MB,
+                end: MB + EFI_SIZE,
+                attributes: attributes(partition_types::EFI, PartitionRole::Boot),
+            },
+            ExistingPartition {
+                start: MB + EFI_SIZE,
+                end: MB + EFI_SIZE + SWAP_MIN,
+                attributes: attributes(partition_types::LINUX_SWAP, PartitionRole::Swap),
+            },
+            ExistingPartition {
+                start: MB + EFI_SIZE + SWAP_MIN,
+                end: 100 * GB,
+                attributes: attributes(partition_types::LINUX_FS, PartitionRole::Root),
+            },
+        ]
+    }
+
+    fn clone_strategy(growth: CloneGrowth) -> Strategy {
+        Strategy::new(AllocationStrategy::Clone {
+            from: reference_layout(),
+            source_size: 100 * GB,
+            growth,
+        })
+    }
+
+    #[test]
+    fn test_best_fit_across_regions() {
+        let disk = MockDisk::new(100 * GB);
+        let device = BlockDevice::mock_device(disk);
+        let mut planner = Planner::new(&device);
+
+        // Leave two 20GB gaps: 10GB..30GB and 60GB..80GB
+        assert!(planner.plan_add_partition(MB, 10 * GB).is_ok());
+        assert!(planner.plan_add_partition(30 * GB, 60 * GB).is_ok());
+        assert!(planner.plan_add_partition(80 * GB, 100 * GB - MB).is_ok());
+
+        let requests = [SizeRequirement::Exact(15 * GB), SizeRequirement::AtLeast(15 * GB)];
+        let strategy_for = |allocation| {
+            let mut strategy = Strategy::new(allocation);
+            for size in &requests {
+                strategy.add_request(request(size.clone()));
+            }
+            strategy
+        };
+
+        // Neither gap alone can hold both partitions
+        assert!(
+            strategy_for(AllocationStrategy::LargestFree)
+                .apply(&mut planner)
+                .is_err()
+        );
+        assert_eq!(planner.current_layout().len(), 3);
+
+        let strategy = strategy_for(AllocationStrategy::BestFitAcrossRegions);
+        assert!(strategy.apply(&mut planner).is_ok());
+        eprintln!("{}", planner.describe_changes());
+
+        let mut layout = planner.current_layout();
+        layout.sort_by_key(|r| r.start);
+        let bounds = layout.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(
+            bounds,
+            vec![
+                (MB, 10 * GB),
+                (10 * GB, 25 * GB),
+                (30 * GB, 60 * GB),
+                (60 * GB, 80 * GB),
+                (80 * GB, 100 * GB - MB),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_best_fit_across_regions_too_large() {
+        let disk = MockDisk::new(100 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        assert!(planner.plan_add_partition(20 * GB, 80 * GB).is_ok());
+
+        let mut strategy = Strategy::new(AllocationStrategy::BestFitAcrossRegions);
+        strategy.add_request(request(SizeRequirement::Exact(25 * GB)));
+
+        assert!(matches!(strategy.apply(&mut planner), Err(PlanError::NoFreeRegions)));
+        assert_eq!(planner.current_layout().len(), 1);
+    }
+
+    #[test]
+    fn test_named_requests() {
+        let disk = MockDisk::new(100 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
         strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Remaining,
+            size: SizeRequirement::Exact(512 * MB),
+            name: Some("EFI System Partition".into()),
             attributes: None,
         });
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Remaining,
+            name: Some("ignored".into()),
+            attributes: Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes {
+                    type_guid: partition_types::LINUX_FS,
+                    name: Some("root".into()),
+                    uuid: None,
+                    ..Default::default()
+                }),
+                role: None,
+                filesystem: None,
+                encryption: None,
+            }),
+        });
+        strategy.add_request(request(SizeRequirement::Exact(GB)));
 
-        eprintln!("\nMinimal Server Strategy:\n{}", strategy.describe());
+        assert!(strategy.apply(&mut planner).is_ok());
+        let names = planner
+            .current_layout()
+            .iter()
+            .map(|r| r.attributes.as_ref().and_then(|a| a.table.as_gpt()?.name.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![Some("EFI System Partition".to_owned()), None, Some("root".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_too_many_partitions() {
+        let disk = MockDisk::new(500 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        for _ in 0..=DEFAULT_MAX_PARTITIONS {
+            strategy.add_request(request(SizeRequirement::Exact(MB)));
+        }
+
+        assert!(matches!(
+            strategy.apply(&mut planner),
+            Err(PlanError::TooManyPartitions {
+                max: DEFAULT_MAX_PARTITIONS
+            })
+        ));
+        assert!(!planner.has_changes());
+    }
+
+    #[test]
+    fn test_clone_layout() {
+        let device = BlockDevice::mock_device(MockDisk::new(200 * GB));
+        let mut planner = Planner::new(&device);
+        let strategy = clone_strategy(CloneGrowth::GrowLast);
+
+        eprintln!("\nClone Strategy:\n{}", strategy.describe());
         assert!(strategy.apply(&mut planner).is_ok());
         eprintln!("{}", planner.describe_changes());
 
         let layout = planner.current_layout();
-        assert_eq!(layout.len(), 2);
+        let reference = reference_layout();
+        assert_eq!(layout.len(), 3);
+        assert!(planner.wipe_disk());
+
+        // Scheme is reproduced, with the last partition taking the extra space
+        for (region, original) in layout.iter().zip(reference.iter()) {
+            assert_eq!(region.start, original.start);
+            let gpt = |a: &Option<PartitionAttributes>| a.as_ref().and_then(|a| a.table.as_gpt()).cloned();
+            assert_eq!(
+                gpt(&region.attributes).unwrap().type_guid,
+                gpt(&original.attributes).unwrap().type_guid
+            );
+            assert_eq!(
+                region.attributes.as_ref().unwrap().role,
+                original.attributes.as_ref().unwrap().role
+            );
+        }
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        assert_eq!(layout[1].size(), SWAP_MIN);
+        assert_eq!(layout[2].end, planner.aligned_offsets().1);
+    }
+
+    #[test]
+    fn test_clone_layout_growth() {
+        let device = BlockDevice::mock_device(MockDisk::new(200 * GB));
+
+        let mut planner = Planner::new(&device);
+        assert!(clone_strategy(CloneGrowth::LeaveFree).apply(&mut planner).is_ok());
+        let layout = planner.current_layout();
+        assert_eq!(layout[2].end, 100 * GB);
+
+        let mut planner = Planner::new(&device);
+        assert!(clone_strategy(CloneGrowth::Proportional).apply(&mut planner).is_ok());
+        let layout = planner.current_layout();
+        assert_eq!(layout[0].start, 2 * MB);
+        assert_eq!(layout[0].size(), 2 * EFI_SIZE);
+        assert_eq!(layout[1].size(), 2 * SWAP_MIN);
+        assert_eq!(layout[2].end, planner.aligned_offsets().1);
+    }
+
+    #[test]
+    fn test_clone_layout_smaller_target() {
+        let device = BlockDevice::mock_device(MockDisk::new(50 * GB));
+        let mut planner = Planner::new(&device);
+
+        let result = clone_strategy(CloneGrowth::GrowLast).apply(&mut planner);
+        assert!(matches!(result, Err(PlanError::TargetTooSmall { .. })));
+        assert!(!planner.has_changes());
+        assert!(!planner.wipe_disk());
+    }
+
+    #[test]
+    fn test_then() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut system = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        system.add_request(efi_partition());
+        system.add_request(request(SizeRequirement::Exact(ROOT_MAX)));
+        let mut data = Strategy::new(AllocationStrategy::LargestFree);
+        data.add_request(home_partition());
+
+        let composed = system.clone().then(data.clone());
+        assert_eq!(composed.strategies().len(), 2);
+        composed.apply(&mut planner).unwrap();
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[2].start, layout[1].end);
+
+        // A later strategy that can't be applied rolls back the earlier ones too
+        let mut too_large = Strategy::new(AllocationStrategy::LargestFree);
+        too_large.add_request(request(SizeRequirement::Exact(500 * GB)));
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let result = system.then(data).then(too_large).apply(&mut planner);
+        assert!(result.is_err());
+        assert!(!planner.has_changes());
     }
 
     #[test]
@@ -423,10 +1514,7 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Try to allocate more than available
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Exact(20 * GB),
-            attributes: None,
-        });
+        strategy.add_request(request(SizeRequirement::Exact(20 * GB)));
 
         assert!(strategy.apply(&mut planner).is_err());
     }
@@ -438,14 +1526,8 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Request more than available in flexible partitions
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::AtLeast(6 * GB),
-            attributes: None,
-        });
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::AtLeast(6 * GB),
-            attributes: None,
-        });
+        strategy.add_request(request(SizeRequirement::AtLeast(6 * GB)));
+        strategy.add_request(request(SizeRequirement::AtLeast(6 * GB)));
 
         // Should fail because total minimum (12GB) exceeds disk size (10GB)
         let result = strategy.apply(&mut planner);
@@ -460,24 +1542,15 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Request sequence where first two would fit but third won't
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range { min: GB, max: 2 * GB },
-            attributes: None,
-        });
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range {
-                min: 2 * GB,
-                max: 4 * GB,
-            },
-            attributes: None,
-        });
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range {
-                min: 25 * GB,
-                max: 120 * GB,
-            },
-            attributes: None,
-        });
+        strategy.add_request(request(SizeRequirement::Range { min: GB, max: 2 * GB }));
+        strategy.add_request(request(SizeRequirement::Range {
+            min: 2 * GB,
+            max: 4 * GB,
+        }));
+        strategy.add_request(request(SizeRequirement::Range {
+            min: 25 * GB,
+            max: 120 * GB,
+        }));
 
         // Should fail and undo partial changes
         let result = strategy.apply(&mut planner);
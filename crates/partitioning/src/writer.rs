@@ -5,19 +5,53 @@
 
 use std::{
     fs,
-    io::{self, Seek, Write},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use disks::BlockDevice;
-use gpt::{GptConfig, mbr, partition_types};
+use gpt::{GptConfig, disk::LogicalBlockSize, header::HeaderBuilder, mbr, partition_types};
+use linux_raw_sys::ioctl::BLKSECDISCARD;
+use log::{info, warn};
+use nix::libc;
 use thiserror::Error;
+use types::{GptFlags, MBR_PRIMARY_PARTITION_COUNT, PartitionTableType};
+use uuid::Uuid;
 
 use crate::{
     GptAttributes, blkpg,
     planner::{Change, Planner},
 };
+/// Unit sysfs reports partition offsets and device sizes in, whatever the logical block size
 const SECTOR_SIZE: u64 = 512;
 
+/// MBR partition type of Linux filesystems, used when no GPT type maps to another
+const MBR_TYPE_LINUX: u8 = 0x83;
+
+/// MBR partition type equivalent to a GPT partition type
+fn mbr_partition_type(type_guid: &partition_types::Type) -> u8 {
+    match type_guid.guid {
+        guid if guid == partition_types::EFI.guid => 0xEF,
+        guid if guid == partition_types::LINUX_SWAP.guid => 0x82,
+        _ => MBR_TYPE_LINUX,
+    }
+}
+
+/// An MBR entry whose CHS addresses are marked unused, so only its LBAs count
+fn mbr_chs_unused() -> mbr::PartRecord {
+    mbr::PartRecord {
+        start_head: 0xFE,
+        start_sector: 0xFF,
+        start_track: 0xFF,
+        end_head: 0xFE,
+        end_sector: 0xFF,
+        end_track: 0xFF,
+        ..mbr::PartRecord::zero()
+    }
+}
+
 /// Errors that can occur when writing changes to disk
 #[derive(Debug, Error)]
 pub enum WriteError {
@@ -29,6 +63,33 @@ pub enum WriteError {
     #[error("Duplicate partition ID: {0}")]
     DuplicatePartitionId(u32),
 
+    /// The write was not confirmed for the target device
+    #[error("Write to device was not confirmed")]
+    Unconfirmed,
+
+    /// The partition table type cannot be written by this writer
+    #[error("Unsupported partition table type: {0}")]
+    UnsupportedTableType(PartitionTableType),
+
+    /// A new partition's ID exceeds the number of entries in the partition table
+    #[error("Partition ID {partition_id} does not fit a {table_type} table")]
+    PartitionIdOutOfRange {
+        partition_id: u32,
+        table_type: PartitionTableType,
+    },
+
+    /// A partition lies beyond the sectors an MBR can address
+    #[error("Partition {0} is out of range for an MBR")]
+    MbrOutOfRange(u32),
+
+    /// A metadata change targets a partition that is not in the table
+    #[error("No partition with ID {0}")]
+    PartitionNotFound(u32),
+
+    /// Two partitions in the layout overlap
+    #[error("Partition {partition_a} overlaps partition {partition_b}")]
+    PartitionOverlap { partition_a: u32, partition_b: u32 },
+
     /// Error from GPT library
     #[error("GPT error: {0}")]
     Gpt(#[from] gpt::GptError),
@@ -37,6 +98,10 @@ pub enum WriteError {
     #[error("GPT error: {0}")]
     Mbr(#[from] gpt::mbr::MBRError),
 
+    /// The partition table could not be backed up, so nothing was written
+    #[error("Failed to back up partition table: {0}")]
+    BackupFailed(io::Error),
+
     /// Underlying I/O error
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
@@ -48,6 +113,39 @@ pub struct DiskWriter<'a> {
     pub device: &'a BlockDevice,
     /// The planner containing the changes to apply
     pub planner: &'a Planner,
+    /// Confirmation that the device may be written to
+    confirmation: Option<WriteConfirmation>,
+    /// How long to wait for the kernel to pick up the new partitions
+    sync_timeout: Duration,
+    /// The type of partition table created when initializing the disk
+    table_type: PartitionTableType,
+}
+
+/// Confirms that a specific device may be written to
+///
+/// The token names the target device path and its size, so a write is refused
+/// if the device changed between enumeration and writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteConfirmation {
+    /// Path to the device in /dev
+    pub device: PathBuf,
+    /// Size of the device in bytes
+    pub size: u64,
+}
+
+impl WriteConfirmation {
+    /// Create a confirmation for the given device path and size
+    pub fn new(device: impl Into<PathBuf>, size: u64) -> Self {
+        Self {
+            device: device.into(),
+            size,
+        }
+    }
+
+    /// Create a confirmation matching a block device as it is now
+    pub fn for_device(device: &BlockDevice) -> Self {
+        Self::new(device.device(), device.size())
+    }
 }
 
 /// Zero out a specific region of the disk
@@ -67,23 +165,146 @@ fn zero_region<W: Write + Seek>(writer: &mut W, offset: u64, size: u64) -> io::R
     Ok(())
 }
 
+/// Amount of the disk zeroed between progress reports when erasing a whole disk
+const ERASE_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Discard `size` bytes from `offset` with the device's secure erase, if supported
+fn secure_discard(device: &fs::File, offset: u64, size: u64) -> io::Result<()> {
+    let range: [u64; 2] = [offset, size];
+    let res = unsafe { libc::ioctl(device.as_raw_fd(), BLKSECDISCARD as _, &range) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 /// Zero out disk headers by wiping first 2MiB of the disk
 fn zero_disk_headers<W: Write + Seek>(writer: &mut W) -> io::Result<()> {
     // Clear first 2MiB to wipe all common boot structures
     zero_region(writer, 0, 2 * 1024 * 1024)
 }
 
-/// Zero out up to 2MiB of a partition by writing 32 * 64KiB blocks
+/// Identifies a partition table backup written by [`DiskWriter::backup_partition_table`]
+const BACKUP_MAGIC: &[u8; 8] = b"DRSPTBK1";
+
+/// Largest area at either end of the disk saved in a partition table backup
+const BACKUP_MAX_AREA: u64 = 1024 * 1024;
+
+/// Byte ranges holding the partition table of a disk of `size` bytes
+///
+/// With a valid primary GPT header these run from the start of the disk to
+/// the first usable LBA, and from after the last usable LBA to the end, which
+/// covers both headers, both entry arrays and the protective MBR. Otherwise
+/// the areas a default GPT would use are given. LBAs are `block_size` bytes.
+fn table_areas(device: &mut fs::File, size: u64, block_size: u64) -> io::Result<[(u64, u64); 2]> {
+    let mut header = [0u8; 512];
+    device.seek(io::SeekFrom::Start(block_size))?;
+    device.read_exact(&mut header)?;
+
+    let lba = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+    // 16KiB of entries after the MBR and header, and before the backup header
+    let default_areas = (2 * block_size + 16384, block_size + 16384);
+    let (head, tail) = if &header[..8] == b"EFI PART" {
+        // The header is untrusted, so LBAs overflowing a byte offset fall back to the defaults
+        let first_usable = lba(40).checked_mul(block_size);
+        let last_usable = lba(48).checked_add(1).and_then(|end| end.checked_mul(block_size));
+        match first_usable.zip(last_usable) {
+            Some((head, end)) => (head, size.saturating_sub(end)),
+            None => default_areas,
+        }
+    } else {
+        default_areas
+    };
+    let head = head.min(BACKUP_MAX_AREA).min(size);
+    let tail = tail.min(BACKUP_MAX_AREA).min(size - head);
+    Ok([(0, head), (size - tail, tail)])
+}
+
+/// Read a little-endian u64 as written to a partition table backup
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Zero out the last 1MiB of a disk of `size` bytes, invalidating any backup GPT
+///
+/// This covers the backup header and a partition entry array of up to 8192
+/// entries, so a table left by an earlier, interrupted write cannot be
+/// mistaken for the new one.
+fn zero_disk_tail<W: Write + Seek>(writer: &mut W, size: u64) -> io::Result<()> {
+    let to_zero = std::cmp::min(size, 1024 * 1024);
+    zero_region(writer, size - to_zero, to_zero)
+}
+
 /// Zero out up to 2MiB of a region by writing 32 * 64KiB blocks
 fn zero_partition_prefix<W: Write + Seek>(writer: &mut W, offset: u64, size: u64) -> io::Result<()> {
     let to_zero = std::cmp::min(size, 2 * 1024 * 1024); // 2MiB max
     zero_region(writer, offset, to_zero)
 }
 
+/// Zero `size_bytes` of `device_path` from `start_bytes`, e.g. a whole partition before formatting
+///
+/// Unlike the planned writes this needs no [`WriteConfirmation`], so callers must be
+/// sure the range is theirs to destroy. The data is synced to the device before returning.
+pub fn zero_partition(device_path: &Path, start_bytes: u64, size_bytes: u64) -> io::Result<()> {
+    let mut device = fs::OpenOptions::new().write(true).open(device_path)?;
+    zero_region(&mut device, start_bytes, size_bytes)?;
+    device.sync_all()
+}
+
 impl<'a> DiskWriter<'a> {
     /// Create a new DiskWriter.
     pub fn new(device: &'a BlockDevice, planner: &'a Planner) -> Self {
-        Self { device, planner }
+        Self {
+            device,
+            planner,
+            confirmation: None,
+            sync_timeout: blkpg::DEFAULT_TIMEOUT,
+            table_type: PartitionTableType::Gpt,
+        }
+    }
+
+    /// Set the type of partition table created when the planner initializes the disk
+    ///
+    /// Defaults to [`PartitionTableType::Gpt`]. New partitions must have IDs within
+    /// the partition count of the type. A GPT is created with that many entries,
+    /// though never fewer than the 128 the UEFI specification requires, and an MBR
+    /// only uses that many of its primary slots. [`PartitionTableType::Auto`] must be
+    /// resolved beforehand and fails validation with [`WriteError::UnsupportedTableType`].
+    pub fn with_table_type(self, table_type: PartitionTableType) -> Self {
+        Self { table_type, ..self }
+    }
+
+    /// Set how long to wait for the kernel to synchronise partitions after writing
+    ///
+    /// Defaults to [`blkpg::DEFAULT_TIMEOUT`].
+    pub fn with_sync_timeout(self, sync_timeout: Duration) -> Self {
+        Self { sync_timeout, ..self }
+    }
+
+    /// Confirm that the device may be written to, required by [`DiskWriter::write`]
+    pub fn with_confirmation(self, confirmation: WriteConfirmation) -> Self {
+        Self {
+            confirmation: Some(confirmation),
+            ..self
+        }
+    }
+
+    /// Ensure a confirmation matching the target device was given
+    fn check_confirmation(&self) -> Result<(), WriteError> {
+        let expected = WriteConfirmation::for_device(self.device);
+        match &self.confirmation {
+            Some(confirmation) if *confirmation == expected => Ok(()),
+            Some(confirmation) => {
+                warn!("Write confirmation {confirmation:?} does not match target {expected:?}");
+                Err(WriteError::Unconfirmed)
+            }
+            None => {
+                warn!("Refusing unconfirmed write to {}", self.device.device().display());
+                Err(WriteError::Unconfirmed)
+            }
+        }
     }
 
     /// Simulate changes without writing to disk
@@ -97,8 +318,30 @@ impl<'a> DiskWriter<'a> {
         Ok(())
     }
 
+    /// Simulate changes without writing to disk and describe what would be written
+    ///
+    /// The report lists the strategies applied to the planner via
+    /// [`Planner::apply_strategy`], followed by the pending changes.
+    pub fn simulate_report(&self) -> Result<String, WriteError> {
+        self.simulate()?;
+
+        let strategies = match self.planner.applied_strategies() {
+            [] => "none".to_string(),
+            names => names.join(", "),
+        };
+        Ok(format!(
+            "Strategies applied: {strategies}\n{}",
+            self.planner.describe_changes()
+        ))
+    }
+
     /// Actually write changes to disk
+    ///
+    /// Fails with [`WriteError::Unconfirmed`] unless a matching
+    /// [`WriteConfirmation`] was given via [`DiskWriter::with_confirmation`].
     pub fn write(&self) -> Result<(), WriteError> {
+        self.check_confirmation()?;
+
         let mut device = fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -110,15 +353,220 @@ impl<'a> DiskWriter<'a> {
         Ok(())
     }
 
+    /// Save the partition table of the device to `backup_path`
+    ///
+    /// The protective MBR and both GPT headers and entry arrays are copied
+    /// along with their offsets and the device size, so they can be put back
+    /// with [`DiskWriter::restore_partition_table_from_backup`]. Partition
+    /// contents are not included.
+    pub fn backup_partition_table(&self, backup_path: impl AsRef<Path>) -> Result<(), WriteError> {
+        let mut device = fs::File::open(self.device.device())?;
+        let size = self.device.size();
+
+        let mut backup = fs::File::create(backup_path)?;
+        backup.write_all(BACKUP_MAGIC)?;
+        backup.write_all(&size.to_le_bytes())?;
+        for (offset, len) in table_areas(&mut device, size, self.device.logical_block_size())? {
+            let mut data = vec![0u8; len as usize];
+            device.seek(io::SeekFrom::Start(offset))?;
+            device.read_exact(&mut data)?;
+            backup.write_all(&offset.to_le_bytes())?;
+            backup.write_all(&len.to_le_bytes())?;
+            backup.write_all(&data)?;
+        }
+        backup.sync_all()?;
+
+        info!("Backed up partition table of {}", self.device.device().display());
+        Ok(())
+    }
+
+    /// Write a partition table saved by [`DiskWriter::backup_partition_table`] back to the device
+    ///
+    /// The backup must come from a device of the same size. This ignores the
+    /// planned changes and requires the same [`WriteConfirmation`] as
+    /// [`DiskWriter::write`]. The kernel is not told about the restored
+    /// partitions, use [`blkpg::sync_gpt_partitions`] for that.
+    pub fn restore_partition_table_from_backup(&self, backup_path: impl AsRef<Path>) -> Result<(), WriteError> {
+        self.check_confirmation()?;
+
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_owned());
+        let backup = fs::read(backup_path)?;
+        let mut reader = backup.as_slice();
+
+        let mut magic = [0u8; BACKUP_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != BACKUP_MAGIC {
+            return Err(invalid("not a partition table backup").into());
+        }
+        if read_u64(&mut reader)? != self.device.size() {
+            return Err(invalid("backup is of a device with a different size").into());
+        }
+        let mut areas = vec![];
+        for _ in 0..2 {
+            let offset = read_u64(&mut reader)?;
+            let len = read_u64(&mut reader)?;
+            // Never allocate or write more than a backup can hold, or past the end of the device
+            if len > BACKUP_MAX_AREA || offset.checked_add(len).is_none_or(|end| end > self.device.size()) {
+                return Err(invalid("backup area lies outside the device").into());
+            }
+            let mut data = vec![0u8; len as usize];
+            reader.read_exact(&mut data)?;
+            areas.push((offset, data));
+        }
+
+        let mut device = fs::OpenOptions::new().write(true).open(self.device.device())?;
+        for (offset, data) in areas {
+            device.seek(io::SeekFrom::Start(offset))?;
+            device.write_all(&data)?;
+        }
+        device.sync_all()?;
+
+        info!("Restored partition table of {}", self.device.device().display());
+        Ok(())
+    }
+
+    /// Back up the partition table into the directory `backup_dir`, then [`write`](DiskWriter::write)
+    ///
+    /// The backup is named after the device and the current time, e.g.
+    /// `sda-1735689600.ptbackup`, and its path is returned. If the backup fails
+    /// nothing is written and [`WriteError::BackupFailed`] is returned. If the
+    /// write fails the backup is kept, so the previous table can be put back
+    /// with [`DiskWriter::restore_partition_table_from_backup`].
+    pub fn backup_and_write(&self, backup_dir: impl AsRef<Path>) -> Result<PathBuf, WriteError> {
+        self.check_confirmation()?;
+
+        let device_name = self
+            .device
+            .device()
+            .file_name()
+            .map_or_else(|| "disk".into(), |name| name.to_string_lossy());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let backup_path = backup_dir.as_ref().join(format!("{device_name}-{timestamp}.ptbackup"));
+
+        self.backup_partition_table(&backup_path).map_err(|e| match e {
+            WriteError::IoError(e) => WriteError::BackupFailed(e),
+            e => e,
+        })?;
+        self.write()?;
+        Ok(backup_path)
+    }
+
+    /// Overwrite the entire device with zeros
+    ///
+    /// This ignores the planned changes and requires the same [`WriteConfirmation`]
+    /// as [`DiskWriter::write`].
+    pub fn write_zero_entire_disk(&self) -> Result<(), WriteError> {
+        self.write_zero_entire_disk_with_progress(|_, _| {})
+    }
+
+    /// Overwrite the entire device with zeros, reporting `(bytes_written, total_bytes)`
+    /// to `progress` as the erase proceeds
+    pub fn write_zero_entire_disk_with_progress<F>(&self, mut progress: F) -> Result<(), WriteError>
+    where
+        F: FnMut(u64, u64),
+    {
+        self.check_confirmation()?;
+
+        let mut device = fs::OpenOptions::new().write(true).open(self.device.device())?;
+        let total = self.device.size();
+        let mut written = 0;
+        while written < total {
+            let size = ERASE_CHUNK_SIZE.min(total - written);
+            zero_region(&mut device, written, size)?;
+            written += size;
+            progress(written, total);
+        }
+        device.sync_all()?;
+
+        info!("Zeroed {total} bytes of {}", self.device.device().display());
+        Ok(())
+    }
+
+    /// Securely erase the entire device
+    ///
+    /// Devices supporting secure discard (typically SSD and NVMe) erase their
+    /// contents in hardware via `BLKSECDISCARD`, which is far faster than writing.
+    /// Any other device is overwritten with zeros instead.
+    pub fn write_secure_erase(&self) -> Result<(), WriteError> {
+        self.check_confirmation()?;
+
+        let device = fs::OpenOptions::new().write(true).open(self.device.device())?;
+        match secure_discard(&device, 0, self.device.size()) {
+            Ok(()) => {
+                info!("Securely discarded {}", self.device.device().display());
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Secure discard unavailable ({e}), falling back to zeroing the disk");
+                self.write_zero_entire_disk()
+            }
+        }
+    }
+
+    /// Erase the signatures that make the device and its partitions recognisable
+    ///
+    /// The primary and backup GPT headers, the protective MBR and every
+    /// filesystem or LUKS2 magic number known to [`superblock::signature_ranges`]
+    /// are zeroed, both at the start of the device and of each existing
+    /// partition. The bulk of the data is left untouched, so this is far faster
+    /// than [`DiskWriter::write_zero_entire_disk`] but does not destroy any data.
+    /// This ignores the planned changes and requires the same [`WriteConfirmation`]
+    /// as [`DiskWriter::write`].
+    pub fn erase_signatures(&self) -> Result<(), WriteError> {
+        self.check_confirmation()?;
+
+        let mut device = fs::OpenOptions::new().write(true).open(self.device.device())?;
+        let total = self.device.size();
+        let block_size = self.device.logical_block_size();
+
+        // Protective MBR, primary GPT header and backup GPT header
+        let mut regions = vec![(0, 2 * block_size)];
+        if total >= block_size {
+            regions.push((total - block_size, block_size));
+        }
+
+        let starts = std::iter::once(0).chain(self.device.partitions().iter().map(|p| p.start * SECTOR_SIZE));
+        for start in starts {
+            regions.extend(
+                superblock::signature_ranges()
+                    .into_iter()
+                    .map(|range| (start + range.start, range.end - range.start))
+                    .filter(|(offset, size)| offset + size <= total),
+            );
+        }
+
+        for (offset, size) in regions {
+            zero_region(&mut device, offset, size)?;
+        }
+        device.sync_all()?;
+
+        info!("Erased signatures on {}", self.device.device().display());
+        Ok(())
+    }
+
     /// Validate all planned changes before applying them by checking:
     /// - Device size matches the planned size
     /// - No duplicate partition IDs exist
-    fn validate_changes(&self) -> Result<(), WriteError> {
+    /// - No partitions overlap
+    /// - A new partition table is of a supported type, with room for every new partition
+    pub fn validate_changes(&self) -> Result<(), WriteError> {
+        if self.planner.wipe_disk() && self.table_type == PartitionTableType::Auto {
+            return Err(WriteError::UnsupportedTableType(self.table_type));
+        }
+
         // Verify partition IDs don't conflict
         let mut used_ids = std::collections::HashSet::new();
         for change in self.planner.changes() {
             match change {
                 Change::AddPartition { partition_id, .. } => {
+                    if self.planner.wipe_disk() && *partition_id > self.table_type.partition_count() {
+                        return Err(WriteError::PartitionIdOutOfRange {
+                            partition_id: *partition_id,
+                            table_type: self.table_type,
+                        });
+                    }
                     if !used_ids.insert(*partition_id) {
                         return Err(WriteError::DuplicatePartitionId(*partition_id));
                     }
@@ -126,9 +574,53 @@ impl<'a> DiskWriter<'a> {
                 Change::DeletePartition { partition_id, .. } => {
                     used_ids.remove(partition_id);
                 }
+                Change::SetPartitionType { .. } | Change::SetPartitionName { .. } => {}
             }
         }
 
+        if let Some((partition_a, partition_b)) = self.planner.detect_overlaps().first().copied() {
+            return Err(WriteError::PartitionOverlap {
+                partition_a,
+                partition_b,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The logical block size to create or open the GPT with, following the device
+    fn logical_block_size(&self) -> Result<LogicalBlockSize, WriteError> {
+        Ok(LogicalBlockSize::try_from(self.device.logical_block_size())?)
+    }
+
+    /// Write empty primary and backup GPT headers with room for `count` partition entries
+    ///
+    /// The gpt crate only creates tables of the default size, so the headers are
+    /// built directly and the table is then opened from the device.
+    fn write_empty_gpt(&self, device: &mut fs::File, count: u32) -> Result<(), WriteError> {
+        let lb_size = self.logical_block_size()?;
+        let block_size = u64::from(lb_size);
+        let total_lba = self.device.size() / block_size;
+        let mut primary = HeaderBuilder::new()
+            .num_parts(count)
+            .backup_lba(total_lba.saturating_sub(1))
+            .disk_guid(Uuid::new_v4())
+            .primary(true)
+            .build(lb_size)
+            .map_err(gpt::GptError::from)?;
+        let mut backup = HeaderBuilder::from_header(&primary)
+            .primary(false)
+            .build(lb_size)
+            .map_err(gpt::GptError::from)?;
+
+        // Empty partition entries are all zeroes
+        let array_size = u64::from(count) * u64::from(primary.part_size);
+        zero_region(device, primary.part_start * block_size, array_size)?;
+        zero_region(device, backup.part_start * block_size, array_size)?;
+
+        primary.write_primary(device, lb_size).map_err(gpt::GptError::from)?;
+        backup.write_backup(device, lb_size).map_err(gpt::GptError::from)?;
+        device.flush()?;
         Ok(())
     }
 
@@ -141,15 +633,22 @@ impl<'a> DiskWriter<'a> {
             blkpg::remove_kernel_partitions(self.device.device())?;
         }
 
+        if self.planner.wipe_disk() && !self.table_type.is_gpt() {
+            return self.apply_mbr_changes(device, writable);
+        }
+
         let mut zero_regions = vec![];
+        let lb_size = self.logical_block_size()?;
+        let block_size = u64::from(lb_size);
 
         let mut gpt_table = if self.planner.wipe_disk() {
             if writable {
-                // Zero out headers including potential ISO structures
+                // Zero out headers including potential ISO structures, and any backup GPT
                 zero_disk_headers(device)?;
+                zero_disk_tail(device, self.device.size())?;
 
                 // Convert total bytes to LBA sectors, subtract 1 as per GPT spec
-                let total_lba = self.device.size() / SECTOR_SIZE;
+                let total_lba = self.device.size() / block_size;
                 let mbr = mbr::ProtectiveMBR::with_lb_size(
                     u32::try_from(total_lba.saturating_sub(1)).unwrap_or(0xFF_FF_FF_FF),
                 );
@@ -157,17 +656,29 @@ impl<'a> DiskWriter<'a> {
                 mbr.overwrite_lba0(device)?;
             }
 
-            let mut c = GptConfig::default()
-                .writable(writable)
-                .logical_block_size(gpt::disk::LogicalBlockSize::Lb512)
-                .create_from_device(device, None)?;
+            let count = self.table_type.partition_count();
+            if writable && count > types::GPT_DEFAULT_PARTITION_COUNT {
+                self.write_empty_gpt(device, count)?;
+                GptConfig::default()
+                    .writable(writable)
+                    .logical_block_size(lb_size)
+                    .open_from_device(device)?
+            } else {
+                let mut c = GptConfig::default()
+                    .writable(writable)
+                    .logical_block_size(lb_size)
+                    .create_from_device(device, None)?;
 
-            if writable {
-                c.write_inplace()?;
+                if writable {
+                    c.write_inplace()?;
+                }
+                c
             }
-            c
         } else {
-            GptConfig::default().writable(writable).open_from_device(device)?
+            GptConfig::default()
+                .writable(writable)
+                .logical_block_size(lb_size)
+                .open_from_device(device)?
         };
 
         let layout = self.planner.current_layout();
@@ -188,19 +699,24 @@ impl<'a> DiskWriter<'a> {
                 Change::AddPartition {
                     start,
                     end,
+                    unit,
                     partition_id,
                     attributes,
                 } => {
-                    // Convert byte offsets to LBA sectors
-                    let start_lba = *start / SECTOR_SIZE;
-                    let size_bytes = *end - *start;
-                    let size_lba = size_bytes / SECTOR_SIZE;
-                    let (part_type, part_name) = match attributes.as_ref().and_then(|a| a.table.as_gpt()) {
-                        Some(GptAttributes { type_guid, name, .. }) => {
-                            (type_guid.clone(), name.clone().unwrap_or_default())
-                        }
-                        None => (partition_types::BASIC, "".to_string()),
-                    };
+                    // Convert planner offsets to LBA sectors
+                    let to_bytes = |value| unit.to_bytes(value, block_size);
+                    let start_lba = to_bytes(*start) / block_size;
+                    let size_lba = to_bytes(*end - *start) / block_size;
+                    let (part_type, part_name, part_guid, flags) =
+                        match attributes.as_ref().and_then(|a| a.table.as_gpt()) {
+                            Some(GptAttributes {
+                                type_guid,
+                                name,
+                                uuid,
+                                flags,
+                            }) => (type_guid.clone(), name.clone().unwrap_or_default(), *uuid, flags.bits()),
+                            None => (partition_types::BASIC, "".to_string(), None, 0),
+                        };
 
                     eprintln!(
                         "Converting partition: bytes {}..{} to LBA {}..{}",
@@ -210,13 +726,44 @@ impl<'a> DiskWriter<'a> {
                         start_lba + size_lba
                     );
                     let id =
-                        gpt_table.add_partition_at(&part_name, *partition_id, start_lba, size_lba, part_type, 0)?;
+                        gpt_table.add_partition_at(&part_name, *partition_id, start_lba, size_lba, part_type, flags)?;
                     println!("Added partition {partition_id}: {id:?}");
+
+                    // The gpt crate always assigns a random GUID, replace it if one was requested
+                    if let Some(guid) = part_guid {
+                        let mut partitions = gpt_table.take_partitions();
+                        if let Some(partition) = partitions.get_mut(&id) {
+                            partition.part_guid = guid;
+                        }
+                        gpt_table.update_partitions(partitions)?;
+                    }
                     // Store start and size for zeroing
                     if writable {
-                        zero_regions.push((*start, *end));
+                        zero_regions.push((to_bytes(*start), to_bytes(*end)));
                     }
                 }
+                Change::SetPartitionType {
+                    partition_id,
+                    type_guid,
+                } => {
+                    // Edit the entry in place, keeping its unique GUID and leaving the data alone
+                    let mut partitions = gpt_table.take_partitions();
+                    let Some(partition) = partitions.get_mut(partition_id) else {
+                        return Err(WriteError::PartitionNotFound(*partition_id));
+                    };
+                    partition.part_type_guid = type_guid.clone();
+                    gpt_table.update_partitions(partitions)?;
+                    println!("Set type of partition {partition_id} to {}", type_guid.guid);
+                }
+                Change::SetPartitionName { partition_id, name } => {
+                    let mut partitions = gpt_table.take_partitions();
+                    let Some(partition) = partitions.get_mut(partition_id) else {
+                        return Err(WriteError::PartitionNotFound(*partition_id));
+                    };
+                    partition.name = name.clone();
+                    gpt_table.update_partitions(partitions)?;
+                    println!("Renamed partition {partition_id} to {name:?}");
+                }
             }
         }
 
@@ -238,9 +785,637 @@ impl<'a> DiskWriter<'a> {
                 zero_partition_prefix(original, start, end - start)?;
             }
 
-            blkpg::create_kernel_partitions(self.device.device())?;
+            blkpg::sync_gpt_partitions_with_timeout(self.device.device(), self.sync_timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the changes to a new MBR, using as many primary slots as the table type allows
+    ///
+    /// Slot `n - 1` holds partition `n`. Partition types are mapped from the GPT
+    /// type GUIDs and the legacy BIOS bootable flag marks a partition active, but
+    /// MBR entries have no names so renames fail with [`WriteError::UnsupportedTableType`].
+    fn apply_mbr_changes(&self, device: &mut fs::File, writable: bool) -> Result<(), WriteError> {
+        let block_size = self.device.logical_block_size();
+        let mut slots = [None; MBR_PRIMARY_PARTITION_COUNT as usize];
+        let slot = |partition_id: u32| match partition_id {
+            1..=MBR_PRIMARY_PARTITION_COUNT if partition_id <= self.table_type.partition_count() => {
+                Ok(partition_id as usize - 1)
+            }
+            _ => Err(WriteError::PartitionIdOutOfRange {
+                partition_id,
+                table_type: self.table_type,
+            }),
+        };
+
+        for change in self.planner.changes() {
+            match change {
+                Change::DeletePartition { partition_id, .. } => {
+                    slots[slot(*partition_id)?] = None;
+                }
+                Change::AddPartition {
+                    start,
+                    end,
+                    unit,
+                    partition_id,
+                    attributes,
+                } => {
+                    let to_lba = |value| {
+                        u32::try_from(unit.to_bytes(value, block_size) / block_size)
+                            .map_err(|_| WriteError::MbrOutOfRange(*partition_id))
+                    };
+                    let lb_start = to_lba(*start)?;
+                    let lb_end = to_lba(*end)?;
+                    let gpt = attributes.as_ref().and_then(|a| a.table.as_gpt());
+                    let bootable = gpt.is_some_and(|a| a.flags.contains(GptFlags::LEGACY_BIOS_BOOTABLE));
+                    slots[slot(*partition_id)?] = Some(mbr::PartRecord {
+                        boot_indicator: if bootable { 0x80 } else { 0x00 },
+                        os_type: gpt.map_or(MBR_TYPE_LINUX, |a| mbr_partition_type(&a.type_guid)),
+                        lb_start,
+                        lb_size: lb_end - lb_start,
+                        ..mbr_chs_unused()
+                    });
+                }
+                Change::SetPartitionType {
+                    partition_id,
+                    type_guid,
+                } => {
+                    let Some(record) = slots[slot(*partition_id)?].as_mut() else {
+                        return Err(WriteError::PartitionNotFound(*partition_id));
+                    };
+                    record.os_type = mbr_partition_type(type_guid);
+                }
+                Change::SetPartitionName { .. } => return Err(WriteError::UnsupportedTableType(self.table_type)),
+            }
+        }
+
+        eprintln!("### MBR is now: {slots:?}");
+
+        if writable {
+            zero_disk_headers(device)?;
+            zero_disk_tail(device, self.device.size())?;
+
+            let mut table = mbr::ProtectiveMBR::new();
+            table.set_disk_signature(*Uuid::new_v4().as_bytes().first_chunk().unwrap());
+            for (index, record) in slots.iter().enumerate() {
+                table.set_partition(index, record.unwrap_or_else(mbr::PartRecord::zero));
+            }
+            table.overwrite_lba0(device)?;
+            device.sync_all()?;
+
+            let partitions = slots
+                .iter()
+                .enumerate()
+                .filter_map(|(index, record)| {
+                    record.map(|record| {
+                        let start = u64::from(record.lb_start) * block_size;
+                        (index as u32 + 1, start, u64::from(record.lb_size) * block_size)
+                    })
+                })
+                .collect::<Vec<_>>();
+            for (_, start, size) in &partitions {
+                zero_partition_prefix(device, *start, *size)?;
+            }
+
+            blkpg::sync_partitions_with_timeout(self.device.device(), partitions, self.sync_timeout)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PartitionAttributes, TableAttributes};
+    use disks::mock::MockDisk;
+    use types::GptFlags;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_confirmation() {
+        let device = BlockDevice::mock_device(MockDisk::new(1024 * MB));
+        let planner = Planner::new(&device);
+
+        // Without a token nothing is written
+        let writer = DiskWriter::new(&device, &planner);
+        assert!(matches!(writer.write(), Err(WriteError::Unconfirmed)));
+
+        // A token for another device is refused
+        let writer =
+            DiskWriter::new(&device, &planner).with_confirmation(WriteConfirmation::new("/dev/sda", 1024 * MB));
+        assert!(matches!(writer.write(), Err(WriteError::Unconfirmed)));
+
+        // As is a token for the right device with a different size
+        let writer = DiskWriter::new(&device, &planner).with_confirmation(WriteConfirmation::new(device.device(), MB));
+        assert!(matches!(writer.write(), Err(WriteError::Unconfirmed)));
+
+        // A matching token gets past the interlock, failing only because the mock has no device node
+        let writer = DiskWriter::new(&device, &planner).with_confirmation(WriteConfirmation::for_device(&device));
+        assert!(writer.check_confirmation().is_ok());
+        assert!(matches!(writer.write(), Err(WriteError::IoError(_))));
+    }
+
+    #[test]
+    fn test_validate_overlap() {
+        let mut disk = MockDisk::new(1024 * MB);
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(50 * MB, 150 * MB);
+        let device = BlockDevice::mock_device(disk);
+        let planner = Planner::new(&device);
+
+        let writer = DiskWriter::new(&device, &planner);
+        assert!(matches!(
+            writer.validate_changes(),
+            Err(WriteError::PartitionOverlap {
+                partition_a: 1,
+                partition_b: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_zero_entire_disk() {
+        let size = 2 * ERASE_CHUNK_SIZE + MB;
+
+        let Some(contents) = crate::sparsefile::with_loop_device(size, |blk| {
+            fs::write(blk.device(), vec![0xAAu8; size as usize])?;
+            let planner = Planner::new(&blk);
+            let writer = DiskWriter::new(&blk, &planner);
+            assert!(matches!(writer.write_zero_entire_disk(), Err(WriteError::Unconfirmed)));
+
+            let writer = writer.with_confirmation(WriteConfirmation::for_device(&blk));
+            let mut reports = vec![];
+            writer.write_zero_entire_disk_with_progress(|written, total| reports.push((written, total)))?;
+            assert_eq!(
+                reports,
+                [(ERASE_CHUNK_SIZE, size), (2 * ERASE_CHUNK_SIZE, size), (size, size)]
+            );
+
+            // Loop devices have no secure discard, so this falls back to zeroing
+            writer.write_secure_erase()?;
+            Ok::<_, WriteError>(fs::read(blk.device())?)
+        }) else {
+            return;
+        };
+
+        assert!(contents.unwrap().iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_write_sector_units() {
+        use crate::planner::Unit;
+
+        // A partition that is not a whole number of MiB
+        let (start, end) = (2048, 2048 + 3 * 1024 + 1);
+
+        let Some(written) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let mut planner = Planner::new_with_units(&blk, Unit::Sectors);
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(start, end).unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            let table = GptConfig::default().writable(false).open(blk.device())?;
+            let partition = &table.partitions()[&1];
+            Ok::<_, Box<dyn std::error::Error>>((partition.first_lba, partition.last_lba + 1))
+        }) else {
+            return;
+        };
+
+        assert_eq!(written.unwrap(), (start, end));
+    }
+
+    #[test]
+    fn test_validate_partition_count() {
+        let device = BlockDevice::mock_device(MockDisk::new(1024 * MB));
+        let mut planner = Planner::new(&device);
+        planner.plan_initialize_disk().unwrap();
+        planner.plan_add_partition(MB, 32 * MB).unwrap();
+        planner.plan_add_partition(32 * MB, 64 * MB).unwrap();
+
+        let validate = |table_type| {
+            DiskWriter::new(&device, &planner)
+                .with_table_type(table_type)
+                .validate_changes()
+        };
+        assert!(validate(PartitionTableType::Msdos).is_ok());
+        assert!(validate(PartitionTableType::GptWithPartCount(2)).is_ok());
+        assert!(matches!(
+            validate(PartitionTableType::MbrWithPartCount(1)),
+            Err(WriteError::PartitionIdOutOfRange { partition_id: 2, .. })
+        ));
+        assert!(matches!(
+            validate(PartitionTableType::Auto),
+            Err(WriteError::UnsupportedTableType(PartitionTableType::Auto))
+        ));
+    }
+
+    #[test]
+    fn test_write_partition_count() {
+        let Some(result) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let mut planner = Planner::new(&blk);
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(MB, 32 * MB).unwrap();
+            let write = |table_type| {
+                DiskWriter::new(&blk, &planner)
+                    .with_confirmation(WriteConfirmation::for_device(&blk))
+                    .with_table_type(table_type)
+                    .write()
+            };
+
+            // Smaller tables still get the minimum of 128 entries
+            let mut headers = vec![];
+            for count in [8, 256] {
+                write(PartitionTableType::GptWithPartCount(count))?;
+                let table = GptConfig::default().writable(false).open(blk.device())?;
+                let header = table.header();
+                headers.push((header.num_parts, header.first_usable, table.partitions()[&1].first_lba));
+            }
+
+            write(PartitionTableType::MbrWithPartCount(2))?;
+            let mut device = fs::File::open(blk.device())?;
+            let table = mbr::ProtectiveMBR::from_disk(&mut device, LogicalBlockSize::Lb512)?;
+            Ok::<_, Box<dyn std::error::Error>>((headers, table.partition(0).unwrap(), table.partition(1).unwrap()))
+        }) else {
+            return;
+        };
+
+        let (headers, first, second) = result.unwrap();
+        assert_eq!(
+            headers,
+            [
+                (128, 2 + 128 * 128 / 512, MB / 512),
+                (256, 2 + 256 * 128 / 512, MB / 512)
+            ]
+        );
+        assert_eq!(
+            (first.os_type, first.lb_start, first.lb_size),
+            (MBR_TYPE_LINUX, (MB / 512) as u32, (31 * MB / 512) as u32)
+        );
+        assert_eq!(second, mbr::PartRecord::zero());
+    }
+
+    #[test]
+    fn test_write_flags() {
+        let flags = GptFlags::LEGACY_BIOS_BOOTABLE | GptFlags::READ_ONLY;
+
+        let Some(result) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let mut planner = Planner::new(&blk);
+            planner.plan_initialize_disk().unwrap();
+            planner
+                .plan_add_partition_with_attributes(
+                    MB,
+                    32 * MB,
+                    Some(PartitionAttributes {
+                        table: TableAttributes::Gpt(GptAttributes {
+                            flags,
+                            ..Default::default()
+                        }),
+                        role: None,
+                        filesystem: None,
+                        encryption: None,
+                    }),
+                )
+                .unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            let table = GptConfig::default().writable(false).open(blk.device())?;
+            Ok::<_, Box<dyn std::error::Error>>((table.partitions()[&1].flags, Planner::from_existing(&blk)?))
+        }) else {
+            return;
+        };
+
+        let (raw_flags, planner) = result.unwrap();
+        assert_eq!(raw_flags, (1 << 2) | (1 << 60));
+        let read_back = planner.current_layout()[0].attributes.clone().unwrap();
+        assert_eq!(read_back.table.as_gpt().unwrap().flags, flags);
+    }
+
+    #[test]
+    fn test_zero_partition() {
+        let image = std::env::temp_dir().join(format!("disks-rs-zero-{}.img", std::process::id()));
+        fs::write(&image, vec![0xAAu8; 256 * 1024]).unwrap();
+
+        // Spans several zeroing chunks and ends part way through one
+        zero_partition(&image, 4096, 200_000).unwrap();
+        let data = fs::read(&image).unwrap();
+        fs::remove_file(&image).unwrap();
+
+        assert!(data[..4096].iter().all(|b| *b == 0xAA));
+        assert!(data[4096..204_096].iter().all(|b| *b == 0));
+        assert!(data[204_096..].iter().all(|b| *b == 0xAA));
+        assert_eq!(data.len(), 256 * 1024);
+    }
+
+    #[test]
+    fn test_write_4k_sectors() {
+        let Some(result) = crate::sparsefile::with_loop_device_block_size(64 * MB, 4096, |mut blk| {
+            assert_eq!(blk.logical_block_size(), 4096);
+            let mut planner = Planner::new(&blk);
+            assert_eq!(planner.gpt_usable_bounds(), (6 * 4096, 64 * MB - 5 * 4096));
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(MB, 32 * MB).unwrap();
+            planner.plan_add_partition(32 * MB, 48 * MB).unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            blk.refresh()?;
+            let kernel = blk.partitions().iter().map(|p| (p.start, p.size)).collect::<Vec<_>>();
+
+            let table = GptConfig::default()
+                .writable(false)
+                .logical_block_size(LogicalBlockSize::Lb4096)
+                .open(blk.device())?;
+            let written = table
+                .partitions()
+                .values()
+                .map(|p| (p.first_lba, p.last_lba))
+                .collect::<Vec<_>>();
+            Ok::<_, Box<dyn std::error::Error>>((
+                kernel,
+                *table.logical_block_size(),
+                table.header().backup_lba,
+                written,
+            ))
+        }) else {
+            return;
+        };
+
+        // The kernel counts partition offsets in 512-byte sectors regardless
+        let (kernel, block_size, backup_lba, written) = result.unwrap();
+        assert_eq!(kernel, vec![(MB / 512, 31 * MB / 512), (32 * MB / 512, 16 * MB / 512)]);
+
+        assert_eq!(block_size, LogicalBlockSize::Lb4096);
+        assert_eq!(backup_lba, 64 * MB / 4096 - 1);
+        assert_eq!(
+            written,
+            [(MB / 4096, 32 * MB / 4096 - 1), (32 * MB / 4096, 48 * MB / 4096 - 1)]
+        );
+    }
+
+    #[test]
+    fn test_refresh_after_write() {
+        let Some(partitions) = crate::sparsefile::with_loop_device(64 * MB, |mut blk| {
+            let mut planner = Planner::new(&blk);
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(MB, 32 * MB).unwrap();
+            planner.plan_add_partition(32 * MB, 48 * MB).unwrap();
+            planner.plan_add_partition(48 * MB, 63 * MB).unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            blk.refresh()?;
+            Ok::<_, Box<dyn std::error::Error>>(blk.partitions().len())
+        }) else {
+            return;
+        };
+
+        // The device read before the write is updated in place
+        assert_eq!(partitions.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_set_partition_metadata() {
+        let Some(result) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let mut planner = Planner::new(&blk);
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(MB, 32 * MB).unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            let before = GptConfig::default().writable(false).open(blk.device())?.partitions()[&1].clone();
+
+            // Data written to the partition must survive the metadata change
+            let data_offset = 2 * MB;
+            let mut file = fs::OpenOptions::new().write(true).open(blk.device())?;
+            file.seek(io::SeekFrom::Start(data_offset))?;
+            file.write_all(b"keep me")?;
+            file.sync_all()?;
+            drop(file);
+
+            let mut planner = Planner::from_existing(&blk)?;
+            planner.plan_set_partition_type(1, partition_types::LINUX_FS).unwrap();
+            planner.plan_set_partition_name(1, "data").unwrap();
+            DiskWriter::new(&blk, &planner)
+                .with_confirmation(WriteConfirmation::for_device(&blk))
+                .write()?;
+            let after = GptConfig::default().writable(false).open(blk.device())?.partitions()[&1].clone();
+
+            let mut data = [0u8; 7];
+            let mut file = fs::File::open(blk.device())?;
+            file.seek(io::SeekFrom::Start(data_offset))?;
+            file.read_exact(&mut data)?;
+            Ok::<_, WriteError>((before, after, data))
+        }) else {
+            return;
+        };
+
+        let (before, after, data) = result.unwrap();
+        assert_eq!(before.part_type_guid, partition_types::BASIC);
+        assert_eq!(after.part_type_guid, partition_types::LINUX_FS);
+        assert_eq!(after.name, "data");
+        assert_eq!(after.part_guid, before.part_guid);
+        assert_eq!((after.first_lba, after.last_lba), (before.first_lba, before.last_lba));
+        assert_eq!(&data, b"keep me");
+    }
+
+    #[test]
+    fn test_rewrite_is_idempotent() {
+        let Some(layouts) = crate::sparsefile::with_loop_device(64 * MB, |blk| {
+            let mut planner = Planner::new(&blk);
+            planner.plan_initialize_disk().unwrap();
+            planner.plan_add_partition(MB, 32 * MB).unwrap();
+            planner.plan_add_partition(32 * MB, 63 * MB).unwrap();
+
+            // Running the same plan again, e.g. after a failed provisioning run, starts afresh
+            (0..2)
+                .map(|_| {
+                    DiskWriter::new(&blk, &planner)
+                        .with_confirmation(WriteConfirmation::for_device(&blk))
+                        .write()?;
+                    let table = GptConfig::default().writable(false).open(blk.device())?;
+                    Ok(table
+                        .partitions()
+                        .iter()
+                        .map(|(id, p)| (*id, p.first_lba, p.last_lba, p.part_type_guid.clone()))
+                        .collect::<Vec<_>>())
+                })
+                .collect::<Result<Vec<_>, WriteError>>()
+        }) else {
+            return;
+        };
+
+        let layouts = layouts.unwrap();
+        assert_eq!(layouts[0].len(), 2);
+        assert_eq!(layouts[0], layouts[1]);
+    }
+
+    /// Write a mock disk holding a GPT out to `image`, for writer code that opens the device path
+    fn mock_gpt_image(
+        image: &std::path::Path,
+        size: u64,
+        partitions: &[(u64, u64, partition_types::Type)],
+    ) -> BlockDevice {
+        let disk = MockDisk::with_existing_gpt(size, partitions).unwrap();
+        disk.image()
+            .unwrap()
+            .write_to(&mut fs::File::create(image).unwrap())
+            .unwrap();
+        BlockDevice::mock_device(disk.with_device_path(image))
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let image = std::env::temp_dir().join(format!("disks-rs-backup-{}.img", std::process::id()));
+        let blk = mock_gpt_image(
+            &image,
+            64 * MB,
+            &[
+                (MB, 17 * MB, partition_types::LINUX_FS),
+                (17 * MB, 63 * MB, partition_types::LINUX_SWAP),
+            ],
+        );
+        let dir = std::env::temp_dir().join(format!("disks-rs-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let layout = || {
+            GptConfig::default().writable(false).open(&image).map(|table| {
+                table
+                    .partitions()
+                    .values()
+                    .map(|p| (p.first_lba, p.last_lba, p.part_type_guid.clone()))
+                    .collect::<Vec<_>>()
+            })
+        };
+        let original = layout().unwrap();
+
+        let mut planner = Planner::new(&blk);
+        planner.plan_initialize_disk().unwrap();
+        let writer = DiskWriter::new(&blk, &planner).with_confirmation(WriteConfirmation::for_device(&blk));
+
+        // Without a backup nothing is written
+        let result = writer.backup_and_write(dir.join("missing"));
+        assert!(matches!(result, Err(WriteError::BackupFailed(_))));
+        assert_eq!(layout().unwrap(), original);
+
+        // An image is not a block device, so the write fails but leaves the backup behind
+        assert!(writer.backup_and_write(&dir).is_err());
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), 1);
+        let name = backups[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with(&format!("{}-", image.file_name().unwrap().to_string_lossy())));
+        assert!(name.ends_with(".ptbackup"));
+
+        writer.erase_signatures().unwrap();
+        assert!(layout().is_err());
+        writer.restore_partition_table_from_backup(&backups[0]).unwrap();
+        let restored = layout();
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&image).unwrap();
+        assert_eq!(restored.unwrap(), original);
+    }
+
+    #[test]
+    fn test_backup_bounds() {
+        let image = std::env::temp_dir().join(format!("disks-rs-bounds-{}.img", std::process::id()));
+        let blk = mock_gpt_image(&image, 64 * MB, &[(MB, 63 * MB, partition_types::LINUX_FS)]);
+
+        // A header with LBAs overflowing a byte offset falls back to the default areas
+        let mut device = fs::OpenOptions::new().read(true).write(true).open(&image).unwrap();
+        device.seek(io::SeekFrom::Start(512 + 40)).unwrap();
+        device.write_all(&u64::MAX.to_le_bytes()).unwrap();
+        let areas = table_areas(&mut device, 64 * MB, 512).unwrap();
+        assert_eq!(areas, [(0, 1024 + 16384), (64 * MB - 512 - 16384, 512 + 16384)]);
+
+        // Backups with areas too large or beyond the device are refused before writing
+        let planner = Planner::new(&blk);
+        let writer = DiskWriter::new(&blk, &planner).with_confirmation(WriteConfirmation::for_device(&blk));
+        let backup = image.with_extension("ptbackup");
+        let mut results = vec![];
+        for (offset, len) in [
+            (0, u64::MAX),
+            (0, 2 * BACKUP_MAX_AREA),
+            (64 * MB - 256, 512),
+            (u64::MAX, 512),
+        ] {
+            let mut contents = BACKUP_MAGIC.to_vec();
+            for value in [64 * MB, offset, len] {
+                contents.extend(value.to_le_bytes());
+            }
+            fs::write(&backup, contents).unwrap();
+            results.push(writer.restore_partition_table_from_backup(&backup));
+        }
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&image).unwrap();
+
+        for result in results {
+            assert!(matches!(result, Err(WriteError::IoError(e)) if e.kind() == io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn test_erase_signatures() {
+        let image = std::env::temp_dir().join(format!("disks-rs-erase-{}.img", std::process::id()));
+        let blk = mock_gpt_image(
+            &image,
+            64 * MB,
+            &[
+                (MB, 17 * MB, partition_types::LINUX_FS),
+                (17 * MB, 63 * MB, partition_types::LINUX_FS),
+            ],
+        );
+
+        // Format both partitions in place within the image
+        for (offset, size) in [(MB, 16 * MB), (17 * MB, 46 * MB)] {
+            let Ok(output) = std::process::Command::new("mkfs.ext4")
+                .arg("-q")
+                .arg("-F")
+                .arg("-E")
+                .arg(format!("offset={offset}"))
+                .arg(&image)
+                .arg(format!("{}k", size / 1024))
+                .output()
+            else {
+                eprintln!("Skipping: mkfs.ext4 not available");
+                fs::remove_file(&image).ok();
+                return;
+            };
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        }
+        let detect = |offset: u64| {
+            let mut file = fs::File::open(&image).unwrap();
+            file.seek(io::SeekFrom::Start(offset)).unwrap();
+            let mut buffer = vec![0u8; 2 * MB as usize];
+            io::Read::read_exact(&mut file, &mut buffer).unwrap();
+            superblock::Superblock::from_bytes(&buffer).map(|sb| sb.kind())
+        };
+        assert!(GptConfig::default().writable(false).open(&image).is_ok());
+        assert_eq!(detect(MB).unwrap(), superblock::Kind::Ext4);
+        assert_eq!(detect(17 * MB).unwrap(), superblock::Kind::Ext4);
+        let size = fs::metadata(&image).unwrap().len();
+
+        let planner = Planner::new(&blk);
+        let writer = DiskWriter::new(&blk, &planner);
+        assert!(matches!(writer.erase_signatures(), Err(WriteError::Unconfirmed)));
+        writer
+            .with_confirmation(WriteConfirmation::for_device(&blk))
+            .erase_signatures()
+            .unwrap();
+
+        assert!(GptConfig::default().writable(false).open(&image).is_err());
+        assert!(detect(MB).is_err());
+        assert!(detect(17 * MB).is_err());
+
+        // Only the signatures were touched, not the bulk of the filesystems
+        assert_eq!(fs::metadata(&image).unwrap().len(), size);
+        let contents = fs::read(&image).unwrap();
+        fs::remove_file(&image).unwrap();
+        assert!(contents[MB as usize..17 * MB as usize].iter().any(|b| *b != 0));
+    }
+}
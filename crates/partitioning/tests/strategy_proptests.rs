@@ -0,0 +1,138 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Property-based tests for partition allocation strategies
+
+use disks::{BlockDevice, mock::MockDisk};
+use partitioning::{
+    planner::{Planner, Region},
+    strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy as PartitionStrategy},
+};
+use proptest::prelude::*;
+
+const MB: u64 = 1024 * 1024;
+
+/// Generate a size requirement with sizes expressed in whole MiB
+fn size_requirement() -> impl Strategy<Value = SizeRequirement> {
+    prop_oneof![
+        (1..4096u64).prop_map(|n| SizeRequirement::Exact(n * MB)),
+        (1..4096u64).prop_map(|n| SizeRequirement::AtLeast(n * MB)),
+        (1..4096u64, 0..4096u64).prop_map(|(min, extra)| SizeRequirement::Range {
+            min: min * MB,
+            max: (min + extra) * MB,
+        }),
+        (1..4096u64).prop_map(|n| SizeRequirement::AtMost(n * MB)),
+        Just(SizeRequirement::Remaining),
+    ]
+}
+
+/// Generate an allocation method, with any specific region in whole MiB
+fn allocation() -> impl Strategy<Value = AllocationStrategy> {
+    prop_oneof![
+        Just(AllocationStrategy::InitializeWholeDisk),
+        Just(AllocationStrategy::LargestFree),
+        Just(AllocationStrategy::BestFitAcrossRegions),
+        (1..4096u64, 1..8192u64).prop_map(|(start, len)| AllocationStrategy::SpecificRegion(Box::new(Region::new(
+            start * MB,
+            (start + len) * MB
+        )))),
+    ]
+}
+
+/// Generate the partitions already on the disk, as `(gap, size)` pairs in whole MiB
+fn existing_partitions() -> impl Strategy<Value = Vec<(u64, u64)>> {
+    prop::collection::vec((0..2048u64, 1..2048u64), 0..4)
+}
+
+/// Build a strategy from the generated allocation method and requirements
+fn build_strategy(allocation: AllocationStrategy, sizes: &[SizeRequirement]) -> PartitionStrategy {
+    let mut strategy = PartitionStrategy::new(allocation);
+    for size in sizes {
+        strategy.add_request(PartitionRequest {
+            size: size.clone(),
+            name: None,
+            attributes: None,
+        });
+    }
+    strategy
+}
+
+/// Build a planner for `device`, planning the existing partitions
+/// one after another, until one no longer fits
+fn build_planner(device: &BlockDevice, existing: &[(u64, u64)]) -> Planner {
+    let mut planner = Planner::new(device);
+    let mut cursor = MB;
+    for (gap, size) in existing {
+        let start = cursor + gap * MB;
+        if planner.plan_add_partition(start, start + size * MB).is_err() {
+            break;
+        }
+        cursor = start + size * MB;
+    }
+    planner
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1000))]
+
+    #[test]
+    fn validate_implies_apply(
+        disk_mb in 1..16_384u64,
+        existing in existing_partitions(),
+        allocation in allocation(),
+        sizes in prop::collection::vec(size_requirement(), 1..8),
+    ) {
+        let device = BlockDevice::mock_device(MockDisk::new(disk_mb * MB));
+        let mut planner = build_planner(&device, &existing);
+        let strategy = build_strategy(allocation, &sizes);
+
+        if strategy.validate(&planner).is_ok() {
+            let result = strategy.apply(&mut planner);
+            prop_assert!(result.is_ok(), "{result:?}");
+        }
+    }
+
+    #[test]
+    fn apply_creates_every_request(
+        disk_mb in 1..16_384u64,
+        existing in existing_partitions(),
+        allocation in allocation(),
+        sizes in prop::collection::vec(size_requirement(), 1..8),
+    ) {
+        let device = BlockDevice::mock_device(MockDisk::new(disk_mb * MB));
+        let mut planner = build_planner(&device, &existing);
+        let before = match allocation {
+            AllocationStrategy::InitializeWholeDisk => 0,
+            _ => planner.current_layout().len(),
+        };
+        let strategy = build_strategy(allocation, &sizes);
+
+        if strategy.apply(&mut planner).is_ok() {
+            prop_assert_eq!(planner.current_layout().len(), before + sizes.len());
+        }
+    }
+
+    #[test]
+    fn apply_never_overlaps(
+        disk_mb in 1..16_384u64,
+        existing in existing_partitions(),
+        allocation in allocation(),
+        sizes in prop::collection::vec(size_requirement(), 1..8),
+    ) {
+        let device = BlockDevice::mock_device(MockDisk::new(disk_mb * MB));
+        let mut planner = build_planner(&device, &existing);
+        let strategy = build_strategy(allocation, &sizes);
+
+        if strategy.apply(&mut planner).is_ok() {
+            let layout = planner.current_layout();
+            for (i, a) in layout.iter().enumerate() {
+                prop_assert!(a.start < a.end);
+                prop_assert!(a.end <= device.size());
+                for b in layout.iter().skip(i + 1) {
+                    prop_assert!(!a.overlaps_with(b), "{}..{} overlaps {}..{}", a.start, a.end, b.start, b.end);
+                }
+            }
+        }
+    }
+}
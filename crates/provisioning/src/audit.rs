@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Machine-readable record of the changes made by a provisioning run
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Value, json};
+
+/// The kinds of event recorded in an audit log
+///
+/// The audit log is a newline-delimited JSON file. Each line is a single JSON
+/// object with the following fields:
+///
+/// - `timestamp`: milliseconds since the Unix epoch at which the event was recorded
+/// - `event_type`: the event name, e.g. `"PartitionCreated"`
+/// - `details`: an object whose fields depend on `event_type`:
+///   - `SignaturesErased`: `disk`, `device`
+///   - `PartitionTableWritten`: `disk`, `device`, `wipe`
+///   - `PartitionCreated`: `disk`, `device`, `partition_id`, `start`, `end`
+///   - `PartitionEncrypted`: `device`, `mapped_device`
+///   - `FilesystemFormatted`: `device`, `filesystem`
+///   - `StrategyApplied`: `strategy`, `disks`
+///
+/// Fields are only ever added to this format, never removed or renamed. Each
+/// line is synced to disk as soon as it is written, so a log cut short by a
+/// crash still holds every event that completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventType {
+    /// The partition table and filesystem signatures of a disk were erased
+    SignaturesErased,
+    /// A partition table was written to a disk
+    PartitionTableWritten,
+    /// A partition was created on a disk
+    PartitionCreated,
+    /// A partition was set up as an encrypted container
+    PartitionEncrypted,
+    /// A partition was formatted with a filesystem
+    FilesystemFormatted,
+    /// All changes for a strategy were applied
+    StrategyApplied,
+}
+
+impl AuditEventType {
+    /// The name recorded as `event_type` in the log
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::SignaturesErased => "SignaturesErased",
+            AuditEventType::PartitionTableWritten => "PartitionTableWritten",
+            AuditEventType::PartitionCreated => "PartitionCreated",
+            AuditEventType::PartitionEncrypted => "PartitionEncrypted",
+            AuditEventType::FilesystemFormatted => "FilesystemFormatted",
+            AuditEventType::StrategyApplied => "StrategyApplied",
+        }
+    }
+}
+
+impl fmt::Display for AuditEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An open audit log, or a no-op sink when auditing is disabled
+pub(crate) struct AuditLog {
+    file: Option<File>,
+}
+
+impl AuditLog {
+    /// Open the log at `path` for appending, or a disabled log if there is no path
+    pub(crate) fn open(path: Option<&Path>) -> io::Result<Self> {
+        let file = path
+            .map(|path| OpenOptions::new().create(true).append(true).open(path))
+            .transpose()?;
+        Ok(Self { file })
+    }
+
+    /// Append an event to the log and sync it to disk
+    pub(crate) fn record(&mut self, event_type: AuditEventType, details: Value) -> io::Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let line = json!({
+            "timestamp": timestamp,
+            "event_type": event_type.as_str(),
+            "details": details,
+        });
+
+        writeln!(file, "{line}")?;
+        file.sync_data()
+    }
+}
@@ -0,0 +1,136 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Device IO performed while executing a provisioning plan
+
+use std::{
+    path::Path,
+    process::{self, Stdio},
+};
+
+use disks::superblock::Superblock;
+use log::debug;
+use partitioning::{
+    FilesystemExt, Formatter,
+    writer::{DiskWriter, WriteConfirmation},
+};
+use types::{EncryptionSpec, Filesystem, KeySource};
+
+use crate::{DevicePlan, ExecuteError, provisioner::mapped_device};
+
+/// Performs the device IO for [`crate::Provisioner::execute_with`]
+///
+/// [`SystemBackend`] writes to the devices and runs the real commands, while
+/// tests substitute a recording implementation, e.g. to execute plans for
+/// mock devices. Mounting is handled separately by [`crate::Mounter`].
+pub trait Backend {
+    /// Erase existing filesystem and partition table signatures from the planned device
+    fn erase_signatures(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError>;
+
+    /// Write the planned partition table and make the kernel aware of the new partitions
+    fn write_table(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError>;
+
+    /// Set up `device` as a LUKS2 container and open it at its mapped device
+    fn encrypt(&mut self, device: &Path, encryption: &EncryptionSpec) -> Result<(), ExecuteError>;
+
+    /// Whether `device` already holds `filesystem`
+    fn has_filesystem(&mut self, device: &Path, filesystem: &Filesystem) -> bool;
+
+    /// Format `device` with `filesystem`
+    fn format(&mut self, device: &Path, filesystem: &Filesystem) -> Result<(), ExecuteError>;
+}
+
+/// Writes to the devices with [`DiskWriter`] and runs the system `cryptsetup` and `mkfs` commands
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemBackend;
+
+impl Backend for SystemBackend {
+    fn erase_signatures(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError> {
+        let device = device_plan.device;
+        DiskWriter::new(device, &device_plan.planner)
+            .with_confirmation(WriteConfirmation::for_device(device))
+            .erase_signatures()?;
+        Ok(())
+    }
+
+    fn write_table(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError> {
+        let device = device_plan.device;
+        // The writer already waits for the kernel to pick up the new partitions
+        DiskWriter::new(device, &device_plan.planner)
+            .with_confirmation(WriteConfirmation::for_device(device))
+            .with_table_type(device_plan.table_type)
+            .write()?;
+        Ok(())
+    }
+
+    fn encrypt(&mut self, device: &Path, encryption: &EncryptionSpec) -> Result<(), ExecuteError> {
+        debug!("Encrypting {}", device.display());
+        let key_file = match &encryption.key_source {
+            KeySource::Passphrase => vec![],
+            KeySource::KeyFile(path) => vec!["--key-file".into(), path.clone().into_os_string()],
+        };
+        let name = mapped_device(device)
+            .file_name()
+            .map(ToOwned::to_owned)
+            .unwrap_or_default();
+
+        let mut format = process::Command::new("cryptsetup");
+        format.args(["luksFormat", "--type", "luks2", "--batch-mode"]);
+        if let Some(cipher) = &encryption.cipher {
+            format.args(["--cipher", cipher]);
+        }
+        format.args(&key_file).arg(device);
+
+        let mut open = process::Command::new("cryptsetup");
+        open.arg("open").args(&key_file).arg(device).arg(name);
+
+        for mut cmd in [format, open] {
+            // Passphrases are prompted for on the terminal
+            let output = cmd
+                .stdin(Stdio::inherit())
+                .output()
+                .map_err(|source| ExecuteError::Encrypt {
+                    device: device.to_owned(),
+                    source,
+                })?;
+            if !output.status.success() {
+                return Err(ExecuteError::EncryptFailed {
+                    device: device.to_owned(),
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn has_filesystem(&mut self, device: &Path, filesystem: &Filesystem) -> bool {
+        match Superblock::from_file(device) {
+            Ok(superblock) => filesystem.matches_detected(&superblock),
+            Err(e) => {
+                debug!("No existing filesystem detected on {}: {e}", device.display());
+                false
+            }
+        }
+    }
+
+    fn format(&mut self, device: &Path, filesystem: &Filesystem) -> Result<(), ExecuteError> {
+        debug!("Formatting {} as {filesystem}", device.display());
+        let output = Formatter::new(filesystem.clone())
+            .force()
+            .format(device)
+            .output()
+            .map_err(|source| ExecuteError::Format {
+                device: device.to_owned(),
+                source,
+            })?;
+
+        if !output.status.success() {
+            return Err(ExecuteError::FormatFailed {
+                device: device.to_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -5,16 +5,20 @@
 
 use crate::Context;
 
-mod create_partition;
+pub(crate) mod create_partition;
 mod create_partition_table;
 mod find_disk;
+mod wipe_signatures;
 
 /// A command
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum Command {
     CreatePartition(Box<create_partition::Command>),
     CreatePartitionTable(Box<create_partition_table::Command>),
     FindDisk(Box<find_disk::Command>),
+    WipeSignatures(Box<wipe_signatures::Command>),
 }
 
 /// Command execution function
@@ -25,6 +29,7 @@ fn command(name: &str) -> Option<CommandExec> {
         "find-disk" => find_disk::parse,
         "create-partition" => create_partition::parse,
         "create-partition-table" => create_partition_table::parse,
+        "wipe-signatures" => wipe_signatures::parse,
         _ => return None,
     })
 }
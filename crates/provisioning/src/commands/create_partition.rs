@@ -3,15 +3,20 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use partitioning::{GptAttributes, PartitionAttributes, TableAttributes, gpt::partition_types};
+use partitioning::{
+    GptAttributes, PartitionAttributes, TableAttributes,
+    gpt::partition_types,
+    strategy::{DEFAULT_KERNEL_COUNT, recommended_efi_size},
+};
 
 use crate::{
-    Constraints, Context, Filesystem, FromKdlProperty, FromKdlType, PartitionRole, PartitionTypeGuid, PartitionTypeKDL,
-    get_kdl_entry, get_kdl_property, get_property_str,
+    Constraints, Context, EncryptionSpec, Filesystem, FromKdlProperty, FromKdlType, GptFlags, PartitionRole,
+    PartitionTypeGuid, PartitionTypeKDL, get_kdl_entry, get_kdl_property, get_property_str,
 };
 
 /// Command to create a partition
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Command {
     /// The disk ID to create the partition on
     pub disk: String,
@@ -19,6 +24,9 @@ pub struct Command {
     /// The reference ID of the partition
     pub id: String,
 
+    /// The name, if any, of the partition
+    pub name: Option<String>,
+
     /// The role, if any, of the partition
     pub role: Option<PartitionRole>,
 
@@ -30,6 +38,12 @@ pub struct Command {
 
     /// The filesystem to format the partition with
     pub filesystem: Option<Filesystem>,
+
+    /// The encryption to set up before formatting, if any
+    pub encryption: Option<EncryptionSpec>,
+
+    /// The GPT attribute flags to set on the partition entry
+    pub flags: GptFlags,
 }
 
 impl Command {
@@ -38,13 +52,21 @@ impl Command {
             table: TableAttributes::Gpt(GptAttributes {
                 type_guid: match &self.partition_type {
                     Some(p) => p.as_guid(),
-                    None => partition_types::BASIC,
+                    None => match &self.filesystem {
+                        Some(fs) => PartitionTypeGuid::recommended_for(fs).as_guid(),
+                        None => partition_types::BASIC,
+                    },
                 },
-                name: self.partition_type.as_ref().map(|p| p.to_string()),
+                name: self
+                    .name
+                    .clone()
+                    .or_else(|| self.partition_type.as_ref().map(|p| p.to_string())),
                 uuid: None,
+                flags: self.flags,
             }),
             role: self.role.clone(),
             filesystem: self.filesystem.clone(),
+            encryption: self.encryption.clone(),
         }
     }
 }
@@ -53,15 +75,26 @@ impl Command {
 pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
     let disk = get_property_str(context.node, "disk")?;
     let id = get_property_str(context.node, "id")?;
+    let name = if context.node.entry("name").is_some() {
+        Some(get_property_str(context.node, "name")?)
+    } else {
+        None
+    };
     let role = if let Ok(role) = get_kdl_property(context.node, "role") {
         Some(PartitionRole::from_kdl_property(role)?)
     } else {
         None
     };
+    let flags = if let Ok(flags) = get_kdl_property(context.node, "flags") {
+        GptFlags::from_kdl_property(flags)?
+    } else {
+        GptFlags::empty()
+    };
 
     let mut constraints = Constraints::default();
     let mut partition_type = None;
     let mut filesystem = None;
+    let mut encryption = None;
 
     for child in context.node.iter_children() {
         match child.name().value() {
@@ -72,6 +105,7 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
                 }
             }
             "filesystem" => filesystem = Some(Filesystem::from_kdl_node(child)?),
+            "encryption" => encryption = Some(EncryptionSpec::from_kdl_node(child)?),
             _ => {
                 return Err(crate::UnsupportedNode {
                     at: child.span(),
@@ -82,10 +116,15 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
         }
     }
 
+    // The ESP can be sized without explicit constraints
+    if matches!(constraints, Constraints::Invalid) && role == Some(PartitionRole::Boot) {
+        constraints = Constraints::Exact(recommended_efi_size(DEFAULT_KERNEL_COUNT));
+    }
+
     if matches!(constraints, Constraints::Invalid) {
         return Err(crate::InvalidArguments {
             at: context.node.span(),
-            advice: Some("create-partition [disk=<disk>] [role=<role>] [constraints=<constraints>] [type=(GUID)] - you must provide constraints".into()),
+            advice: Some("create-partition [disk=<disk>] [name=<name>] [role=<role>] [flags=<flags>] [constraints=<constraints>] [type=(GUID)] - you must provide constraints".into()),
         }
         .into());
     }
@@ -93,9 +132,12 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
     Ok(super::Command::CreatePartition(Box::new(Command {
         disk,
         id,
+        name,
         role,
         constraints,
         partition_type,
         filesystem,
+        encryption,
+        flags,
     })))
 }
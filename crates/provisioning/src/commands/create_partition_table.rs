@@ -2,11 +2,14 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::{Context, get_property_str};
-use crate::{FromKdlProperty, PartitionTableType, get_kdl_property};
+use crate::{Context, UnsupportedValue, get_property_str, kdl_value_to_integer};
+use crate::{
+    FromKdlProperty, GPT_DEFAULT_PARTITION_COUNT, MBR_PRIMARY_PARTITION_COUNT, PartitionTableType, get_kdl_property,
+};
 
 /// Command to create a partition table
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Command {
     /// The type of partition table to create
     pub table_type: PartitionTableType,
@@ -16,7 +19,33 @@ pub struct Command {
 /// Generate a command to create a partition table
 pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
     let kind = get_kdl_property(context.node, "type")?;
-    let table_type = PartitionTableType::from_kdl_property(kind)?;
+    let mut table_type = PartitionTableType::from_kdl_property(kind)?;
+    if let Some(entry) = context.node.entry("partitions") {
+        if table_type == PartitionTableType::Auto {
+            return Err(UnsupportedValue {
+                at: entry.span(),
+                advice: Some("`partitions` cannot be used with type=\"auto\", choose 'gpt' or 'msdos'".into()),
+            }
+            .into());
+        }
+        let range = match table_type.is_gpt() {
+            true => i128::from(GPT_DEFAULT_PARTITION_COUNT)..=i128::from(u32::MAX),
+            false => 1..=i128::from(MBR_PRIMARY_PARTITION_COUNT),
+        };
+        let count = kdl_value_to_integer(entry)?;
+        if !range.contains(&count) {
+            return Err(UnsupportedValue {
+                at: entry.span(),
+                advice: Some(format!(
+                    "`partitions` must be between {} and {} for {table_type}",
+                    range.start(),
+                    range.end()
+                )),
+            }
+            .into());
+        }
+        table_type = table_type.with_partition_count(count as u32);
+    }
     let disk = get_property_str(context.node, "disk")?;
 
     Ok(super::Command::CreatePartitionTable(Box::new(Command {
@@ -7,6 +7,7 @@ use itertools::Itertools;
 use crate::{Constraints, Context};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Command {
     pub name: String,
     pub constraints: Option<Constraints>,
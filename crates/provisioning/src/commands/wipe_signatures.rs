@@ -0,0 +1,22 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{Context, get_property_str};
+
+/// Command to erase the partition table and filesystem signatures of a disk
+///
+/// Only the signatures are zeroed, making the disk appear blank without
+/// overwriting its contents. It is usually followed by `create-partition-table`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Command {
+    pub disk: String,
+}
+
+/// Generate a command to wipe the signatures on a disk
+pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
+    let disk = get_property_str(context.node, "disk")?;
+
+    Ok(super::Command::WipeSignatures(Box::new(Command { disk })))
+}
@@ -0,0 +1,42 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detection of the firmware the machine boots with
+
+use std::path::Path;
+
+use types::PartitionTableType;
+
+/// Directory the kernel only populates when booted via UEFI
+const EFI_SYSFS_DIR: &str = "/sys/firmware/efi";
+
+/// The firmware interface used to boot the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    /// UEFI firmware
+    Uefi,
+    /// Legacy BIOS firmware, or UEFI in compatibility (CSM) mode
+    Bios,
+}
+
+impl Firmware {
+    /// Detect the firmware of the running system from `/sys/firmware/efi`
+    pub fn detect() -> Self {
+        if Path::new(EFI_SYSFS_DIR).exists() {
+            Self::Uefi
+        } else {
+            Self::Bios
+        }
+    }
+
+    /// The partition table type this firmware boots from
+    ///
+    /// UEFI boots from GPT, while legacy BIOS needs an MBR.
+    pub fn partition_table_type(&self) -> PartitionTableType {
+        match self {
+            Self::Uefi => PartitionTableType::Gpt,
+            Self::Bios => PartitionTableType::Msdos,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Strategies and mock disks shared by the provisioning tests
+
+use disks::{BlockDevice, mock::MockDisk};
+
+use crate::{Parser, Provisioner};
+
+/// Size of the mock disk, large enough for every test strategy
+const DISK_SIZE: u64 = 150 * 1024 * 1024 * 1024;
+
+/// Parsed strategies and a mock disk to plan them for
+pub(crate) struct Fixture {
+    pub(crate) strategies: Parser,
+    pub(crate) device: BlockDevice,
+}
+
+impl Fixture {
+    /// The strategies of `tests/use_whole_disk.kdl`
+    pub(crate) fn whole_disk() -> Self {
+        Self::new(Parser::new_for_path("tests/use_whole_disk.kdl").unwrap())
+    }
+
+    /// The strategies parsed from `source`
+    pub(crate) fn from_source(source: &str) -> Self {
+        Self::new(Parser::new("test.kdl", source).unwrap())
+    }
+
+    fn new(strategies: Parser) -> Self {
+        Self {
+            strategies,
+            device: BlockDevice::mock_device(MockDisk::new(DISK_SIZE)),
+        }
+    }
+
+    /// Add the mock disk and every strategy to `provisioner`
+    pub(crate) fn provision<'a>(&'a self, mut provisioner: Provisioner<'a>) -> Provisioner<'a> {
+        provisioner.push_device(&self.device);
+        for def in self.strategies.strategies.iter() {
+            provisioner.add_strategy(def);
+        }
+        provisioner
+    }
+}
@@ -8,6 +8,24 @@ use itertools::{Either, Itertools};
 use kdl::{KdlDocument, KdlNode};
 use miette::{Diagnostic, NamedSource, Severity};
 
+mod audit;
+pub use audit::AuditEventType;
+
+mod backend;
+pub use backend::{Backend, SystemBackend};
+
+mod firmware;
+pub use firmware::Firmware;
+
+#[cfg(test)]
+mod fixture;
+
+mod mount;
+pub use mount::{MountGuard, Mounter, PlannedMount, SubvolumeMount, SystemMounter};
+
+mod mount_config;
+pub use mount_config::{FstabPlugin, MountPlugin, ShellPlugin, SystemdMountPlugin};
+
 mod provisioner;
 pub use provisioner::*;
 
@@ -24,6 +42,7 @@ pub struct Context<'a> {
 
 /// A strategy definition
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct StrategyDefinition {
     /// The name of the strategy
     pub name: String,
@@ -34,10 +53,27 @@ pub struct StrategyDefinition {
     /// The strategy that this strategy inherits from
     pub inherits: Option<String>,
 
+    /// The exact number of disks this strategy must be applied to
+    pub requires_disk_count: Option<usize>,
+
+    /// Whether all disks used by this strategy must be the same size
+    pub requires_equal_disk_sizes: bool,
+
     /// The commands to execute
     pub commands: Vec<Command>,
 }
 
+#[cfg(feature = "serde")]
+impl StrategyDefinition {
+    /// Serialize the strategy as pretty-printed JSON, e.g. to show what it will do in a UI
+    ///
+    /// Commands are keyed by their KDL node name, and values use the names
+    /// accepted in KDL where one exists.
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(self).expect("strategy definitions are always serializable")
+    }
+}
+
 /// A parser for provisioning strategies
 #[derive(Debug)]
 pub struct Parser {
@@ -129,6 +165,32 @@ impl Parser {
         } else {
             None
         };
+        let requires_disk_count = match node.entry("requires-disk-count").map(kdl_value_to_integer) {
+            Some(Ok(count)) if count > 0 => Some(count as usize),
+            Some(Ok(_)) => {
+                errors.push(
+                    UnsupportedValue {
+                        at: node.entry("requires-disk-count").unwrap().span(),
+                        advice: Some("`requires-disk-count` must be at least 1".into()),
+                    }
+                    .into(),
+                );
+                None
+            }
+            Some(Err(e)) => {
+                errors.push(e);
+                None
+            }
+            None => None,
+        };
+        let requires_equal_disk_sizes = match node.entry("requires-equal-disk-sizes").map(kdl_value_to_bool) {
+            Some(Ok(equal)) => equal,
+            Some(Err(e)) => {
+                errors.push(e);
+                false
+            }
+            None => false,
+        };
 
         // Collect all failures in this strategy
         let (commands, child_errors): (Vec<_>, Vec<_>) =
@@ -155,6 +217,8 @@ impl Parser {
             name,
             summary,
             inherits,
+            requires_disk_count,
+            requires_equal_disk_sizes,
             commands,
         };
 
@@ -164,7 +228,13 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use crate::Parser;
+    use partitioning::gpt::partition_types;
+    use types::GptPartitionType;
+
+    use crate::{
+        BtrfsSubvol, Command, Constraints, EncryptionSpec, Filesystem, GptFlags, KeySource, ParseError, Parser,
+        PartitionRole, PartitionTableType, StandardFilesystemType, commands::create_partition,
+    };
 
     #[test]
     //#[should_panic]
@@ -173,4 +243,394 @@ mod tests {
         eprintln!("p: {_p:?}");
         Ok(())
     }
+
+    /// Parse a strategy holding a single command
+    fn parse_command(command: &str) -> Result<Command, ParseError> {
+        let source = format!(
+            r#"strategy name="test" summary="Test" {{
+    {command}
+}}
+"#
+        );
+        let parser = Parser::new("test.kdl", &source)?;
+        Ok(parser.strategies.into_iter().next().unwrap().commands.remove(0))
+    }
+
+    /// Parse a single create-partition command with the given properties and children
+    fn parse_partition(properties: &str, children: &str) -> Result<Box<create_partition::Command>, ParseError> {
+        let command = parse_command(&format!(
+            r#"create-partition disk="root_disk" id="part" {properties} {{
+        {children}
+    }}"#
+        ))?;
+        match command {
+            Command::CreatePartition(command) => Ok(command),
+            command => panic!("unexpected command {command:?}"),
+        }
+    }
+
+    /// Parse a single create-partition command with the given constraints body
+    fn parse_constraints(constraints: &str) -> Result<Constraints, ParseError> {
+        let command = parse_partition("", &format!("constraints {{\n            {constraints}\n        }}"))?;
+        Ok(command.constraints)
+    }
+
+    /// Parse a single create-partition command with the given filesystem type
+    fn partition_type_for_filesystem(filesystem: &str) -> GptPartitionType {
+        let children = format!("constraints {{ min (GiB)1; }}\n        filesystem {{ type \"{filesystem}\"; }}");
+        let command = parse_partition("", &children).unwrap();
+        command.attributes().table.as_gpt().unwrap().type_guid.clone()
+    }
+
+    #[test]
+    fn test_default_type_from_filesystem() {
+        assert_eq!(partition_type_for_filesystem("swap"), partition_types::LINUX_SWAP);
+        assert_eq!(partition_type_for_filesystem("ext4"), partition_types::LINUX_FS);
+        assert_eq!(partition_type_for_filesystem("fat32"), partition_types::EFI);
+    }
+
+    #[test]
+    fn test_btrfs_subvolumes() {
+        let filesystem = |filesystem: &str| {
+            let children =
+                format!("constraints {{ min (GiB)20; }}\n        filesystem {{\n            {filesystem}\n        }}");
+            parse_partition(r#"role="root""#, &children).map(|command| command.attributes())
+        };
+
+        let attributes = filesystem(
+            r#"type "btrfs"
+            subvolume "@" mountpoint="/"
+            subvolume "@home" mountpoint="/home"
+            subvolume "@snapshots""#,
+        )
+        .unwrap();
+        assert_eq!(
+            attributes.subvolumes(),
+            [
+                BtrfsSubvol {
+                    name: "@".into(),
+                    mount_point: Some("/".into()),
+                },
+                BtrfsSubvol {
+                    name: "@home".into(),
+                    mount_point: Some("/home".into()),
+                },
+                BtrfsSubvol {
+                    name: "@snapshots".into(),
+                    mount_point: None,
+                },
+            ]
+        );
+        assert!(filesystem(r#"type "btrfs""#).unwrap().subvolumes().is_empty());
+
+        // Only Btrfs has subvolumes
+        assert!(
+            filesystem(
+                r#"type "ext4"
+            subvolume "@""#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_esp_default_size() {
+        let command = parse_partition(r#"role="boot""#, "").unwrap();
+        assert_eq!(command.constraints, Constraints::Exact(512 * 1024 * 1024));
+
+        // Other roles still need explicit constraints
+        assert!(parse_partition(r#"role="root""#, "").is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = |flags: &str| {
+            parse_partition(flags, "constraints { min (GiB)1; }")
+                .map(|command| command.attributes().table.as_gpt().unwrap().flags)
+        };
+
+        assert_eq!(flags("").unwrap(), GptFlags::empty());
+        assert_eq!(
+            flags(r#"flags="legacy-bios-bootable""#).unwrap(),
+            GptFlags::LEGACY_BIOS_BOOTABLE
+        );
+        let parsed = flags(r#"flags="required, read-only,no-automount""#).unwrap();
+        assert_eq!(
+            parsed,
+            GptFlags::REQUIRED | GptFlags::READ_ONLY | GptFlags::NO_AUTOMOUNT
+        );
+        assert_eq!(parsed.bits(), 1 | (1 << 60) | (1 << 63));
+        assert_eq!(parsed.to_string(), "required,read-only,no-automount");
+        assert!(flags(r#"flags="bootable""#).is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let role = |role: &str| {
+            parse_partition(&format!(r#"role="{role}""#), "constraints { min (GiB)1; }")
+                .map(|command| command.role.unwrap())
+        };
+
+        let expected = [
+            ("esp", PartitionRole::Boot, "/efi"),
+            ("xbootldr", PartitionRole::ExtendedBoot, "/boot"),
+            ("root", PartitionRole::Root, "/"),
+            ("usr", PartitionRole::Usr, "/usr"),
+            ("home", PartitionRole::Home, "/home"),
+            ("var", PartitionRole::Var, "/var"),
+            ("srv", PartitionRole::Srv, "/srv"),
+            ("swap", PartitionRole::Swap, "swap"),
+        ];
+        for (token, expected, path) in expected {
+            let parsed = role(token).unwrap();
+            assert_eq!(parsed, expected);
+            assert_eq!(parsed.as_path(), path);
+        }
+
+        // Every role round-trips through its canonical name
+        for expected in PartitionRole::ALL {
+            assert_eq!(role(&expected.to_string()).unwrap(), expected);
+        }
+
+        let err = role("tmp").unwrap_err();
+        assert!(
+            err.to_json_array()[0]["advice"]
+                .as_str()
+                .unwrap()
+                .contains("'xbootldr'")
+        );
+    }
+
+    #[test]
+    fn test_default_mount_options() {
+        let ext4 = Filesystem::Standard {
+            filesystem_type: StandardFilesystemType::Ext4,
+            label: None,
+            uuid: None,
+            subvolumes: None,
+        };
+        let fat = Filesystem::Fat32 {
+            label: None,
+            volume_id: None,
+        };
+
+        let boot = PartitionRole::Boot.default_mount_options(&fat);
+        assert!(boot.iter().any(|option| option.starts_with("umask=")));
+        assert!(!PartitionRole::ExtendedBoot.default_mount_options(&ext4).is_empty());
+        assert_eq!(PartitionRole::Root.default_mount_options(&ext4), vec!["noatime"]);
+        assert_eq!(
+            PartitionRole::Home.default_mount_options(&ext4),
+            vec!["nodev", "nosuid"]
+        );
+        assert!(PartitionRole::Swap.default_mount_options(&ext4).is_empty());
+    }
+
+    #[test]
+    fn test_filesystem_capabilities() {
+        let all = StandardFilesystemType::all();
+        assert!(all.contains(&StandardFilesystemType::Ext4));
+        for filesystem_type in &all {
+            assert_eq!(
+                filesystem_type.to_string().parse::<StandardFilesystemType>().unwrap(),
+                *filesystem_type
+            );
+        }
+
+        let ext4 = StandardFilesystemType::Ext4.capabilities();
+        assert!(ext4.supports_label && ext4.supports_uuid && ext4.supports_resize);
+        assert_eq!(ext4.max_label_len, 16);
+
+        let swap = StandardFilesystemType::Swap.capabilities();
+        assert_eq!(swap.max_label_len, 16);
+        assert!(!swap.supports_resize);
+
+        let fat = Filesystem::Fat32 {
+            label: None,
+            volume_id: None,
+        };
+        assert_eq!(fat.capabilities().max_label_len, 11);
+    }
+
+    #[test]
+    fn test_encryption() {
+        let source = r#"strategy name="test" summary="Encrypted root" {
+    create-partition disk="root_disk" id="root" role="root" {
+        constraints {
+            min (GiB)20
+        }
+        encryption {
+            cipher "aes-xts-plain64"
+            key-file "/run/installer/root.key"
+        }
+        filesystem {
+            type "ext4"
+        }
+    }
+    create-partition disk="root_disk" id="home" {
+        constraints {
+            remaining
+        }
+        encryption
+    }
+}
+"#;
+        let parser = Parser::new("encrypted.kdl", source).unwrap();
+        let encryption = |index: usize| match &parser.strategies[0].commands[index] {
+            Command::CreatePartition(command) => command.attributes().encryption,
+            command => panic!("unexpected command {command:?}"),
+        };
+
+        assert_eq!(
+            encryption(0),
+            Some(EncryptionSpec {
+                cipher: Some("aes-xts-plain64".into()),
+                key_source: KeySource::KeyFile("/run/installer/root.key".into()),
+            })
+        );
+        assert_eq!(
+            encryption(1),
+            Some(EncryptionSpec {
+                cipher: None,
+                key_source: KeySource::Passphrase,
+            })
+        );
+    }
+
+    #[test]
+    fn test_partition_table_count() {
+        let table_type = |properties: &str| {
+            parse_command(&format!(r#"create-partition-table disk="root_disk" {properties}"#)).map(|command| {
+                match command {
+                    Command::CreatePartitionTable(command) => command.table_type,
+                    command => panic!("unexpected command {command:?}"),
+                }
+            })
+        };
+
+        assert_eq!(table_type(r#"type="gpt""#).unwrap(), PartitionTableType::Gpt);
+        assert_eq!(
+            table_type(r#"type="gpt" partitions=256"#).unwrap(),
+            PartitionTableType::GptWithPartCount(256)
+        );
+        assert_eq!(
+            table_type(r#"type="msdos" partitions=2"#).unwrap(),
+            PartitionTableType::MbrWithPartCount(2)
+        );
+        assert_eq!(PartitionTableType::Msdos.partition_count(), 4);
+
+        // GPT needs at least 128 entries and MBR has at most 4 primary partitions
+        assert!(table_type(r#"type="gpt" partitions=64"#).is_err());
+        assert!(table_type(r#"type="msdos" partitions=5"#).is_err());
+
+        // Auto is resolved at plan time, so the entry count is left to the resolved table
+        assert_eq!(table_type(r#"type="auto""#).unwrap(), PartitionTableType::Auto);
+        assert!(table_type(r#"type="auto" partitions=128"#).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_json_pretty() {
+        let parser = Parser::new(
+            "json.kdl",
+            r#"strategy name="json" summary="Export" {
+    find-disk "root_disk"
+    create-partition-table type="gpt" disk="root_disk"
+    create-partition disk="root_disk" id="esp" role="boot" flags="required" {
+        constraints { exactly (GB)1; }
+        type (GUID)"efi-system-partition"
+        filesystem { type "fat32"; label "EFI"; }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&parser.strategies[0].to_json_pretty()).unwrap();
+        assert_eq!(json["name"], "json");
+        let commands = json["commands"].as_array().unwrap();
+        assert_eq!(commands[0]["find-disk"]["name"], "root_disk");
+        assert_eq!(commands[1]["create-partition-table"]["table_type"], "gpt");
+
+        let partition = &commands[2]["create-partition"];
+        assert_eq!(partition["role"], "boot");
+        assert_eq!(partition["partition_type"], "efi-system-partition");
+        assert_eq!(partition["flags"], "required");
+        assert_eq!(partition["constraints"]["exact"], 1_000_000_000);
+        assert_eq!(partition["filesystem"]["fat32"]["label"], "EFI");
+    }
+
+    #[test]
+    fn test_constraints_min() {
+        let constraints = parse_constraints("min (GiB)30").unwrap();
+        assert_eq!(constraints, Constraints::AtLeast(30 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_constraints_range() {
+        let constraints = parse_constraints("min (MiB)512\n            max (GiB)2").unwrap();
+        assert_eq!(
+            constraints,
+            Constraints::Range {
+                min: 512 * 1024 * 1024,
+                max: 2 * 1024 * 1024 * 1024
+            }
+        );
+        assert_eq!(
+            parse_constraints("exact (MiB)100").unwrap(),
+            Constraints::Exact(100 * 1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn test_constraints_max() {
+        let constraints = parse_constraints("max (GiB)8").unwrap();
+        assert_eq!(constraints, Constraints::AtMost(8 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_constraints_contradictory() {
+        let err = parse_constraints("exact (GiB)1\n            min (GiB)2").unwrap_err();
+        let json = err.to_json_array();
+        assert_eq!(json[0]["code"], "unsupported-value");
+        assert_eq!(json[0]["advice"], "`exact` cannot be combined with other constraints");
+
+        // The diagnostic points at the conflicting `min` child
+        let source = err.src.inner();
+        let offset = json[0]["span"]["offset"].as_u64().unwrap() as usize;
+        assert!(source[offset..].starts_with("min (GiB)2"));
+
+        let err = parse_constraints("min (GiB)4\n            max (GiB)2").unwrap_err();
+        assert_eq!(err.to_json_array()[0]["advice"], "`max` must not be smaller than `min`");
+    }
+
+    #[test]
+    fn test_error_json() {
+        let source = r#"strategy name="broken" summary="Missing constraints" {
+    create-partition disk="root_disk" id="root"
+}
+bogus
+"#;
+        let err = Parser::new("broken.kdl", source).unwrap_err();
+        let json = err.to_json_array();
+        let diagnostics = json.as_array().unwrap();
+        assert_eq!(diagnostics.len(), 2);
+
+        let invalid = &diagnostics[0];
+        assert_eq!(invalid["code"], "invalid-arguments");
+        assert_eq!(invalid["message"], "invalid arguments");
+        assert!(
+            invalid["advice"]
+                .as_str()
+                .unwrap()
+                .contains("you must provide constraints")
+        );
+        let offset = invalid["span"]["offset"].as_u64().unwrap() as usize;
+        let length = invalid["span"]["length"].as_u64().unwrap() as usize;
+        assert!(source[offset..offset + length].starts_with("create-partition"));
+
+        let unsupported = &diagnostics[1];
+        assert_eq!(unsupported["code"], "unsupported-node");
+        assert_eq!(unsupported["message"], "unsupported node: bogus");
+        assert!(unsupported["advice"].is_null());
+    }
 }
@@ -0,0 +1,451 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Mounting a provisioned layout under a target root, e.g. for a chroot
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use disks::run_command;
+use log::{debug, warn};
+use types::PartitionRole;
+
+use crate::Plan;
+
+/// Performs the mounts for [`Plan::mount_all_with`]
+///
+/// [`SystemMounter`] runs the real commands, while tests substitute a
+/// recording implementation to check the order of operations.
+pub trait Mounter {
+    /// Mount `device` on `target` with `options`, which may not exist yet
+    fn mount(&mut self, device: &Path, target: &Path, options: &[String]) -> io::Result<()>;
+
+    /// Mount a subvolume of the Btrfs filesystem on `device` on `target` with `options`, which may not exist yet
+    fn mount_subvolume(&mut self, device: &Path, subvolume: &str, target: &Path, options: &[String]) -> io::Result<()>;
+
+    /// Unmount whatever is mounted on `target`
+    fn unmount(&mut self, target: &Path) -> io::Result<()>;
+
+    /// Enable `device` as swap
+    fn swapon(&mut self, device: &Path) -> io::Result<()>;
+
+    /// Disable `device` as swap
+    fn swapoff(&mut self, device: &Path) -> io::Result<()>;
+}
+
+/// Mounts with the system `mount`, `umount`, `swapon` and `swapoff` commands
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemMounter;
+
+impl Mounter for SystemMounter {
+    fn mount(&mut self, device: &Path, target: &Path, options: &[String]) -> io::Result<()> {
+        fs::create_dir_all(target)?;
+        disks::mount_detected_with_options(device, target, options)
+    }
+
+    fn mount_subvolume(&mut self, device: &Path, subvolume: &str, target: &Path, options: &[String]) -> io::Result<()> {
+        fs::create_dir_all(target)?;
+        let options = std::iter::once(format!("subvol={subvolume}"))
+            .chain(options.iter().cloned())
+            .collect::<Vec<_>>();
+        run_command(
+            Command::new("mount")
+                .args(["-t", "btrfs", "-o"])
+                .arg(options.join(","))
+                .arg(device)
+                .arg(target),
+        )
+    }
+
+    fn unmount(&mut self, target: &Path) -> io::Result<()> {
+        run_command(Command::new("umount").arg(target))
+    }
+
+    fn swapon(&mut self, device: &Path) -> io::Result<()> {
+        run_command(Command::new("swapon").arg(device))
+    }
+
+    fn swapoff(&mut self, device: &Path) -> io::Result<()> {
+        run_command(Command::new("swapoff").arg(device))
+    }
+}
+
+/// Something mounted by [`Plan::mount_all_with`] that must be undone
+#[derive(Debug)]
+enum Mounted {
+    /// A filesystem mounted on the path
+    Mount(PathBuf),
+    /// A swap device that was enabled
+    Swap(PathBuf),
+}
+
+/// Keeps a layout mounted, unmounting it in reverse order when dropped
+///
+/// Errors while unmounting are logged rather than reported, as `Drop` cannot
+/// fail. Call [`MountGuard::unmount`] to handle them instead.
+pub struct MountGuard {
+    mounter: Box<dyn Mounter>,
+    mounted: Vec<Mounted>,
+}
+
+impl MountGuard {
+    /// Unmount everything, stopping at the first error
+    ///
+    /// Anything still mounted after an error is unmounted when the guard is dropped.
+    pub fn unmount(mut self) -> io::Result<()> {
+        self.unmount_all()
+    }
+
+    fn unmount_all(&mut self) -> io::Result<()> {
+        while let Some(mounted) = self.mounted.pop() {
+            debug!("Undoing {mounted:?}");
+            match &mounted {
+                Mounted::Mount(target) => self.mounter.unmount(target),
+                Mounted::Swap(device) => self.mounter.swapoff(device),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MountGuard {
+    fn drop(&mut self) {
+        while !self.mounted.is_empty() {
+            if let Err(e) = self.unmount_all() {
+                warn!("Failed to unmount: {e}");
+            }
+        }
+    }
+}
+
+/// A Btrfs subvolume to mount, see [`Plan::subvolume_mounts`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubvolumeMount {
+    /// The device holding the filesystem, or its opened container when encrypted
+    pub device: PathBuf,
+    /// Name of the subvolume within the filesystem
+    pub subvolume: String,
+}
+
+/// A single mount performed by [`Plan::mount_all_with`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMount<'a> {
+    /// The role being mounted, or `None` for a subvolume mounted at its own mount point
+    pub role: Option<&'a PartitionRole>,
+    /// The device to mount
+    pub device: &'a Path,
+    /// Where the device is mounted in the target system, or `swap`
+    pub mount_point: &'a Path,
+    /// The Btrfs subvolume to mount instead of the top of the filesystem
+    pub subvolume: Option<&'a str>,
+    /// Options to mount with, see [`Plan::mount_options`]
+    pub options: &'a [String],
+}
+
+impl PlannedMount<'_> {
+    /// Whether this enables swap rather than mounting a filesystem
+    pub fn is_swap(&self) -> bool {
+        self.role == Some(&PartitionRole::Swap)
+    }
+
+    /// Where this is mounted when the target system is assembled under `target_root`
+    pub fn target(&self, target_root: &Path) -> PathBuf {
+        match self.mount_point.strip_prefix("/").unwrap_or(self.mount_point) {
+            relative if relative.as_os_str().is_empty() => target_root.to_owned(),
+            relative => target_root.join(relative),
+        }
+    }
+}
+
+impl Plan<'_> {
+    /// Returns every mount for the layout, in the order they must be mounted
+    ///
+    /// Mount points are ordered by depth so that `/` is mounted before anything
+    /// beneath it. Swap comes last. A subvolume mounted at the mount point of a
+    /// role on the same device, such as `@` at `/` for the root partition,
+    /// replaces the mount of the top of the filesystem for that role.
+    pub fn mount_order(&self) -> Vec<PlannedMount<'_>> {
+        let options = |role: Option<&PartitionRole>| {
+            role.and_then(|role| self.mount_options.get(role))
+                .map_or(&[][..], Vec::as_slice)
+        };
+        let subvolumes = self.subvolume_mounts.iter().map(|(mount_point, mount)| {
+            let role = self
+                .role_mounts
+                .iter()
+                .find(|(role, device)| **device == mount.device && Path::new(role.as_path()) == mount_point)
+                .map(|(role, _)| role);
+            PlannedMount {
+                role,
+                device: &mount.device,
+                mount_point,
+                subvolume: Some(&mount.subvolume),
+                options: options(role),
+            }
+        });
+        let roles = self
+            .role_mounts
+            .iter()
+            .filter(|(role, device)| {
+                self.subvolume_mounts
+                    .get(Path::new(role.as_path()))
+                    .is_none_or(|mount| mount.device != **device)
+            })
+            .map(|(role, device)| PlannedMount {
+                role: Some(role),
+                device,
+                mount_point: Path::new(role.as_path()),
+                subvolume: None,
+                options: options(Some(role)),
+            });
+
+        let mut mounts = roles.chain(subvolumes).collect::<Vec<_>>();
+        mounts.sort_by_key(|mount| {
+            (
+                mount.is_swap(),
+                mount.mount_point.components().count(),
+                mount.mount_point,
+            )
+        });
+        mounts
+    }
+
+    /// Mount the layout under `target_root` using the system commands
+    ///
+    /// See [`Plan::mount_all_with`].
+    pub fn mount_all(&self, target_root: impl AsRef<Path>) -> io::Result<MountGuard> {
+        self.mount_all_with(target_root, SystemMounter)
+    }
+
+    /// Mount everything in [`Plan::mount_order`] under `target_root`, enabling swap
+    ///
+    /// Everything mounted is undone in reverse order when the returned guard is
+    /// dropped, including when a later mount fails. Btrfs subvolumes must
+    /// already exist, as they are not created when formatting.
+    pub fn mount_all_with(
+        &self,
+        target_root: impl AsRef<Path>,
+        mounter: impl Mounter + 'static,
+    ) -> io::Result<MountGuard> {
+        let target_root = target_root.as_ref();
+        let mut guard = MountGuard {
+            mounter: Box::new(mounter),
+            mounted: vec![],
+        };
+
+        for mount in self.mount_order() {
+            let device = mount.device;
+            if mount.is_swap() {
+                debug!("Enabling swap on {}", device.display());
+                guard.mounter.swapon(device)?;
+                guard.mounted.push(Mounted::Swap(device.to_owned()));
+                continue;
+            }
+
+            let target = mount.target(target_root);
+            match mount.subvolume {
+                Some(subvolume) => {
+                    debug!("Mounting {}:{subvolume} on {}", device.display(), target.display());
+                    guard
+                        .mounter
+                        .mount_subvolume(device, subvolume, &target, mount.options)?;
+                }
+                None => {
+                    debug!("Mounting {} on {}", device.display(), target.display());
+                    guard.mounter.mount(device, &target, mount.options)?;
+                }
+            }
+            guard.mounted.push(Mounted::Mount(target));
+        }
+
+        Ok(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    use super::*;
+    use crate::{Provisioner, fixture::Fixture};
+
+    /// Records every operation, failing mounts on the given target
+    struct RecordingMounter {
+        log: Rc<RefCell<Vec<String>>>,
+        fail_on: Option<PathBuf>,
+    }
+
+    impl Mounter for RecordingMounter {
+        fn mount(&mut self, device: &Path, target: &Path, options: &[String]) -> io::Result<()> {
+            if self.fail_on.as_deref() == Some(target) {
+                return Err(io::Error::other("mount failed"));
+            }
+            let options = match options {
+                [] => String::new(),
+                options => format!(" -o {}", options.join(",")),
+            };
+            self.log
+                .borrow_mut()
+                .push(format!("mount{options} {} {}", device.display(), target.display()));
+            Ok(())
+        }
+
+        fn mount_subvolume(
+            &mut self,
+            device: &Path,
+            subvolume: &str,
+            target: &Path,
+            options: &[String],
+        ) -> io::Result<()> {
+            let options = match options {
+                [] => String::new(),
+                options => format!(" -o {}", options.join(",")),
+            };
+            self.log.borrow_mut().push(format!(
+                "mount{options} {} subvol={subvolume} {}",
+                device.display(),
+                target.display()
+            ));
+            Ok(())
+        }
+
+        fn unmount(&mut self, target: &Path) -> io::Result<()> {
+            self.log.borrow_mut().push(format!("umount {}", target.display()));
+            Ok(())
+        }
+
+        fn swapon(&mut self, device: &Path) -> io::Result<()> {
+            self.log.borrow_mut().push(format!("swapon {}", device.display()));
+            Ok(())
+        }
+
+        fn swapoff(&mut self, device: &Path) -> io::Result<()> {
+            self.log.borrow_mut().push(format!("swapoff {}", device.display()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mount_all() {
+        let fixture = Fixture::whole_disk();
+        let provisioner = fixture.provision(Provisioner::new());
+        let mut plans = provisioner.plan().unwrap();
+        let plan = &mut plans[0];
+        plan.role_mounts = HashMap::from([
+            (PartitionRole::Boot, "/dev/mock01".into()),
+            (PartitionRole::Home, "/dev/mock04".into()),
+            (PartitionRole::Root, "/dev/mock03".into()),
+            (PartitionRole::Swap, "/dev/mock05".into()),
+            (PartitionRole::ExtendedBoot, "/dev/mock02".into()),
+        ]);
+        plan.mount_options
+            .insert(PartitionRole::Home, vec!["nodev".into(), "noexec".into()]);
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let guard = plan
+            .mount_all_with(
+                "/mnt",
+                RecordingMounter {
+                    log: log.clone(),
+                    fail_on: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "mount -o noatime /dev/mock03 /mnt",
+                "mount -o umask=0077,noexec,nosuid,nodev /dev/mock02 /mnt/boot",
+                "mount -o umask=0077,noexec,nosuid,nodev /dev/mock01 /mnt/efi",
+                "mount -o nodev,noexec /dev/mock04 /mnt/home",
+                "swapon /dev/mock05",
+            ]
+        );
+
+        log.borrow_mut().clear();
+        drop(guard);
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "swapoff /dev/mock05",
+                "umount /mnt/home",
+                "umount /mnt/efi",
+                "umount /mnt/boot",
+                "umount /mnt",
+            ]
+        );
+
+        // A failed mount unwinds the ones before it
+        log.borrow_mut().clear();
+        let result = plan.mount_all_with(
+            "/mnt",
+            RecordingMounter {
+                log: log.clone(),
+                fail_on: Some("/mnt/efi".into()),
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "mount -o noatime /dev/mock03 /mnt",
+                "mount -o umask=0077,noexec,nosuid,nodev /dev/mock02 /mnt/boot",
+                "umount /mnt/boot",
+                "umount /mnt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mount_subvolumes() {
+        let source = r#"strategy name="btrfs" summary="Btrfs root" {
+    find-disk "root_disk"
+    create-partition-table type="gpt" disk="root_disk"
+    create-partition disk="root_disk" id="esp" role="boot"
+    create-partition disk="root_disk" id="root" role="root" {
+        constraints {
+            remaining
+        }
+        filesystem {
+            type "btrfs"
+            subvolume "@" mountpoint="/"
+            subvolume "@home" mountpoint="/home"
+            subvolume "@snapshots"
+        }
+    }
+}
+"#;
+        let fixture = Fixture::from_source(source);
+        let provisioner = fixture.provision(Provisioner::new());
+        let plans = provisioner.plan().unwrap();
+
+        let mounts = plans[0].mount_order();
+        assert_eq!(mounts[0].role, Some(&PartitionRole::Root));
+        assert_eq!(mounts[0].subvolume, Some("@"));
+        assert_eq!(mounts[2].role, None);
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let guard = plans[0]
+            .mount_all_with(
+                "/mnt",
+                RecordingMounter {
+                    log: log.clone(),
+                    fail_on: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "mount -o noatime /dev/mock02 subvol=@ /mnt",
+                "mount /dev/mock01 /mnt/efi",
+                "mount /dev/mock02 subvol=@home /mnt/home",
+            ]
+        );
+        drop(guard);
+    }
+}
@@ -0,0 +1,270 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Mount configuration for the provisioned system, e.g. `/etc/fstab`
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use types::{Filesystem, PartitionRole};
+
+use crate::PlannedMount;
+
+/// Describes a planned mount in an OS-specific format
+///
+/// Used with [`crate::Provisioner::generate_mount_config`], which keeps the disk
+/// layout separate from how the installed system is told to mount it.
+pub trait MountPlugin {
+    /// Describe a single mount
+    ///
+    /// `filesystem` is `None` for existing partitions that are used without
+    /// being formatted, whose type is left for the system to detect.
+    fn describe_mount(&self, mount: &PlannedMount<'_>, filesystem: Option<&Filesystem>) -> String;
+}
+
+/// Returns the type passed to `mount -t`, or `None` to let it be detected
+fn mount_type(filesystem: Option<&Filesystem>) -> Option<String> {
+    filesystem.map(|filesystem| match filesystem {
+        Filesystem::Fat32 { .. } => "vfat".to_owned(),
+        Filesystem::Standard { filesystem_type, .. } => filesystem_type.to_string(),
+    })
+}
+
+/// Returns the mount options including any Btrfs subvolume
+fn mount_options(mount: &PlannedMount<'_>) -> Vec<String> {
+    mount
+        .subvolume
+        .map(|subvolume| format!("subvol={subvolume}"))
+        .into_iter()
+        .chain(mount.options.iter().cloned())
+        .collect()
+}
+
+/// Returns how the device is referred to, by UUID when one was requested for the filesystem
+fn mount_source(mount: &PlannedMount<'_>, filesystem: Option<&Filesystem>) -> String {
+    match filesystem {
+        Some(Filesystem::Standard { uuid: Some(uuid), .. }) => format!("UUID={uuid}"),
+        _ => mount.device.display().to_string(),
+    }
+}
+
+/// Generates `/etc/fstab` entries
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FstabPlugin;
+
+impl MountPlugin for FstabPlugin {
+    fn describe_mount(&self, mount: &PlannedMount<'_>, filesystem: Option<&Filesystem>) -> String {
+        let source = mount_source(mount, filesystem);
+        if mount.is_swap() {
+            return format!("{source} none swap defaults 0 0");
+        }
+
+        let options = match mount_options(mount) {
+            options if options.is_empty() => "defaults".to_owned(),
+            options => options.join(","),
+        };
+        let pass = if mount.role == Some(&PartitionRole::Root) { 1 } else { 2 };
+        format!(
+            "{source} {} {} {options} 0 {pass}",
+            mount.mount_point.display(),
+            mount_type(filesystem).as_deref().unwrap_or("auto"),
+        )
+    }
+}
+
+/// Generates systemd `.mount` and `.swap` units
+///
+/// Each unit starts with a comment naming the file it should be saved as.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemdMountPlugin;
+
+impl SystemdMountPlugin {
+    /// Escape a path for use as a unit name, as `systemd-escape --path` does
+    fn unit_name(path: &Path, suffix: &str) -> String {
+        let path = path.to_string_lossy();
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return format!("-.{suffix}");
+        }
+
+        let mut name = String::new();
+        for (i, byte) in path.bytes().enumerate() {
+            match byte {
+                b'/' => name.push('-'),
+                b'.' if i == 0 => name.push_str("\\x2e"),
+                b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b':' => name.push(b as char),
+                b => {
+                    let _ = write!(name, "\\x{b:02x}");
+                }
+            }
+        }
+        format!("{name}.{suffix}")
+    }
+}
+
+impl MountPlugin for SystemdMountPlugin {
+    fn describe_mount(&self, mount: &PlannedMount<'_>, filesystem: Option<&Filesystem>) -> String {
+        let what = mount.device.display();
+        if mount.is_swap() {
+            return format!(
+                "# {}\n[Unit]\nDescription=Swap on {what}\n\n[Swap]\nWhat={what}\n\n[Install]\nWantedBy=swap.target\n",
+                Self::unit_name(mount.device, "swap")
+            );
+        }
+
+        let mut unit = format!(
+            "# {}\n[Unit]\nDescription=Mount {what} on {}\n\n[Mount]\nWhat={what}\nWhere={}\n",
+            Self::unit_name(mount.mount_point, "mount"),
+            mount.mount_point.display(),
+            mount.mount_point.display(),
+        );
+        if let Some(fstype) = mount_type(filesystem) {
+            let _ = writeln!(unit, "Type={fstype}");
+        }
+        let options = mount_options(mount);
+        if !options.is_empty() {
+            let _ = writeln!(unit, "Options={}", options.join(","));
+        }
+        unit.push_str("\n[Install]\nWantedBy=local-fs.target\n");
+        unit
+    }
+}
+
+/// Generates shell commands mounting the layout under a target root
+///
+/// Every path is single-quoted, so the commands are safe to run whatever the
+/// target root and device names contain.
+#[derive(Debug, Clone)]
+pub struct ShellPlugin {
+    target_root: PathBuf,
+}
+
+impl ShellPlugin {
+    /// Mount points are created and mounted beneath `target_root`, e.g. `/mnt`
+    pub fn new(target_root: impl Into<PathBuf>) -> Self {
+        Self {
+            target_root: target_root.into(),
+        }
+    }
+}
+
+/// Quote `path` as a single shell word, so spaces and other special characters are kept literally
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+impl MountPlugin for ShellPlugin {
+    fn describe_mount(&self, mount: &PlannedMount<'_>, filesystem: Option<&Filesystem>) -> String {
+        if mount.is_swap() {
+            return format!("swapon {}", shell_quote(mount.device));
+        }
+
+        let target = shell_quote(&mount.target(&self.target_root));
+        let mut command = format!("mkdir -p {target} && mount");
+        if let Some(fstype) = mount_type(filesystem) {
+            let _ = write!(command, " -t {fstype}");
+        }
+        let options = mount_options(mount);
+        if !options.is_empty() {
+            let _ = write!(command, " -o {}", options.join(","));
+        }
+        let _ = write!(command, " {} {target}", shell_quote(mount.device));
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::StandardFilesystemType;
+
+    use super::*;
+
+    #[test]
+    fn test_plugins() {
+        let ext4 = Filesystem::Standard {
+            filesystem_type: StandardFilesystemType::Ext4,
+            label: None,
+            uuid: Some("0f5b2c1e-8d3a-4b6f-9e21-7c4d5a6b8e90".into()),
+            subvolumes: None,
+        };
+        let options = vec!["noatime".to_owned()];
+        let root = PlannedMount {
+            role: Some(&PartitionRole::Root),
+            device: Path::new("/dev/sda2"),
+            mount_point: Path::new("/"),
+            subvolume: None,
+            options: &options,
+        };
+        let home = PlannedMount {
+            role: None,
+            device: Path::new("/dev/sda3"),
+            mount_point: Path::new("/home"),
+            subvolume: Some("@home"),
+            options: &[],
+        };
+        let swap = PlannedMount {
+            role: Some(&PartitionRole::Swap),
+            device: Path::new("/dev/sda4"),
+            mount_point: Path::new("swap"),
+            subvolume: None,
+            options: &[],
+        };
+
+        assert_eq!(
+            FstabPlugin.describe_mount(&root, Some(&ext4)),
+            "UUID=0f5b2c1e-8d3a-4b6f-9e21-7c4d5a6b8e90 / ext4 noatime 0 1"
+        );
+        assert_eq!(
+            FstabPlugin.describe_mount(&home, None),
+            "/dev/sda3 /home auto subvol=@home 0 2"
+        );
+        assert_eq!(
+            FstabPlugin.describe_mount(&swap, None),
+            "/dev/sda4 none swap defaults 0 0"
+        );
+
+        let unit = SystemdMountPlugin.describe_mount(&root, Some(&ext4));
+        assert!(unit.starts_with("# -.mount\n"));
+        assert!(unit.contains("What=/dev/sda2\nWhere=/\nType=ext4\nOptions=noatime\n"));
+        assert!(
+            SystemdMountPlugin
+                .describe_mount(&home, None)
+                .starts_with("# home.mount\n")
+        );
+        assert!(
+            SystemdMountPlugin
+                .describe_mount(&swap, None)
+                .contains("[Swap]\nWhat=/dev/sda4\n")
+        );
+        assert_eq!(
+            SystemdMountPlugin::unit_name(Path::new("/var/lib-x"), "mount"),
+            "var-lib\\x2dx.mount"
+        );
+
+        let shell = ShellPlugin::new("/mnt");
+        assert_eq!(
+            shell.describe_mount(&root, Some(&ext4)),
+            "mkdir -p '/mnt' && mount -t ext4 -o noatime '/dev/sda2' '/mnt'"
+        );
+        assert_eq!(
+            shell.describe_mount(&home, None),
+            "mkdir -p '/mnt/home' && mount -o subvol=@home '/dev/sda3' '/mnt/home'"
+        );
+        assert_eq!(shell.describe_mount(&swap, None), "swapon '/dev/sda4'");
+
+        // Paths stay single words, even with spaces and quotes in them
+        let shell = ShellPlugin::new("/mnt/new root");
+        assert_eq!(
+            shell.describe_mount(&home, None),
+            "mkdir -p '/mnt/new root/home' && mount -o subvol=@home '/dev/sda3' '/mnt/new root/home'"
+        );
+        let shell = ShellPlugin::new("/mnt/it's");
+        assert_eq!(
+            shell.describe_mount(&root, Some(&ext4)),
+            r"mkdir -p '/mnt/it'\''s' && mount -t ext4 -o noatime '/dev/sda2' '/mnt/it'\''s'"
+        );
+    }
+}
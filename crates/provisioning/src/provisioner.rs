@@ -3,17 +3,33 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+};
 
-use disks::BlockDevice;
-use log::{debug, trace, warn};
+use disks::{BlockDevice, partition::Partition};
+use itertools::Itertools;
+use log::{debug, info, trace, warn};
 use partitioning::{
-    planner::{PARTITION_ALIGNMENT, Planner},
+    PartitionAttributes, TableAttributes,
+    gpt::{self, partition_types},
+    planner::{Change, PARTITION_ALIGNMENT, Planner},
     strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy},
+    writer::WriteError,
 };
-use types::{Filesystem, PartitionRole};
+use serde_json::{Value, json};
+use thiserror::Error;
+use types::{EncryptionSpec, Filesystem, PartitionRole, PartitionTableType};
+use uuid::Uuid;
 
-use crate::{Constraints, StrategyDefinition, commands::Command};
+use crate::{
+    Backend, Constraints, Firmware, MountPlugin, StrategyDefinition, SubvolumeMount, SystemBackend,
+    audit::{AuditEventType, AuditLog},
+    commands::Command,
+};
 
 /// Provisioner
 pub struct Provisioner<'a> {
@@ -22,6 +38,96 @@ pub struct Provisioner<'a> {
 
     /// Strategy configurations
     configs: HashMap<String, &'a StrategyDefinition>,
+
+    /// Receives progress events during execution
+    listener: RefCell<Option<Listener>>,
+
+    /// Seed for deterministic partition and filesystem UUIDs
+    uuid_seed: Option<u64>,
+
+    /// Path of the newline-delimited JSON audit log, if any
+    audit_log: Option<PathBuf>,
+
+    /// Whether to reuse an existing EFI System Partition instead of creating one
+    reuse_esp: bool,
+
+    /// Whether to keep filesystems that already match the requested ones instead of formatting
+    reuse_filesystems: bool,
+
+    /// Firmware that `create-partition-table type="auto"` is resolved for
+    firmware: Firmware,
+}
+
+/// Callback receiving [`ProvisionEvent`]s
+type Listener = Box<dyn FnMut(ProvisionEvent)>;
+
+/// Progress events emitted while executing a plan
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProvisionEvent {
+    /// The partition table of a disk is about to be written
+    TableWrite { disk: String, device: PathBuf, wipe: bool },
+    /// A partition was created on a disk
+    PartitionAdded {
+        disk: String,
+        partition_id: u32,
+        device: PathBuf,
+    },
+    /// A partition was set up as an encrypted container and opened
+    Encrypted { device: PathBuf, mapped_device: PathBuf },
+    /// Formatting of a partition has started
+    FormatStarted { device: PathBuf, filesystem: Filesystem },
+    /// Formatting of a partition has finished
+    FormatFinished { device: PathBuf, filesystem: Filesystem },
+    /// A partition is ready to be mounted for its role
+    MountReady { role: PartitionRole, device: PathBuf },
+}
+
+/// Errors that can occur while executing a plan
+#[derive(Debug, Error)]
+pub enum ExecuteError {
+    /// Writing the partition table failed
+    #[error("failed to write partition table: {0}")]
+    Write(#[from] WriteError),
+
+    /// The format command could not be run
+    #[error("failed to run formatter for {}: {source}", device.display())]
+    Format { device: PathBuf, source: io::Error },
+
+    /// The format command reported an error
+    #[error("formatting {} failed: {stderr}", device.display())]
+    FormatFailed { device: PathBuf, stderr: String },
+
+    /// The encryption command could not be run
+    #[error("failed to run cryptsetup for {}: {source}", device.display())]
+    Encrypt { device: PathBuf, source: io::Error },
+
+    /// The encryption command reported an error
+    #[error("encrypting {} failed: {stderr}", device.display())]
+    EncryptFailed { device: PathBuf, stderr: String },
+
+    /// The audit log could not be written
+    #[error("failed to write audit log: {0}")]
+    AuditLog(io::Error),
+}
+
+/// Errors that can occur while planning
+#[derive(Debug, Error)]
+pub enum PlanError {
+    /// Not enough devices in the pool match the strategy's disk requirements
+    #[error("strategy {strategy} requires {required} disks, found {found}")]
+    InsufficientMatchingDisks {
+        strategy: String,
+        required: usize,
+        found: usize,
+    },
+
+    /// The strategy requires disks of equal size, but no such set was found
+    #[error("strategy {strategy} requires disks of equal size")]
+    UnequalDiskSizes { strategy: String },
+
+    /// The partition table of a device could not be read
+    #[error("failed to read partition table of {}: {source}", device.display())]
+    ReadPartitionTable { device: PathBuf, source: io::Error },
 }
 
 /// Compiled plan
@@ -32,8 +138,20 @@ pub struct Plan<'a> {
     // Global mount points
     pub role_mounts: HashMap<PartitionRole, PathBuf>,
 
+    // Btrfs subvolumes to mount, by their mount point in the target system
+    pub subvolume_mounts: HashMap<PathBuf, SubvolumeMount>,
+
+    // Options each role is mounted with, replace an entry to override the defaults
+    pub mount_options: HashMap<PartitionRole, Vec<String>>,
+
     // Filesystems to be formatted
     pub filesystems: HashMap<PathBuf, Filesystem>,
+
+    // Partitions to be encrypted before formatting
+    pub encryptions: HashMap<PathBuf, EncryptionSpec>,
+
+    // Existing partitions used as-is, which must never be formatted
+    pub preserved: HashSet<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +159,10 @@ pub struct DevicePlan<'a> {
     pub device: &'a BlockDevice,
     pub planner: Planner,
     pub strategy: Strategy,
+    /// The type of partition table written when the disk is initialized
+    pub table_type: PartitionTableType,
+    /// Whether existing signatures are erased before the partition table is written
+    pub erase_signatures: bool,
 }
 
 impl Default for Provisioner<'_> {
@@ -56,9 +178,315 @@ impl<'a> Provisioner<'a> {
         Self {
             devices: Vec::new(),
             configs: HashMap::new(),
+            listener: RefCell::new(None),
+            uuid_seed: None,
+            audit_log: None,
+            reuse_esp: false,
+            reuse_filesystems: false,
+            firmware: Firmware::detect(),
+        }
+    }
+
+    /// Reuse an existing EFI System Partition rather than creating a new one
+    ///
+    /// When [`Provisioner::find_existing_esp`] locates an ESP on a disk that the
+    /// plan does not wipe, any `create-partition` with the `boot` role is
+    /// satisfied by that partition. It is mounted for the role but kept out of
+    /// the plan's filesystems and recorded in [`Plan::preserved`] instead.
+    pub fn with_esp_reuse(self) -> Self {
+        Self {
+            reuse_esp: true,
+            ..self
+        }
+    }
+
+    /// Keep existing filesystems that already match the requested ones
+    ///
+    /// Before formatting, [`Provisioner::execute`] reads the superblock on the
+    /// device and skips the format if it holds the requested filesystem type,
+    /// with the requested label and UUID when those are set. Otherwise the
+    /// device is formatted as usual, replacing whatever was there.
+    pub fn with_filesystem_reuse(self) -> Self {
+        Self {
+            reuse_filesystems: true,
+            ..self
+        }
+    }
+
+    /// Resolve `create-partition-table type="auto"` for `firmware` rather than the running system
+    ///
+    /// Useful when preparing a disk for another machine, such as an image for
+    /// legacy BIOS built on a UEFI host.
+    pub fn with_firmware(self, firmware: Firmware) -> Self {
+        Self { firmware, ..self }
+    }
+
+    /// The partition table type that `create-partition-table type="auto"` resolves to
+    ///
+    /// The firmware is detected from the running system unless set with
+    /// [`Provisioner::with_firmware`]:
+    ///
+    /// - UEFI resolves to GPT
+    /// - Legacy BIOS resolves to MBR (`msdos`)
+    ///
+    /// Explicit `gpt` and `msdos` tables are never changed. An MBR has four
+    /// primary partition slots, so a strategy resolved to it for BIOS may create
+    /// at most four partitions.
+    pub fn target_table_for_firmware(&self) -> PartitionTableType {
+        self.firmware.partition_table_type()
+    }
+
+    /// Find an existing EFI System Partition on any device in the pool
+    ///
+    /// Each device's GPT is searched for a partition with the EFI System
+    /// Partition type GUID. Mock devices and devices without a valid GPT are
+    /// skipped, while failing to read a device is an error.
+    pub fn find_existing_esp(&self) -> Result<Option<(&'a BlockDevice, &'a Partition)>, PlanError> {
+        for device in self.devices.iter().filter(|device| !device.is_mock()) {
+            let planner = match Planner::from_existing(device) {
+                Ok(planner) => planner,
+                // The GPT itself was rejected, so there is no ESP to find
+                Err(e) if e.get_ref().is_some_and(|e| e.is::<gpt::GptError>()) => {
+                    debug!("Not searching {} for an ESP: {e}", device.device().display());
+                    continue;
+                }
+                Err(source) => {
+                    return Err(PlanError::ReadPartitionTable {
+                        device: device.device().to_owned(),
+                        source,
+                    });
+                }
+            };
+            let partition = planner
+                .find_partition_by_type_guid(&partition_types::EFI)
+                .and_then(|region| region.partition_id)
+                .and_then(|id| device.partitions().iter().find(|partition| partition.number == id));
+            if let Some(partition) = partition {
+                debug!("Found existing ESP {}", partition.device.display());
+                return Ok(Some((*device, partition)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Record every change made by [`Provisioner::execute`] in a newline-delimited JSON file
+    ///
+    /// Events are appended to the file, which is created if needed. See the
+    /// [`AuditEventType`] documentation for the format of each line.
+    pub fn with_audit_log(self, path: impl AsRef<Path>) -> Self {
+        Self {
+            audit_log: Some(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Generate partition GUIDs and filesystem UUIDs deterministically from `seed`
+    ///
+    /// Without a seed, any identifiers not set in the configuration are chosen
+    /// at random when the disk is written and formatted. With a seed, the same
+    /// configuration on the same devices always yields the same identifiers.
+    pub fn with_uuid_seed(self, seed: u64) -> Self {
+        Self {
+            uuid_seed: Some(seed),
+            ..self
+        }
+    }
+
+    /// Fill in any unset partition GUID and filesystem UUID from the seed, if any
+    ///
+    /// `key` uniquely identifies the partition within the strategy.
+    fn seed_attributes(&self, key: &str, attributes: &mut PartitionAttributes) {
+        let Some(seed) = self.uuid_seed else {
+            return;
+        };
+
+        match &mut attributes.table {
+            TableAttributes::Gpt(gpt) => {
+                gpt.uuid
+                    .get_or_insert_with(|| seeded_uuid(seed, &format!("{key}/partition")));
+            }
+        }
+
+        match &mut attributes.filesystem {
+            Some(Filesystem::Standard { uuid, .. }) => {
+                uuid.get_or_insert_with(|| seeded_uuid(seed, &format!("{key}/filesystem")).to_string());
+            }
+            Some(Filesystem::Fat32 { volume_id, .. }) => {
+                volume_id.get_or_insert_with(|| seeded_uuid(seed, &format!("{key}/filesystem")).as_fields().0);
+            }
+            None => {}
+        }
+    }
+
+    /// Set a listener to receive [`ProvisionEvent`]s during [`Provisioner::execute`]
+    pub fn with_listener(self, listener: impl FnMut(ProvisionEvent) + 'static) -> Self {
+        Self {
+            listener: RefCell::new(Some(Box::new(listener))),
+            ..self
+        }
+    }
+
+    /// Send an event to the listener, if any
+    fn emit(&self, event: ProvisionEvent) {
+        trace!("Provisioning event: {event:?}");
+        if let Some(listener) = self.listener.borrow_mut().as_mut() {
+            listener(event);
         }
     }
 
+    /// Execute a plan: write partition tables, create partitions and format them
+    ///
+    /// See [`Provisioner::execute_with`].
+    pub fn execute(&self, plan: &Plan<'_>) -> Result<(), ExecuteError> {
+        self.execute_with(plan, SystemBackend)
+    }
+
+    /// Execute a plan, performing all device IO through `backend`
+    ///
+    /// Events are emitted and audited as each step is performed, so a backend
+    /// that only records the steps, e.g. for mock devices, still produces the
+    /// full event stream.
+    pub fn execute_with(&self, plan: &Plan<'_>, mut backend: impl Backend) -> Result<(), ExecuteError> {
+        info!("Executing plan for strategy {}", plan.strategy.name);
+        let mut audit = AuditLog::open(self.audit_log.as_deref()).map_err(ExecuteError::AuditLog)?;
+
+        let mut disks = plan.device_assignments.iter().collect::<Vec<_>>();
+        disks.sort_by_key(|(disk, _)| *disk);
+
+        for (disk, device_plan) in disks {
+            let device = device_plan.device;
+            if device_plan.erase_signatures {
+                backend.erase_signatures(device_plan)?;
+                audit
+                    .record(
+                        AuditEventType::SignaturesErased,
+                        json!({ "disk": disk, "device": device.device() }),
+                    )
+                    .map_err(ExecuteError::AuditLog)?;
+
+                // With nothing else planned the disk is left blank
+                if device_plan.planner.changes().is_empty() && !device_plan.planner.wipe_disk() {
+                    continue;
+                }
+            }
+
+            self.emit(ProvisionEvent::TableWrite {
+                disk: disk.clone(),
+                device: device.device().to_owned(),
+                wipe: device_plan.planner.wipe_disk(),
+            });
+
+            backend.write_table(device_plan)?;
+            audit
+                .record(
+                    AuditEventType::PartitionTableWritten,
+                    json!({
+                        "disk": disk,
+                        "device": device.device(),
+                        "wipe": device_plan.planner.wipe_disk(),
+                    }),
+                )
+                .map_err(ExecuteError::AuditLog)?;
+
+            for change in device_plan.planner.changes() {
+                if let Change::AddPartition {
+                    start,
+                    end,
+                    partition_id,
+                    ..
+                } = change
+                {
+                    let partition = device.partition_path(*partition_id as usize);
+                    audit
+                        .record(
+                            AuditEventType::PartitionCreated,
+                            json!({
+                                "disk": disk,
+                                "device": partition,
+                                "partition_id": partition_id,
+                                "start": start,
+                                "end": end,
+                            }),
+                        )
+                        .map_err(ExecuteError::AuditLog)?;
+                    self.emit(ProvisionEvent::PartitionAdded {
+                        disk: disk.clone(),
+                        partition_id: *partition_id,
+                        device: partition,
+                    });
+                }
+            }
+        }
+
+        let mut encryptions = plan.encryptions.iter().collect::<Vec<_>>();
+        encryptions.sort_by_key(|(device, _)| *device);
+
+        for (device, encryption) in encryptions {
+            let mapped_device = mapped_device(device);
+            backend.encrypt(device, encryption)?;
+            audit
+                .record(
+                    AuditEventType::PartitionEncrypted,
+                    json!({ "device": device, "mapped_device": mapped_device }),
+                )
+                .map_err(ExecuteError::AuditLog)?;
+            self.emit(ProvisionEvent::Encrypted {
+                device: device.clone(),
+                mapped_device,
+            });
+        }
+
+        let mut filesystems = plan.filesystems.iter().collect::<Vec<_>>();
+        filesystems.sort_by_key(|(device, _)| *device);
+
+        for (partition, filesystem) in filesystems {
+            let device = &match plan.encryptions.contains_key(partition) {
+                true => mapped_device(partition),
+                false => partition.clone(),
+            };
+            if self.reuse_filesystems && backend.has_filesystem(device, filesystem) {
+                info!("Keeping existing {filesystem} on {}", device.display());
+                continue;
+            }
+            self.emit(ProvisionEvent::FormatStarted {
+                device: device.clone(),
+                filesystem: filesystem.clone(),
+            });
+            backend.format(device, filesystem)?;
+            audit
+                .record(
+                    AuditEventType::FilesystemFormatted,
+                    json!({ "device": device, "filesystem": filesystem.to_string() }),
+                )
+                .map_err(ExecuteError::AuditLog)?;
+            self.emit(ProvisionEvent::FormatFinished {
+                device: device.clone(),
+                filesystem: filesystem.clone(),
+            });
+        }
+
+        let mut mounts = plan.role_mounts.iter().collect::<Vec<_>>();
+        mounts.sort_by_key(|(_, device)| *device);
+
+        for (role, device) in mounts {
+            self.emit(ProvisionEvent::MountReady {
+                role: role.clone(),
+                device: device.clone(),
+            });
+        }
+
+        let mut disks = plan.device_assignments.keys().collect::<Vec<_>>();
+        disks.sort();
+        audit
+            .record(
+                AuditEventType::StrategyApplied,
+                json!({ "strategy": plan.strategy.name, "disks": disks }),
+            )
+            .map_err(ExecuteError::AuditLog)?;
+
+        Ok(())
+    }
+
     /// Add a strategy configuration
     pub fn add_strategy(&mut self, config: &'a StrategyDefinition) {
         debug!("Adding strategy: {}", config.name);
@@ -85,25 +513,174 @@ impl<'a> Provisioner<'a> {
     }
 
     /// Attempt all strategies on the pool of devices
-    pub fn plan(&self) -> Vec<Plan<'_>> {
+    ///
+    /// Plans not meeting a strategy's disk count or equal size requirements are
+    /// discarded, and an error is returned if a strategy with such requirements
+    /// could not be planned at all.
+    pub fn plan(&self) -> Result<Vec<Plan<'_>>, PlanError> {
         trace!("Planning device provisioning");
         let mut plans = Vec::new();
+        let esp = if self.reuse_esp {
+            self.find_existing_esp()?
+        } else {
+            None
+        };
         for strategy in self.configs.values() {
             debug!("Attempting strategy: {}", strategy.name);
-            self.create_plans_for_strategy(strategy, &mut HashMap::new(), &mut plans);
+            let mut strategy_plans = Vec::new();
+            self.create_plans_for_strategy(strategy, esp, &mut HashMap::new(), &mut strategy_plans);
+            plans.extend(self.check_disk_requirements(strategy, strategy_plans)?);
         }
         debug!("Generated {} plans", plans.len());
-        plans
+        Ok(plans)
+    }
+
+    /// Plan all strategies and describe the result as JSON, without applying anything
+    ///
+    /// The output is an object with a single `plans` array, ordered by strategy
+    /// name. Each plan is an object with the following fields:
+    ///
+    /// - `strategy`: the name of the strategy
+    /// - `devices`: one object per disk, ordered by disk name, with its `disk`
+    ///   name, `device` path, `table_type`, whether it will `erase_signatures`, and
+    ///   its `changes` as given by [`Planner::describe_changes_json`]
+    /// - `filesystems`: the `device` and `filesystem` to format, ordered by device
+    /// - `encrypted`: the partitions set up as encrypted containers
+    /// - `preserved`: existing partitions that are used without being formatted
+    /// - `mounts`: the `role`, `device`, `mount_point`, Btrfs `subvolume` and mount
+    ///   `options` of each mount, in [`Plan::mount_order`]. `role` is `null` for
+    ///   subvolumes mounted at their own mount point and `subvolume` is `null` when
+    ///   the top of the filesystem is mounted
+    ///
+    /// Fields are only ever added to this format, never removed or renamed.
+    pub fn dry_run(&self) -> Result<Value, PlanError> {
+        let mut plans = self.plan()?;
+        plans.sort_by(|a, b| a.strategy.name.cmp(&b.strategy.name));
+
+        let plans = plans
+            .iter()
+            .map(|plan| {
+                let devices = plan
+                    .device_assignments
+                    .iter()
+                    .sorted_by_key(|(disk, _)| *disk)
+                    .map(|(disk, device_plan)| {
+                        json!({
+                            "disk": disk,
+                            "device": device_plan.device.device(),
+                            "table_type": device_plan.table_type.to_string(),
+                            "erase_signatures": device_plan.erase_signatures,
+                            "changes": device_plan.planner.describe_changes_json(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let filesystems = plan
+                    .filesystems
+                    .iter()
+                    .sorted_by_key(|(device, _)| *device)
+                    .map(|(device, filesystem)| json!({ "device": device, "filesystem": filesystem.to_string() }))
+                    .collect::<Vec<_>>();
+                let mounts = plan
+                    .mount_order()
+                    .into_iter()
+                    .map(|mount| {
+                        json!({
+                            "role": mount.role.map(ToString::to_string),
+                            "device": mount.device,
+                            "mount_point": mount.mount_point,
+                            "subvolume": mount.subvolume,
+                            "options": mount.options,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "strategy": plan.strategy.name,
+                    "devices": devices,
+                    "filesystems": filesystems,
+                    "encrypted": plan.encryptions.keys().sorted().collect::<Vec<_>>(),
+                    "preserved": plan.preserved.iter().sorted().collect::<Vec<_>>(),
+                    "mounts": mounts,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "plans": plans }))
+    }
+
+    /// Describe every mount of `plan` with `plugin`, in [`Plan::mount_order`]
+    ///
+    /// For example [`crate::FstabPlugin`] gives the lines of `/etc/fstab` for the
+    /// installed system.
+    pub fn generate_mount_config(&self, plan: &Plan<'_>, plugin: &dyn MountPlugin) -> Vec<String> {
+        plan.mount_order()
+            .iter()
+            .map(|mount| plugin.describe_mount(mount, plan.mounted_filesystem(mount.device)))
+            .collect()
+    }
+
+    /// Filter plans to those meeting the disk requirements of their strategy chain
+    fn check_disk_requirements<'b>(
+        &'b self,
+        strategy: &'b StrategyDefinition,
+        plans: Vec<Plan<'b>>,
+    ) -> Result<Vec<Plan<'b>>, PlanError> {
+        let chain = self.strategy_parents(strategy);
+        let required = chain.iter().rev().find_map(|s| s.requires_disk_count);
+        let equal_sizes = chain.iter().any(|s| s.requires_equal_disk_sizes);
+        if required.is_none() && !equal_sizes {
+            return Ok(plans);
+        }
+
+        let total = plans.len();
+        let plans = plans
+            .into_iter()
+            .filter(|plan| required.is_none_or(|n| plan.device_assignments.len() == n))
+            .filter(|plan| !equal_sizes || plan.device_assignments.values().map(|d| d.device.size()).all_equal())
+            .collect::<Vec<_>>();
+        debug!(
+            "{} of {total} plans for strategy {} meet its disk requirements",
+            plans.len(),
+            strategy.name
+        );
+        if !plans.is_empty() {
+            return Ok(plans);
+        }
+
+        // Report how many devices could have been used by the strategy
+        let found = self
+            .devices
+            .iter()
+            .filter(|device| {
+                chain.iter().flat_map(|s| &s.commands).any(|command| match command {
+                    Command::FindDisk(command) => device_matches(command.constraints.as_ref(), device),
+                    _ => false,
+                })
+            })
+            .count();
+
+        match required {
+            Some(required) if found < required || !equal_sizes => Err(PlanError::InsufficientMatchingDisks {
+                strategy: strategy.name.clone(),
+                required,
+                found,
+            }),
+            _ => Err(PlanError::UnequalDiskSizes {
+                strategy: strategy.name.clone(),
+            }),
+        }
     }
 
     fn create_plans_for_strategy<'b>(
         &'b self,
         strategy: &'b StrategyDefinition,
+        esp: Option<(&'b BlockDevice, &'b Partition)>,
         device_assignments: &mut HashMap<String, DevicePlan<'b>>,
         plans: &mut Vec<Plan<'b>>,
     ) {
         trace!("Creating plans for strategy: {}", strategy.name);
         let chain = self.strategy_parents(strategy);
+        let mut reused = HashMap::new();
 
         for command in chain.iter().flat_map(|s| &s.commands) {
             match command {
@@ -118,12 +695,7 @@ impl<'a> Provisioner<'a> {
                     let matching_devices: Vec<_> = self
                         .devices
                         .iter()
-                        .filter(|d| match command.constraints.as_ref() {
-                            Some(Constraints::AtLeast(n)) => d.size() >= *n,
-                            Some(Constraints::Exact(n)) => d.size() == *n,
-                            Some(Constraints::Range { min, max }) => d.size() >= *min && d.size() <= *max,
-                            _ => true,
-                        })
+                        .filter(|d| device_matches(command.constraints.as_ref(), d))
                         .filter(|d| {
                             !device_assignments.values().any(|assigned| {
                                 std::ptr::eq(assigned.device as *const BlockDevice, **d as *const BlockDevice)
@@ -144,10 +716,12 @@ impl<'a> Provisioner<'a> {
                                 planner: Planner::new(device)
                                     .with_start_offset(PARTITION_ALIGNMENT)
                                     .with_end_offset(device.size() - PARTITION_ALIGNMENT),
-                                strategy: Strategy::new(AllocationStrategy::LargestFree),
+                                strategy: Strategy::new(AllocationStrategy::LargestFree).with_name(&strategy.name),
+                                table_type: PartitionTableType::Gpt,
+                                erase_signatures: false,
                             },
                         );
-                        self.create_plans_for_strategy(strategy, &mut new_assignments, plans);
+                        self.create_plans_for_strategy(strategy, esp, &mut new_assignments, plans);
                     }
 
                     return;
@@ -155,12 +729,40 @@ impl<'a> Provisioner<'a> {
                 Command::CreatePartitionTable(command) => {
                     if let Some(device_plan) = device_assignments.get_mut(&command.disk) {
                         debug!("Creating partition table on disk {}", command.disk);
-                        device_plan.strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+                        device_plan.strategy =
+                            Strategy::new(AllocationStrategy::InitializeWholeDisk).with_name(&strategy.name);
+                        let table_type = match command.table_type {
+                            PartitionTableType::Auto => self.target_table_for_firmware(),
+                            table_type => table_type,
+                        };
+                        // The table only has room for its own number of partitions
+                        device_plan.planner = device_plan
+                            .planner
+                            .clone()
+                            .with_max_partitions(table_type.partition_count() as usize);
+                        device_plan.table_type = table_type;
                     } else {
                         warn!("Could not find disk {} to create partition table", command.disk);
                     }
                 }
+                Command::WipeSignatures(command) => {
+                    if let Some(device_plan) = device_assignments.get_mut(&command.disk) {
+                        debug!("Wiping signatures on disk {}", command.disk);
+                        device_plan.erase_signatures = true;
+                    } else {
+                        warn!("Could not find disk {} to wipe signatures", command.disk);
+                    }
+                }
                 Command::CreatePartition(command) => {
+                    if let Some(partition) = esp
+                        .filter(|_| command.role == Some(PartitionRole::Boot))
+                        .filter(|(device, _)| !is_wiped(device_assignments, device))
+                        .map(|(_, partition)| partition)
+                    {
+                        debug!("Reusing existing ESP {} for {}", partition.device.display(), command.id);
+                        reused.insert(PartitionRole::Boot, partition.device.clone());
+                        continue;
+                    }
                     if let Some(device_plan) = device_assignments.get_mut(&command.disk) {
                         debug!("Adding partition request for disk {}", command.disk);
                         device_plan.strategy.add_request(PartitionRequest {
@@ -168,9 +770,16 @@ impl<'a> Provisioner<'a> {
                                 Constraints::AtLeast(n) => SizeRequirement::AtLeast(*n),
                                 Constraints::Exact(n) => SizeRequirement::Exact(*n),
                                 Constraints::Range { min, max } => SizeRequirement::Range { min: *min, max: *max },
+                                Constraints::AtMost(n) => SizeRequirement::AtMost(*n),
                                 _ => SizeRequirement::Remaining,
                             },
-                            attributes: Some(command.attributes()),
+                            name: command.name.clone(),
+                            attributes: Some({
+                                let mut attributes = command.attributes();
+                                let key = format!("{}/{}/{}", strategy.name, command.disk, command.id);
+                                self.seed_attributes(&key, &mut attributes);
+                                attributes
+                            }),
                         });
                     } else {
                         warn!("Could not find disk {} to create partition", command.disk);
@@ -180,22 +789,47 @@ impl<'a> Provisioner<'a> {
         }
 
         let mut role_mounts = HashMap::new();
+        let mut subvolume_mounts = HashMap::new();
+        let mut mount_options = HashMap::new();
         let mut filesystems = HashMap::new();
+        let mut encryptions = HashMap::new();
 
         // OK lets now apply any mutations to the device assignments
         for (disk_name, device_plan) in device_assignments.iter_mut() {
             debug!("Applying device plan for disk {disk_name}");
-            if let Err(e) = device_plan.strategy.apply(&mut device_plan.planner) {
+            if let Err(e) = device_plan.planner.apply_strategy(&device_plan.strategy) {
                 warn!("Failed to apply strategy for disk {disk_name}: {e:?}");
             }
             for region in device_plan.planner.current_layout().iter() {
                 if let Some(id) = region.partition_id {
                     let device_path = device_plan.device.partition_path(id as usize);
                     if let Some(attributes) = region.attributes.as_ref() {
+                        // Encrypted partitions are mounted through their opened container
+                        let mount_path = match attributes.encryption {
+                            Some(_) => mapped_device(&device_path),
+                            None => device_path.clone(),
+                        };
+                        for subvolume in attributes.subvolumes() {
+                            if let Some(mount_point) = subvolume.mount_point.as_ref() {
+                                subvolume_mounts.insert(
+                                    PathBuf::from(mount_point),
+                                    SubvolumeMount {
+                                        device: mount_path.clone(),
+                                        subvolume: subvolume.name.clone(),
+                                    },
+                                );
+                            }
+                        }
                         if let Some(role) = attributes.role.as_ref() {
-                            role_mounts.insert(role.clone(), device_path.clone());
+                            role_mounts.insert(role.clone(), mount_path);
+                        }
+                        if let Some(encryption) = attributes.encryption.as_ref() {
+                            encryptions.insert(device_path.clone(), encryption.clone());
                         }
                         if let Some(fs) = attributes.filesystem.as_ref() {
+                            if let Some(role) = attributes.role.as_ref() {
+                                mount_options.insert(role.clone(), role.default_mount_options(fs));
+                            }
                             filesystems.insert(device_path, fs.clone());
                         }
                     }
@@ -203,44 +837,166 @@ impl<'a> Provisioner<'a> {
             }
         }
 
+        let preserved = reused.values().cloned().collect();
+        role_mounts.extend(reused);
+
         // All commands processed successfully - create a plan
         debug!("Creating final plan for strategy {}", strategy.name);
         plans.push(Plan {
             strategy,
             role_mounts,
+            subvolume_mounts,
+            mount_options,
             filesystems,
+            encryptions,
+            preserved,
             device_assignments: device_assignments.clone(),
         });
     }
 }
 
+/// Whether `device` is assigned to a disk that is given a new partition table or has its signatures wiped
+fn is_wiped(device_assignments: &HashMap<String, DevicePlan<'_>>, device: &BlockDevice) -> bool {
+    device_assignments.values().any(|device_plan| {
+        std::ptr::eq(device_plan.device, device)
+            && (device_plan.erase_signatures
+                || matches!(
+                    device_plan.strategy.allocation(),
+                    AllocationStrategy::InitializeWholeDisk
+                ))
+    })
+}
+
+impl Plan<'_> {
+    /// The filesystem formatted on a mounted device, following encrypted partitions to their container
+    fn mounted_filesystem(&self, device: &Path) -> Option<&Filesystem> {
+        self.filesystems.get(device).or_else(|| {
+            self.filesystems
+                .iter()
+                .find(|(partition, _)| self.encryptions.contains_key(*partition) && mapped_device(partition) == device)
+                .map(|(_, filesystem)| filesystem)
+        })
+    }
+}
+
+/// Path of the opened LUKS2 container for an encrypted partition, e.g. `/dev/mapper/luks-sda2`
+pub(crate) fn mapped_device(partition: &Path) -> PathBuf {
+    let name = partition.file_name().unwrap_or_default().to_string_lossy();
+    Path::new("/dev/mapper").join(format!("luks-{name}"))
+}
+
+/// Whether a device satisfies the size constraints of a `find-disk` command
+fn device_matches(constraints: Option<&Constraints>, device: &BlockDevice) -> bool {
+    match constraints {
+        Some(Constraints::AtLeast(n)) => device.size() >= *n,
+        Some(Constraints::Exact(n)) => device.size() == *n,
+        Some(Constraints::Range { min, max }) => device.size() >= *min && device.size() <= *max,
+        Some(Constraints::AtMost(n)) => device.size() <= *n,
+        _ => true,
+    }
+}
+
+/// Derive a random (version 4) UUID deterministically from a seed and key
+fn seeded_uuid(seed: u64, key: &str) -> Uuid {
+    // FNV-1a over the key, mixed with the seed
+    let mut state = key.bytes().fold(0xcbf2_9ce4_8422_2325u64 ^ seed, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+
+    // splitmix64 to spread the hash over 128 bits
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&next().to_le_bytes());
+    bytes[8..].copy_from_slice(&next().to_le_bytes());
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{fs, rc::Rc};
+
     use disks::mock::MockDisk;
+    use partitioning::writer::DiskWriter;
     use test_log::test;
 
-    use crate::Parser;
+    use crate::{Parser, fixture::Fixture};
 
     use super::*;
 
+    /// Records the device IO of an executed plan instead of performing it
+    #[derive(Default)]
+    struct RecordingBackend {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Backend for RecordingBackend {
+        fn erase_signatures(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError> {
+            let device = device_plan.device.device();
+            self.log.borrow_mut().push(format!("erase {}", device.display()));
+            Ok(())
+        }
+
+        fn write_table(&mut self, device_plan: &DevicePlan<'_>) -> Result<(), ExecuteError> {
+            // Refuse any table the real writer would
+            DiskWriter::new(device_plan.device, &device_plan.planner)
+                .with_table_type(device_plan.table_type)
+                .validate_changes()?;
+            let device = device_plan.device.device();
+            self.log
+                .borrow_mut()
+                .push(format!("write {} as {}", device.display(), device_plan.table_type));
+            Ok(())
+        }
+
+        fn encrypt(&mut self, device: &Path, _: &EncryptionSpec) -> Result<(), ExecuteError> {
+            self.log.borrow_mut().push(format!("encrypt {}", device.display()));
+            Ok(())
+        }
+
+        fn has_filesystem(&mut self, _: &Path, _: &Filesystem) -> bool {
+            false
+        }
+
+        fn format(&mut self, device: &Path, filesystem: &Filesystem) -> Result<(), ExecuteError> {
+            self.log
+                .borrow_mut()
+                .push(format!("format {} as {filesystem}", device.display()));
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_use_whole_disk() {
-        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
-        let def = test_strategies.strategies;
-        let device = BlockDevice::mock_device(MockDisk::new(150 * 1024 * 1024 * 1024));
-        let mut provisioner = Provisioner::new();
-        provisioner.push_device(&device);
-        for def in def.iter() {
-            provisioner.add_strategy(def);
-        }
+        let fixture = Fixture::whole_disk();
+        let provisioner = fixture.provision(Provisioner::new());
 
-        let plans = provisioner.plan();
+        let plans = provisioner.plan().unwrap();
         assert_eq!(plans.len(), 1);
 
         let plan = &plans[0];
         assert_eq!(plan.device_assignments.len(), 1);
 
-        for plan in plans {
+        let device_plan = plan.device_assignments.values().next().unwrap();
+        let names = device_plan
+            .planner
+            .current_layout()
+            .iter()
+            .map(|r| {
+                let attributes = r.attributes.as_ref().unwrap();
+                attributes.table.as_gpt().unwrap().name.clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names[0].as_deref(), Some("EFI System Partition"));
+        assert_eq!(names[2].as_deref(), Some("AerynOS Root"));
+
+        for plan in plans.iter() {
             eprintln!("Plan: {}", plan.strategy.name);
             for (disk, device_plan) in plan.device_assignments.iter() {
                 println!("strategy for {disk} is now: {}", device_plan.strategy.describe());
@@ -248,4 +1004,575 @@ mod tests {
             }
         }
     }
+
+    /// Plan `use_whole_disk.kdl` and collect the partition GUIDs and filesystem identifiers
+    fn planned_identifiers(seed: Option<u64>) -> Vec<(Option<Uuid>, Option<String>)> {
+        let fixture = Fixture::whole_disk();
+        let provisioner = match seed {
+            Some(seed) => Provisioner::new().with_uuid_seed(seed),
+            None => Provisioner::new(),
+        };
+        let provisioner = fixture.provision(provisioner);
+
+        let plans = provisioner.plan().unwrap();
+        let device_plan = plans[0].device_assignments.values().next().unwrap();
+        device_plan
+            .planner
+            .current_layout()
+            .iter()
+            .map(|r| {
+                let attributes = r.attributes.as_ref().unwrap();
+                let filesystem = match attributes.filesystem.as_ref() {
+                    Some(Filesystem::Standard { uuid, .. }) => uuid.clone(),
+                    Some(Filesystem::Fat32 { volume_id, .. }) => volume_id.map(|id| format!("{id:08X}")),
+                    None => None,
+                };
+                (attributes.table.as_gpt().unwrap().uuid, filesystem)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_uuid_seed() {
+        let first = planned_identifiers(Some(42));
+        let second = planned_identifiers(Some(42));
+        assert_eq!(first, second);
+        assert!(first.iter().all(|(partition, fs)| partition.is_some() && fs.is_some()));
+
+        // Every partition gets a distinct GUID
+        let mut guids = first
+            .iter()
+            .map(|(partition, _)| partition.unwrap())
+            .collect::<Vec<_>>();
+        guids.sort();
+        guids.dedup();
+        assert_eq!(guids.len(), first.len());
+
+        assert_ne!(planned_identifiers(Some(43)), first);
+
+        // Without a seed, identifiers are left for the writer and formatters to choose
+        assert!(
+            planned_identifiers(None)
+                .iter()
+                .all(|(partition, fs)| partition.is_none() && fs.is_none())
+        );
+    }
+
+    #[test]
+    fn test_execute_encrypted() {
+        let source = r#"strategy name="encrypted" summary="Encrypted root" {
+    find-disk "root_disk"
+    create-partition-table type="gpt" disk="root_disk"
+    create-partition disk="root_disk" id="esp" role="boot" {
+        filesystem {
+            type "fat32"
+        }
+    }
+    create-partition disk="root_disk" id="root" role="root" {
+        constraints {
+            remaining
+        }
+        encryption {
+            key-file "/run/installer/root.key"
+        }
+        filesystem {
+            type "ext4"
+        }
+    }
+}
+"#;
+        let parser = Parser::new("encrypted.kdl", source).unwrap();
+        let device = BlockDevice::mock_device(MockDisk::new(50 * 1024 * 1024 * 1024));
+        let events = Rc::new(RefCell::new(vec![]));
+        let sink = events.clone();
+        let mut provisioner = Provisioner::new().with_listener(move |event| sink.borrow_mut().push(event));
+        provisioner.push_device(&device);
+        provisioner.add_strategy(&parser.strategies[0]);
+
+        let plans = provisioner.plan().unwrap();
+        assert_eq!(plans[0].encryptions.len(), 1);
+        assert_eq!(
+            plans[0].role_mounts[&PartitionRole::Root],
+            PathBuf::from("/dev/mapper/luks-mock02")
+        );
+        let backend = RecordingBackend::default();
+        let io = backend.log.clone();
+        provisioner.execute_with(&plans[0], backend).unwrap();
+        assert_eq!(
+            *io.borrow(),
+            vec![
+                "write /dev/mock0 as gpt",
+                "encrypt /dev/mock02",
+                "format /dev/mock01 as fat32",
+                "format /dev/mapper/luks-mock02 as ext4",
+            ]
+        );
+
+        let steps = events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                ProvisionEvent::Encrypted { device, mapped_device } => {
+                    Some(format!("encrypt {} as {}", device.display(), mapped_device.display()))
+                }
+                ProvisionEvent::FormatStarted { device, .. } => Some(format!("format {}", device.display())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            vec![
+                "encrypt /dev/mock02 as /dev/mapper/luks-mock02",
+                "format /dev/mock01",
+                "format /dev/mapper/luks-mock02",
+            ]
+        );
+    }
+
+    const MIRROR: &str = r#"strategy name="mirror" summary="Mirror two disks" requires-disk-count=2 requires-equal-disk-sizes=#true {
+    find-disk "first" {
+        constraints {
+            min (GiB)10
+        }
+    }
+    find-disk "second" {
+        constraints {
+            min (GiB)10
+        }
+    }
+}
+"#;
+
+    /// Plan the mirror strategy against devices of the given sizes in GiB
+    fn plan_mirror(sizes: &[u64]) -> Result<usize, PlanError> {
+        let parser = Parser::new("mirror.kdl", MIRROR).unwrap();
+        let devices = sizes
+            .iter()
+            .map(|size| BlockDevice::mock_device(MockDisk::new(size * 1024 * 1024 * 1024)))
+            .collect::<Vec<_>>();
+        let mut provisioner = Provisioner::new();
+        for device in &devices {
+            provisioner.push_device(device);
+        }
+        provisioner.add_strategy(&parser.strategies[0]);
+        provisioner.plan().map(|plans| plans.len())
+    }
+
+    #[test]
+    fn test_disk_requirements() {
+        let parser = Parser::new("mirror.kdl", MIRROR).unwrap();
+        assert_eq!(parser.strategies[0].requires_disk_count, Some(2));
+        assert!(parser.strategies[0].requires_equal_disk_sizes);
+
+        // Both assignments of the two disks are valid plans
+        assert_eq!(plan_mirror(&[20, 20]).unwrap(), 2);
+        // The odd one out is never paired
+        assert_eq!(plan_mirror(&[20, 30, 20]).unwrap(), 2);
+
+        assert!(matches!(
+            plan_mirror(&[20, 5]),
+            Err(PlanError::InsufficientMatchingDisks {
+                required: 2,
+                found: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            plan_mirror(&[20, 30]),
+            Err(PlanError::UnequalDiskSizes { .. })
+        ));
+    }
+
+    #[test]
+    fn test_execute_events() {
+        let fixture = Fixture::whole_disk();
+        let events = Rc::new(RefCell::new(vec![]));
+        let sink = events.clone();
+        let provisioner =
+            fixture.provision(Provisioner::new().with_listener(move |event| sink.borrow_mut().push(event)));
+
+        let plans = provisioner.plan().unwrap();
+        let backend = RecordingBackend::default();
+        let io = backend.log.clone();
+        provisioner.execute_with(&plans[0], backend).unwrap();
+        assert_eq!(io.borrow().len(), 4);
+        assert_eq!(io.borrow()[0], "write /dev/mock0 as gpt");
+
+        let phases = events
+            .borrow()
+            .iter()
+            .map(|event| match event {
+                ProvisionEvent::TableWrite { wipe, .. } => format!("table wipe={wipe}"),
+                ProvisionEvent::PartitionAdded { partition_id, .. } => format!("partition {partition_id}"),
+                ProvisionEvent::Encrypted { device, .. } => format!("encrypt {}", device.display()),
+                ProvisionEvent::FormatStarted { device, .. } => format!("format {}", device.display()),
+                ProvisionEvent::FormatFinished { device, .. } => format!("formatted {}", device.display()),
+                ProvisionEvent::MountReady { role, .. } => format!("mount {role:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            vec![
+                "table wipe=true",
+                "partition 1",
+                "partition 2",
+                "partition 3",
+                "format /dev/mock01",
+                "formatted /dev/mock01",
+                "format /dev/mock02",
+                "formatted /dev/mock02",
+                "format /dev/mock03",
+                "formatted /dev/mock03",
+                "mount Boot",
+                "mount ExtendedBoot",
+                "mount Root",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let fixture = Fixture::whole_disk();
+        let provisioner = fixture.provision(Provisioner::new());
+
+        let output = provisioner.dry_run().unwrap();
+        let plan = &output["plans"][0];
+        assert_eq!(plan["strategy"], "whole_disk");
+        assert_eq!(plan["devices"][0]["device"], "/dev/mock0");
+        assert_eq!(plan["devices"][0]["table_type"], "gpt");
+        assert_eq!(plan["devices"][0]["changes"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            plan["devices"][0]["changes"][0]["type_guid"],
+            partition_types::EFI.guid.to_string()
+        );
+        assert_eq!(
+            plan["filesystems"][2],
+            json!({ "device": "/dev/mock03", "filesystem": "xfs (label: ROOT)" })
+        );
+        assert_eq!(
+            plan["mounts"][0],
+            json!({
+                "role": "root",
+                "device": "/dev/mock03",
+                "mount_point": "/",
+                "subvolume": null,
+                "options": ["noatime"],
+            })
+        );
+        assert_eq!(plan["preserved"], json!([]));
+
+        // Nothing was applied to the device
+        assert!(fixture.device.partitions().is_empty());
+    }
+
+    #[test]
+    fn test_generate_mount_config() {
+        let fixture = Fixture::whole_disk();
+        let provisioner = fixture.provision(Provisioner::new());
+        let plans = provisioner.plan().unwrap();
+
+        let fstab = provisioner.generate_mount_config(&plans[0], &crate::FstabPlugin);
+        assert_eq!(
+            fstab,
+            vec![
+                "/dev/mock03 / xfs noatime 0 1",
+                "/dev/mock02 /boot vfat umask=0077,noexec,nosuid,nodev 0 2",
+                "/dev/mock01 /efi vfat umask=0077,noexec,nosuid,nodev 0 2",
+            ]
+        );
+
+        let units = provisioner.generate_mount_config(&plans[0], &crate::SystemdMountPlugin);
+        assert_eq!(units.len(), 3);
+        assert!(units[0].starts_with("# -.mount\n"));
+        assert!(units[2].contains("Where=/efi\nType=vfat\n"));
+
+        let shell = provisioner.generate_mount_config(&plans[0], &crate::ShellPlugin::new("/mnt"));
+        assert_eq!(
+            shell[0],
+            "mkdir -p '/mnt' && mount -t xfs -o noatime '/dev/mock03' '/mnt'"
+        );
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join(format!("disks-rs-audit-{}.jsonl", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let fixture = Fixture::whole_disk();
+        let provisioner = fixture.provision(Provisioner::new().with_audit_log(&path));
+
+        let plans = provisioner.plan().unwrap();
+        provisioner
+            .execute_with(&plans[0], RecordingBackend::default())
+            .unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entries = log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        let types = entries
+            .iter()
+            .map(|entry| entry["event_type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                "PartitionTableWritten",
+                "PartitionCreated",
+                "PartitionCreated",
+                "PartitionCreated",
+                "FilesystemFormatted",
+                "FilesystemFormatted",
+                "FilesystemFormatted",
+                "StrategyApplied",
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry["timestamp"].as_u64().unwrap() > 0));
+
+        let created = &entries[1]["details"];
+        assert_eq!(created["device"], "/dev/mock01");
+        assert_eq!(created["partition_id"], 1);
+        assert!(created["end"].as_u64().unwrap() > created["start"].as_u64().unwrap());
+        assert_eq!(entries[7]["details"]["strategy"], plans[0].strategy.name);
+    }
+
+    #[test]
+    fn test_wipe_signatures() {
+        let audit_types = |table: &str| {
+            let source = format!(
+                r#"strategy name="blank" summary="Wipe signatures" {{
+    find-disk "root_disk"
+    wipe-signatures disk="root_disk"
+    {table}
+}}
+"#
+            );
+            let parser = Parser::new("wipe.kdl", &source).unwrap();
+            let path = std::env::temp_dir().join(format!("disks-rs-wipe-audit-{}.jsonl", std::process::id()));
+            std::fs::remove_file(&path).ok();
+
+            let device = BlockDevice::mock_device(MockDisk::new(64 * 1024 * 1024 * 1024));
+            let mut provisioner = Provisioner::new().with_audit_log(&path);
+            provisioner.push_device(&device);
+            provisioner.add_strategy(&parser.strategies[0]);
+
+            let plans = provisioner.plan().unwrap();
+            assert!(plans[0].device_assignments["root_disk"].erase_signatures);
+            provisioner
+                .execute_with(&plans[0], RecordingBackend::default())
+                .unwrap();
+
+            let log = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            log.lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["event_type"].to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Alone the disk is left blank, otherwise the new table is written afterwards
+        assert_eq!(audit_types(""), [r#""SignaturesErased""#, r#""StrategyApplied""#]);
+        assert_eq!(
+            audit_types(r#"create-partition-table type="gpt" disk="root_disk""#),
+            [
+                r#""SignaturesErased""#,
+                r#""PartitionTableWritten""#,
+                r#""StrategyApplied""#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_auto_table_type() {
+        let source = r#"strategy name="auto" summary="Table for the firmware" {
+    find-disk "root_disk"
+    create-partition-table type="auto" disk="root_disk"
+}
+"#;
+        let parser = Parser::new("auto.kdl", source).unwrap();
+        let table_type = |firmware: Firmware| {
+            let device = BlockDevice::mock_device(MockDisk::new(64 * 1024 * 1024 * 1024));
+            let mut provisioner = Provisioner::new().with_firmware(firmware);
+            provisioner.push_device(&device);
+            provisioner.add_strategy(&parser.strategies[0]);
+            assert_eq!(provisioner.target_table_for_firmware(), firmware.partition_table_type());
+
+            let plans = provisioner.plan().unwrap();
+            plans[0].device_assignments["root_disk"].table_type
+        };
+
+        assert_eq!(table_type(Firmware::Uefi), PartitionTableType::Gpt);
+        assert_eq!(table_type(Firmware::Bios), PartitionTableType::Msdos);
+    }
+
+    #[test]
+    fn test_execute_bios_table() {
+        let source = r#"strategy name="bios" summary="Install for legacy BIOS" {
+    find-disk "root_disk"
+    create-partition-table type="auto" disk="root_disk"
+    create-partition disk="root_disk" id="boot" role="boot" {
+        constraints {
+            exact (GiB)1
+        }
+        filesystem {
+            type "ext4"
+        }
+    }
+    create-partition disk="root_disk" id="root" role="root" {
+        constraints {
+            remaining
+        }
+        filesystem {
+            type "xfs"
+        }
+    }
+}
+"#;
+        let fixture = Fixture::from_source(source);
+        let provisioner = fixture.provision(Provisioner::new().with_firmware(Firmware::Bios));
+        let plans = provisioner.plan().unwrap();
+
+        let backend = RecordingBackend::default();
+        let io = backend.log.clone();
+        provisioner.execute_with(&plans[0], backend).unwrap();
+        assert_eq!(
+            *io.borrow(),
+            vec![
+                "write /dev/mock0 as msdos",
+                "format /dev/mock01 as ext4",
+                "format /dev/mock02 as xfs",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_partition_count() {
+        const MB: u64 = 1024 * 1024;
+
+        // Plans as many 1MiB partitions as the table allows, plus one more
+        let planned = |table: &str, count: u64| {
+            let source = format!(
+                r#"strategy name="count" summary="Sized table" {{
+    find-disk "root_disk"
+    create-partition-table {table} disk="root_disk"
+}}
+"#
+            );
+            let fixture = Fixture::from_source(&source);
+            let provisioner = fixture.provision(Provisioner::new());
+            let plans = provisioner.plan().unwrap();
+            let mut planner = plans[0].device_assignments["root_disk"].planner.clone();
+            let (start, _) = planner.offsets();
+            let results = (0..=count)
+                .map(|i| planner.plan_add_partition(start + i * MB, start + (i + 1) * MB).is_ok())
+                .collect::<Vec<_>>();
+            (planner.max_partitions(), results)
+        };
+
+        // Past the default 128 entries of a GPT
+        let (max, results) = planned(r#"type="gpt" partitions=256"#, 256);
+        assert_eq!(max, 256);
+        assert!(results[..256].iter().all(|ok| *ok));
+        assert!(!results[256]);
+
+        // And fewer than the 4 primary partitions of an MBR
+        let (max, results) = planned(r#"type="msdos" partitions=2"#, 2);
+        assert_eq!(max, 2);
+        assert_eq!(results, [true, true, false]);
+    }
+
+    #[test]
+    fn test_find_existing_esp_read_error() {
+        // A disk known to sysfs whose device node cannot be opened
+        let sysroot = std::env::temp_dir().join(format!("provisioning-esp-sysroot-{}", std::process::id()));
+        let disk = sysroot.join("sys/class/block/sdzy");
+        fs::create_dir_all(&disk).unwrap();
+        fs::write(disk.join("size"), "2048\n").unwrap();
+        let device = BlockDevice::from_sysfs_path(&sysroot, "sdzy");
+        fs::remove_dir_all(&sysroot).unwrap();
+        let device = device.unwrap();
+
+        let mut provisioner = Provisioner::new().with_esp_reuse();
+        provisioner.push_device(&device);
+        assert!(matches!(
+            provisioner.find_existing_esp(),
+            Err(PlanError::ReadPartitionTable { device, .. }) if device == Path::new("/dev/sdzy")
+        ));
+        assert!(matches!(provisioner.plan(), Err(PlanError::ReadPartitionTable { .. })));
+    }
+
+    #[test]
+    fn test_reuse_esp() {
+        use partitioning::{
+            GptAttributes, sparsefile,
+            writer::{DiskWriter, WriteConfirmation},
+        };
+
+        const MB: u64 = 1024 * 1024;
+
+        let Some(result) =
+            sparsefile::with_loop_device(64 * MB, |mut device| -> Result<_, Box<dyn std::error::Error>> {
+                // Write a GPT holding a single ESP, which the writer tells the kernel about
+                let mut planner = Planner::new(&device);
+                planner.plan_initialize_disk()?;
+                planner.plan_add_partition_with_attributes(
+                    MB,
+                    33 * MB,
+                    Some(PartitionAttributes {
+                        table: TableAttributes::Gpt(GptAttributes {
+                            type_guid: partition_types::EFI,
+                            ..Default::default()
+                        }),
+                        role: None,
+                        filesystem: None,
+                        encryption: None,
+                    }),
+                )?;
+                DiskWriter::new(&device, &planner)
+                    .with_confirmation(WriteConfirmation::for_device(&device))
+                    .write()?;
+
+                // Rescan so the new partition is visible
+                device.refresh()?;
+                let target = Fixture::whole_disk();
+                let mut provisioner = Provisioner::new().with_esp_reuse();
+                provisioner.push_device(&device);
+                let provisioner = target.provision(provisioner);
+
+                let esp = provisioner
+                    .find_existing_esp()?
+                    .map(|(_, partition)| partition.device.clone());
+                let plan = provisioner
+                    .plan()?
+                    .into_iter()
+                    .next()
+                    .map(|plan| {
+                        let partitions = plan.device_assignments["root_disk"]
+                            .planner
+                            .current_layout()
+                            .iter()
+                            .filter(|region| region.partition_id.is_some())
+                            .count();
+                        (plan.role_mounts, plan.filesystems, plan.preserved, partitions)
+                    })
+                    .ok_or("no plan")?;
+                Ok((device.device().to_owned(), esp, plan))
+            })
+        else {
+            return;
+        };
+
+        let (loop_device, esp, (role_mounts, filesystems, preserved, partitions)) = result.unwrap();
+        let esp = esp.expect("ESP not found");
+        assert_eq!(esp, PathBuf::from(format!("{}p1", loop_device.display())));
+
+        // The ESP is mounted but never formatted, and not created on the target disk
+        assert_eq!(role_mounts[&PartitionRole::Boot], esp);
+        assert!(!filesystems.contains_key(&esp));
+        assert_eq!(preserved, HashSet::from([esp]));
+        assert_eq!(partitions, 2);
+        assert_eq!(filesystems.len(), 2);
+    }
 }
@@ -0,0 +1,26 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Feed arbitrary bytes through superblock detection
+//!
+//! Detection parses untrusted on-disk data, so every input must yield either a
+//! superblock or an error, never a panic.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use superblock::Superblock;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = Superblock::from_bytes(data) {
+        let _ = block.kind();
+        let _ = block.uuid();
+        let _ = block.label();
+        let _ = block.label_lossy();
+        let _ = block.formatted_sector_size();
+        let _ = block.label_with_reader(&mut Cursor::new(data));
+    }
+});
@@ -7,7 +7,10 @@
 //! This module provides functionality for reading and parsing BTRFS filesystem superblocks,
 //! which contain critical metadata about the filesystem including UUIDs and labels.
 
+use std::io::{self, Read, Seek, SeekFrom};
+
 use crate::{Detection, UnicodeError};
+use bitflags::bitflags;
 use uuid::Uuid;
 use zerocopy::*;
 
@@ -18,7 +21,7 @@ use zerocopy::*;
 /// - Size and usage information
 /// - Root tree locations
 /// - Compatibility flags
-#[derive(FromBytes, Debug)]
+#[derive(FromBytes, IntoBytes, Immutable, Debug, Clone)]
 #[repr(C)]
 pub struct Btrfs {
     /// Checksum of the superblock data
@@ -88,16 +91,34 @@ pub struct Btrfs {
     /// Number of global root entries
     pub nr_global_roots: U64<LittleEndian>,
     /// Reserved for future use
-    pub reserved: [u8; 32],
+    pub reserved: [U64<LittleEndian>; 27],
     /// System chunk array data
     pub sys_chunk_array: [u8; 2048],
-    /// Backup copy of root tree info
-    pub root_backup: [u8; 256],
+    /// Backup copies of the root tree info, four of 168 bytes each
+    pub root_backup: [u8; 672],
+    /// Padding to the end of the 4KiB superblock, covered by the checksum
+    pub padding: [u8; 565],
 }
 
 /// Offset where the BTRFS superblock starts (65536 bytes)
 pub const START_POSITION: u64 = 0x10000;
 
+/// Offset of the first superblock mirror (64MiB)
+pub const MIRROR_POSITION: u64 = 0x4000000;
+
+/// Checksum algorithms a BTRFS filesystem can use, from `csum_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumType {
+    /// CRC32C, the default
+    Crc32c,
+    /// 64-bit xxHash
+    Xxhash,
+    /// SHA-256
+    Sha256,
+    /// BLAKE2b-256
+    Blake2b,
+}
+
 /// Magic number identifying a BTRFS superblock ("_BHRfS_M")
 pub const MAGIC: U64<LittleEndian> = U64::new(0x4D5F53665248425F);
 
@@ -115,6 +136,66 @@ impl Detection for Btrfs {
     }
 }
 
+bitflags! {
+    /// Incompatible feature flags stored in the BTRFS superblock
+    ///
+    /// Unknown bits are retained so newer filesystems round-trip unchanged.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BtrfsIncompatFlags: u64 {
+        /// Mixed back references
+        const MIXED_BACKREF = 1 << 0;
+        /// A default subvolume has been set
+        const DEFAULT_SUBVOL = 1 << 1;
+        /// Data and metadata share block groups
+        const MIXED_GROUPS = 1 << 2;
+        /// LZO compression in use
+        const COMPRESS_LZO = 1 << 3;
+        /// ZSTD compression in use
+        const COMPRESS_ZSTD = 1 << 4;
+        /// Metadata blocks larger than the page size
+        const BIG_METADATA = 1 << 5;
+        /// Extended inode references
+        const EXTENDED_IREF = 1 << 6;
+        /// RAID5 or RAID6 block group profiles
+        const RAID56 = 1 << 7;
+        /// Skinny metadata extent references
+        const SKINNY_METADATA = 1 << 8;
+        /// Holes are not stored as file extents
+        const NO_HOLES = 1 << 9;
+        /// Separate metadata UUID
+        const METADATA_UUID = 1 << 10;
+        /// RAID1C3 or RAID1C4 block group profiles
+        const RAID1C34 = 1 << 11;
+        /// Zoned block device support
+        const ZONED = 1 << 12;
+        /// Extent tree v2
+        const EXTENT_TREE_V2 = 1 << 13;
+        /// RAID stripe tree
+        const RAID_STRIPE_TREE = 1 << 14;
+        /// Simple quotas
+        const SIMPLE_QUOTA = 1 << 16;
+
+        const _ = !0;
+    }
+}
+
+impl BtrfsIncompatFlags {
+    /// Whether RAID5/6 profiles are in use, which lack reliable parity protection
+    pub fn has_raid56(&self) -> bool {
+        self.contains(Self::RAID56)
+    }
+
+    /// Whether RAID1C3/RAID1C4 profiles are in use
+    pub fn has_raid1c34(&self) -> bool {
+        self.contains(Self::RAID1C34)
+    }
+
+    /// Whether mixed back references are in use
+    pub fn has_mixed_backref(&self) -> bool {
+        self.contains(Self::MIXED_BACKREF)
+    }
+}
+
 impl Btrfs {
     /// Return the encoded UUID for this superblock as a string
     pub fn uuid(&self) -> Result<String, UnicodeError> {
@@ -125,4 +206,212 @@ impl Btrfs {
     pub fn label(&self) -> Result<String, UnicodeError> {
         Ok(std::str::from_utf8(&self.label)?.trim_end_matches('\0').to_owned())
     }
+
+    /// Return the volume label, replacing invalid UTF-8 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.label).trim_end_matches('\0').to_owned()
+    }
+
+    /// Read the superblock mirror at [`MIRROR_POSITION`]
+    ///
+    /// Returns `None` if the device is too small to hold a mirror, or if no valid
+    /// mirror is found there.
+    pub fn read_mirror<R: Read + Seek>(reader: &mut R, device_size: u64) -> io::Result<Option<Self>> {
+        if device_size < MIRROR_POSITION + Self::SIZE as u64 {
+            return Ok(None);
+        }
+
+        reader.seek(SeekFrom::Start(MIRROR_POSITION))?;
+        let mut bytes = vec![0u8; Self::SIZE];
+        reader.read_exact(&mut bytes)?;
+
+        match Self::read_from_bytes(&bytes) {
+            Ok(mirror) if mirror.magic == MAGIC && mirror.bytenr.get() == MIRROR_POSITION => Ok(Some(mirror)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Use the mirror superblock when this one has an empty label
+    ///
+    /// Some tools only update the label in the mirror copy. The mirror is only
+    /// preferred if it belongs to the same filesystem, has a label, and is at
+    /// least as recent as this superblock.
+    pub fn with_mirror_label<R: Read + Seek>(self, reader: &mut R, device_size: u64) -> io::Result<Self> {
+        if self.label.iter().any(|b| *b != 0) {
+            return Ok(self);
+        }
+
+        match Self::read_mirror(reader, device_size)? {
+            Some(mirror)
+                if mirror.fsid == self.fsid
+                    && mirror.generation.get() >= self.generation.get()
+                    && mirror.label.iter().any(|b| *b != 0) =>
+            {
+                Ok(mirror)
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// Return the decoded incompatible feature flags
+    pub fn incompat_flags(&self) -> BtrfsIncompatFlags {
+        BtrfsIncompatFlags::from_bits_retain(self.incompat_flags.get())
+    }
+
+    /// Whether the UUID tree, used to look up subvolumes by UUID, is present and current
+    ///
+    /// There is no feature flag for the UUID tree. The kernel creates it on first
+    /// mount and records the generation it was last updated in, so a tree written
+    /// by an older generation than the filesystem's is rescanned before use.
+    pub fn has_uuid_tree(&self) -> bool {
+        let uuid_tree_generation = self.uuid_tree_generation.get();
+        uuid_tree_generation != 0 && uuid_tree_generation == self.generation.get()
+    }
+
+    /// Returns the checksum algorithm, or `None` if `csum_type` is unknown
+    pub fn checksum_type(&self) -> Option<ChecksumType> {
+        match self.csum_type.get() {
+            0 => Some(ChecksumType::Crc32c),
+            1 => Some(ChecksumType::Xxhash),
+            2 => Some(ChecksumType::Sha256),
+            3 => Some(ChecksumType::Blake2b),
+            _ => None,
+        }
+    }
+
+    /// Whether the superblock checksum matches its contents
+    ///
+    /// The checksum covers everything after the `csum` field. Only CRC32C and
+    /// xxHash are computed, so this is always false for the other algorithms.
+    pub fn verify_checksum(&self) -> bool {
+        let data = &self.as_bytes()[std::mem::size_of_val(&self.csum)..];
+        match self.checksum_type() {
+            Some(ChecksumType::Crc32c) => self.csum[..4] == crc32c::crc32c(data).to_le_bytes(),
+            Some(ChecksumType::Xxhash) => self.csum[..8] == xxhash_rust::xxh64::xxh64(data, 0).to_le_bytes(),
+            Some(ChecksumType::Sha256 | ChecksumType::Blake2b) | None => false,
+        }
+    }
+
+    /// Whether a default subvolume other than the top level has ever been set
+    pub fn has_default_subvol(&self) -> bool {
+        self.incompat_flags().contains(BtrfsIncompatFlags::DEFAULT_SUBVOL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture_image;
+
+    fn fixture() -> Btrfs {
+        Btrfs::read_from_bytes(&fixture_image("btrfs")[START_POSITION as usize..][..Btrfs::SIZE]).unwrap()
+    }
+
+    /// Overwrite the label of the superblock at `offset` in the image
+    fn set_label(image: &mut [u8], offset: u64, label: &[u8]) {
+        let field = &mut image[offset as usize + std::mem::offset_of!(Btrfs, label)..][..256];
+        field.fill(0);
+        field[..label.len()].copy_from_slice(label);
+    }
+
+    /// Adjust the generation of the superblock at `offset` in the image
+    fn bump_generation(image: &mut [u8], offset: u64, delta: i64) {
+        let field = &mut image[offset as usize + std::mem::offset_of!(Btrfs, generation)..][..8];
+        let generation = u64::from_le_bytes(field.try_into().unwrap()).wrapping_add_signed(delta);
+        field.copy_from_slice(&generation.to_le_bytes());
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        assert_eq!(Btrfs::SIZE, 4096);
+        assert_eq!(std::mem::offset_of!(Btrfs, sys_chunk_array), 0x32B);
+
+        let block = fixture();
+        assert_eq!(block.checksum_type(), Some(ChecksumType::Crc32c));
+        assert!(block.verify_checksum());
+
+        // Any change to the covered bytes, including the padding, is caught
+        let mut corrupt = block.clone();
+        corrupt.padding[0] ^= 1;
+        assert!(!corrupt.verify_checksum());
+
+        let mut xxhash = block;
+        xxhash.csum_type = U16::new(1);
+        assert!(!xxhash.verify_checksum());
+        let digest = xxhash_rust::xxh64::xxh64(&xxhash.as_bytes()[32..], 0);
+        xxhash.csum[..8].copy_from_slice(&digest.to_le_bytes());
+        assert!(xxhash.verify_checksum());
+    }
+
+    #[test]
+    fn test_mirror_label() {
+        let mut image = fixture_image("btrfs");
+        set_label(&mut image, START_POSITION, b"");
+        set_label(&mut image, MIRROR_POSITION, b"secondary");
+        bump_generation(&mut image, MIRROR_POSITION, 1);
+
+        let block = crate::Superblock::from_reader(&mut io::Cursor::new(&image)).unwrap();
+        assert_eq!(block.label().unwrap(), "secondary");
+
+        // Without room for a mirror the primary superblock is used as-is
+        let truncated = &image[..MIRROR_POSITION as usize];
+        let block = crate::Superblock::from_reader(&mut io::Cursor::new(truncated)).unwrap();
+        assert_eq!(block.label().unwrap(), "");
+    }
+
+    #[test]
+    fn test_mirror_label_stale() {
+        let mut image = fixture_image("btrfs");
+        set_label(&mut image, START_POSITION, b"");
+        bump_generation(&mut image, MIRROR_POSITION, -1);
+
+        let block = crate::Superblock::from_reader(&mut io::Cursor::new(&image)).unwrap();
+        assert_eq!(block.label().unwrap(), "");
+    }
+
+    #[test]
+    fn test_incompat_flags() {
+        // mkfs.btrfs defaults on a single device
+        let flags = fixture().incompat_flags();
+        assert_eq!(
+            flags,
+            BtrfsIncompatFlags::MIXED_BACKREF
+                | BtrfsIncompatFlags::EXTENDED_IREF
+                | BtrfsIncompatFlags::SKINNY_METADATA
+                | BtrfsIncompatFlags::NO_HOLES
+        );
+        assert!(flags.has_mixed_backref());
+        assert!(!flags.has_raid56());
+        assert!(!flags.has_raid1c34());
+    }
+
+    #[test]
+    fn test_raid_profiles() {
+        let mut sb = fixture();
+        sb.incompat_flags = U64::new(sb.incompat_flags.get() | 1 << 7 | 1 << 11 | 1 << 63);
+
+        let flags = sb.incompat_flags();
+        assert!(flags.has_raid56());
+        assert!(flags.has_raid1c34());
+        // Unknown bits survive for forward compatibility
+        assert_eq!(flags.bits(), sb.incompat_flags.get());
+    }
+
+    #[test]
+    fn test_uuid_tree_and_default_subvol() {
+        // The fixture has never been mounted, so the kernel has not created a UUID tree
+        let mut sb = fixture();
+        assert!(!sb.has_uuid_tree());
+        assert!(!sb.has_default_subvol());
+
+        sb.uuid_tree_generation = sb.generation;
+        assert!(sb.has_uuid_tree());
+        sb.uuid_tree_generation = U64::new(sb.generation.get() - 1);
+        assert!(!sb.has_uuid_tree());
+        sb.uuid_tree_generation = U64::new(0);
+        assert!(!sb.has_uuid_tree());
+
+        sb.incompat_flags = U64::new(sb.incompat_flags.get() | 1 << 1);
+        assert!(sb.has_default_subvol());
+    }
 }
@@ -8,13 +8,15 @@
 //! The superblock contains critical metadata about the filesystem including UUID, volume label,
 //! and various configuration parameters.
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use crate::{Detection, UnicodeError};
 use uuid::Uuid;
 use zerocopy::*;
 
 /// EXT4 Superblock definition that mirrors the on-disk format used by the Linux kernel.
 /// Contains metadata and configuration for an EXT4 filesystem.
-#[derive(Debug, FromBytes)]
+#[derive(Debug, Clone, FromBytes)]
 #[repr(C)]
 pub struct Ext4 {
     /// Total count of inodes in filesystem
@@ -119,6 +121,8 @@ pub struct Ext4 {
     pub jnl_blocks: [U32<LittleEndian>; 17],
     /// High 32-bits of block count
     pub blocks_count_hi: U32<LittleEndian>,
+    /// High 32-bits of reserved block count
+    pub r_blocks_count_hi: U32<LittleEndian>,
     /// High 32-bits of free block count
     pub free_blocks_count_hi: U32<LittleEndian>,
     /// Minimum inode extra size
@@ -190,9 +194,24 @@ pub struct Ext4 {
 /// Magic number that identifies an EXT4 superblock
 pub const MAGIC: U16<LittleEndian> = U16::new(0xEF53);
 
+/// Size bounds for resizing a filesystem, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeLimits {
+    /// The current size of the filesystem
+    pub current: u64,
+    /// The smallest size the filesystem could be shrunk to without losing data
+    pub minimum: u64,
+}
+
 /// Start position of superblock in filesystem
 pub const START_POSITION: u64 = 1024;
 
+/// Incompatible feature flag enabling 64-bit block counts
+pub const FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+
+/// Compatible feature flag marking a filesystem with a journal
+pub const FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x4;
+
 impl Detection for Ext4 {
     type Magic = U16<LittleEndian>;
 
@@ -213,8 +232,259 @@ impl Ext4 {
         Ok(Uuid::from_bytes(self.uuid).hyphenated().to_string())
     }
 
+    /// Returns true if the filesystem uses 64-bit block counts
+    pub fn is_64bit(&self) -> bool {
+        self.feature_incompat.get() & FEATURE_INCOMPAT_64BIT != 0
+    }
+
+    /// Returns true if the journal lives on a separate device rather than in an inode
+    ///
+    /// Such a filesystem cannot be mounted without its journal device, so it
+    /// should not be reused unless that device is kept as well.
+    pub fn has_external_journal(&self) -> bool {
+        self.feature_compat.get() & FEATURE_COMPAT_HAS_JOURNAL != 0
+            && self.journal_inum.get() == 0
+            && (self.journal_uuid().is_some() || self.journal_dev.get() != 0)
+    }
+
+    /// Returns the UUID of the external journal device, if one is recorded
+    pub fn journal_uuid(&self) -> Option<String> {
+        let uuid = Uuid::from_bytes(self.journal_uuid);
+        (!uuid.is_nil()).then(|| uuid.hyphenated().to_string())
+    }
+
+    /// Returns the block size in bytes, or `None` if the recorded size is out of range
+    pub fn block_size(&self) -> Option<u64> {
+        1u64.checked_shl(self.log_block_size.get().checked_add(10)?)
+    }
+
+    /// Combine the low and high halves of a block count, honouring the 64BIT feature
+    fn combine_count(&self, lo: U32<LittleEndian>, hi: U32<LittleEndian>) -> u64 {
+        if self.is_64bit() {
+            (u64::from(hi.get()) << 32) | u64::from(lo.get())
+        } else {
+            u64::from(lo.get())
+        }
+    }
+
+    /// Returns the total number of blocks in the filesystem
+    pub fn blocks_count(&self) -> u64 {
+        self.combine_count(self.block_counts_lo, self.blocks_count_hi)
+    }
+
+    /// Returns the number of blocks reserved for the superuser
+    pub fn r_blocks_count(&self) -> u64 {
+        self.combine_count(self.r_blocks_count_lo, self.r_blocks_count_hi)
+    }
+
+    /// Returns the number of free blocks
+    pub fn free_blocks_count(&self) -> u64 {
+        self.combine_count(self.free_blocks_count_lo, self.free_blocks_count_hi)
+    }
+
+    /// Returns the total size of the filesystem in bytes
+    pub fn total_bytes(&self) -> Option<u64> {
+        Some(self.blocks_count().saturating_mul(self.block_size()?))
+    }
+
+    /// Returns the free space of the filesystem in bytes
+    pub fn free_bytes(&self) -> Option<u64> {
+        Some(self.free_blocks_count().saturating_mul(self.block_size()?))
+    }
+
+    /// Returns the number of blocks in use, including any 64-bit high words
+    pub fn used_blocks(&self) -> u64 {
+        self.blocks_count().saturating_sub(self.free_blocks_count())
+    }
+
+    /// Returns the number of inodes in use
+    pub fn used_inodes(&self) -> u32 {
+        self.inodes_count.get().saturating_sub(self.free_inodes_count.get())
+    }
+
+    /// Returns the fraction of blocks in use, from 0.0 to 1.0
+    ///
+    /// A filesystem reporting no blocks at all is treated as empty.
+    pub fn occupancy_ratio(&self) -> f64 {
+        match self.blocks_count() {
+            0 => 0.0,
+            total => self.used_blocks() as f64 / total as f64,
+        }
+    }
+
+    /// Returns the bounds for resizing the filesystem, e.g. with `resize2fs`
+    ///
+    /// The minimum is the space taken by blocks in use. `resize2fs` needs some
+    /// extra room for metadata on top of this, so treat it as a lower bound.
+    /// Returns `None` if the block size is out of range.
+    pub fn resize_limits(&self) -> Option<ResizeLimits> {
+        Some(ResizeLimits {
+            current: self.total_bytes()?,
+            minimum: self.used_blocks().saturating_mul(self.block_size()?),
+        })
+    }
+
+    /// Returns the Unix timestamp of the last write to the filesystem
+    pub fn last_write_time(&self) -> u32 {
+        self.w_time.get()
+    }
+
+    /// Returns how long ago the filesystem was created
+    ///
+    /// `None` if the superblock predates the creation time field, which then
+    /// reads as zero, or the creation time lies in the future.
+    pub fn age_since_format(&self) -> Option<Duration> {
+        self.age_since_format_at(SystemTime::now())
+    }
+
+    /// Returns the time between the filesystem's creation and `now`
+    fn age_since_format_at(&self, now: SystemTime) -> Option<Duration> {
+        let created = match self.mkfs_time.get() {
+            0 => return None,
+            secs => UNIX_EPOCH + Duration::from_secs(u64::from(secs)),
+        };
+        now.duration_since(created).ok()
+    }
+
     /// Return the volume label as valid utf8
     pub fn label(&self) -> Result<String, UnicodeError> {
-        Ok(std::str::from_utf8(&self.volume_name)?.into())
+        Ok(std::str::from_utf8(&self.volume_name)?
+            .trim_end_matches('\0')
+            .to_owned())
+    }
+
+    /// Return the volume label, replacing invalid UTF-8 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.volume_name)
+            .trim_end_matches('\0')
+            .to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture_image;
+
+    fn empty_superblock() -> Ext4 {
+        Ext4::read_from_bytes(&vec![0u8; Ext4::SIZE]).unwrap()
+    }
+
+    #[test]
+    fn test_superblock_size() {
+        assert_eq!(Ext4::SIZE, 1024);
+        assert_eq!(std::mem::offset_of!(Ext4, blocks_count_hi), 0x150);
+        assert_eq!(std::mem::offset_of!(Ext4, checksum), 0x3FC);
+        assert_eq!(std::mem::offset_of!(Ext4, w_time), 0x30);
+        assert_eq!(std::mem::offset_of!(Ext4, mkfs_time), 0x108);
+    }
+
+    #[test]
+    fn test_64bit_block_count() {
+        let mut sb = empty_superblock();
+        sb.log_block_size = U32::new(2); // 4KiB blocks
+        sb.block_counts_lo = U32::new(0x10);
+        sb.blocks_count_hi = U32::new(0x2);
+        sb.free_blocks_count_lo = U32::new(0x8);
+        sb.free_blocks_count_hi = U32::new(0x1);
+        sb.r_blocks_count_lo = U32::new(0x4);
+        sb.r_blocks_count_hi = U32::new(0x1);
+
+        // Without the 64BIT feature the high words are ignored
+        assert_eq!(sb.blocks_count(), 0x10);
+        assert_eq!(sb.total_bytes(), Some(0x10 * 4096));
+
+        sb.feature_incompat = U32::new(FEATURE_INCOMPAT_64BIT);
+        assert!(sb.is_64bit());
+        assert_eq!(sb.blocks_count(), 0x2_0000_0010);
+        assert_eq!(sb.free_blocks_count(), 0x1_0000_0008);
+        assert_eq!(sb.r_blocks_count(), 0x1_0000_0004);
+        assert_eq!(sb.total_bytes(), Some(0x2_0000_0010 * 4096));
+        assert_eq!(sb.free_bytes(), Some(0x1_0000_0008 * 4096));
+    }
+
+    #[test]
+    fn test_block_size_out_of_range() {
+        let mut sb = empty_superblock();
+        sb.block_counts_lo = U32::new(0x10);
+        sb.log_block_size = U32::new(53);
+        assert_eq!(sb.block_size(), Some(1 << 63));
+        assert_eq!(sb.total_bytes(), Some(u64::MAX));
+
+        for log_block_size in [54, 64, u32::MAX] {
+            sb.log_block_size = U32::new(log_block_size);
+            assert_eq!(sb.block_size(), None);
+            assert_eq!(sb.total_bytes(), None);
+            assert_eq!(sb.free_bytes(), None);
+            assert!(sb.resize_limits().is_none());
+        }
+    }
+
+    #[test]
+    fn test_usage() {
+        let mut sb = empty_superblock();
+        assert_eq!(sb.used_blocks(), 0);
+        assert_eq!(sb.used_inodes(), 0);
+        assert_eq!(sb.occupancy_ratio(), 0.0);
+
+        sb.block_counts_lo = U32::new(1000);
+        sb.free_blocks_count_lo = U32::new(220);
+        sb.inodes_count = U32::new(512);
+        sb.free_inodes_count = U32::new(500);
+        assert_eq!(sb.used_blocks(), 780);
+        assert_eq!(sb.used_inodes(), 12);
+        assert!((sb.occupancy_ratio() - 0.78).abs() < f64::EPSILON);
+
+        sb.free_blocks_count_lo = U32::new(1000);
+        assert_eq!(sb.occupancy_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_resize_limits() {
+        let image = fixture_image("ext4");
+
+        let sb = Ext4::read_from_bytes(&image[START_POSITION as usize..][..Ext4::SIZE]).unwrap();
+        let limits = sb.resize_limits().unwrap();
+        assert_eq!(limits.current, sb.total_bytes().unwrap());
+        assert_eq!(limits.minimum, sb.total_bytes().unwrap() - sb.free_bytes().unwrap());
+        assert!(limits.minimum > 0);
+        assert!(limits.minimum <= limits.current);
+
+        // The fixture keeps its journal in an inode
+        assert!(!sb.has_external_journal());
+        assert_eq!(sb.journal_uuid(), None);
+    }
+
+    #[test]
+    fn test_external_journal() {
+        let mut sb = empty_superblock();
+        sb.feature_compat = U32::new(FEATURE_COMPAT_HAS_JOURNAL);
+        sb.journal_inum = U32::new(8);
+        assert!(!sb.has_external_journal());
+
+        let uuid = "0f5b2c1e-8d3a-4b6f-9e21-7c4d5a6b8e90";
+        sb.journal_inum = U32::new(0);
+        sb.journal_uuid = *Uuid::parse_str(uuid).unwrap().as_bytes();
+        assert!(sb.has_external_journal());
+        assert_eq!(sb.journal_uuid().as_deref(), Some(uuid));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut sb = empty_superblock();
+        assert_eq!(sb.last_write_time(), 0);
+        assert_eq!(sb.age_since_format(), None);
+
+        sb.w_time = U32::new(1_700_000_000);
+        sb.mkfs_time = U32::new(1_600_000_000);
+        assert_eq!(sb.last_write_time(), 1_700_000_000);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_600_086_400);
+        assert_eq!(sb.age_since_format_at(now), Some(Duration::from_secs(86_400)));
+        assert!(sb.age_since_format().unwrap() > Duration::from_secs(86_400));
+
+        // A creation time in the future gives no age
+        let before = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        assert_eq!(sb.age_since_format_at(before), None);
     }
 }
@@ -13,7 +13,8 @@
 //! - Encryption settings
 //! - Device information
 
-use crate::{Detection, UnicodeError};
+use crate::{Detection, UnicodeError, decode_utf16le_lossy};
+use bitflags::bitflags;
 use uuid::Uuid;
 use zerocopy::*;
 
@@ -35,7 +36,7 @@ pub const MAX_STOP_REASON: usize = 32;
 pub const MAX_ERRORS: usize = 16;
 
 /// Represents the F2FS superblock structure that exists on disk
-#[derive(Debug, FromBytes, Unaligned)]
+#[derive(Debug, Clone, Copy, FromBytes, Unaligned)]
 #[repr(C, packed)]
 pub struct F2FS {
     /// Magic number to identify F2FS filesystem
@@ -106,7 +107,7 @@ pub struct F2FS {
     pub version: [u8; VERSION_LEN],
     /// Initial filesystem version
     pub init_version: [u8; VERSION_LEN],
-    /// Feature flags
+    /// Feature flags, see [`F2FS::features()`]
     pub feature: U32<LittleEndian>,
     /// Encryption level
     pub encryption_level: u8,
@@ -132,6 +133,49 @@ pub struct F2FS {
     pub crc: U32<LittleEndian>,
 }
 
+bitflags! {
+    /// Feature flags stored in the F2FS superblock
+    ///
+    /// Unknown bits are retained so newer filesystems round-trip unchanged.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct F2fsFeatures: u32 {
+        /// Filesystem-level encryption (fscrypt)
+        const ENCRYPT = 0x0001;
+        /// Zoned block device support
+        const BLKZONED = 0x0002;
+        /// Atomic write support
+        const ATOMIC_WRITE = 0x0004;
+        /// Extended inode attributes
+        const EXTRA_ATTR = 0x0008;
+        /// Project quota
+        const PRJQUOTA = 0x0010;
+        /// Inode checksums
+        const INODE_CHKSUM = 0x0020;
+        /// Flexible inline extended attributes
+        const FLEXIBLE_INLINE_XATTR = 0x0040;
+        /// Quota stored in hidden inodes
+        const QUOTA_INO = 0x0080;
+        /// Inode creation time
+        const INODE_CRTIME = 0x0100;
+        /// lost+found directory
+        const LOST_FOUND = 0x0200;
+        /// fs-verity support
+        const VERITY = 0x0400;
+        /// Superblock checksum
+        const SB_CHKSUM = 0x0800;
+        /// Case-insensitive file names
+        const CASEFOLD = 0x1000;
+        /// Transparent compression
+        const COMPRESSION = 0x2000;
+        /// Read-only filesystem
+        const RO = 0x4000;
+        /// Device alias files
+        const DEVICE_ALIAS = 0x8000;
+
+        const _ = !0;
+    }
+}
+
 /// Represents a device entry in the F2FS superblock
 #[derive(Debug, Clone, Copy, FromBytes)]
 #[repr(C, packed)]
@@ -177,4 +221,65 @@ impl F2FS {
         // Need valid grapheme step and skip (u16)\0 nul termination in fixed block size
         Ok(prelim_label.trim_end_matches('\0').to_owned())
     }
+
+    /// Returns the volume label, replacing invalid UTF-16 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        decode_utf16le_lossy(self.volume_name.as_bytes())
+            .trim_end_matches('\0')
+            .to_owned()
+    }
+
+    /// Returns the decoded feature flags
+    pub fn features(&self) -> F2fsFeatures {
+        F2fsFeatures::from_bits_retain(self.feature.get())
+    }
+
+    /// Whether the filesystem has encryption enabled
+    pub fn is_encrypted(&self) -> bool {
+        self.features().contains(F2fsFeatures::ENCRYPT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture_image;
+
+    fn empty_superblock() -> F2FS {
+        F2FS::read_from_bytes(&vec![0u8; F2FS::SIZE]).unwrap()
+    }
+
+    #[test]
+    fn test_features() {
+        let mut sb = empty_superblock();
+        assert!(sb.features().is_empty());
+        assert!(!sb.is_encrypted());
+
+        sb.feature = U32::new(0x0001 | 0x2000 | 0x8000_0000);
+        assert!(sb.is_encrypted());
+        assert!(sb.features().contains(F2fsFeatures::COMPRESSION));
+        // Unknown bits survive for forward compatibility
+        assert_eq!(sb.features().bits(), sb.feature.get());
+    }
+
+    #[test]
+    fn test_fixture_features() {
+        let memory = fixture_image("f2fs");
+
+        let sb = F2FS::read_from_bytes(&memory[START_POSITION as usize..][..F2FS::SIZE]).unwrap();
+        // The fixture was created with mkfs.f2fs defaults, which enable no optional features
+        assert_eq!(sb.features(), F2fsFeatures::empty());
+        assert!(!sb.is_encrypted());
+    }
+
+    #[test]
+    fn test_label_lone_surrogate() {
+        let mut sb = empty_superblock();
+        sb.volume_name[0] = U16::new(u16::from(b'o'));
+        sb.volume_name[1] = U16::new(u16::from(b's'));
+        sb.volume_name[2] = U16::new(0xD83D);
+
+        assert!(sb.label().is_err());
+        assert_eq!(sb.label_lossy(), "os\u{fffd}");
+    }
 }
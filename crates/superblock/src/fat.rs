@@ -10,7 +10,7 @@
 //! - Volume name and UUID
 //! - Encryption settings
 
-use crate::{Detection, UnicodeError};
+use crate::{BASELINE_CONFIDENCE, Detection, UnicodeError};
 use zerocopy::*;
 
 /// Starting position of superblock in bytes
@@ -19,7 +19,7 @@ pub const START_POSITION: u64 = 0;
 const MAGIC: [u8; 2] = [0x55, 0xAA];
 
 #[repr(C, packed)]
-#[derive(FromBytes, Unaligned, Debug)]
+#[derive(FromBytes, Unaligned, Debug, Clone, Copy)]
 pub struct Fat {
     /// Boot strap short or near jump
     pub ignored: [u8; 3],
@@ -112,27 +112,99 @@ impl Detection for Fat {
     fn is_valid_magic(magic: &Self::Magic) -> bool {
         *magic == MAGIC
     }
+
+    /// The boot signature is also found in MBRs and other boot sectors, so the
+    /// BIOS parameter block must be plausible too
+    fn confidence(&self) -> u8 {
+        let sector_size = self.sector_size.get();
+        let plausible = sector_size.is_power_of_two()
+            && (512..=4096).contains(&sector_size)
+            && self.sec_per_clus.is_power_of_two()
+            && (1..=4).contains(&self.fats);
+        let fs_type = match self.fat_type() {
+            FatType::Fat12 | FatType::Fat16 => &self.fat16().common.fs_type,
+            FatType::Fat32 => &self.fat32().common.fs_type,
+        };
+
+        if !plausible {
+            0
+        } else if fs_type.starts_with(b"FAT") {
+            BASELINE_CONFIDENCE + 25
+        } else {
+            BASELINE_CONFIDENCE
+        }
+    }
 }
 
+/// Largest cluster count of a FAT12 volume, plus one
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+
+/// Largest cluster count of a FAT16 volume, plus one
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FatType {
+    Fat12,
     Fat16,
     Fat32,
 }
 
 impl Fat {
+    /// Returns the FAT type, as determined by the cluster count
+    ///
+    /// Following the Microsoft specification, volumes with fewer than 4085
+    /// clusters are FAT12 and those with fewer than 65525 are FAT16. A FAT32
+    /// boot sector is always treated as FAT32 though, as the Linux kernel
+    /// does, since `mkfs.fat -F 32` creates such volumes with fewer clusters.
     pub fn fat_type(&self) -> FatType {
-        // this is how the linux kernel does it in https://github.com/torvalds/linux/blob/master/fs/fat/inode.c
-        if self.fat_length == 0 && self.fat32().fat32_length != 0 {
-            FatType::Fat32
-        } else {
-            FatType::Fat16
+        if self.has_fat32_fields() {
+            return FatType::Fat32;
+        }
+        match self.fat_cluster_count() {
+            n if n < FAT12_MAX_CLUSTERS => FatType::Fat12,
+            n if n < FAT16_MAX_CLUSTERS => FatType::Fat16,
+            _ => FatType::Fat32,
         }
     }
 
+    /// Returns the number of data clusters, computed from the BIOS parameter block
+    ///
+    /// Zero if the sector or cluster size is zero, or the metadata claims more
+    /// sectors than the volume has.
+    pub fn fat_cluster_count(&self) -> u32 {
+        let sector_size = u32::from(self.sector_size.get());
+        let sec_per_clus = u32::from(self.sec_per_clus);
+        if sector_size == 0 || sec_per_clus == 0 {
+            return 0;
+        }
+
+        let root_dir_sectors = (u32::from(self.dir_entries.get()) * 32).div_ceil(sector_size);
+        let fat_size = match self.fat_length.get() {
+            0 => self.fat32().fat32_length.get(),
+            n => u32::from(n),
+        };
+        let total_sectors = match self.sectors.get() {
+            0 => self.total_sect.get(),
+            n => u32::from(n),
+        };
+        let metadata_sectors = u32::from(self._reserved.get())
+            .saturating_add(u32::from(self.fats).saturating_mul(fat_size))
+            .saturating_add(root_dir_sectors);
+        total_sectors.saturating_sub(metadata_sectors) / sec_per_clus
+    }
+
+    /// Whether the boot sector has the FAT32 layout, which moves the label and volume ID
+    ///
+    /// This is how the Linux kernel tells them apart, see
+    /// <https://github.com/torvalds/linux/blob/master/fs/fat/inode.c>
+    fn has_fat32_fields(&self) -> bool {
+        self.fat_length == 0 && self.fat32().fat32_length != 0
+    }
+
     /// Returns the filesystem id
     pub fn uuid(&self) -> Result<String, UnicodeError> {
         Ok(match self.fat_type() {
-            FatType::Fat16 => vol_id(self.fat16().common.vol_id),
+            FatType::Fat12 | FatType::Fat16 => vol_id(self.fat16().common.vol_id),
             FatType::Fat32 => vol_id(self.fat32().common.vol_id),
         })
     }
@@ -140,11 +212,22 @@ impl Fat {
     /// Returns the volume label
     pub fn label(&self) -> Result<String, UnicodeError> {
         match self.fat_type() {
-            FatType::Fat16 => vol_label(&self.fat16().common.vol_label),
+            FatType::Fat12 | FatType::Fat16 => vol_label(&self.fat16().common.vol_label),
             FatType::Fat32 => vol_label(&self.fat32().common.vol_label),
         }
     }
 
+    /// Returns the volume label, replacing invalid sequences with U+FFFD
+    ///
+    /// FAT labels are always decoded lossily, so this never differs from [`Fat::label`].
+    pub fn label_lossy(&self) -> String {
+        let vol_label = match self.fat_type() {
+            FatType::Fat12 | FatType::Fat16 => &self.fat16().common.vol_label,
+            FatType::Fat32 => &self.fat32().common.vol_label,
+        };
+        String::from_utf8_lossy(vol_label).trim_end_matches(' ').to_string()
+    }
+
     fn fat16(&self) -> &Fat16Fields {
         let bytes: &[u8; size_of::<Fat16Fields>()] = first_n_bytes(&self.shared);
         transmute_ref!(bytes)
@@ -170,3 +253,45 @@ fn vol_label(vol_label: &[u8; 11]) -> Result<String, UnicodeError> {
 fn vol_id(vol_id: U32<LittleEndian>) -> String {
     format!("{:04X}-{:04X}", vol_id >> 16, vol_id & 0xFFFF)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn boot_sector(bytes: &[u8]) -> Fat {
+        Fat::read_from_bytes(&bytes[..Fat::SIZE]).unwrap()
+    }
+
+    #[test]
+    fn test_fat_type() {
+        let fat16 = boot_sector(&fs::read("tests/fat16.img").unwrap());
+        assert_eq!(fat16.fat_cluster_count(), 8167);
+        assert_eq!(fat16.fat_type(), FatType::Fat16);
+
+        // mkfs.fat -F 32 on a small image leaves fewer clusters than the specification requires
+        let fat32 = boot_sector(&fs::read("tests/fat32.img").unwrap());
+        assert_eq!(fat32.fat_cluster_count(), 32232);
+        assert_eq!(fat32.fat_type(), FatType::Fat32);
+
+        // A 1.44MB floppy, which has a FAT16-style boot sector but is FAT12
+        let mut floppy = Fat::new_zeroed();
+        floppy.sector_size = U16::new(512);
+        floppy.sec_per_clus = 1;
+        floppy._reserved = U16::new(1);
+        floppy.fats = 2;
+        floppy.dir_entries = U16::new(224);
+        floppy.sectors = U16::new(2880);
+        floppy.fat_length = U16::new(9);
+        assert_eq!(floppy.fat_cluster_count(), 2880 - 1 - 18 - 14);
+        assert_eq!(floppy.fat_type(), FatType::Fat12);
+
+        // Implausible parameters give no clusters rather than dividing by zero
+        floppy.sec_per_clus = 0;
+        assert_eq!(floppy.fat_cluster_count(), 0);
+        floppy.sec_per_clus = 1;
+        floppy.sectors = U16::new(10);
+        assert_eq!(floppy.fat_cluster_count(), 0);
+    }
+}
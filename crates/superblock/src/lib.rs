@@ -5,20 +5,30 @@
 //! Superblock detection and handling for various filesystems
 //!
 //! This module provides functionality to detect and read superblocks from different
-//! filesystem types including Btrfs, Ext4, F2FS, LUKS2, and XFS.
+//! filesystem types including Btrfs, Ext4, F2FS, FAT, LUKS2, NILFS2, and XFS.
 
-use std::io::{self, BufRead, Cursor, Read, Seek};
+use std::{
+    fs,
+    io::{self, BufRead, Cursor, Read, Seek},
+    ops::Range,
+    path::Path,
+};
 
 use snafu::{ResultExt, Snafu};
-use zerocopy::FromBytes;
+use uuid::Uuid;
+use zerocopy::{FromBytes, FromZeros, LittleEndian, U16, U32};
 
 pub mod btrfs;
 pub mod ext4;
 pub mod f2fs;
 pub mod fat;
 pub mod luks2;
+pub mod nilfs2;
 pub mod xfs;
 
+/// Confidence in a superblock whose magic number is valid, when nothing else is checked
+pub const BASELINE_CONFIDENCE: u8 = 50;
+
 /// Common interface for superblock detection
 pub trait Detection: Sized + FromBytes {
     /// The magic number type for this superblock
@@ -35,6 +45,20 @@ pub trait Detection: Sized + FromBytes {
 
     /// Check if the magic number is valid for this superblock type
     fn is_valid_magic(magic: &Self::Magic) -> bool;
+
+    /// How likely a superblock with a valid magic number is to really be this type, from 0 to 100
+    ///
+    /// Types with short or common magic numbers cross-check other fields,
+    /// returning 0 to reject a superblock that only matches by chance and more
+    /// than [`BASELINE_CONFIDENCE`] when the fields clearly identify the type.
+    fn confidence(&self) -> u8 {
+        BASELINE_CONFIDENCE
+    }
+
+    /// Number of bytes from the start of the device needed to detect and read this superblock
+    fn bytes_needed() -> u64 {
+        (Self::OFFSET + Self::SIZE as u64).max(Self::MAGIC_OFFSET + std::mem::size_of::<Self::Magic>() as u64)
+    }
 }
 
 /// Errors that can occur when reading superblocks
@@ -47,9 +71,16 @@ pub enum Error {
     /// No known filesystem superblock was detected
     #[snafu(display("unknown superblock"))]
     UnknownSuperblock,
+
+    /// A string in the superblock could not be decoded
+    #[snafu(display("{source}"), context(false))]
+    Unicode { source: UnicodeError },
 }
 
 /// Errors that can occur when decoding strings from FS metadata
+///
+/// Every `uuid()` and `label()` accessor returns this error. It converts into
+/// [`Error`] with `?`, so detection and string access can share one error type.
 #[derive(Debug, Snafu)]
 pub enum UnicodeError {
     /// Error decoding UTF-8 string data
@@ -61,7 +92,30 @@ pub enum UnicodeError {
     InvalidUtf16 { source: std::string::FromUtf16Error },
 }
 
+/// Decode UTF-16 code units, replacing unpaired surrogates with U+FFFD
+pub(crate) fn decode_utf16_lossy(units: impl IntoIterator<Item = u16>) -> String {
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Decode little-endian UTF-16 bytes, replacing invalid sequences with U+FFFD
+///
+/// A trailing odd byte cannot form a code unit and is replaced as well.
+pub(crate) fn decode_utf16le_lossy(bytes: &[u8]) -> String {
+    let chunks = bytes.chunks_exact(2);
+    let trailing = !chunks.remainder().is_empty();
+    let mut decoded = decode_utf16_lossy(chunks.map(|c| u16::from_le_bytes([c[0], c[1]])));
+    if trailing {
+        decoded.push(char::REPLACEMENT_CHARACTER);
+    }
+    decoded
+}
+
 /// Attempts to detect a superblock of the given type from the reader
+///
+/// Superblocks with a valid magic number but a [`Detection::confidence`] of
+/// zero are not returned.
 pub fn detect_superblock<T: Detection, R: BufRead + Seek>(reader: &mut R) -> io::Result<Option<T>> {
     reader.seek(io::SeekFrom::Start(T::MAGIC_OFFSET))?;
     let mut magic_buf = vec![0u8; std::mem::size_of::<T::Magic>()];
@@ -72,16 +126,36 @@ pub fn detect_superblock<T: Detection, R: BufRead + Seek>(reader: &mut R) -> io:
             reader.seek(io::SeekFrom::Start(T::OFFSET))?;
             let mut block_buf = vec![0u8; T::SIZE];
             reader.read_exact(&mut block_buf)?;
-            if let Ok(block) = FromBytes::read_from_bytes(&block_buf) {
-                Ok(Some(block))
-            } else {
-                Ok(None)
+            match T::read_from_bytes(&block_buf) {
+                Ok(block) if block.confidence() > 0 => Ok(Some(block)),
+                _ => Ok(None),
             }
         }
         _ => Ok(None),
     }
 }
 
+/// Byte range of the magic number checked when detecting a superblock of the given type
+fn magic_range<T: Detection>() -> Range<u64> {
+    T::MAGIC_OFFSET..T::MAGIC_OFFSET + std::mem::size_of::<T::Magic>() as u64
+}
+
+/// Byte ranges, relative to the start of a device, of every magic number checked during detection
+///
+/// Zeroing all of these ranges leaves nothing for [`Superblock::from_reader`]
+/// to detect, while the rest of the filesystem is left intact.
+pub fn signature_ranges() -> [Range<u64>; 7] {
+    [
+        magic_range::<btrfs::Btrfs>(),
+        magic_range::<ext4::Ext4>(),
+        magic_range::<f2fs::F2FS>(),
+        magic_range::<fat::Fat>(),
+        magic_range::<luks2::Luks2>(),
+        magic_range::<nilfs2::Nilfs2>(),
+        magic_range::<xfs::Xfs>(),
+    ]
+}
+
 /// Supported filesystem types that can be detected and read
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Kind {
@@ -97,6 +171,8 @@ pub enum Kind {
     Xfs,
     /// FAT filesystem
     Fat,
+    /// NILFS2 log-structured filesystem
+    Nilfs2,
 }
 
 impl std::fmt::Display for Kind {
@@ -108,10 +184,12 @@ impl std::fmt::Display for Kind {
             Kind::F2FS => f.write_str("f2fs"),
             Kind::Xfs => f.write_str("xfs"),
             Kind::Fat => f.write_str("fat"),
+            Kind::Nilfs2 => f.write_str("nilfs2"),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub enum Superblock {
     Btrfs(Box<btrfs::Btrfs>),
     Ext4(Box<ext4::Ext4>),
@@ -119,6 +197,7 @@ pub enum Superblock {
     Luks2(Box<luks2::Luks2>),
     Xfs(Box<xfs::Xfs>),
     Fat(Box<fat::Fat>),
+    Nilfs2(Box<nilfs2::Nilfs2>),
 }
 
 impl Superblock {
@@ -131,6 +210,7 @@ impl Superblock {
             Superblock::Luks2(_) => Kind::Luks2,
             Superblock::Xfs(_) => Kind::Xfs,
             Superblock::Fat(_) => Kind::Fat,
+            Superblock::Nilfs2(_) => Kind::Nilfs2,
         }
     }
 
@@ -143,6 +223,7 @@ impl Superblock {
             Superblock::Luks2(block) => block.uuid(),
             Superblock::Xfs(block) => block.uuid(),
             Superblock::Fat(block) => block.uuid(),
+            Superblock::Nilfs2(block) => block.uuid(),
         }
     }
 
@@ -155,21 +236,181 @@ impl Superblock {
             Superblock::Luks2(block) => block.label(),
             Superblock::Xfs(block) => block.label(),
             Superblock::Fat(block) => block.label(),
+            Superblock::Nilfs2(block) => block.label(),
         }
     }
-}
 
-impl Superblock {
+    /// Returns the volume label, consulting metadata beyond the superblock if needed
+    ///
+    /// LUKS2 volumes often leave the header label empty, in which case the label is
+    /// taken from the JSON config read via `reader`. An unreadable config is treated
+    /// as having no label. All other filesystems behave as [`Superblock::label`].
+    pub fn label_with_reader<R: Read + Seek>(&self, reader: &mut R) -> Result<String, UnicodeError> {
+        let label = self.label()?;
+        match self {
+            Superblock::Luks2(block) if label.is_empty() => Ok(block
+                .read_config(reader)
+                .ok()
+                .and_then(|config| config.label())
+                .unwrap_or_default()),
+            _ => Ok(label),
+        }
+    }
+
+    /// Construct a minimal in-memory superblock, for use in tests
+    ///
+    /// All fields other than the UUID and label are zeroed, so only [`Superblock::kind`],
+    /// [`Superblock::uuid`] and [`Superblock::label`] are meaningful. Labels are
+    /// truncated to the space available in the on-disk format.
+    ///
+    /// # Panics
+    ///
+    /// If `uuid` is not a valid UUID, or for FAT a `XXXX-XXXX` volume ID.
+    #[doc(hidden)]
+    pub fn synthetic(kind: Kind, uuid: &str, label: &str) -> Self {
+        fn copy_truncated(dest: &mut [u8], src: &[u8]) {
+            let len = src.len().min(dest.len());
+            dest[..len].copy_from_slice(&src[..len]);
+        }
+        let uuid_bytes = || *Uuid::parse_str(uuid).expect("invalid synthetic UUID").as_bytes();
+
+        match kind {
+            Kind::Btrfs => {
+                let mut block = btrfs::Btrfs::new_box_zeroed().unwrap();
+                block.fsid = uuid_bytes();
+                copy_truncated(&mut block.label, label.as_bytes());
+                Superblock::Btrfs(block)
+            }
+            Kind::Ext4 => {
+                let mut block = ext4::Ext4::new_box_zeroed().unwrap();
+                block.uuid = uuid_bytes();
+                copy_truncated(&mut block.volume_name, label.as_bytes());
+                Superblock::Ext4(block)
+            }
+            Kind::F2FS => {
+                let mut block = f2fs::F2FS::new_box_zeroed().unwrap();
+                block.uuid = uuid_bytes();
+                let mut volume_name = block.volume_name;
+                for (dest, unit) in volume_name.iter_mut().zip(label.encode_utf16()) {
+                    *dest = U16::<LittleEndian>::new(unit);
+                }
+                block.volume_name = volume_name;
+                Superblock::F2FS(block)
+            }
+            Kind::Luks2 => {
+                let mut block = luks2::Luks2::new_box_zeroed().unwrap();
+                let mut uuid_field = [0u8; luks2::UUID_LEN];
+                copy_truncated(&mut uuid_field, uuid.as_bytes());
+                block.uuid = uuid_field;
+                let mut label_field = [0u8; luks2::LABEL_LEN];
+                copy_truncated(&mut label_field, label.as_bytes());
+                block.label = label_field;
+                Superblock::Luks2(block)
+            }
+            Kind::Xfs => {
+                let mut block = xfs::Xfs::new_box_zeroed().unwrap();
+                block.uuid = uuid_bytes();
+                copy_truncated(&mut block.fname, label.as_bytes());
+                Superblock::Xfs(block)
+            }
+            Kind::Fat => {
+                // A FAT16 boot sector (non-zero FAT length), where the volume ID and label follow the drive number, state and signature
+                let vol_id = u32::from_str_radix(&uuid.replace('-', ""), 16).expect("invalid synthetic volume ID");
+                let mut block = fat::Fat::new_box_zeroed().unwrap();
+                block.fat_length = U16::new(1);
+                let mut shared = block.shared;
+                shared[3..7].copy_from_slice(U32::<LittleEndian>::new(vol_id).as_ref());
+                shared[7..18].fill(b' ');
+                copy_truncated(&mut shared[7..18], label.as_bytes());
+                block.shared = shared;
+                Superblock::Fat(block)
+            }
+            Kind::Nilfs2 => {
+                let mut block = nilfs2::Nilfs2::new_box_zeroed().unwrap();
+                block.uuid = uuid_bytes();
+                copy_truncated(&mut block.volume_name, label.as_bytes());
+                Superblock::Nilfs2(block)
+            }
+        }
+    }
+
+    /// Returns the volume label, replacing any undecodable data with U+FFFD
+    ///
+    /// Unlike [`Superblock::label`] this never fails, which is useful when
+    /// displaying labels read from corrupt or foreign metadata.
+    pub fn label_lossy(&self) -> String {
+        match self {
+            Superblock::Btrfs(block) => block.label_lossy(),
+            Superblock::Ext4(block) => block.label_lossy(),
+            Superblock::F2FS(block) => block.label_lossy(),
+            Superblock::Luks2(block) => block.label_lossy(),
+            Superblock::Xfs(block) => block.label_lossy(),
+            Superblock::Fat(block) => block.label_lossy(),
+            Superblock::Nilfs2(block) => block.label_lossy(),
+        }
+    }
+
+    /// Returns the sector size in bytes recorded when the filesystem was formatted
+    ///
+    /// This is the smallest unit the filesystem addresses, which for ext4,
+    /// btrfs and NILFS2 is the block size. Returns `None` for LUKS2, as a container has no
+    /// filesystem sector size of its own, and when a corrupt superblock records a
+    /// size too large to represent.
+    pub fn formatted_sector_size(&self) -> Option<u64> {
+        match self {
+            Superblock::Btrfs(block) => Some(block.sectorsize.get().into()),
+            Superblock::Ext4(block) => block.block_size(),
+            Superblock::F2FS(block) => 1u64.checked_shl(block.log_sectorsize.get()),
+            Superblock::Luks2(_) => None,
+            Superblock::Xfs(block) => Some(block.sectsize.get().into()),
+            Superblock::Fat(block) => Some(block.sector_size.get().into()),
+            Superblock::Nilfs2(block) => block.block_size(),
+        }
+    }
+
+    /// Number of bytes from the start of a device needed to detect any supported superblock
+    ///
+    /// This is the largest [`Detection::bytes_needed`] of all types, and the
+    /// least [`Superblock::from_bytes`] must be given.
+    pub fn max_bytes_needed() -> u64 {
+        [
+            btrfs::Btrfs::bytes_needed(),
+            ext4::Ext4::bytes_needed(),
+            f2fs::F2FS::bytes_needed(),
+            fat::Fat::bytes_needed(),
+            luks2::Luks2::bytes_needed(),
+            nilfs2::Nilfs2::bytes_needed(),
+            xfs::Xfs::bytes_needed(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or_default()
+    }
+
     /// Attempt to detect and read a filesystem superblock from raw bytes
     ///
-    /// This is more efficient than using a reader as it avoids multiple seeks
+    /// This is more efficient than using a reader as it avoids multiple seeks.
+    /// Types whose superblock lies beyond the end of `bytes` are skipped, so
+    /// passing [`Superblock::max_bytes_needed`] bytes checks every type.
+    /// When several types are detected, the one with the highest
+    /// [`Detection::confidence`] is returned, with ties going to the type
+    /// checked first.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
         let mut cursor = Cursor::new(bytes);
+        let mut best: Option<(u8, Self)> = None;
 
         macro_rules! try_detect {
             ($variant:ident, $ty:ty) => {
-                if let Some(sb) = detect_superblock::<$ty, _>(&mut cursor).context(IoSnafu)? {
-                    return Ok(Self::$variant(Box::new(sb)));
+                let detected = match detect_superblock::<$ty, _>(&mut cursor) {
+                    // Too short to hold this type, but it may still hold another
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                    result => result.context(IoSnafu)?,
+                };
+                if let Some(sb) = detected {
+                    let confidence = sb.confidence();
+                    if best.as_ref().is_none_or(|(best, _)| confidence > *best) {
+                        best = Some((confidence, Self::$variant(Box::new(sb))));
+                    }
                 }
             };
         }
@@ -181,8 +422,9 @@ impl Superblock {
         try_detect!(Xfs, xfs::Xfs);
         try_detect!(Luks2, luks2::Luks2);
         try_detect!(Fat, fat::Fat);
+        try_detect!(Nilfs2, nilfs2::Nilfs2);
 
-        Err(Error::UnknownSuperblock)
+        best.map(|(_, block)| block).ok_or(Error::UnknownSuperblock)
     }
 
     /// Attempt to detect and read a filesystem superblock from a reader
@@ -190,26 +432,195 @@ impl Superblock {
     /// Note: This will read the minimum necessary bytes to detect the superblock,
     /// which is more efficient than reading the entire device.
     pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
-        // Preallocate a fixed buffer for the largest superblock we need to read
-        let mut bytes = vec![0u8; 128 * 1024]; // 128KB covers all superblock offsets
+        // Read exactly enough to cover the furthest superblock
+        let mut bytes = vec![0u8; Self::max_bytes_needed() as usize];
         reader.rewind().context(IoSnafu)?;
         reader.read_exact(&mut bytes).context(IoSnafu)?;
 
-        Self::from_bytes(&bytes)
+        match Self::from_bytes(&bytes)? {
+            // Btrfs may only carry the label in its mirror superblock
+            Superblock::Btrfs(block) if block.label.iter().all(|b| *b == 0) => {
+                let device_size = reader.seek(io::SeekFrom::End(0)).context(IoSnafu)?;
+                let block = block.with_mirror_label(reader, device_size).context(IoSnafu)?;
+                Ok(Superblock::Btrfs(Box::new(block)))
+            }
+            block => Ok(block),
+        }
+    }
+
+    /// Attempt to detect and read a filesystem superblock from a file or device path
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = fs::File::open(path).context(IoSnafu)?;
+        Self::from_reader(&mut file)
     }
 }
 
+/// Unpack the zstd-compressed `tests/{name}.img.zst` fixture into memory
+#[cfg(test)]
+pub(crate) fn fixture_image(name: &str) -> Vec<u8> {
+    let mut fi = fs::File::open(format!("tests/{name}.img.zst")).expect("Cannot find test image");
+    let mut stream = zstd::stream::Decoder::new(&mut fi).expect("Unable to decode stream");
+    let mut memory = Vec::new();
+    stream
+        .read_to_end(&mut memory)
+        .expect("Could not unpack filesystem in memory");
+    memory
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{
-        fs,
-        io::{Cursor, Read},
-    };
+    use std::{fs, io::Cursor};
 
-    use crate::Kind;
+    use crate::{Kind, decode_utf16_lossy, decode_utf16le_lossy, fixture_image};
 
     use super::Superblock;
 
+    #[test]
+    fn test_synthetic() {
+        let uuid = "731af94c-9990-4eed-944d-5d230dbe8a0d";
+        let block = Superblock::synthetic(Kind::Ext4, uuid, "root");
+        assert_eq!(block.kind(), Kind::Ext4);
+        assert_eq!(block.uuid().unwrap(), uuid);
+        assert_eq!(block.label().unwrap(), "root");
+
+        // Clones are independent copies of the same superblock
+        let cloned = block.clone();
+        assert_eq!(cloned.uuid().unwrap(), uuid);
+
+        for kind in [Kind::Btrfs, Kind::F2FS, Kind::Luks2, Kind::Nilfs2, Kind::Xfs] {
+            let block = Superblock::synthetic(kind.clone(), uuid, "data");
+            assert_eq!(block.kind(), kind);
+            assert_eq!(block.uuid().unwrap(), uuid);
+            assert_eq!(block.label().unwrap(), "data");
+        }
+
+        let fat = Superblock::synthetic(Kind::Fat, "A1B2-C3D4", "EFI");
+        assert_eq!(fat.uuid().unwrap(), "A1B2-C3D4");
+        assert_eq!(fat.label().unwrap(), "EFI");
+    }
+
+    #[test]
+    fn test_unicode_error_conversion() {
+        fn read_label(block: &Superblock) -> Result<String, crate::Error> {
+            Ok(block.label()?)
+        }
+
+        let uuid = "731af94c-9990-4eed-944d-5d230dbe8a0d";
+        assert_eq!(
+            read_label(&Superblock::synthetic(Kind::Ext4, uuid, "root")).unwrap(),
+            "root"
+        );
+
+        // Truncation to the 16 byte label field splits the final character
+        let block = Superblock::synthetic(Kind::Ext4, uuid, "aaaaaaaaaaaaaaa\u{e9}");
+        assert!(matches!(read_label(&block), Err(crate::Error::Unicode { .. })));
+        assert_eq!(block.label_lossy(), "aaaaaaaaaaaaaaa\u{fffd}");
+    }
+
+    #[test]
+    fn test_fuzz_seeds() {
+        // Seed corpus entries are truncated or degenerate inputs that must be
+        // rejected without panicking, or corrupt ones whose accessors must not panic
+        for entry in fs::read_dir("fuzz/corpus/superblock").expect("Cannot find fuzz corpus") {
+            let path = entry.unwrap().path();
+            let bytes = fs::read(&path).unwrap();
+            let corrupt = path.to_string_lossy().contains("-corrupt-");
+            match Superblock::from_bytes(&bytes) {
+                Ok(block) => {
+                    assert!(corrupt, "{path:?} should not be detected");
+                    let _ = block.formatted_sector_size();
+                    let _ = block.label_with_reader(&mut Cursor::new(&bytes));
+                }
+                Err(_) => assert!(!corrupt, "{path:?} should be detected"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_confidence() {
+        // A boot sector carrying the FAT signature, as in an MBR
+        let mut bytes = vec![0u8; 128 * 1024];
+        bytes[510..512].copy_from_slice(&[0x55, 0xAA]);
+        assert!(matches!(
+            Superblock::from_bytes(&bytes),
+            Err(crate::Error::UnknownSuperblock)
+        ));
+
+        // 512-byte sectors, 4 sectors per cluster and 2 FATs make a plausible FAT16
+        bytes[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bytes[13] = 4;
+        bytes[16] = 2;
+        bytes[22..24].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(Superblock::from_bytes(&bytes).unwrap().kind(), Kind::Fat);
+
+        // A stale ext4 magic is checked first but loses to a FAT naming itself as such
+        bytes[1024 + 0x38..1024 + 0x3A].copy_from_slice(&0xEF53u16.to_le_bytes());
+        assert_eq!(Superblock::from_bytes(&bytes).unwrap().kind(), Kind::Ext4);
+        bytes[54..62].copy_from_slice(b"FAT16   ");
+        assert_eq!(Superblock::from_bytes(&bytes).unwrap().kind(), Kind::Fat);
+    }
+
+    #[test]
+    fn test_max_bytes_needed() {
+        use crate::{Detection, btrfs::Btrfs};
+
+        // Btrfs, 64KiB into the device, has the furthest superblock
+        let needed = Superblock::max_bytes_needed();
+        assert_eq!(needed, Btrfs::bytes_needed());
+        assert_eq!(needed, 64 * 1024 + std::mem::size_of::<Btrfs>() as u64);
+
+        // Nothing beyond that is read
+        let mut image = fixture_image("ext4");
+        image.truncate(needed as usize);
+        let block = Superblock::from_reader(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(block.kind(), Kind::Ext4);
+    }
+
+    #[test]
+    fn test_short_buffer() {
+        // Long enough for ext4 but far short of the btrfs superblock
+        let mut image = fixture_image("ext4");
+        image.truncate(4096);
+        assert_eq!(Superblock::from_bytes(&image).unwrap().kind(), Kind::Ext4);
+
+        // Nothing detected in what is there
+        assert!(matches!(
+            Superblock::from_bytes(&[0u8; 4096]),
+            Err(crate::Error::UnknownSuperblock)
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_sector_size() {
+        // A valid ext4 magic with a log block size far beyond any real filesystem
+        let mut bytes = vec![0u8; 4096];
+        bytes[1024 + 0x38..1024 + 0x3A].copy_from_slice(&0xEF53u16.to_le_bytes());
+        bytes[1024 + 0x18..1024 + 0x1C].copy_from_slice(&64u32.to_le_bytes());
+        let block = Superblock::from_bytes(&bytes).unwrap();
+        assert_eq!(block.kind(), Kind::Ext4);
+        assert_eq!(block.formatted_sector_size(), None);
+
+        bytes[1024 + 0x18..1024 + 0x1C].copy_from_slice(&2u32.to_le_bytes());
+        let block = Superblock::from_bytes(&bytes).unwrap();
+        assert_eq!(block.formatted_sector_size(), Some(4096));
+    }
+
+    #[test]
+    fn test_utf16_lone_surrogate() {
+        // A high surrogate with no following low surrogate
+        let units = [u16::from(b'A'), 0xD800, u16::from(b'B')];
+        assert!(String::from_utf16(&units).is_err());
+        assert_eq!(decode_utf16_lossy(units), "A\u{fffd}B");
+        assert_eq!(decode_utf16_lossy([0xD800]), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_utf16_odd_length() {
+        assert_eq!(decode_utf16le_lossy(b"A\0B\0C"), "AB\u{fffd}");
+        assert_eq!(decode_utf16le_lossy(b"A"), "\u{fffd}");
+        assert_eq!(decode_utf16le_lossy(b""), "");
+    }
+
     #[test_log::test]
     fn test_determination() {
         let tests = vec![
@@ -218,46 +629,54 @@ mod tests {
                 Kind::Btrfs,
                 "blsforme testing",
                 "829d6a03-96a5-4749-9ea2-dbb6e59368b2",
+                Some(4096),
             ),
             (
                 "ext4",
                 Kind::Ext4,
                 "blsforme testing",
                 "731af94c-9990-4eed-944d-5d230dbe8a0d",
+                Some(1024),
             ),
             (
                 "f2fs",
                 Kind::F2FS,
                 "blsforme testing",
                 "d2c85810-4e75-4274-bc7d-a78267af7443",
+                Some(512),
             ),
-            ("luks+ext4", Kind::Luks2, "", "be373cae-2bd1-4ad5-953f-3463b2e53e59"),
-            ("xfs", Kind::Xfs, "BLSFORME", "45e8a3bf-8114-400f-95b0-380d0fb7d42d"),
-            ("fat16", Kind::Fat, "TESTLABEL", "A1B2-C3D4"),
-            ("fat32", Kind::Fat, "TESTLABEL", "A1B2-C3D4"),
+            (
+                "luks+ext4",
+                Kind::Luks2,
+                "",
+                "be373cae-2bd1-4ad5-953f-3463b2e53e59",
+                None,
+            ),
+            (
+                "xfs",
+                Kind::Xfs,
+                "BLSFORME",
+                "45e8a3bf-8114-400f-95b0-380d0fb7d42d",
+                Some(512),
+            ),
+            ("fat16", Kind::Fat, "TESTLABEL", "A1B2-C3D4", Some(512)),
+            ("fat32", Kind::Fat, "TESTLABEL", "A1B2-C3D4", Some(512)),
         ];
 
-        // Pre-allocate a buffer for determination tests
-        let mut memory: Vec<u8> = Vec::with_capacity(512 * 1024);
-
-        for (fsname, kind, label, uuid) in tests.into_iter() {
+        for (fsname, kind, label, uuid, sector_size) in tests.into_iter() {
             // Swings and roundabouts: Unpack ztd image in memory to get the Seekable trait we need
             // While each Superblock API is non-seekable, we enforce superblock::for_reader to be seekable
             // to make sure we pre-read a blob and pass it in for rewind/speed.
-            memory.clear();
-
-            let mut fi = fs::File::open(format!("tests/{fsname}.img.zst")).expect("Cannot find test image");
-            let mut stream = zstd::stream::Decoder::new(&mut fi).expect("Unable to decode stream");
-            stream
-                .read_to_end(&mut memory)
-                .expect("Could not unpack filesystem in memory");
+            let mut memory = fixture_image(fsname);
 
             let mut cursor = Cursor::new(&mut memory);
             let block = Superblock::from_reader(&mut cursor).expect("Failed to find right block implementation");
             eprintln!("{fsname}.img.zstd: superblock matched to {}", block.kind());
             assert_eq!(block.kind(), kind);
             assert_eq!(block.label().unwrap(), label);
+            assert_eq!(block.label_lossy(), label);
             assert_eq!(block.uuid().unwrap(), uuid);
+            assert_eq!(block.formatted_sector_size(), sector_size);
 
             // Is it possible to get the JSON config out of LUKS2?
             if let Superblock::Luks2(block) = block {
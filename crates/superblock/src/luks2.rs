@@ -35,4 +35,8 @@ pub enum ConfigError {
     /// Error decoding UTF-8 string data
     #[snafu(display("invalid utf8 in decode"))]
     InvalidUtf8 { source: std::str::Utf8Error },
+
+    /// A size in the header or JSON metadata is out of range
+    #[snafu(display("invalid size"))]
+    InvalidSize,
 }
@@ -18,10 +18,64 @@ pub struct Luks2Config {
     /// Map of segment IDs to their corresponding segment configurations.
     /// Segments define the encrypted regions of the device.
     pub segments: HashMap<u64, Luks2Segment>,
-    // pub tokens: HashMap<u64, Value>,
+    /// Map of token IDs to their corresponding token configurations.
+    /// Tokens carry external metadata such as unlock hints or user-defined values.
+    #[serde(default)]
+    pub tokens: HashMap<u64, Luks2Token>,
     // pub digests: HashMap<u64, Value>,
 }
 
+impl Luks2Config {
+    /// Total size in bytes of the LUKS2 metadata preceding the encrypted data
+    ///
+    /// Sums the binary header and JSON area (for both the primary and secondary
+    /// header copies) and the keyslots area that follows them. Returns `None`
+    /// if the sizes recorded in the metadata overflow.
+    pub fn total_header_bytes(&self) -> Option<u64> {
+        BINARY_HEADER_SIZE
+            .checked_add(self.config.json_size)?
+            .checked_mul(2)?
+            .checked_add(self.config.keyslots_size)
+    }
+
+    /// Returns the number of keyslots holding key material
+    pub fn active_keyslot_count(&self) -> usize {
+        self.keyslots.len()
+    }
+
+    /// Returns the cipher protecting the key material stored in a keyslot, e.g. `aes-xts-plain64`
+    pub fn keyslot_cipher(&self, slot: u64) -> Option<&str> {
+        self.keyslots.get(&slot).map(|keyslot| keyslot.area.encryption.as_str())
+    }
+
+    /// Returns the size in bits of the key protecting a keyslot's key material
+    ///
+    /// XTS modes split the key in two, so `aes-xts-plain64` with 512 bits is AES-256.
+    pub fn keyslot_key_size_bits(&self, slot: u64) -> Option<u32> {
+        self.keyslots
+            .get(&slot)
+            .and_then(|keyslot| keyslot.area.key_size.checked_mul(8))
+            .and_then(|bits| u32::try_from(bits).ok())
+    }
+
+    /// Returns the label stored in a user-defined token, if any
+    ///
+    /// LUKS2 has no dedicated label field in its JSON metadata, so a label is
+    /// taken from the lowest numbered token with a non-empty string `label` property.
+    pub fn label(&self) -> Option<String> {
+        let mut ids = self.tokens.keys().collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter()
+            .find_map(|id| match self.tokens[id].properties.get("label") {
+                Some(serde_json::Value::String(label)) if !label.is_empty() => Some(label.clone()),
+                _ => None,
+            })
+    }
+}
+
+/// Size of the binary LUKS2 header preceding each JSON area
+pub const BINARY_HEADER_SIZE: u64 = 4096;
+
 /// Core LUKS2 configuration data containing essential metadata about the encrypted device.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Luks2ConfigData {
@@ -117,6 +171,22 @@ pub struct Luks2KeyslotArea {
     pub key_size: u64,
 }
 
+/// A token attaching external metadata to the LUKS2 device.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Luks2Token {
+    /// Type of token, e.g. `systemd-tpm2` or a user-defined type
+    #[serde(rename = "type")]
+    pub token_type: String,
+
+    /// Keyslots this token is associated with
+    #[serde(default)]
+    pub keyslots: Vec<String>,
+
+    /// Any further type-specific properties of the token
+    #[serde(flatten)]
+    pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
 /// Configuration for a disk segment defining an encrypted region of the device.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Luks2Segment {
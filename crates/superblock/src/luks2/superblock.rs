@@ -21,15 +21,14 @@
 //! - JSON metadata area containing encryption parameters
 //!
 
-use std::{
-    io::{Read, Seek},
-    ops::Sub,
-};
+use std::io::{Read, Seek};
 
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 use zerocopy::*;
 
-use super::{ConfigError, InvalidJsonSnafu, InvalidUtf8Snafu, IoSnafu, Luks2Config};
+use super::{
+    BINARY_HEADER_SIZE, ConfigError, InvalidJsonSnafu, InvalidSizeSnafu, InvalidUtf8Snafu, IoSnafu, Luks2Config,
+};
 use crate::{Detection, UnicodeError};
 
 /// Length of the magic number field in bytes
@@ -44,13 +43,15 @@ pub const SALT_LEN: usize = 64;
 pub const UUID_LEN: usize = 40;
 /// Length of the checksum field in bytes
 pub const CHECKSUM_LEN: usize = 64;
+/// Largest header size, binary header and JSON area together, permitted by the LUKS2 spec
+pub const MAX_HDR_SIZE: u64 = 4 * 1024 * 1024;
 
 /// LUKS2 on-disk header format
 ///
 /// Per the `cryptsetup` docs for dm-crypt backed LUKS2, header is at first byte.
 /// The header contains metadata about the encrypted volume including magic number,
 /// version, checksums and JSON configuration.
-#[derive(FromBytes, Unaligned, Debug)]
+#[derive(FromBytes, Unaligned, Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct Luks2 {
     /// Magic number identifying LUKS2 format
@@ -120,6 +121,11 @@ impl Luks2 {
         Ok(std::str::from_utf8(&self.label)?.trim_end_matches('\0').to_owned())
     }
 
+    /// Get the label of the LUKS2 volume, replacing invalid UTF-8 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.label).trim_end_matches('\0').to_owned()
+    }
+
     /// Read and parse the JSON configuration areas from the LUKS2 header
     ///
     /// # Arguments
@@ -128,9 +134,16 @@ impl Luks2 {
     ///
     /// # Returns
     ///
-    /// Returns parsed Luks2Config on success, Error on failure
+    /// Returns parsed Luks2Config on success, Error on failure. A header size
+    /// outside the range permitted by the spec is [`ConfigError::InvalidSize`].
     pub fn read_config<R: Read + Seek>(&self, reader: &mut R) -> Result<Luks2Config, ConfigError> {
-        let mut json_data = vec![0u8; self.hdr_size.get().sub(4096) as usize];
+        let json_size = self
+            .hdr_size
+            .get()
+            .checked_sub(BINARY_HEADER_SIZE)
+            .filter(|_| self.hdr_size.get() <= MAX_HDR_SIZE)
+            .context(InvalidSizeSnafu)?;
+        let mut json_data = vec![0u8; json_size as usize];
         // Skip the header and read the JSON data
         reader
             .seek(std::io::SeekFrom::Start(std::mem::size_of::<Luks2>() as u64))
@@ -144,4 +157,128 @@ impl Luks2 {
         let config: Luks2Config = serde_json::from_str(raw_input).context(InvalidJsonSnafu)?;
         Ok(config)
     }
+
+    /// Determine the byte range to copy to back up the full LUKS2 header
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reader positioned over the LUKS2 device, used to read the JSON config
+    ///
+    /// # Returns
+    ///
+    /// Returns the `(offset, length)` of the header area, suitable for `dd`,
+    /// or [`ConfigError::InvalidSize`] if the recorded sizes overflow
+    pub fn backup_range<R: Read + Seek>(&self, reader: &mut R) -> Result<(u64, u64), ConfigError> {
+        let config = self.read_config(reader)?;
+        Ok((0, config.total_header_bytes().context(InvalidSizeSnafu)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture_image;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_backup_range() {
+        let image = fixture_image("luks+ext4");
+        let luks = Luks2::read_from_bytes(&image[..Luks2::SIZE]).unwrap();
+        let (offset, length) = luks.backup_range(&mut Cursor::new(&image)).unwrap();
+
+        let hdr_size = luks.hdr_size.get();
+        assert_eq!(offset, 0);
+        assert!(length > 0);
+        assert_eq!(length % hdr_size, 0);
+
+        // Both header copies precede the keyslots area
+        let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(config.total_header_bytes(), Some(length));
+        assert_eq!(length, 2 * hdr_size + config.config.keyslots_size);
+        eprintln!("LUKS2 header backup: offset {offset}, length {length}");
+    }
+
+    /// Rewrite the primary JSON area of `image` with the result of `edit`
+    fn edit_json(image: &mut [u8], luks: &Luks2, edit: impl FnOnce(&mut serde_json::Value)) {
+        let json_area = Luks2::SIZE..luks.hdr_size.get() as usize;
+        let mut json: serde_json::Value = serde_json::from_str(
+            std::str::from_utf8(&image[json_area.clone()])
+                .unwrap()
+                .trim_end_matches('\0'),
+        )
+        .unwrap();
+        edit(&mut json);
+        let json = serde_json::to_vec(&json).unwrap();
+        image[json_area.clone()].fill(0);
+        image[json_area.start..][..json.len()].copy_from_slice(&json);
+    }
+
+    #[test]
+    fn test_backup_range_overflow() {
+        let mut image = fixture_image("luks+ext4");
+        let luks = Luks2::read_from_bytes(&image[..Luks2::SIZE]).unwrap();
+        edit_json(&mut image, &luks, |json| {
+            json["config"]["json_size"] = u64::MAX.to_string().into();
+        });
+
+        let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(config.total_header_bytes(), None);
+        assert!(matches!(
+            luks.backup_range(&mut Cursor::new(&image)),
+            Err(ConfigError::InvalidSize)
+        ));
+    }
+
+    #[test]
+    fn test_config_label() {
+        let mut image = fixture_image("luks+ext4");
+        let luks = Luks2::read_from_bytes(&image[..Luks2::SIZE]).unwrap();
+        let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(config.label(), None);
+
+        // Rewrite the JSON area with a user-defined label token
+        edit_json(&mut image, &luks, |json| {
+            json["tokens"] = serde_json::json!({
+                "0": { "type": "systemd-tpm2", "keyslots": ["0"] },
+                "1": { "type": "user-label", "keyslots": [], "label": "cryptroot" }
+            });
+        });
+
+        // An empty header label falls back to the config label
+        image[std::mem::offset_of!(Luks2, label)..][..LABEL_LEN].fill(0);
+        let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(config.tokens.len(), 2);
+        assert_eq!(config.label().as_deref(), Some("cryptroot"));
+
+        let block = crate::Superblock::from_bytes(&image).unwrap();
+        assert_eq!(block.label().unwrap(), "");
+        assert_eq!(block.label_with_reader(&mut Cursor::new(&image)).unwrap(), "cryptroot");
+
+        // The header label takes precedence when set
+        image[std::mem::offset_of!(Luks2, label)..][..6].copy_from_slice(b"header");
+        let block = crate::Superblock::from_bytes(&image).unwrap();
+        assert_eq!(block.label_with_reader(&mut Cursor::new(&image)).unwrap(), "header");
+    }
+
+    #[test]
+    fn test_keyslots() {
+        let mut image = fixture_image("luks+ext4");
+        let luks = Luks2::read_from_bytes(&image[..Luks2::SIZE]).unwrap();
+        let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+
+        assert_eq!(config.active_keyslot_count(), 1);
+        assert_eq!(config.keyslot_cipher(0), Some("aes-xts-plain64"));
+        assert_eq!(config.keyslot_key_size_bits(0), Some(512));
+        assert_eq!(config.keyslot_cipher(1), None);
+        assert_eq!(config.keyslot_key_size_bits(1), None);
+
+        // Key sizes too large to express in bits are refused rather than wrapped
+        for key_size in [u64::MAX, u64::from(u32::MAX)] {
+            edit_json(&mut image, &luks, |json| {
+                json["keyslots"]["0"]["area"]["key_size"] = key_size.into();
+            });
+            let config = luks.read_config(&mut Cursor::new(&image)).unwrap();
+            assert_eq!(config.keyslot_key_size_bits(0), None);
+        }
+    }
 }
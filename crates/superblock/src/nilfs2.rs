@@ -0,0 +1,205 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! NILFS2 superblock handling
+//!
+//! NILFS2 is a log-structured filesystem. It keeps a primary superblock 1024
+//! bytes into the device and a secondary copy near the end, of which only the
+//! primary is read here.
+
+use crate::{BASELINE_CONFIDENCE, Detection, UnicodeError};
+use uuid::Uuid;
+use zerocopy::*;
+
+/// Length of the volume label
+pub const LABEL_LEN: usize = 80;
+
+/// NILFS2 superblock definition that mirrors the on-disk format used by the Linux kernel
+#[derive(Debug, Clone, FromBytes)]
+#[repr(C)]
+pub struct Nilfs2 {
+    /// Revision level
+    pub rev_level: U32<LittleEndian>,
+    /// Minor revision level
+    pub minor_rev_level: U16<LittleEndian>,
+    /// Magic signature, see [`MAGIC`]
+    pub magic: U16<LittleEndian>,
+    /// Number of bytes covered by the checksum
+    pub bytes: U16<LittleEndian>,
+    /// Superblock flags
+    pub flags: U16<LittleEndian>,
+    /// Seed for the checksum
+    pub crc_seed: U32<LittleEndian>,
+    /// Checksum of the superblock
+    pub sum: U32<LittleEndian>,
+    /// Block size as `1 << (log_block_size + 10)`
+    pub log_block_size: U32<LittleEndian>,
+    /// Number of segments in the filesystem
+    pub nsegments: U64<LittleEndian>,
+    /// Size of the block device in bytes
+    pub dev_size: U64<LittleEndian>,
+    /// Block number of the first segment
+    pub first_data_block: U64<LittleEndian>,
+    /// Number of blocks in a full segment
+    pub blocks_per_segment: U32<LittleEndian>,
+    /// Percentage of segments reserved
+    pub r_segments_percentage: U32<LittleEndian>,
+    /// Last checkpoint number
+    pub last_cno: U64<LittleEndian>,
+    /// Block address of the partial segment written last
+    pub last_pseg: U64<LittleEndian>,
+    /// Sequence number of the segment written last
+    pub last_seq: U64<LittleEndian>,
+    /// Free block count
+    pub free_blocks_count: U64<LittleEndian>,
+    /// Creation time
+    pub ctime: U64<LittleEndian>,
+    /// Last mount time
+    pub mtime: U64<LittleEndian>,
+    /// Last write time
+    pub wtime: U64<LittleEndian>,
+    /// Mount count
+    pub mnt_count: U16<LittleEndian>,
+    /// Maximum mount count
+    pub max_mnt_count: U16<LittleEndian>,
+    /// Filesystem state
+    pub state: U16<LittleEndian>,
+    /// Behaviour when detecting errors
+    pub errors: U16<LittleEndian>,
+    /// Time of the last check
+    pub lastcheck: U64<LittleEndian>,
+    /// Maximum time between checks
+    pub checkinterval: U32<LittleEndian>,
+    /// Creator OS
+    pub creator_os: U32<LittleEndian>,
+    /// Default uid for reserved blocks
+    pub def_resuid: U16<LittleEndian>,
+    /// Default gid for reserved blocks
+    pub def_resgid: U16<LittleEndian>,
+    /// First non-reserved inode
+    pub first_ino: U32<LittleEndian>,
+    /// Size of an inode
+    pub inode_size: U16<LittleEndian>,
+    /// Size of a DAT entry
+    pub dat_entry_size: U16<LittleEndian>,
+    /// Size of a checkpoint
+    pub checkpoint_size: U16<LittleEndian>,
+    /// Size of a segment usage entry
+    pub segment_usage_size: U16<LittleEndian>,
+    /// Filesystem UUID
+    pub uuid: [u8; 16],
+    /// Volume label, null-terminated when shorter than the field
+    pub volume_name: [u8; LABEL_LEN],
+    /// Commit interval of segments
+    pub c_interval: U32<LittleEndian>,
+    /// Threshold of data for segment construction
+    pub c_block_max: U32<LittleEndian>,
+    /// Compatible feature set
+    pub feature_compat: U64<LittleEndian>,
+    /// Read-only compatible feature set
+    pub feature_compat_ro: U64<LittleEndian>,
+    /// Incompatible feature set
+    pub feature_incompat: U64<LittleEndian>,
+    /// Padding to the end of the block
+    pub reserved: [U32<LittleEndian>; 186],
+}
+
+/// Magic number identifying a NILFS2 superblock
+pub const MAGIC: U16<LittleEndian> = U16::new(0x3434);
+
+/// Start position of the primary superblock
+pub const START_POSITION: u64 = 1024;
+
+impl Detection for Nilfs2 {
+    type Magic = U16<LittleEndian>;
+
+    const OFFSET: u64 = START_POSITION;
+
+    const MAGIC_OFFSET: u64 = START_POSITION + 0x06;
+
+    const SIZE: usize = std::mem::size_of::<Nilfs2>();
+
+    fn is_valid_magic(magic: &Self::Magic) -> bool {
+        *magic == MAGIC
+    }
+
+    /// The magic is only two ASCII characters, so the revision and block size are checked too
+    fn confidence(&self) -> u8 {
+        if self.rev_level.get() == 2 && self.log_block_size.get() <= 6 {
+            BASELINE_CONFIDENCE
+        } else {
+            0
+        }
+    }
+}
+
+impl Nilfs2 {
+    /// Return the encoded UUID for this superblock
+    pub fn uuid(&self) -> Result<String, UnicodeError> {
+        Ok(Uuid::from_bytes(self.uuid).hyphenated().to_string())
+    }
+
+    /// Return the volume label as valid utf8
+    pub fn label(&self) -> Result<String, UnicodeError> {
+        let len = self.volume_name.iter().position(|b| *b == 0).unwrap_or(LABEL_LEN);
+        Ok(std::str::from_utf8(&self.volume_name[..len])?.to_owned())
+    }
+
+    /// Return the volume label, replacing invalid UTF-8 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        let len = self.volume_name.iter().position(|b| *b == 0).unwrap_or(LABEL_LEN);
+        String::from_utf8_lossy(&self.volume_name[..len]).into_owned()
+    }
+
+    /// Returns the block size in bytes
+    pub fn block_size(&self) -> Option<u64> {
+        1u64.checked_shl(self.log_block_size.get().checked_add(10)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{Kind, Superblock, detect_superblock};
+
+    /// Build an image holding just a primary superblock as written by `mkfs.nilfs2`
+    fn image(label: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 128 * 1024];
+        let block = &mut bytes[START_POSITION as usize..];
+        block[0..4].copy_from_slice(&2u32.to_le_bytes());
+        block[6..8].copy_from_slice(&0x3434u16.to_le_bytes());
+        block[0x14..0x18].copy_from_slice(&2u32.to_le_bytes());
+        block[0x98..0xa8].copy_from_slice(
+            Uuid::parse_str("5d84a3d1-6a8e-4f3c-9d0b-2f1e7c6a9b01")
+                .unwrap()
+                .as_bytes(),
+        );
+        block[0xa8..0xa8 + label.len()].copy_from_slice(label);
+        bytes
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(std::mem::size_of::<Nilfs2>(), 1024);
+
+        let bytes = image(b"archive");
+        let block = detect_superblock::<Nilfs2, _>(&mut Cursor::new(&bytes))
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.uuid().unwrap(), "5d84a3d1-6a8e-4f3c-9d0b-2f1e7c6a9b01");
+        assert_eq!(block.label().unwrap(), "archive");
+        assert_eq!(block.block_size(), Some(4096));
+
+        let block = Superblock::from_bytes(&bytes).unwrap();
+        assert_eq!(block.kind(), Kind::Nilfs2);
+        assert_eq!(block.label().unwrap(), "archive");
+        assert_eq!(block.formatted_sector_size(), Some(4096));
+
+        // A label filling the whole field has no terminator
+        let block = Superblock::from_bytes(&image(&[b'a'; LABEL_LEN])).unwrap();
+        assert_eq!(block.label().unwrap().len(), LABEL_LEN);
+    }
+}
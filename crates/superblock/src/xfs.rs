@@ -40,7 +40,7 @@ pub const MAX_LABEL_LEN: usize = 12;
 ///
 /// This structure maps directly to the on-disk format of an XFS superblock.
 /// All multi-byte integer fields are stored in big-endian byte order.
-#[derive(FromBytes, Debug)]
+#[derive(FromBytes, Debug, Clone)]
 #[repr(C, align(8))]
 pub struct Xfs {
     /// Magic number, must contain 'XFSB'
@@ -161,6 +161,9 @@ pub struct Xfs {
     pub meta_uuid: [u8; 16],
 }
 
+/// Incompatible feature flag set when the filesystem must be repaired before mounting
+pub const FEAT_INCOMPAT_NEEDSREPAIR: u32 = 1 << 4;
+
 /// XFS superblock magic number ('XFSB' in ASCII)
 pub const MAGIC: U32<BigEndian> = U32::new(0x58465342);
 
@@ -174,6 +177,60 @@ impl Xfs {
     pub fn label(&self) -> Result<String, UnicodeError> {
         Ok(std::str::from_utf8(&self.fname)?.trim_end_matches('\0').to_owned())
     }
+
+    /// Returns the volume label, replacing invalid UTF-8 sequences with U+FFFD
+    pub fn label_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.fname).trim_end_matches('\0').to_owned()
+    }
+
+    /// Returns the number of allocation groups
+    pub fn ag_count(&self) -> u32 {
+        self.agcount.get()
+    }
+
+    /// Returns the size of a single allocation group in bytes
+    pub fn ag_size_bytes(&self) -> u64 {
+        u64::from(self.agblocks.get()) * u64::from(self.blocksize.get())
+    }
+
+    /// Returns the stripe unit in filesystem blocks, or 0 if unset
+    pub fn stripe_unit(&self) -> u32 {
+        self.unit.get()
+    }
+
+    /// Returns the stripe width in filesystem blocks, or 0 if unset
+    pub fn stripe_width(&self) -> u32 {
+        self.width.get()
+    }
+
+    /// Returns true if the filesystem has a realtime subvolume on a separate device
+    ///
+    /// The superblock records no identity for the realtime device, neither a
+    /// name nor a UUID of its own, so it has to be given to `mount` explicitly.
+    pub fn has_realtime_device(&self) -> bool {
+        self.rblocks.get() > 0
+    }
+
+    /// Returns true if `xfs_repair` must be run before the filesystem can be mounted
+    pub fn needs_repair(&self) -> bool {
+        self.features_incompat.get() & FEAT_INCOMPAT_NEEDSREPAIR != 0
+    }
+
+    /// Returns true if the superblock indicates the log was cleanly unmounted
+    ///
+    /// XFS has no dirty bit; whether the log is clean is only known for certain
+    /// by walking the log itself. This uses a superblock-only heuristic:
+    /// - mkfs must have completed (`sb_inprogress` is zero)
+    /// - an internal log (`sb_logstart` non-zero) must lie within the data
+    ///   subvolume, i.e. `sb_logstart + sb_logblocks <= sb_dblocks`
+    /// - no log incompatible features may be set, as the kernel only sets these
+    ///   while the log holds items needing them and clears them on clean unmount
+    pub fn log_is_clean(&self) -> bool {
+        let logstart = self.logstart.get();
+        let log_in_bounds = logstart == 0 || logstart.saturating_add(self.logblocks.get().into()) <= self.dblocks.get();
+
+        self.inprogress == 0 && log_in_bounds && self.features_log_incompat.get() == 0
+    }
 }
 
 impl Detection for Xfs {
@@ -189,3 +246,65 @@ impl Detection for Xfs {
         *magic == MAGIC
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture_image;
+
+    #[test]
+    fn test_geometry() {
+        let image = fixture_image("xfs");
+        let xfs = Xfs::read_from_bytes(&image[..Xfs::SIZE]).unwrap();
+
+        assert_eq!(xfs.ag_count(), 4);
+        assert_eq!(xfs.ag_size_bytes(), 32000 * 4096);
+        assert_eq!(xfs.stripe_unit(), 0);
+        assert_eq!(xfs.stripe_width(), 0);
+    }
+
+    #[test]
+    fn test_log_is_clean() {
+        let image = fixture_image("xfs");
+        let xfs = Xfs::read_from_bytes(&image[..Xfs::SIZE]).unwrap();
+        assert!(xfs.log_is_clean());
+        assert!(!xfs.needs_repair());
+
+        // Log incompatible features are only set while the log is dirty
+        let mut dirty = image.clone();
+        dirty[std::mem::offset_of!(Xfs, features_log_incompat)..][..4].copy_from_slice(&1u32.to_be_bytes());
+        assert!(!Xfs::read_from_bytes(&dirty[..Xfs::SIZE]).unwrap().log_is_clean());
+
+        // An internal log extending past the data subvolume is inconsistent
+        let mut overrun = image.clone();
+        overrun[std::mem::offset_of!(Xfs, logblocks)..][..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(!Xfs::read_from_bytes(&overrun[..Xfs::SIZE]).unwrap().log_is_clean());
+
+        let mut repair = image;
+        repair[std::mem::offset_of!(Xfs, features_incompat)..][..4]
+            .copy_from_slice(&FEAT_INCOMPAT_NEEDSREPAIR.to_be_bytes());
+        assert!(Xfs::read_from_bytes(&repair[..Xfs::SIZE]).unwrap().needs_repair());
+    }
+
+    #[test]
+    fn test_realtime_device() {
+        let mut image = fixture_image("xfs");
+        let xfs = Xfs::read_from_bytes(&image[..Xfs::SIZE]).unwrap();
+        assert!(!xfs.has_realtime_device());
+
+        image[std::mem::offset_of!(Xfs, rblocks)..][..8].copy_from_slice(&4096u64.to_be_bytes());
+        let xfs = Xfs::read_from_bytes(&image[..Xfs::SIZE]).unwrap();
+        assert!(xfs.has_realtime_device());
+    }
+
+    #[test]
+    fn test_stripe_geometry() {
+        let mut image = fixture_image("xfs");
+        image[std::mem::offset_of!(Xfs, unit)..][..4].copy_from_slice(&16u32.to_be_bytes());
+        image[std::mem::offset_of!(Xfs, width)..][..4].copy_from_slice(&64u32.to_be_bytes());
+        let xfs = Xfs::read_from_bytes(&image[..Xfs::SIZE]).unwrap();
+
+        assert_eq!(xfs.stripe_unit(), 16);
+        assert_eq!(xfs.stripe_width(), 64);
+    }
+}
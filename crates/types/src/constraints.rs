@@ -8,6 +8,8 @@ use crate::{get_kdl_entry, kdl_value_to_storage_size};
 /// Constraints for partition size, 1:1 mapping to SizeRequirements in
 /// partitioning strategy internals.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum Constraints {
     /// Exact size in bytes
     Exact(u64),
@@ -15,6 +17,8 @@ pub enum Constraints {
     AtLeast(u64),
     /// Between min and max bytes
     Range { min: u64, max: u64 },
+    /// Up to max bytes, using less if that is all that is available
+    AtMost(u64),
     /// Use all remaining space
     Remaining,
 
@@ -25,34 +29,84 @@ pub enum Constraints {
 
 #[cfg(feature = "kdl")]
 impl Constraints {
+    /// Build constraints from the `min`, `max`, `exact` (or `exactly`) and
+    /// `remaining` children of a `constraints` node
+    ///
+    /// Contradictory combinations are rejected with a diagnostic pointing at
+    /// the offending child.
     pub fn from_kdl_node(node: &kdl::KdlNode) -> Result<Self, crate::Error> {
-        let range = node
-            .iter_children()
-            .find(|n| n.name().value() == "min")
-            .zip(node.iter_children().find(|n| n.name().value() == "max"));
-
-        if let Some((min, max)) = range {
-            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)? as u64;
-            let max = kdl_value_to_storage_size(get_kdl_entry(max, &0)?)? as u64;
-
-            Ok(Self::Range {
-                min: min as u64,
-                max: max as u64,
-            })
-        } else if let Some(min) = node.iter_children().find(|n| n.name().value() == "min") {
-            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)? as u64;
-            Ok(Self::AtLeast(min as u64))
-        } else if let Some(exact) = node.iter_children().find(|n| n.name().value() == "exactly") {
-            let exact = kdl_value_to_storage_size(get_kdl_entry(exact, &0)?)? as u64;
-            Ok(Self::Exact(exact as u64))
-        } else if node.iter_children().any(|n| n.name().value() == "remaining") {
-            Ok(Self::Remaining)
-        } else {
-            Err(crate::Error::MissingProperty(crate::MissingProperty {
+        let mut min = None;
+        let mut max = None;
+        let mut exact = None;
+        let mut remaining = None;
+
+        for child in node.iter_children() {
+            let slot = match child.name().value() {
+                "min" => &mut min,
+                "max" => &mut max,
+                "exact" | "exactly" => &mut exact,
+                "remaining" => &mut remaining,
+                _ => {
+                    return Err(crate::UnsupportedNode {
+                        at: child.span(),
+                        name: child.name().value().into(),
+                    }
+                    .into());
+                }
+            };
+            if slot.is_some() {
+                return Err(unsupported(
+                    child,
+                    format!("`{}` may only be given once", child.name().value()),
+                ));
+            }
+            *slot = Some(child);
+        }
+
+        let size = |child: &kdl::KdlNode| kdl_value_to_storage_size(get_kdl_entry(child, &0)?);
+
+        if let Some(exact) = exact {
+            if let Some(other) = min.or(max).or(remaining) {
+                return Err(unsupported(other, "`exact` cannot be combined with other constraints"));
+            }
+            return Ok(Self::Exact(size(exact)?));
+        }
+
+        if remaining.is_some() {
+            if let Some(other) = min.or(max) {
+                return Err(unsupported(
+                    other,
+                    "`remaining` cannot be combined with other constraints",
+                ));
+            }
+            return Ok(Self::Remaining);
+        }
+
+        match (min, max) {
+            (Some(min), Some(max_node)) => {
+                let min = size(min)?;
+                let max = size(max_node)?;
+                if min > max {
+                    return Err(unsupported(max_node, "`max` must not be smaller than `min`"));
+                }
+                Ok(Self::Range { min, max })
+            }
+            (Some(min), None) => Ok(Self::AtLeast(size(min)?)),
+            (None, Some(max)) => Ok(Self::AtMost(size(max)?)),
+            (None, None) => Err(crate::Error::MissingProperty(crate::MissingProperty {
                 at: node.span(),
-                id: "min, max, exactly or remaining",
+                id: "min, max, exact or remaining",
                 advice: Some("add one of these properties".into()),
-            }))
+            })),
         }
     }
 }
+
+#[cfg(feature = "kdl")]
+fn unsupported(child: &kdl::KdlNode, advice: impl Into<String>) -> crate::Error {
+    crate::UnsupportedValue {
+        at: child.span(),
+        advice: Some(advice.into()),
+    }
+    .into()
+}
@@ -0,0 +1,56 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+
+#[cfg(feature = "kdl")]
+use crate::{get_kdl_entry, kdl_value_to_string};
+
+/// Encryption to apply to a partition before it is formatted
+///
+/// The partition is set up as a LUKS2 container and any filesystem is created
+/// inside it rather than on the partition itself.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EncryptionSpec {
+    /// The cipher to encrypt with, or the cryptsetup default if unset
+    pub cipher: Option<String>,
+    /// Where the key for the first keyslot comes from
+    pub key_source: KeySource,
+}
+
+/// The source of the key for a LUKS2 keyslot
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum KeySource {
+    /// Prompt interactively for a passphrase
+    Passphrase,
+    /// Read the key from a file
+    KeyFile(PathBuf),
+}
+
+#[cfg(feature = "kdl")]
+impl EncryptionSpec {
+    pub fn from_kdl_node(node: &kdl::KdlNode) -> Result<Self, crate::Error> {
+        let mut cipher = None;
+        let mut key_source = KeySource::Passphrase;
+
+        for entry in node.iter_children() {
+            match entry.name().value() {
+                "cipher" => cipher = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
+                "key-file" => key_source = KeySource::KeyFile(kdl_value_to_string(get_kdl_entry(entry, &0)?)?.into()),
+                _ => {
+                    return Err(crate::UnsupportedNode {
+                        at: entry.span(),
+                        name: entry.name().value().into(),
+                    }
+                    .into());
+                }
+            }
+        }
+
+        Ok(Self { cipher, key_source })
+    }
+}
@@ -67,6 +67,52 @@ pub enum Error {
     UnsupportedValue(#[from] UnsupportedValue),
 }
 
+#[cfg(feature = "kdl")]
+impl Error {
+    /// Stable machine-readable code for this error
+    fn json_code(&self) -> String {
+        if let Some(code) = self.code() {
+            return code.to_string();
+        }
+
+        match self {
+            Error::IO(_) => "io",
+            Error::Kdl(_) => "kdl",
+            Error::UnknownType => "unknown-type",
+            Error::UnknownVariant => "unknown-variant",
+            Error::InvalidArguments(_) => "invalid-arguments",
+            Error::InvalidType(_) => "invalid-type",
+            Error::UnsupportedNode(_) => "unsupported-node",
+            Error::MissingEntry(_) => "missing-entry",
+            Error::MissingNode(_) => "missing-node",
+            Error::MissingProperty(_) => "missing-property",
+            Error::UnsupportedValue(_) => "unsupported-value",
+        }
+        .to_owned()
+    }
+
+    /// Render the error as a JSON object for tooling integration
+    ///
+    /// The object has the form
+    /// `{ "code": "...", "message": "...", "span": { "offset": .., "length": .. }, "advice": "..." }`,
+    /// with `span` and `advice` set to `null` when unavailable.
+    pub fn to_json(&self) -> serde_json::Value {
+        let span = self.labels().and_then(|mut labels| labels.next()).map(|label| {
+            serde_json::json!({
+                "offset": label.offset(),
+                "length": label.len(),
+            })
+        });
+
+        serde_json::json!({
+            "code": self.json_code(),
+            "message": self.to_string(),
+            "span": span,
+            "advice": self.help().map(|h| h.to_string()),
+        })
+    }
+}
+
 #[cfg(feature = "kdl")]
 /// Merged error for parsing failures
 /// Returns a list of diagnostics for the user
@@ -80,6 +126,14 @@ pub struct ParseError {
     pub diagnostics: Vec<Error>,
 }
 
+#[cfg(feature = "kdl")]
+impl ParseError {
+    /// Render all collected diagnostics as a JSON array, see [`Error::to_json`]
+    pub fn to_json_array(&self) -> serde_json::Value {
+        self.diagnostics.iter().map(Error::to_json).collect()
+    }
+}
+
 #[cfg(feature = "kdl")]
 /// Error for invalid types
 #[derive(Debug, Diagnostic, Error)]
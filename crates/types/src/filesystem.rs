@@ -14,6 +14,8 @@ use super::FromKdlProperty;
 /// The filesystem information for a partition
 /// This is used to format the partition with a filesystem
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum Filesystem {
     Fat32 {
         label: Option<String>,
@@ -23,20 +25,80 @@ pub enum Filesystem {
         filesystem_type: StandardFilesystemType,
         label: Option<String>,
         uuid: Option<String>,
+        /// Subvolumes to create after formatting, only valid for Btrfs
+        subvolumes: Option<Vec<BtrfsSubvol>>,
     },
 }
 
+/// A Btrfs subvolume and where it is mounted
+///
+/// Subvolumes are created after the filesystem is formatted rather than by
+/// the mkfs command itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct BtrfsSubvol {
+    /// Path of the subvolume within the filesystem, e.g. `@home`
+    pub name: String,
+    /// Where the subvolume is mounted in the target system, e.g. `/home`
+    #[cfg_attr(feature = "serde", serde(rename = "mountpoint"))]
+    pub mount_point: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum StandardFilesystemType {
+    Btrfs,
     F2fs,
     Ext4,
     Xfs,
     Swap,
 }
 
+/// What a filesystem supports, e.g. for offering it in a "format as" menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct FsCapabilities {
+    /// A label can be set when formatting
+    pub supports_label: bool,
+    /// A UUID, or FAT volume ID, can be set when formatting
+    pub supports_uuid: bool,
+    /// The filesystem can be grown after it is created, without reformatting
+    pub supports_resize: bool,
+    /// Longest label accepted by the mkfs command, in bytes
+    pub max_label_len: usize,
+}
+
+impl StandardFilesystemType {
+    /// Every supported filesystem type other than FAT, which is [`Filesystem::Fat32`]
+    pub fn all() -> [Self; 5] {
+        [Self::Btrfs, Self::Ext4, Self::F2fs, Self::Xfs, Self::Swap]
+    }
+
+    /// Returns what the filesystem supports
+    pub fn capabilities(&self) -> FsCapabilities {
+        let (supports_resize, max_label_len) = match self {
+            Self::Btrfs => (true, 255),
+            Self::Ext4 => (true, 16),
+            Self::F2fs => (true, 512),
+            Self::Xfs => (true, 12),
+            Self::Swap => (false, 16),
+        };
+        FsCapabilities {
+            supports_label: true,
+            supports_uuid: true,
+            supports_resize,
+            max_label_len,
+        }
+    }
+}
+
 impl fmt::Display for StandardFilesystemType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Btrfs => f.write_str("btrfs"),
             Self::Ext4 => f.write_str("ext4"),
             Self::F2fs => f.write_str("f2fs"),
             Self::Xfs => f.write_str("xfs"),
@@ -45,11 +107,84 @@ impl fmt::Display for StandardFilesystemType {
     }
 }
 
+impl Filesystem {
+    /// Returns the name of the filesystem type, e.g. `ext4`
+    fn type_name(&self) -> String {
+        match self {
+            Self::Fat32 { .. } => "fat32".into(),
+            Self::Standard { filesystem_type, .. } => filesystem_type.to_string(),
+        }
+    }
+
+    /// Returns the label, if any
+    fn label(&self) -> Option<&str> {
+        match self {
+            Self::Fat32 { label, .. } | Self::Standard { label, .. } => label.as_deref(),
+        }
+    }
+
+    /// Returns what the filesystem supports
+    pub fn capabilities(&self) -> FsCapabilities {
+        match self {
+            Self::Fat32 { .. } => FsCapabilities {
+                supports_label: true,
+                supports_uuid: true,
+                supports_resize: false,
+                max_label_len: 11,
+            },
+            Self::Standard { filesystem_type, .. } => filesystem_type.capabilities(),
+        }
+    }
+
+    /// Returns the Btrfs subvolumes to create, if any
+    pub fn subvolumes(&self) -> &[BtrfsSubvol] {
+        match self {
+            Self::Standard {
+                subvolumes: Some(subvolumes),
+                ..
+            } => subvolumes,
+            _ => &[],
+        }
+    }
+
+    /// Returns a short description of the filesystem, omitting any UUID or volume ID
+    pub fn summary(&self) -> String {
+        match self.label() {
+            Some(label) => format!("{} (label: {label})", self.type_name()),
+            None => self.type_name(),
+        }
+    }
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut details = vec![];
+        if let Some(label) = self.label() {
+            details.push(format!("label: {label}"));
+        }
+        match self {
+            Self::Fat32 {
+                volume_id: Some(volume_id),
+                ..
+            } => details.push(format!("volume_id: {:04X}-{:04X}", volume_id >> 16, volume_id & 0xFFFF)),
+            Self::Standard { uuid: Some(uuid), .. } => details.push(format!("uuid: {uuid}")),
+            _ => {}
+        }
+
+        if details.is_empty() {
+            f.write_str(&self.type_name())
+        } else {
+            write!(f, "{} ({})", self.type_name(), details.join(", "))
+        }
+    }
+}
+
 impl FromStr for StandardFilesystemType {
     type Err = crate::Error;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
+            "btrfs" => Ok(Self::Btrfs),
             "ext4" => Ok(Self::Ext4),
             "f2fs" => Ok(Self::F2fs),
             "xfs" => Ok(Self::Xfs),
@@ -65,7 +200,7 @@ impl FromKdlProperty<'_> for StandardFilesystemType {
         let value = kdl_value_to_string(entry)?;
         let v = value.parse().map_err(|_| crate::UnsupportedValue {
             at: entry.span(),
-            advice: Some("'fat32', 'ext4', 'f2fs', 'xfs' 'swap' are supported".into()),
+            advice: Some("'fat32', 'btrfs', 'ext4', 'f2fs', 'xfs' 'swap' are supported".into()),
         })?;
         Ok(v)
     }
@@ -78,6 +213,7 @@ impl Filesystem {
         let mut label = None;
         let mut uuid = None;
         let mut volume_id = None;
+        let mut subvolumes: Option<Vec<BtrfsSubvol>> = None;
 
         for entry in node.iter_children() {
             match entry.name().value() {
@@ -85,6 +221,10 @@ impl Filesystem {
                 "label" => label = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
                 "uuid" => uuid = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
                 "volume_id" => volume_id = Some(kdl_value_to_integer(get_kdl_entry(entry, &0)?)? as u32),
+                "subvolume" => subvolumes.get_or_insert_default().push(BtrfsSubvol {
+                    name: kdl_value_to_string(get_kdl_entry(entry, &0)?)?,
+                    mount_point: entry.entry("mountpoint").map(kdl_value_to_string).transpose()?,
+                }),
                 _ => {
                     return Err(crate::UnsupportedNode {
                         at: entry.span(),
@@ -100,6 +240,14 @@ impl Filesystem {
             name: "type".into(),
         })?;
 
+        if subvolumes.is_some() && fs_type != "btrfs" {
+            return Err(crate::InvalidArguments {
+                at: node.span(),
+                advice: Some(format!("subvolumes are only supported for btrfs, not {fs_type}")),
+            }
+            .into());
+        }
+
         match fs_type.as_str() {
             "fat32" => {
                 if uuid.is_some() {
@@ -123,6 +271,7 @@ impl Filesystem {
                     filesystem_type: fs_type.parse()?,
                     label,
                     uuid,
+                    subvolumes,
                 })
             }
         }
@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{fmt, str::FromStr};
+
+use bitflags::bitflags;
+
+#[cfg(feature = "kdl")]
+use crate::kdl_value_to_string;
+
+#[cfg(feature = "kdl")]
+use super::FromKdlProperty;
+
+bitflags! {
+    /// Attribute flags stored in a GPT partition entry
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct GptFlags: u64 {
+        /// The partition is required for the platform to function
+        const REQUIRED = 1 << 0;
+        /// Firmware must not produce a block IO protocol for the partition
+        const NO_BLOCK_IO = 1 << 1;
+        /// Legacy BIOS bootable, as used by some BIOS boot loaders
+        const LEGACY_BIOS_BOOTABLE = 1 << 2;
+        /// Mount the partition read-only (systemd discoverable partitions)
+        const READ_ONLY = 1 << 60;
+        /// Hide the partition from automatic discovery
+        const HIDDEN = 1 << 62;
+        /// Do not mount the partition automatically
+        const NO_AUTOMOUNT = 1 << 63;
+    }
+}
+
+impl GptFlags {
+    /// Each named flag with the name used in KDL
+    const NAMES: [(Self, &'static str); 6] = [
+        (Self::REQUIRED, "required"),
+        (Self::NO_BLOCK_IO, "no-block-io"),
+        (Self::LEGACY_BIOS_BOOTABLE, "legacy-bios-bootable"),
+        (Self::READ_ONLY, "read-only"),
+        (Self::HIDDEN, "hidden"),
+        (Self::NO_AUTOMOUNT, "no-automount"),
+    ];
+}
+
+impl fmt::Display for GptFlags {
+    /// Formats the flags as a comma separated list of names, as accepted by [`GptFlags::from_str`]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        f.write_str(&names.join(","))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for GptFlags {
+    /// Serializes the flags in their [`Display`](fmt::Display) form
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for GptFlags {
+    type Err = crate::Error;
+
+    /// Attempt to parse a comma separated list of flag names, e.g. `"required,read-only"`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .try_fold(Self::empty(), |flags, name| {
+                Self::NAMES
+                    .iter()
+                    .find(|(_, n)| *n == name)
+                    .map(|(flag, _)| flags | *flag)
+                    .ok_or(crate::Error::UnknownVariant)
+            })
+    }
+}
+
+#[cfg(feature = "kdl")]
+impl FromKdlProperty<'_> for GptFlags {
+    fn from_kdl_property(entry: &kdl::KdlEntry) -> Result<Self, crate::Error> {
+        let value = kdl_value_to_string(entry)?;
+        let v = value.parse().map_err(|_| crate::UnsupportedValue {
+            at: entry.span(),
+            advice: Some(
+                "a comma separated list of 'required', 'no-block-io', 'legacy-bios-bootable', 'read-only', 'hidden' and 'no-automount'"
+                    .into(),
+            ),
+        })?;
+        Ok(v)
+    }
+}
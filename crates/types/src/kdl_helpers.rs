@@ -100,6 +100,16 @@ pub fn kdl_value_to_integer(entry: &kdl::KdlEntry) -> Result<i128, Error> {
     Ok(value)
 }
 
+// Get a boolean property from a value
+pub fn kdl_value_to_bool(entry: &kdl::KdlEntry) -> Result<bool, Error> {
+    let value = entry.value().as_bool().ok_or(InvalidType {
+        at: entry.span(),
+        expected_type: KdlType::Boolean,
+    })?;
+
+    Ok(value)
+}
+
 // Convert a KDL value to a storage size
 pub fn kdl_value_to_storage_size(entry: &kdl::KdlEntry) -> Result<u64, Error> {
     let value = kdl_value_to_integer(entry)?;
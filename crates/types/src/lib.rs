@@ -23,5 +23,9 @@ pub mod constraints;
 pub use constraints::*;
 pub mod filesystem;
 pub use filesystem::*;
+pub mod encryption;
+pub use encryption::*;
 mod partition_type;
 pub use partition_type::*;
+mod gpt_flags;
+pub use gpt_flags::*;
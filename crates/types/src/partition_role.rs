@@ -5,6 +5,8 @@
 
 use std::{fmt, str::FromStr};
 
+use crate::{Filesystem, StandardFilesystemType};
+
 #[cfg(feature = "kdl")]
 use crate::kdl_value_to_string;
 
@@ -13,6 +15,8 @@ use super::FromKdlProperty;
 
 /// The role assigned to a partition
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum PartitionRole {
     /// Boot partition (usually ESP)
     Boot,
@@ -23,23 +27,70 @@ pub enum PartitionRole {
     /// Root filesystem
     Root,
 
+    /// `/usr` mount
+    Usr,
+
     /// Home directory mount
     Home,
 
+    /// `/var` mount
+    Var,
+
+    /// `/srv` mount
+    Srv,
+
     /// Swap partition
     Swap,
 }
 
 impl PartitionRole {
+    /// Every role, covering the systemd discoverable partition mount points
+    pub const ALL: [Self; 8] = [
+        Self::Boot,
+        Self::ExtendedBoot,
+        Self::Root,
+        Self::Usr,
+        Self::Home,
+        Self::Var,
+        Self::Srv,
+        Self::Swap,
+    ];
+
     pub fn as_path(&self) -> &'static str {
         match self {
             Self::Boot => "/efi",
             Self::ExtendedBoot => "/boot",
             Self::Root => "/",
+            Self::Usr => "/usr",
             Self::Home => "/home",
+            Self::Var => "/var",
+            Self::Srv => "/srv",
             Self::Swap => "swap",
         }
     }
+
+    /// Returns the default mount options for this role formatted with `filesystem`
+    ///
+    /// Boot partitions are not executable and, on FAT, readable by root only.
+    /// The system partitions skip access time updates, while `/home` and `/srv`
+    /// refuse device nodes and setuid binaries. Swap takes no options.
+    pub fn default_mount_options(&self, filesystem: &Filesystem) -> Vec<String> {
+        let options: &[&str] = match (self, filesystem) {
+            (Self::Swap, _)
+            | (
+                _,
+                Filesystem::Standard {
+                    filesystem_type: StandardFilesystemType::Swap,
+                    ..
+                },
+            ) => &[],
+            (Self::Boot | Self::ExtendedBoot, Filesystem::Fat32 { .. }) => &["umask=0077", "noexec", "nosuid", "nodev"],
+            (Self::Boot | Self::ExtendedBoot, Filesystem::Standard { .. }) => &["noexec", "nosuid", "nodev"],
+            (Self::Root | Self::Usr | Self::Var, _) => &["noatime"],
+            (Self::Home | Self::Srv, _) => &["nodev", "nosuid"],
+        };
+        options.iter().map(|option| option.to_string()).collect()
+    }
 }
 
 impl fmt::Display for PartitionRole {
@@ -48,7 +99,10 @@ impl fmt::Display for PartitionRole {
             Self::Boot => f.write_str("boot"),
             Self::ExtendedBoot => f.write_str("extended-boot"),
             Self::Root => f.write_str("root"),
+            Self::Usr => f.write_str("usr"),
             Self::Home => f.write_str("home"),
+            Self::Var => f.write_str("var"),
+            Self::Srv => f.write_str("srv"),
             Self::Swap => f.write_str("swap"),
         }
     }
@@ -58,12 +112,18 @@ impl FromStr for PartitionRole {
     type Err = crate::Error;
 
     /// Attempt to convert a string to a partition role
+    ///
+    /// `esp` and `xbootldr` are accepted as the systemd names for `boot` and
+    /// `extended-boot`.
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
-            "boot" => Ok(Self::Boot),
-            "extended-boot" => Ok(Self::ExtendedBoot),
+            "boot" | "esp" => Ok(Self::Boot),
+            "extended-boot" | "xbootldr" => Ok(Self::ExtendedBoot),
             "root" => Ok(Self::Root),
+            "usr" => Ok(Self::Usr),
             "home" => Ok(Self::Home),
+            "var" => Ok(Self::Var),
+            "srv" => Ok(Self::Srv),
             "swap" => Ok(Self::Swap),
             _ => Err(crate::Error::UnknownVariant),
         }
@@ -76,7 +136,10 @@ impl FromKdlProperty<'_> for PartitionRole {
         let value = kdl_value_to_string(entry)?;
         let v = value.parse().map_err(|_| crate::UnsupportedValue {
             at: entry.span(),
-            advice: Some("'boot', 'extended-boot', 'root', 'home' and 'swap' are supported".into()),
+            advice: Some(
+                "'boot' (or 'esp'), 'extended-boot' (or 'xbootldr'), 'root', 'usr', 'home', 'var', 'srv' and 'swap' are supported"
+                    .into(),
+            ),
         })?;
         Ok(v)
     }
@@ -10,21 +10,65 @@ use super::FromKdlProperty;
 #[cfg(feature = "kdl")]
 use crate::kdl_value_to_string;
 
+/// Default number of partition entries in a GPT
+pub const GPT_DEFAULT_PARTITION_COUNT: u32 = 128;
+
+/// Number of primary partition slots in an MBR
+pub const MBR_PRIMARY_PARTITION_COUNT: u32 = 4;
+
 /// The type of partition table to create
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum PartitionTableType {
     /// GUID Partition Table
     Gpt,
 
+    /// GUID Partition Table with room for the given number of partition entries
+    GptWithPartCount(u32),
+
     /// Master Boot Record
     Msdos,
+
+    /// Master Boot Record limited to the given number of primary partitions
+    MbrWithPartCount(u32),
+
+    /// Chosen for the machine's firmware when planning: GPT for UEFI, MBR for legacy BIOS
+    Auto,
+}
+
+impl PartitionTableType {
+    /// The number of partitions the table has room for
+    pub fn partition_count(&self) -> u32 {
+        match self {
+            Self::Gpt | Self::Auto => GPT_DEFAULT_PARTITION_COUNT,
+            Self::Msdos => MBR_PRIMARY_PARTITION_COUNT,
+            Self::GptWithPartCount(count) | Self::MbrWithPartCount(count) => *count,
+        }
+    }
+
+    /// Returns true for the GPT variants
+    pub fn is_gpt(&self) -> bool {
+        matches!(self, Self::Gpt | Self::GptWithPartCount(_))
+    }
+
+    /// The same table type with room for `count` partitions
+    pub fn with_partition_count(self, count: u32) -> Self {
+        match self.is_gpt() {
+            true => Self::GptWithPartCount(count),
+            false => Self::MbrWithPartCount(count),
+        }
+    }
 }
 
 impl fmt::Display for PartitionTableType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Gpt => f.write_str("gpt"),
+            Self::GptWithPartCount(count) => write!(f, "gpt ({count} partitions)"),
             Self::Msdos => f.write_str("msdos"),
+            Self::MbrWithPartCount(count) => write!(f, "msdos ({count} partitions)"),
+            Self::Auto => f.write_str("auto"),
         }
     }
 }
@@ -37,6 +81,7 @@ impl FromStr for PartitionTableType {
         match value {
             "gpt" => Ok(Self::Gpt),
             "msdos" => Ok(Self::Msdos),
+            "auto" => Ok(Self::Auto),
             _ => Err(crate::Error::UnknownVariant),
         }
     }
@@ -48,7 +93,7 @@ impl FromKdlProperty<'_> for PartitionTableType {
         let value = kdl_value_to_string(entry)?;
         let v = value.parse().map_err(|_| crate::UnsupportedValue {
             at: entry.span(),
-            advice: Some("'gpt' and 'msdos' are supported".into()),
+            advice: Some("'gpt', 'msdos' and 'auto' are supported".into()),
         })?;
         Ok(v)
     }
@@ -8,6 +8,8 @@ use std::{fmt, str::FromStr};
 pub use gpt::partition_types::Type as GptPartitionType;
 pub use uuid::Uuid;
 
+use crate::{Filesystem, StandardFilesystemType};
+
 #[cfg(feature = "kdl")]
 use crate::{UnsupportedValue, get_kdl_entry, kdl_value_to_string};
 
@@ -59,10 +61,15 @@ impl<'a> FromKdlType<'a> for PartitionTypeKDL {
 
 /// Represents GPT partition type GUIDs
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum PartitionTypeGuid {
+    #[cfg_attr(feature = "serde", serde(rename = "efi-system-partition"))]
     EfiSystemPartition,
+    #[cfg_attr(feature = "serde", serde(rename = "linux-extended-boot"))]
     ExtendedBootLoader,
+    #[cfg_attr(feature = "serde", serde(rename = "linux-swap"))]
     LinuxSwap,
+    #[cfg_attr(feature = "serde", serde(rename = "linux-fs"))]
     LinuxFilesystem,
 }
 
@@ -102,6 +109,21 @@ impl PartitionTypeGuid {
         }
     }
 
+    /// Returns the recommended partition type for a filesystem
+    ///
+    /// FAT32 is assumed to be an EFI System Partition, swap gets the swap
+    /// type and everything else is a generic Linux filesystem.
+    pub fn recommended_for(fs: &Filesystem) -> Self {
+        match fs {
+            Filesystem::Fat32 { .. } => Self::EfiSystemPartition,
+            Filesystem::Standard {
+                filesystem_type: StandardFilesystemType::Swap,
+                ..
+            } => Self::LinuxSwap,
+            Filesystem::Standard { .. } => Self::LinuxFilesystem,
+        }
+    }
+
     #[cfg(feature = "kdl")]
     pub fn from_kdl_node(node: &kdl::KdlNode) -> Result<Self, crate::Error> {
         let value = kdl_value_to_string(get_kdl_entry(node, &0)?)?;